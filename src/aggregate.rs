@@ -0,0 +1,597 @@
+use std::{error, io::{self, Write}, path, str::FromStr};
+use std::collections::{HashMap, HashSet};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use crate::extract::{FileSummary, RegionRecord, UNLABELED};
+use crate::report::csv_field;
+use crate::input::xml_sources;
+use crate::options::Options;
+use crate::patterns::matches_glob;
+use crate::qc::{QC_RULE_COLUMNS, QcRules, qc_rule_values};
+use crate::process_source;
+use crate::report::{is_csv_file, read_records};
+
+/// Column names of the per-slide summary
+pub const SLIDE_SUMMARY_HEADER: &str = "Slide Name,regions,num weak positive,num positive,num strong positive,num all positive,num total,positivity";
+
+/// Column names of the per-label summary
+pub const LABEL_SUMMARY_HEADER: &str = "Slide Name,text label,regions,num weak positive,num positive,num strong positive,num all positive,num total,positivity";
+
+/// Column names of the cohort summary
+pub const COHORT_SUMMARY_HEADER: &str = "text label,slides,regions,n,mean positivity,median positivity,positivity IQR,min positivity,max positivity";
+
+/// Column names of the group comparison
+pub const COMPARISON_HEADER: &str = "group,slides,mean positivity,SD positivity,mean difference,cohens d";
+
+/// Label of the cohort summary line covering every region
+pub const ALL_LABELS: &str = "(all)";
+
+/// What regions are added up by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GroupBy {
+    /// All regions of a slide together
+    #[default]
+    Slide,
+    /// Regions of a slide with the same text label together
+    Label,
+    /// Regions with the same text label across all slides, see `CohortSummary`
+    Cohort,
+}
+
+impl FromStr for GroupBy {
+    type Err = Box<dyn error::Error>;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "slide" => Ok(GroupBy::Slide),
+            "label" => Ok(GroupBy::Label),
+            "cohort" => Ok(GroupBy::Cohort),
+            other => Err(format!("Unknown grouping {}, expected slide, label or cohort", other).into()),
+        }
+    }
+}
+
+/// Counts of several regions added up
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Aggregate {
+    pub slide_name: String,
+    /// Text label of the regions, None when all regions of the slide are added up
+    pub label: Option<String>,
+    /// Number of regions added
+    pub regions: usize,
+    pub num_wpositive: f64,
+    pub num_positive: f64,
+    pub num_spositive: f64,
+    pub num_all_positive: f64,
+    pub num_total: f64,
+}
+
+impl Aggregate {
+    /// Add the counts of a region, missing counts count as 0
+    pub fn add(&mut self, record: &RegionRecord) {
+        self.regions += 1;
+        self.num_wpositive += record.num_wpositive.unwrap_or(0.0);
+        self.num_positive += record.num_positive.unwrap_or(0.0);
+        self.num_spositive += record.num_spositive.unwrap_or(0.0);
+        self.num_all_positive += record.num_all_positive;
+        self.num_total += record.num_total.unwrap_or(0.0);
+    }
+
+    /// Positivity of all regions together, i.e. weighted by their total counts; None without any counts
+    pub fn positivity(&self) -> Option<f64> {
+        (self.num_total > 0.0).then(|| self.num_all_positive / self.num_total)
+    }
+}
+
+/// Labels of the regions that count towards slide-level metrics, as globs where `*` stands for any text
+/// A region counts if its label matches one of `include`, or there are none, and none of `exclude`, e.g.
+/// `include = ["Tumor*"]` and `exclude = ["Control*"]` for slide positivity over the tumor regions only.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LabelFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl LabelFilter {
+    /// True if regions labelled `label` count
+    pub fn matches(&self, label: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|g| matches_glob(label, g))) && !self.exclude.iter().any(|g| matches_glob(label, g))
+    }
+}
+
+/// Add up the regions of each slide, in the order the slides first appear
+pub fn aggregate_slides(records: &[RegionRecord]) -> Vec<Aggregate> {
+    aggregate(records, GroupBy::Slide)
+}
+
+/// Add up the regions of each slide whose label passes `filter`, in the order the slides first appear
+/// Slides without any such region are kept with no regions, so their positivity is missing.
+pub fn aggregate_slides_filtered(records: &[RegionRecord], filter: &LabelFilter) -> Vec<Aggregate> {
+    let mut slides: IndexMap<&str, Aggregate> = IndexMap::new();
+    for record in records {
+        let slide = slides.entry(&record.slide_name)
+            .or_insert_with(|| Aggregate { slide_name: record.slide_name.clone(), ..Aggregate::default() });
+        if filter.matches(&record.text_label) {
+            slide.add(record);
+        }
+    }
+    slides.into_values().collect()
+}
+
+/// Add up the regions of each slide and label, in the order they first appear
+pub fn aggregate_labels(records: &[RegionRecord]) -> Vec<Aggregate> {
+    aggregate(records, GroupBy::Label)
+}
+
+/// Add up the regions of each group
+pub fn aggregate(records: &[RegionRecord], by: GroupBy) -> Vec<Aggregate> {
+    let mut groups: IndexMap<(&str, Option<&String>), Aggregate> = IndexMap::new();
+    for record in records {
+        let label = match by {
+            GroupBy::Slide => None,
+            GroupBy::Label | GroupBy::Cohort => Some(&record.text_label),
+        };
+        groups.entry((&record.slide_name, label))
+            .or_insert_with(|| Aggregate { slide_name: record.slide_name.clone(), label: label.cloned(), ..Aggregate::default() })
+            .add(record);
+    }
+    groups.into_values().collect()
+}
+
+/// Overview of the regions with one label across a whole batch of slides
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CohortSummary {
+    pub label: String,
+    /// Number of slides with regions of this label
+    pub slides: usize,
+    pub regions: usize,
+    /// Positivity of every region that has one
+    pub positivities: Vec<f64>,
+}
+
+impl CohortSummary {
+    /// Mean of the region positivities, None without any
+    pub fn mean_positivity(&self) -> Option<f64> {
+        (!self.positivities.is_empty()).then(|| self.positivities.iter().sum::<f64>() / self.positivities.len() as f64)
+    }
+
+    /// Median of the region positivities, None without any
+    pub fn median_positivity(&self) -> Option<f64> {
+        quantile(&self.positivities, 0.5)
+    }
+
+    /// Interquartile range of the region positivities, None without any
+    pub fn positivity_iqr(&self) -> Option<f64> {
+        quantile(&self.positivities, 0.75).zip(quantile(&self.positivities, 0.25)).map(|(q3, q1)| q3 - q1)
+    }
+
+    /// Lowest region positivity, None without any
+    pub fn min_positivity(&self) -> Option<f64> {
+        quantile(&self.positivities, 0.0)
+    }
+
+    /// Highest region positivity, None without any
+    pub fn max_positivity(&self) -> Option<f64> {
+        quantile(&self.positivities, 1.0)
+    }
+}
+
+/// Quantile `q` of `values` with linear interpolation between the closest ranks, None without values
+pub(crate) fn quantile(values: &[f64], q: f64) -> Option<f64> {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+    if sorted.is_empty() {
+        return None;
+    }
+    sorted.sort_by(f64::total_cmp);
+    let rank = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64))
+}
+
+/// Overview of a batch: a line for all regions followed by one per label, in the order labels first appear
+pub fn cohort_summary(records: &[RegionRecord]) -> Vec<CohortSummary> {
+    let mut all = CohortSummary { label: ALL_LABELS.to_string(), ..CohortSummary::default() };
+    let mut labels: IndexMap<&str, CohortSummary> = IndexMap::new();
+    let mut seen_slides: HashSet<&str> = HashSet::new();
+    let mut seen: HashSet<(&str, &str)> = HashSet::new();
+    for record in records {
+        let label = labels.entry(&record.text_label)
+            .or_insert_with(|| CohortSummary { label: record.text_label.clone(), ..CohortSummary::default() });
+        if seen_slides.insert(&record.slide_name) {
+            all.slides += 1;
+        }
+        if seen.insert((&record.slide_name, &record.text_label)) {
+            label.slides += 1;
+        }
+        for summary in [&mut all, label] {
+            summary.regions += 1;
+            summary.positivities.extend(record.positivity);
+        }
+    }
+    std::iter::once(all).chain(labels.into_values()).collect()
+}
+
+/// Write one CSV line per cohort summary, without a header
+/// `n` is the number of regions with a positivity, which the statistics are taken over.
+pub fn write_cohort_records<W: Write>(summaries: &[CohortSummary], writer: &mut W) -> io::Result<()> {
+    for c in summaries {
+        writeln!(writer, "{},{},{},{},{},{},{},{},{}",
+            csv_field(&c.label),
+            c.slides,
+            c.regions,
+            c.positivities.len(),
+            c.mean_positivity().unwrap_or(f64::NAN),
+            c.median_positivity().unwrap_or(f64::NAN),
+            c.positivity_iqr().unwrap_or(f64::NAN),
+            c.min_positivity().unwrap_or(f64::NAN),
+            c.max_positivity().unwrap_or(f64::NAN))?;
+    }
+    Ok(())
+}
+
+/// Slide positivities of one group of slides, e.g. treated or control
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GroupComparison {
+    pub group: String,
+    /// Positivity of all regions of each slide together, for slides with any counts
+    pub positivities: Vec<f64>,
+}
+
+impl GroupComparison {
+    /// Mean slide positivity, None without any
+    pub fn mean_positivity(&self) -> Option<f64> {
+        (!self.positivities.is_empty()).then(|| self.positivities.iter().sum::<f64>() / self.positivities.len() as f64)
+    }
+
+    /// Sample standard deviation of the slide positivities, None with fewer than 2
+    pub fn sd_positivity(&self) -> Option<f64> {
+        let mean = self.mean_positivity().filter(|_| self.positivities.len() >= 2)?;
+        let squares: f64 = self.positivities.iter().map(|p| (p - mean).powi(2)).sum();
+        Some((squares / (self.positivities.len() - 1) as f64).sqrt())
+    }
+
+    /// Mean positivity minus that of `reference`
+    pub fn mean_difference(&self, reference: &GroupComparison) -> Option<f64> {
+        self.mean_positivity().zip(reference.mean_positivity()).map(|(m, r)| m - r)
+    }
+
+    /// Cohen's d against `reference`, the mean difference over the pooled standard deviation
+    /// None unless both groups have at least 2 slides and some spread.
+    pub fn cohens_d(&self, reference: &GroupComparison) -> Option<f64> {
+        let (n1, n2) = (self.positivities.len() as f64, reference.positivities.len() as f64);
+        let (sd1, sd2) = self.sd_positivity().zip(reference.sd_positivity())?;
+        let pooled = (((n1 - 1.0) * sd1.powi(2) + (n2 - 1.0) * sd2.powi(2)) / (n1 + n2 - 2.0)).sqrt();
+        self.mean_difference(reference).filter(|_| pooled > 0.0).map(|d| d / pooled)
+    }
+}
+
+/// Slide positivities grouped by the value of the extra text `column`, e.g. a metadata column, sorted by group
+/// Only regions whose label passes `filter` count towards the slide positivities.
+pub fn compare_groups(records: &[RegionRecord], column: &str, filter: &LabelFilter) -> Vec<GroupComparison> {
+    // Each slide is in the group of its first record
+    let mut slide_groups: HashMap<&str, &str> = HashMap::new();
+    for record in records {
+        slide_groups.entry(&record.slide_name).or_insert_with(|| {
+            record.text_columns.iter().find(|(name, _)| name == column).map(|(_, value)| value.as_str()).unwrap_or_default()
+        });
+    }
+    let mut groups: IndexMap<&str, GroupComparison> = IndexMap::new();
+    for slide in aggregate_slides_filtered(records, filter) {
+        let group = slide_groups[slide.slide_name.as_str()];
+        groups.entry(group)
+            .or_insert_with(|| GroupComparison { group: group.to_string(), ..GroupComparison::default() })
+            .positivities.extend(slide.positivity());
+    }
+    let mut groups: Vec<GroupComparison> = groups.into_values().collect();
+    groups.sort_by(|a, b| a.group.cmp(&b.group));
+    groups
+}
+
+/// Write one CSV line per group, without a header
+/// Differences and effect sizes are against the first group, e.g. `control` before `treated`, so left NaN on its
+/// own line.
+pub fn write_comparison_records<W: Write>(groups: &[GroupComparison], writer: &mut W) -> io::Result<()> {
+    let Some(reference) = groups.first() else { return Ok(()) };
+    for (i, g) in groups.iter().enumerate() {
+        let versus = (i > 0).then_some(reference);
+        writeln!(writer, "{},{},{},{},{},{}",
+            csv_field(&g.group),
+            g.positivities.len(),
+            g.mean_positivity().unwrap_or(f64::NAN),
+            g.sd_positivity().unwrap_or(f64::NAN),
+            versus.and_then(|r| g.mean_difference(r)).unwrap_or(f64::NAN),
+            versus.and_then(|r| g.cohens_d(r)).unwrap_or(f64::NAN))?;
+    }
+    Ok(())
+}
+
+/// Write one CSV line per aggregate, without a header
+/// The label column is only written for aggregates by label. Positivity is NaN without any counts, as in the
+/// region report.
+pub fn write_aggregate_records<W: Write>(aggregates: &[Aggregate], writer: &mut W) -> io::Result<()> {
+    for a in aggregates {
+        write_aggregate_fields(a, writer)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Write one CSV line per slide aggregate followed by the `QC_RULE_COLUMNS` of checking it against `rules`
+/// Returns the number of slides failing the rules
+pub fn write_checked_aggregate_records<W: Write>(aggregates: &[Aggregate], rules: &QcRules, writer: &mut W) -> io::Result<usize> {
+    let mut failures = 0;
+    for a in aggregates {
+        write_aggregate_fields(a, writer)?;
+        let failed = rules.slide_failures(a);
+        failures += usize::from(!failed.is_empty());
+        let [result, failed] = qc_rule_values(&failed);
+        writeln!(writer, ",{},{}", result, failed)?;
+    }
+    Ok(failures)
+}
+
+/// Write the fields of an aggregate, without ending the line
+fn write_aggregate_fields<W: Write>(a: &Aggregate, writer: &mut W) -> io::Result<()> {
+    write!(writer, "{},", csv_field(&a.slide_name))?;
+    if let Some(label) = &a.label {
+        write!(writer, "{},", csv_field(label))?;
+    }
+    write!(writer, "{},{},{},{},{},{},{}",
+        a.regions,
+        a.num_wpositive,
+        a.num_positive,
+        a.num_spositive,
+        a.num_all_positive,
+        a.num_total,
+        a.positivity().unwrap_or(f64::NAN))
+}
+
+/// Metrics of each label in the pivot table, as column name suffixes
+pub const PIVOT_METRICS: [&str; 5] = ["positivity", "nwp", "np", "nsp", "ntotal"];
+
+/// Labels of all regions in alphabetical order, regions without a text being `unlabeled`
+fn pivot_labels(records: &[RegionRecord]) -> Vec<&str> {
+    let mut labels: Vec<&str> = records.iter().map(|r| if r.text_label.is_empty() { UNLABELED } else { r.text_label.as_str() }).collect();
+    labels.sort_unstable();
+    labels.dedup();
+    labels
+}
+
+/// Write a pivot table with one row per slide and, for every label, the `PIVOT_METRICS` of its regions added up
+/// e.g. `Tumor_positivity` and `Tumor_ntotal`. Metrics of labels a slide has no regions of are NaN.
+pub fn write_pivot<W: Write>(records: &[RegionRecord], writer: &mut W) -> io::Result<()> {
+    let labels = pivot_labels(records);
+    write!(writer, "Slide Name")?;
+    for label in &labels {
+        for metric in PIVOT_METRICS {
+            write!(writer, ",{}", csv_field(&format!("{}_{}", label, metric)))?;
+        }
+    }
+    writeln!(writer)?;
+    // Regions without a text are added up with those labelled `unlabeled`, as they share its columns
+    let mut by_label: HashMap<(&str, &str), Aggregate> = HashMap::new();
+    for record in records {
+        let label = if record.text_label.is_empty() { UNLABELED } else { record.text_label.as_str() };
+        by_label.entry((&record.slide_name, label)).or_default().add(record);
+    }
+    for slide in aggregate_slides(records) {
+        write!(writer, "{}", csv_field(&slide.slide_name))?;
+        for label in &labels {
+            let values = match by_label.get(&(slide.slide_name.as_str(), *label)) {
+                Some(a) => [a.positivity().unwrap_or(f64::NAN), a.num_wpositive, a.num_positive, a.num_spositive, a.num_total],
+                None => [f64::NAN; 5],
+            };
+            for value in values {
+                write!(writer, ",{}", value)?;
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Process every XML file in `search_path` and write the pivot table by label into `writer`
+/// Returns a summary for each file processed
+pub fn run_pivot_to_writer<W: Write>(search_path: &path::Path, options: &Options, writer: &mut W) -> Result<Vec<FileSummary>, Box<dyn error::Error>> {
+    let (records, summaries) = collect_records(search_path, options)?;
+    write_pivot(&records, writer)?;
+    Ok(summaries)
+}
+
+/// Process every XML file in `search_path` and print the pivot table by label to stdout
+/// Returns the number of files that could not be processed
+pub fn run_pivot(search_path: &path::Path, options: &Options) -> Result<usize, Box<dyn error::Error>> {
+    let summaries = run_pivot_to_writer(search_path, options, &mut io::stdout().lock())?;
+    Ok(summaries.iter().filter(|s| s.failed()).count())
+}
+
+/// Process every XML file in `search_path` and write the summary grouped `by` slide, label or cohort into `writer`
+/// Returns a summary for each file processed, fails after writing if more regions and slides fail the QC rules
+/// than their budget
+pub fn run_summarize_to_writer<W: Write>(search_path: &path::Path, by: GroupBy, options: &Options, writer: &mut W) -> Result<Vec<FileSummary>, Box<dyn error::Error>> {
+    let (records, summaries) = collect_records(search_path, options)?;
+    // Records read from a report come without summaries, so their regions are checked here
+    let region_failures = if is_csv_file(search_path) && options.qc_rules.is_active() {
+        records.iter().filter(|r| !options.qc_rules.record_failures(r).is_empty()).count()
+    } else {
+        summaries.iter().map(|s| s.qc_failures).sum()
+    };
+    let mut slide_failures = 0;
+    match by {
+        GroupBy::Slide => {
+            let slides = aggregate_slides_filtered(&records, &options.slide_labels);
+            if options.qc_rules.is_active() {
+                writeln!(writer, "{},{}", SLIDE_SUMMARY_HEADER, QC_RULE_COLUMNS.join(","))?;
+                slide_failures = write_checked_aggregate_records(&slides, &options.qc_rules, writer)?;
+            } else {
+                writeln!(writer, "{}", SLIDE_SUMMARY_HEADER)?;
+                write_aggregate_records(&slides, writer)?;
+            }
+        },
+        GroupBy::Label => {
+            writeln!(writer, "{}", LABEL_SUMMARY_HEADER)?;
+            write_aggregate_records(&aggregate(&records, by), writer)?;
+        },
+        GroupBy::Cohort => {
+            writeln!(writer, "{}", COHORT_SUMMARY_HEADER)?;
+            write_cohort_records(&cohort_summary(&records), writer)?;
+        },
+    }
+    options.qc_rules.check_budget(region_failures, slide_failures)?;
+    Ok(summaries)
+}
+
+/// Process every XML file in `search_path` and print the summary grouped `by` slide, label or cohort to stdout
+/// Returns the number of files that could not be processed, fails if more regions and slides fail the QC rules
+/// than their budget
+pub fn run_summarize(search_path: &path::Path, by: GroupBy, options: &Options) -> Result<usize, Box<dyn error::Error>> {
+    let summaries = run_summarize_to_writer(search_path, by, options, &mut io::stdout().lock())?;
+    Ok(summaries.iter().filter(|s| s.failed()).count())
+}
+
+/// Process every XML file in `search_path` and write the comparison of the slide groups given by `column` into `writer`
+/// Returns a summary for each file processed
+pub fn run_compare_to_writer<W: Write>(search_path: &path::Path, column: &str, options: &Options, writer: &mut W) -> Result<Vec<FileSummary>, Box<dyn error::Error>> {
+    // Records read from a report carry their own columns, only known once read
+    let from_report = is_csv_file(search_path);
+    if !from_report && !options.text_column_names().contains(&column) {
+        return Err(format!("Unknown group column {}, add it with --metadata or --filename-pattern", column).into());
+    }
+    let (records, summaries) = collect_records(search_path, options)?;
+    if from_report && !records.iter().any(|r| r.text_columns.iter().any(|(name, _)| name == column)) {
+        return Err(format!("Unknown group column {}, not found in {}", column, search_path.display()).into());
+    }
+    writeln!(writer, "{}", COMPARISON_HEADER)?;
+    write_comparison_records(&compare_groups(&records, column, &options.slide_labels), writer)?;
+    Ok(summaries)
+}
+
+/// Process every XML file in `search_path` and print the comparison of the slide groups given by `column` to stdout
+/// Returns the number of files that could not be processed
+pub fn run_compare(search_path: &path::Path, column: &str, options: &Options) -> Result<usize, Box<dyn error::Error>> {
+    let summaries = run_compare_to_writer(search_path, column, options, &mut io::stdout().lock())?;
+    Ok(summaries.iter().filter(|s| s.failed()).count())
+}
+
+/// Records and summaries of every XML file in `search_path`, or the records of a CSV report written before
+/// without any summaries
+fn collect_records(search_path: &path::Path, options: &Options) -> Result<(Vec<RegionRecord>, Vec<FileSummary>), Box<dyn error::Error>> {
+    if is_csv_file(search_path) {
+        return Ok((read_records(search_path)?, Vec::new()));
+    }
+    let mut summaries = Vec::new();
+    let mut records = Vec::new();
+    for source in xml_sources(search_path, options)? {
+        let (file_records, summary) = process_source(&source, options);
+        records.extend(file_records);
+        summaries.push(summary);
+    }
+    Ok((records, summaries))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extract::RegionRecord;
+    use crate::metadata::parse_csv_line;
+    use super::{ALL_LABELS, GroupBy, GroupComparison, LabelFilter, aggregate, aggregate_slides_filtered, cohort_summary, compare_groups, write_pivot};
+
+    /// Record of a region with `positive` of `total` positive counts
+    fn record(slide: &str, label: &str, positive: f64, total: f64) -> RegionRecord {
+        RegionRecord {
+            filename: slide.replace(".svs", ".xml"),
+            slide_name: slide.to_string(),
+            region_id: String::from("1"),
+            text_label: label.to_string(),
+            positivity: (total > 0.0).then(|| positive / total),
+            num_wpositive: None,
+            num_positive: Some(positive),
+            num_spositive: None,
+            num_all_positive: positive,
+            num_total: Some(total),
+            algorithm: String::new(),
+            columns: Vec::new(),
+            text_columns: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn pivot_adds_up_unlabeled_regions_with_those_labelled_unlabeled() {
+        let records = [
+            record("case1.svs", "", 1.0, 10.0),
+            record("case1.svs", "unlabeled", 3.0, 10.0),
+            record("case1.svs", "Tumor", 5.0, 10.0),
+            record("case2.svs", "Tumor", 2.0, 10.0),
+        ];
+        let mut pivot = Vec::new();
+        write_pivot(&records, &mut pivot).unwrap();
+        let rows: Vec<Vec<String>> = String::from_utf8(pivot).unwrap().lines().map(parse_csv_line).collect();
+        assert_eq!(rows[0], ["Slide Name", "Tumor_positivity", "Tumor_nwp", "Tumor_np", "Tumor_nsp", "Tumor_ntotal", "unlabeled_positivity", "unlabeled_nwp", "unlabeled_np", "unlabeled_nsp", "unlabeled_ntotal"]);
+        assert_eq!(rows[1], ["case1.svs", "0.5", "0", "5", "0", "10", "0.2", "0", "4", "0", "20"]);
+        assert_eq!(rows[2], ["case2.svs", "0.2", "0", "2", "0", "10", "NaN", "NaN", "NaN", "NaN", "NaN"]);
+    }
+
+    #[test]
+    fn slides_and_labels_are_added_up_in_order_of_appearance() {
+        let records = [
+            record("case2.svs", "Tumor", 2.0, 10.0),
+            record("case1.svs", "Stroma", 1.0, 30.0),
+            record("case2.svs", "Tumor", 6.0, 10.0),
+            record("case2.svs", "Stroma", 0.0, 0.0),
+        ];
+        let slides = aggregate(&records, GroupBy::Slide);
+        let totals: Vec<(&str, usize, f64, f64)> = slides.iter().map(|a| (a.slide_name.as_str(), a.regions, a.num_all_positive, a.num_total)).collect();
+        assert_eq!(totals, [("case2.svs", 3, 8.0, 20.0), ("case1.svs", 1, 1.0, 30.0)]);
+        // Weighted by the counts, not the mean of 0.2 and 0.6
+        assert_eq!(slides[0].positivity(), Some(0.4));
+        let labels = aggregate(&records, GroupBy::Label);
+        let keys: Vec<(&str, Option<&str>, usize)> = labels.iter().map(|a| (a.slide_name.as_str(), a.label.as_deref(), a.regions)).collect();
+        assert_eq!(keys, [("case2.svs", Some("Tumor"), 2), ("case1.svs", Some("Stroma"), 1), ("case2.svs", Some("Stroma"), 1)]);
+        assert_eq!(labels[2].positivity(), None);
+        // Slides without any region passing the filter are kept without regions
+        let filter = LabelFilter { include: vec![String::from("tum*")], exclude: Vec::new() };
+        let filtered = aggregate_slides_filtered(&records, &filter);
+        assert_eq!((filtered[0].regions, filtered[1].regions, filtered[1].positivity()), (2, 0, None));
+    }
+
+    #[test]
+    fn cohort_summary_gives_quantiles_of_region_positivities() {
+        let records = [
+            record("case1.svs", "Tumor", 1.0, 10.0),
+            record("case1.svs", "Tumor", 2.0, 10.0),
+            record("case2.svs", "Tumor", 4.0, 10.0),
+            record("case2.svs", "Tumor", 8.0, 10.0),
+            record("case2.svs", "Stroma", 0.0, 0.0),
+        ];
+        let summaries = cohort_summary(&records);
+        let labels: Vec<(&str, usize, usize, usize)> = summaries.iter().map(|c| (c.label.as_str(), c.slides, c.regions, c.positivities.len())).collect();
+        assert_eq!(labels, [(ALL_LABELS, 2, 5, 4), ("Tumor", 2, 4, 4), ("Stroma", 1, 1, 0)]);
+        let tumor = &summaries[1];
+        let close = |value: Option<f64>, expected: f64| value.is_some_and(|v| (v - expected).abs() < 1e-12);
+        assert!(close(tumor.mean_positivity(), 0.375));
+        assert!(close(tumor.median_positivity(), 0.3));
+        // Quartiles interpolated between ranks: 0.175 and 0.5
+        assert!(close(tumor.positivity_iqr(), 0.325));
+        assert!(close(tumor.min_positivity(), 0.1) && close(tumor.max_positivity(), 0.8));
+        assert_eq!((summaries[2].mean_positivity(), summaries[2].median_positivity()), (None, None));
+    }
+
+    #[test]
+    fn groups_are_compared_by_cohens_d() {
+        let control = GroupComparison { group: String::from("control"), positivities: vec![0.1, 0.2, 0.3] };
+        let treated = GroupComparison { group: String::from("treated"), positivities: vec![0.3, 0.4, 0.5] };
+        assert!((control.sd_positivity().unwrap() - 0.1).abs() < 1e-12);
+        assert!((treated.mean_difference(&control).unwrap() - 0.2).abs() < 1e-12);
+        assert!((treated.cohens_d(&control).unwrap() - 2.0).abs() < 1e-9);
+        let single = GroupComparison { group: String::from("single"), positivities: vec![0.5] };
+        assert_eq!((single.sd_positivity(), single.cohens_d(&control)), (None, None));
+
+        let mut records = vec![record("case1.svs", "Tumor", 1.0, 10.0), record("case2.svs", "Tumor", 3.0, 10.0), record("case3.svs", "Tumor", 5.0, 10.0)];
+        for (r, arm) in records.iter_mut().zip(["treated", "control", "treated"]) {
+            r.text_columns.push((String::from("arm"), arm.to_string()));
+        }
+        let groups = compare_groups(&records, "arm", &LabelFilter::default());
+        let slides: Vec<(&str, &[f64])> = groups.iter().map(|g| (g.group.as_str(), g.positivities.as_slice())).collect();
+        assert_eq!(slides, [("control", &[0.3][..]), ("treated", &[0.1, 0.5][..])]);
+    }
+}
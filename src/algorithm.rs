@@ -0,0 +1,428 @@
+use std::{error, fmt};
+use crate::model::{Annotation, XmlValue};
+use crate::patterns::{AttributePatterns, matches_pattern};
+
+/// Attribute names written by Positive Pixel Count versions from about 2008 to 2015, per column in the order
+/// positivity, weak positive, positive, strong positive and total
+/// They are matched like any pattern, ignoring case and whitespace, so `Nwp` also finds `Nwp: Number of Weak Positive`.
+const LEGACY_PPC_NAMES: [&[&str]; 5] = [
+    &["Positivity"],
+    &["Nwp", "Number of Weak Positive", "NumberWeakPositive", "Weak Positive Pixels"],
+    &["Np", "Number of Positive", "NumberPositive", "Positive Pixels"],
+    &["Nsp", "Number of Strong Positive", "NumberStrongPositive", "Strong Positive Pixels"],
+    &["NTotal", "Total Number", "Number of Total", "NumberTotal", "Total Pixels"],
+];
+
+/// Aperio image analysis algorithms that write type 3 layers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlgorithmKind {
+    PositivePixelCount,
+    Nuclear,
+    Membrane,
+    Cytoplasmic,
+    ColorDeconvolution,
+    Microvessel,
+    RareEvent,
+    Genie,
+    Unknown,
+}
+
+impl AlgorithmKind {
+    /// Every algorithm, in the order their columns are reported
+    pub const ALL: [AlgorithmKind; 9] = [
+        AlgorithmKind::PositivePixelCount, AlgorithmKind::Nuclear, AlgorithmKind::Membrane, AlgorithmKind::Cytoplasmic,
+        AlgorithmKind::ColorDeconvolution, AlgorithmKind::Microvessel, AlgorithmKind::RareEvent, AlgorithmKind::Genie,
+        AlgorithmKind::Unknown,
+    ];
+
+    /// Parse a comma separated list of algorithm names, e.g. `ppc,nuclear`
+    pub fn parse_list(names: &str) -> Result<Vec<AlgorithmKind>, Box<dyn error::Error>> {
+        names.split(',').map(|name| match name.trim().to_ascii_lowercase().as_str() {
+            "ppc" | "positive-pixel-count" => Ok(AlgorithmKind::PositivePixelCount),
+            "nuclear" => Ok(AlgorithmKind::Nuclear),
+            "membrane" => Ok(AlgorithmKind::Membrane),
+            "cytoplasmic" => Ok(AlgorithmKind::Cytoplasmic),
+            "deconvolution" | "color-deconvolution" => Ok(AlgorithmKind::ColorDeconvolution),
+            "microvessel" => Ok(AlgorithmKind::Microvessel),
+            "rare-event" | "rare-events" => Ok(AlgorithmKind::RareEvent),
+            "genie" => Ok(AlgorithmKind::Genie),
+            "unknown" => Ok(AlgorithmKind::Unknown),
+            other => Err(format!("Unknown algorithm {}, expected ppc, nuclear, membrane, cytoplasmic, deconvolution, microvessel, rare-event, genie or unknown", other).into()),
+        }).collect()
+    }
+
+    /// Names of the output columns holding the results of layers made by this algorithm, in the order
+    /// positivity, weak positive, positive, strong positive and total
+    pub fn columns(&self) -> &'static [&'static str; 5] {
+        match self {
+            AlgorithmKind::PositivePixelCount => &["ppc_positivity", "ppc_nwp", "ppc_np", "ppc_nsp", "ppc_ntotal"],
+            AlgorithmKind::Nuclear => &["nuclear_positivity", "nuclear_nwp", "nuclear_np", "nuclear_nsp", "nuclear_ntotal"],
+            AlgorithmKind::Membrane => &["membrane_positivity", "membrane_nwp", "membrane_np", "membrane_nsp", "membrane_ntotal"],
+            AlgorithmKind::Cytoplasmic => &["cytoplasmic_positivity", "cytoplasmic_nwp", "cytoplasmic_np", "cytoplasmic_nsp", "cytoplasmic_ntotal"],
+            AlgorithmKind::ColorDeconvolution => &["deconvolution_positivity", "deconvolution_nwp", "deconvolution_np", "deconvolution_nsp", "deconvolution_ntotal"],
+            AlgorithmKind::Microvessel => &["microvessel_positivity", "microvessel_nwp", "microvessel_np", "microvessel_nsp", "microvessel_ntotal"],
+            AlgorithmKind::RareEvent => &["rare_event_positivity", "rare_event_nwp", "rare_event_np", "rare_event_nsp", "rare_event_ntotal"],
+            AlgorithmKind::Genie => &["genie_positivity", "genie_nwp", "genie_np", "genie_nsp", "genie_ntotal"],
+            AlgorithmKind::Unknown => &["unknown_positivity", "unknown_nwp", "unknown_np", "unknown_nsp", "unknown_ntotal"],
+        }
+    }
+
+    /// Results specific to this algorithm, reported in addition to the standard columns, each with the attribute
+    /// names it is read from, tried in order and matched like the attribute patterns
+    /// Empty for algorithms whose results all fit the standard columns.
+    pub fn result_columns(&self) -> &'static [(&'static str, &'static [&'static str])] {
+        match self {
+            AlgorithmKind::Nuclear => NUCLEAR_RESULTS,
+            AlgorithmKind::Membrane => MEMBRANE_RESULTS,
+            AlgorithmKind::Cytoplasmic => CYTOPLASMIC_RESULTS,
+            AlgorithmKind::RareEvent => RARE_EVENT_RESULTS,
+            _ => &[],
+        }
+    }
+
+    /// Parse a comma separated list of algorithms to report the specific results of, e.g. `nuclear`, or `all` for
+    /// every algorithm with specific results, each layer then reporting those of the algorithm that made it
+    pub fn parse_results(names: &str) -> Result<Vec<AlgorithmKind>, Box<dyn error::Error>> {
+        if names.trim().eq_ignore_ascii_case("all") {
+            return Ok(Self::ALL.into_iter().filter(|k| !k.result_columns().is_empty()).collect());
+        }
+        let kinds = Self::parse_list(names)?;
+        if kinds.contains(&AlgorithmKind::Genie) {
+            return Err("GENIE results are per class, list the classes to report with --genie-classes".into());
+        }
+        if let Some(kind) = kinds.iter().find(|k| k.result_columns().is_empty()) {
+            return Err(format!("No specific results are known for {} layers, their results are in the standard columns", Algorithm { kind: *kind, version: None }).into());
+        }
+        Ok(kinds)
+    }
+}
+
+/// Results of the IHC Nuclear algorithm: the percentage of nuclei of each intensity, the total number of nuclei
+/// and their average intensity
+const NUCLEAR_RESULTS: &[(&str, &[&str])] = &[
+    ("nuclear_percent_0", &["(0+) Percent Nuclei", "(0+) Nuclei (%)", "(%) 0+ Nuclei", "Percent 0+ Nuclei"]),
+    ("nuclear_percent_1", &["(1+) Percent Nuclei", "(1+) Nuclei (%)", "(%) 1+ Nuclei", "Percent 1+ Nuclei"]),
+    ("nuclear_percent_2", &["(2+) Percent Nuclei", "(2+) Nuclei (%)", "(%) 2+ Nuclei", "Percent 2+ Nuclei"]),
+    ("nuclear_percent_3", &["(3+) Percent Nuclei", "(3+) Nuclei (%)", "(%) 3+ Nuclei", "Percent 3+ Nuclei"]),
+    ("nuclear_total", &["Total Nuclei", "Num Nuclei", "Number of Nuclei"]),
+    ("nuclear_average_intensity", &["Average Positive Intensity", "Average Nuclear Intensity", "Average Intensity"]),
+];
+
+/// Results of the IHC Membrane algorithm used for HER2 scoring: the percentage of cells of each membrane staining
+/// intensity and the membrane completeness
+const MEMBRANE_RESULTS: &[(&str, &[&str])] = &[
+    ("membrane_percent_3", &["%(3+) Cells", "(3+) Percent Cells", "(%) 3+ Cells", "Percent 3+ Cells", "Percent Cells 3+"]),
+    ("membrane_percent_2", &["%(2+) Cells", "(2+) Percent Cells", "(%) 2+ Cells", "Percent 2+ Cells", "Percent Cells 2+"]),
+    ("membrane_percent_1", &["%(1+) Cells", "(1+) Percent Cells", "(%) 1+ Cells", "Percent 1+ Cells", "Percent Cells 1+"]),
+    ("membrane_percent_0", &["%(0+) Cells", "(0+) Percent Cells", "(%) 0+ Cells", "Percent 0+ Cells", "Percent Cells 0+"]),
+    ("membrane_completeness", &["Completeness", "Membrane Completeness", "Average Completeness", "(%) Complete", "Percent Complete"]),
+];
+
+/// Results of the Cytoplasmic v2 algorithm: the percentage of cells of each cytoplasmic staining intensity and the
+/// average cytoplasmic intensity
+const CYTOPLASMIC_RESULTS: &[(&str, &[&str])] = &[
+    ("cytoplasmic_percent_0", &["(0+) Percent Cells", "%(0+) Cells", "(%) 0+ Cells", "Percent 0+ Cells", "Percent Cells 0+"]),
+    ("cytoplasmic_percent_1", &["(1+) Percent Cells", "%(1+) Cells", "(%) 1+ Cells", "Percent 1+ Cells", "Percent Cells 1+"]),
+    ("cytoplasmic_percent_2", &["(2+) Percent Cells", "%(2+) Cells", "(%) 2+ Cells", "Percent 2+ Cells", "Percent Cells 2+"]),
+    ("cytoplasmic_percent_3", &["(3+) Percent Cells", "%(3+) Cells", "(%) 3+ Cells", "Percent 3+ Cells", "Percent Cells 3+"]),
+    ("cytoplasmic_average_intensity", &["Average Cytoplasmic Intensity", "Avg Cytoplasmic Intensity", "Average Cytoplasm Intensity", "Cytoplasmic Intensity"]),
+];
+
+/// Results of the Rare Event Detection algorithm: the number of events found, their density and the area searched
+const RARE_EVENT_RESULTS: &[(&str, &[&str])] = &[
+    ("rare_event_count", &["Num Events", "Number of Events", "Number of Rare Events", "Event Count", "Rare Events"]),
+    ("rare_event_density", &["Events/mm^2", "Events/mm2", "Events per mm2", "Event Density"]),
+    ("rare_event_area", &["Area Analyzed", "Analysis Area", "Area (mm^2)", "Area (mm2)"]),
+];
+
+/// Classes of GENIE classifier layers whose area and percentage of the region are reported, e.g. `Tumor` and `Stroma`
+/// Each class `Tumor` gives the columns `genie_tumor_area` and `genie_tumor_percent`, read from attribute names
+/// such as `Tumor Area (mm2)` and `Tumor Area (%)` or `Tumor Percentage`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GenieClasses {
+    /// Output column and attribute name patterns of each result, two per class
+    columns: Vec<(String, Vec<String>)>,
+}
+
+impl GenieClasses {
+    /// Parse a comma separated list of class names as used by the classifier
+    pub fn parse_list(names: &str) -> Result<Self, Box<dyn error::Error>> {
+        let mut classes = Self::default();
+        for class in names.split(',').map(str::trim) {
+            if class.is_empty() {
+                return Err(format!("Empty class name in {}", names).into());
+            }
+            let column = class.split_whitespace().collect::<Vec<_>>().join("_").to_lowercase();
+            // Areas are tried in units before the plain name, which leaves names in percent to the longer patterns below
+            classes.columns.push((format!("genie_{}_area", column), [
+                "{} Area (mm", "{} Area (um", "{} Area (µm", "Area {}", "{} Area",
+            ].iter().map(|p| p.replace("{}", class)).collect()));
+            classes.columns.push((format!("genie_{}_percent", column), [
+                "{} Area (%)", "{} (%)", "{} Percentage", "{} Percent", "Percent {}", "(%) {}",
+            ].iter().map(|p| p.replace("{}", class)).collect()));
+        }
+        Ok(classes)
+    }
+
+    /// True without any classes
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// Names of the output columns
+    pub fn columns(&self) -> impl Iterator<Item = &str> {
+        self.columns.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Output columns with the attribute name patterns they are read from
+    pub(crate) fn patterns(&self) -> Vec<(&str, Vec<String>)> {
+        self.columns.iter().map(|(name, patterns)| (name.as_str(), patterns.clone())).collect()
+    }
+}
+
+/// The algorithm and version that produced an analysis layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Algorithm {
+    pub kind: AlgorithmKind,
+    /// Version from the layer attributes or name, e.g. `9.1`
+    pub version: Option<String>,
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.kind {
+            AlgorithmKind::PositivePixelCount => "Positive Pixel Count",
+            AlgorithmKind::Nuclear => "Nuclear",
+            AlgorithmKind::Membrane => "Membrane",
+            AlgorithmKind::Cytoplasmic => "Cytoplasmic",
+            AlgorithmKind::ColorDeconvolution => "Color Deconvolution",
+            AlgorithmKind::Microvessel => "Microvessel",
+            AlgorithmKind::RareEvent => "Rare Event",
+            AlgorithmKind::Genie => "GENIE Classifier",
+            AlgorithmKind::Unknown => "Unknown",
+        };
+        match &self.version {
+            Some(version) => write!(f, "{} {}", name, version),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Algorithm {
+    /// True if the algorithm counts weak and strong positives separately
+    pub fn has_intensity_bins(&self) -> bool {
+        matches!(self.kind, AlgorithmKind::PositivePixelCount | AlgorithmKind::Unknown)
+    }
+
+    /// True if the algorithm only counts events, so its layers may lack a positivity and a total count
+    pub fn counts_only(&self) -> bool {
+        self.kind == AlgorithmKind::RareEvent
+    }
+
+    /// True if layers of the algorithm must report a number of positives
+    /// IHC Membrane only reports the percentage of cells of each staining intensity.
+    pub fn counts_positives(&self) -> bool {
+        self.kind != AlgorithmKind::Membrane
+    }
+
+    /// Percentage results adding up to the positivity of algorithms that do not report one, with their attribute
+    /// name patterns, empty for the others
+    pub(crate) fn positive_percentages(&self) -> Vec<(&'static str, Vec<String>)> {
+        let percentages: &[(&str, &[&str])] = match self.kind {
+            AlgorithmKind::Membrane => &MEMBRANE_RESULTS[..3],
+            _ => &[],
+        };
+        percentages.iter().map(|(name, names)| (*name, names.iter().map(|n| n.to_string()).collect())).collect()
+    }
+
+    /// True if layers of the algorithm must report a total count
+    /// Rare events may be counted without one, and microvessel analysis reports areas instead.
+    pub fn requires_total(&self) -> bool {
+        !matches!(self.kind, AlgorithmKind::RareEvent | AlgorithmKind::Microvessel)
+    }
+
+    /// True if the layer can be read: its algorithm was recognized, or for an unknown one its attribute headers,
+    /// or attribute names without headers, match the patterns of a result
+    pub fn recognizes(&self, layer: &Annotation, patterns: &AttributePatterns) -> bool {
+        if self.kind != AlgorithmKind::Unknown {
+            return true;
+        }
+        let columns = [&patterns.positivity, &patterns.num_wpositive, &patterns.num_positive, &patterns.num_spositive, &patterns.num_total];
+        let matches = |name: &str| columns.iter().any(|column| column.iter().any(|p| matches_pattern(name, p)));
+        match &layer.regions.region_attribute_headers.attribute_header {
+            Some(headers) => headers.iter().any(|h| matches(&h.name)),
+            None => layer.regions.region.iter().flat_map(|r| r.attributes.attribute.iter().flatten()).any(|a| matches(&a.name)),
+        }
+    }
+
+    /// Patterns for the results of this algorithm, tried after the ones in `patterns`
+    /// Positivities reported as percentages are listed in `PERCENT_POSITIVITY` too, see `positivity_scale`.
+    pub fn patterns(&self, patterns: &AttributePatterns) -> AttributePatterns {
+        let mut patterns = patterns.clone();
+        let extend = |column: &mut Vec<String>, names: &[&str]| column.extend(names.iter().map(|n| n.to_string()));
+        match self.kind {
+            AlgorithmKind::Nuclear => {
+                extend(&mut patterns.positivity, &["(%) Positive Nuclei", "Percent Positive Nuclei"]);
+                extend(&mut patterns.num_positive, &["Num Positive Nuclei", "Positive Nuclei"]);
+                extend(&mut patterns.num_total, &["Num Nuclei", "Total Nuclei", "Num Cells"]);
+            },
+            // Positivity is the share of 1+ to 3+ cells, see `positive_percentages`, completeness is no positivity
+            AlgorithmKind::Membrane => {
+                extend(&mut patterns.num_total, &["Num Cells", "Total Cells", "Number of Cells"]);
+            },
+            AlgorithmKind::Cytoplasmic => {
+                extend(&mut patterns.positivity, &["(%) Positive Cells", "Percent Positive Cells", "%Positive Cells"]);
+                extend(&mut patterns.num_positive, &["Num Positive Cells", "Positive Cells"]);
+                extend(&mut patterns.num_total, &["Num Cells", "Total Cells", "Number of Cells"]);
+            },
+            AlgorithmKind::ColorDeconvolution => {
+                extend(&mut patterns.positivity, &["Percent Positive", "(%) Positive"]);
+                extend(&mut patterns.num_positive, &["Positive Pixels", "Num Positive"]);
+                extend(&mut patterns.num_total, &["Total Pixels", "Num Pixels"]);
+            },
+            AlgorithmKind::Microvessel => {
+                extend(&mut patterns.positivity, &["Vessel Area (%)", "Percent Vessel Area"]);
+                extend(&mut patterns.num_positive, &["Num Vessels", "Vessel Count"]);
+            },
+            // Events are the positives, out of the cells when these are counted
+            AlgorithmKind::RareEvent => {
+                extend(&mut patterns.positivity, &["Percent Events", "(%) Events", "Event Percentage"]);
+                extend(&mut patterns.num_positive, &["Num Events", "Number of Events", "Number of Rare Events", "Event Count", "Rare Events"]);
+                extend(&mut patterns.num_total, &["Num Cells", "Total Cells", "Number of Cells"]);
+            },
+            // Older versions, whose layers often lack a macro name, wrote the same results under other names
+            AlgorithmKind::PositivePixelCount | AlgorithmKind::Unknown => {
+                let columns = [&mut patterns.positivity, &mut patterns.num_wpositive, &mut patterns.num_positive, &mut patterns.num_spositive, &mut patterns.num_total];
+                for (column, names) in columns.into_iter().zip(LEGACY_PPC_NAMES) {
+                    extend(column, names);
+                }
+            },
+            // Classes are reported with GenieClasses instead
+            AlgorithmKind::Genie => {},
+        }
+        patterns
+    }
+}
+
+/// Positivity results written as percentages, while we report positivity as a fraction like Positive Pixel Count
+const PERCENT_POSITIVITY: &[&str] = &[
+    "(%) Positive Nuclei", "Percent Positive Nuclei",
+    "(%) Positive Cells", "Percent Positive Cells", "%Positive Cells",
+    "Percent Positive", "(%) Positive",
+    "Vessel Area (%)", "Percent Vessel Area",
+    "Percent Events", "(%) Events", "Event Percentage",
+];
+
+/// Factor turning a positivity found by `pattern` into a fraction
+pub(crate) fn positivity_scale(pattern: &str) -> f64 {
+    if PERCENT_POSITIVITY.contains(&pattern) { 0.01 } else { 1.0 }
+}
+
+/// Work out which algorithm produced an analysis layer
+/// The layer and macro names and the values of the layer attributes, such as an `Algorithm` attribute, are checked
+/// first, then the names of the layer attributes for GENIE classifiers, then the names of the attribute headers, e.g. `Average Cytoplasmic Intensity` for cytoplasmic staining or `%(3+) Cells`
+/// and `Completeness` for membrane staining.
+/// The version comes from a `Version` layer attribute or a trailing `v9`-style word in the name.
+pub fn detect_algorithm(layer: &Annotation) -> Algorithm {
+    let macro_name = match layer.extra.get("@MacroName") {
+        Some(XmlValue::Text(name)) => name.as_str(),
+        _ => "",
+    };
+    let names = format!("{} {}", layer.name, macro_name).to_ascii_lowercase();
+    // Layer attribute values may name the algorithm too, but not its version
+    let attribute_values: Vec<&str> = layer.attributes.attribute.iter().flatten().map(|a| a.value.as_str()).collect();
+    let described = format!("{} {}", names, attribute_values.join(" ")).to_ascii_lowercase();
+    let headers: Vec<String> = layer.regions.region_attribute_headers.attribute_header.iter().flatten()
+        .map(|h| h.name.to_ascii_lowercase())
+        .collect();
+    let has_header = |prefix: &str| headers.iter().any(|h| h.starts_with(prefix));
+    // GENIE layers name their classifier in a layer attribute
+    let layer_attributes: Vec<String> = layer.attributes.attribute.iter().flatten().map(|a| a.name.to_ascii_lowercase()).collect();
+    // Names spaced or not, e.g. the legacy Positive Pixel Count NumberWeakPositivePixels or the membrane %(3+) Cells
+    let has_compact_header = |text: &str| headers.iter().any(|h| h.split_whitespace().collect::<String>().contains(text));
+    let kind = if described.contains("positive pixel") {
+        AlgorithmKind::PositivePixelCount
+    } else if described.contains("nuclear") {
+        AlgorithmKind::Nuclear
+    } else if described.contains("membrane") {
+        AlgorithmKind::Membrane
+    } else if described.contains("cytoplasm") {
+        AlgorithmKind::Cytoplasmic
+    } else if described.contains("color deconvolution") || described.contains("colour deconvolution") {
+        AlgorithmKind::ColorDeconvolution
+    } else if described.contains("microvessel") {
+        AlgorithmKind::Microvessel
+    } else if described.contains("rare event") {
+        AlgorithmKind::RareEvent
+    } else if described.contains("genie") || layer_attributes.iter().any(|a| a.contains("classifier") || a.contains("genie")) {
+        AlgorithmKind::Genie
+    } else if (has_header("nwp") && has_header("nsp")) || (has_compact_header("weakpositive") && has_compact_header("strongpositive")) {
+        AlgorithmKind::PositivePixelCount
+    } else if headers.iter().any(|h| h.contains("cytoplasm")) {
+        AlgorithmKind::Cytoplasmic
+    } else if headers.iter().any(|h| h.contains("membrane") || h.contains("complete")) || has_compact_header("(3+)cells") {
+        AlgorithmKind::Membrane
+    } else if headers.iter().any(|h| h.contains("nuclei")) {
+        AlgorithmKind::Nuclear
+    } else if headers.iter().any(|h| h.contains("vessel")) {
+        AlgorithmKind::Microvessel
+    } else if headers.iter().any(|h| h.contains("events")) {
+        AlgorithmKind::RareEvent
+    } else {
+        AlgorithmKind::Unknown
+    };
+    let version = layer.attributes.attribute.iter().flatten()
+        .find(|a| a.name.eq_ignore_ascii_case("Version") && !a.value.trim().is_empty())
+        .map(|a| a.value.trim().to_string())
+        .or_else(|| {
+            // e.g. "Positive Pixel Count v9"
+            names.split_whitespace().rev()
+                .find_map(|word| word.strip_prefix('v').filter(|v| v.starts_with(|c: char| c.is_ascii_digit())))
+                .map(str::to_string)
+        });
+    Algorithm { kind, version }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse_xml_str;
+    use super::{Algorithm, AlgorithmKind, detect_algorithm};
+
+    /// Algorithm detected for an analysis layer given by its attributes and content
+    fn detect(layer_attributes: &str, content: &str) -> Algorithm {
+        let xml = format!("<Annotations><Annotation Id=\"2\" Type=\"3\" {}>{}</Annotation></Annotations>", layer_attributes, content);
+        detect_algorithm(&parse_xml_str(&xml).unwrap().annotation[0])
+    }
+
+    /// Attribute headers with the given names
+    fn headers(names: &[&str]) -> String {
+        let headers: String = names.iter().enumerate().map(|(i, name)| format!("<AttributeHeader Id=\"{}\" Name=\"{}\"/>", i + 1, name)).collect();
+        format!("<Regions><RegionAttributeHeaders>{}</RegionAttributeHeaders></Regions>", headers)
+    }
+
+    #[test]
+    fn names_give_the_algorithm_and_version() {
+        let algorithm = detect(r#"Name="Positive Pixel Count v9""#, "");
+        assert_eq!(algorithm, Algorithm { kind: AlgorithmKind::PositivePixelCount, version: Some(String::from("9")) });
+        assert_eq!(algorithm.to_string(), "Positive Pixel Count 9");
+        assert_eq!(detect(r#"Name="Layer 2" MacroName="Nuclear v9""#, "").kind, AlgorithmKind::Nuclear);
+        assert_eq!(detect(r#"Name="Colour Deconvolution""#, "").kind, AlgorithmKind::ColorDeconvolution);
+        // A Version attribute takes precedence over the name
+        let versioned = detect(r#"Name="Membrane v9""#, r#"<Attributes><Attribute Name="Version" Id="0" Value="9.1"/></Attributes>"#);
+        assert_eq!(versioned, Algorithm { kind: AlgorithmKind::Membrane, version: Some(String::from("9.1")) });
+        let described = detect(r#"Name="Layer 2""#, r#"<Attributes><Attribute Name="Algorithm" Id="0" Value="Microvessel Analysis"/></Attributes>"#);
+        assert_eq!(described, Algorithm { kind: AlgorithmKind::Microvessel, version: None });
+        assert_eq!(detect(r#"Name="Layer 2""#, r#"<Attributes><Attribute Name="Classifier" Id="0" Value="Tumor/Stroma"/></Attributes>"#).kind, AlgorithmKind::Genie);
+    }
+
+    #[test]
+    fn headers_give_the_algorithm_of_unnamed_layers() {
+        assert_eq!(detect("", &headers(&["Positivity =", "Nwp = Number of Weak Positive", "Nsp = Number of Strong Positive"])).kind, AlgorithmKind::PositivePixelCount);
+        assert_eq!(detect("", &headers(&["Number Weak Positive Pixels", "NumberStrongPositivePixels"])).kind, AlgorithmKind::PositivePixelCount);
+        assert_eq!(detect("", &headers(&["Average Cytoplasmic Intensity"])).kind, AlgorithmKind::Cytoplasmic);
+        assert_eq!(detect("", &headers(&["%(3+) Cells", "%(2+) Cells"])).kind, AlgorithmKind::Membrane);
+        assert_eq!(detect("", &headers(&["Total Nuclei"])).kind, AlgorithmKind::Nuclear);
+        assert_eq!(detect("", &headers(&["Number of Events"])).kind, AlgorithmKind::RareEvent);
+        assert_eq!(detect("", &headers(&["Score"])), Algorithm { kind: AlgorithmKind::Unknown, version: None });
+    }
+}
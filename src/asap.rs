@@ -0,0 +1,219 @@
+use std::error;
+use serde::{Deserialize, Serialize};
+use quick_xml::se::Serializer;
+use crate::builder::{AnnotationBuilder, AnnotationsBuilder, DEFAULT_LINE_COLOR, RegionBuilder};
+use crate::convert::ExportRegion;
+use crate::encoding::decode_xml;
+use crate::model::{Annotations, RegionType};
+use crate::number::{DecimalSeparator, parse_number};
+use crate::parse::skip_leading_junk;
+use crate::render::{hex_to_bgr, layer_color};
+
+/// Group ASAP uses for annotations outside any group
+const NO_GROUP: &str = "None";
+/// Groups whose annotations are excluded from analysis, as in the CAMELYON16 annotations
+const EXCLUSION_GROUPS: [&str; 2] = ["Exclusion", "_2"];
+
+/// Root `ASAP_Annotations` element
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct AsapDocument {
+    #[serde(rename = "Annotations", default)]
+    annotations: AsapAnnotations,
+    #[serde(rename = "AnnotationGroups", default)]
+    groups: AsapGroups,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct AsapAnnotations {
+    #[serde(rename = "Annotation", default)]
+    annotation: Vec<AsapAnnotation>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AsapAnnotation {
+    #[serde(rename = "@Name", default)]
+    name: String,
+    #[serde(rename = "@Type", default)]
+    annotation_type: String,
+    #[serde(rename = "@PartOfGroup", default)]
+    part_of_group: String,
+    #[serde(rename = "@Color", default)]
+    color: String,
+    #[serde(rename = "Coordinates", default)]
+    coordinates: AsapCoordinates,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct AsapCoordinates {
+    #[serde(rename = "Coordinate", default)]
+    coordinate: Vec<AsapCoordinate>,
+}
+
+/// Kept as text since some ASAP versions write coordinates with a decimal comma
+#[derive(Serialize, Deserialize, Debug)]
+struct AsapCoordinate {
+    #[serde(rename = "@Order", default)]
+    order: usize,
+    #[serde(rename = "@X")]
+    x: String,
+    #[serde(rename = "@Y")]
+    y: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct AsapGroups {
+    #[serde(rename = "Group", default)]
+    group: Vec<AsapGroup>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AsapGroup {
+    #[serde(rename = "@Name")]
+    name: String,
+    #[serde(rename = "@PartOfGroup", default)]
+    part_of_group: String,
+    #[serde(rename = "@Color", default)]
+    color: String,
+    #[serde(rename = "Attributes", default)]
+    attributes: AsapAttributes,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct AsapAttributes {}
+
+/// Read an ASAP annotation XML file
+/// Every group becomes a user-drawn layer named and colored after it. Regions carry the annotation name as
+/// their text, or the group name if ASAP generated the name. Annotations in the `Exclusion` or `_2` groups become NegativeROA regions. Dots and point sets
+/// become polylines through their points, the closest ImageScope has.
+/// Returns the annotations and a description of every annotation skipped for lack of a matching region type.
+pub fn import_asap_xml(contents: &[u8]) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+    let xml = decode_xml(contents)?;
+    let document: AsapDocument = quick_xml::de::from_str(skip_leading_junk(&xml))?;
+    // Layers in the order of the group list, then any groups only mentioned by annotations
+    let mut layers: Vec<(String, u32, Vec<RegionBuilder>)> = document.groups.group.iter()
+        .map(|g| (g.name.clone(), hex_to_bgr(&g.color).unwrap_or(DEFAULT_LINE_COLOR), Vec::new()))
+        .collect();
+    let mut skipped = Vec::new();
+    for annotation in document.annotations.annotation {
+        let group = if annotation.part_of_group == NO_GROUP { "" } else { annotation.part_of_group.as_str() };
+        let position = match layers.iter().position(|(name, _, _)| name == group) {
+            Some(position) => position,
+            None => {
+                layers.push((group.to_string(), hex_to_bgr(&annotation.color).unwrap_or(DEFAULT_LINE_COLOR), Vec::new()));
+                layers.len() - 1
+            },
+        };
+        let region_type = match annotation.annotation_type.as_str() {
+            "Polygon" | "Spline" => RegionType::Freehand,
+            "Rectangle" => RegionType::Rectangle,
+            "Measurement" => RegionType::Ruler,
+            "PointSet" | "Dot" => RegionType::Polyline,
+            other => {
+                skipped.push(format!("Skipping ASAP {} annotation {:?}, ImageScope has no matching region type", other, annotation.name));
+                continue;
+            },
+        };
+        let mut coordinates = annotation.coordinates.coordinate;
+        coordinates.sort_by_key(|c| c.order);
+        let mut points = Vec::with_capacity(coordinates.len());
+        for coordinate in &coordinates {
+            match (parse_number(&coordinate.x, DecimalSeparator::Auto), parse_number(&coordinate.y, DecimalSeparator::Auto)) {
+                (Some(x), Some(y)) => points.push((x, y)),
+                _ => return Err(format!("Invalid coordinate ({}, {}) in ASAP annotation {:?}", coordinate.x, coordinate.y, annotation.name).into()),
+            }
+        }
+        let negative = EXCLUSION_GROUPS.iter().any(|g| g.eq_ignore_ascii_case(group));
+        // ASAP names annotations "Annotation 0", "Annotation 1", ... unless they were renamed
+        let generated = annotation.name.strip_prefix("Annotation ").is_some_and(|n| n.trim().parse::<usize>().is_ok());
+        let text = if annotation.name.is_empty() || generated { group } else { annotation.name.as_str() };
+        layers[position].2.push(RegionBuilder::new().region_type(region_type).text(text).negative_roa(negative).vertices(points));
+    }
+
+    let mut builder = AnnotationsBuilder::new();
+    for (name, color, regions) in layers {
+        let mut layer = AnnotationBuilder::new().name(name).line_color(color);
+        for region in regions {
+            layer = layer.region(region);
+        }
+        builder = builder.layer(layer);
+    }
+    Ok((builder.build(), skipped))
+}
+
+/// ASAP annotation XML with one group per layer
+/// NegativeROA regions go into an `Exclusion` group. Ellipses are written as polygons and polylines as
+/// point sets, since ASAP has neither.
+pub fn to_asap_xml(regions: &[ExportRegion]) -> Result<String, Box<dyn error::Error>> {
+    let mut document = AsapDocument::default();
+    for (i, r) in regions.iter().enumerate() {
+        let group = if r.region.is_negative() {
+            String::from(EXCLUSION_GROUPS[0])
+        } else if r.layer.name.is_empty() {
+            format!("Layer {}", r.layer.id)
+        } else {
+            r.layer.name.clone()
+        };
+        let color = if r.region.is_negative() { String::from("#000000") } else { layer_color(r.layer).unwrap_or_else(|| String::from("#00ff00")) };
+        if !document.groups.group.iter().any(|g| g.name == group) {
+            document.groups.group.push(AsapGroup { name: group.clone(), part_of_group: String::from(NO_GROUP), color: color.clone(), attributes: AsapAttributes {} });
+        }
+        let annotation_type = match r.region.region_type {
+            RegionType::Rectangle => "Rectangle",
+            RegionType::Arrow | RegionType::Ruler => "Measurement",
+            RegionType::Polyline => "PointSet",
+            _ => "Polygon",
+        };
+        let label = r.region.label();
+        document.annotations.annotation.push(AsapAnnotation {
+            name: if label.is_empty() { format!("Annotation {}", i) } else { label },
+            annotation_type: annotation_type.to_string(),
+            part_of_group: group,
+            color,
+            coordinates: AsapCoordinates {
+                coordinate: r.region.outline().iter().enumerate()
+                    .map(|(order, &(x, y))| AsapCoordinate { order, x: x.to_string(), y: y.to_string() })
+                    .collect(),
+            },
+        });
+    }
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n");
+    let mut serializer = Serializer::with_root(&mut xml, Some("ASAP_Annotations"))?;
+    serializer.indent('\t', 1);
+    document.serialize(serializer)?;
+    xml.push('\n');
+    Ok(xml)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::model::RegionType;
+    use crate::render::layer_color;
+    use super::{import_asap_xml, to_asap_xml};
+
+    #[test]
+    fn regions_round_trip() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new().name("Tumor").line_color(255)
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Tumor 1").vertices([(0.0, 0.0), (10.0, 10.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Polyline).text("Margin").vertices([(0.0, 0.0), (5.0, 5.0), (10.0, 0.0)]))
+                .region(RegionBuilder::new().text("Necrosis").negative_roa(true).vertices([(2.0, 2.0), (4.0, 2.0), (4.0, 4.0)])))
+            .build();
+        let xml = to_asap_xml(&export_regions(&annotations, &[])).unwrap();
+        let (imported, skipped) = import_asap_xml(xml.as_bytes()).unwrap();
+        assert!(skipped.is_empty());
+        let names: Vec<&str> = imported.annotation.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["Tumor", "Exclusion"]);
+        // ImageScope colors are BGR, so 255 is red
+        assert_eq!(layer_color(&imported.annotation[0]).as_deref(), Some("#ff0000"));
+        let tumor = &imported.annotation[0].regions.region;
+        let types: Vec<&RegionType> = tumor.iter().map(|r| &r.region_type).collect();
+        assert_eq!(types, [&RegionType::Rectangle, &RegionType::Polyline]);
+        assert_eq!(tumor[1].label(), "Margin");
+        assert_eq!(tumor[1].outline(), [(0.0, 0.0), (5.0, 5.0), (10.0, 0.0)]);
+        let exclusion = &imported.annotation[1].regions.region[0];
+        assert!(exclusion.is_negative());
+        assert_eq!(exclusion.region_type, RegionType::Freehand);
+    }
+}
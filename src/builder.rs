@@ -0,0 +1,302 @@
+use crate::model::{Annotation, AnnotationAttributes, Annotations, Extra, Region, RegionAttributes, RegionType, Regions, Vertex, Vertices, XmlValue};
+
+/// Build a complete annotation document
+/// Layer and region IDs are numbered from 1 when not set explicitly, region IDs across the whole document as
+/// ImageScope does
+#[derive(Debug, Default)]
+pub struct AnnotationsBuilder {
+    microns_per_pixel: Option<f64>,
+    layers: Vec<AnnotationBuilder>,
+}
+
+impl AnnotationsBuilder {
+    /// Start an empty document
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the scan resolution, also used to fill in micron lengths and areas
+    pub fn microns_per_pixel(mut self, microns_per_pixel: f64) -> Self {
+        self.microns_per_pixel = Some(microns_per_pixel);
+        self
+    }
+
+    /// Add an annotation layer
+    pub fn layer(mut self, layer: AnnotationBuilder) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Assemble the document
+    pub fn build(self) -> Annotations {
+        let mpp = self.microns_per_pixel;
+        Annotations {
+            microns_per_pixel: mpp.map(|m| format!("{:.6}", m)),
+            annotation: self.layers.into_iter().enumerate().scan(0, |regions_before, (i, layer)| {
+                let first_region = *regions_before;
+                *regions_before += layer.regions.len();
+                Some(layer.build(i + 1, first_region, mpp))
+            }).collect(),
+            extra: Extra::new(),
+        }
+    }
+}
+
+/// Build a single annotation layer
+#[derive(Debug)]
+pub struct AnnotationBuilder {
+    id: Option<String>,
+    name: String,
+    annotation_type: String,
+    line_color: u32,
+    regions: Vec<RegionBuilder>,
+}
+
+/// ImageScope's default green layer outline, as a BGR integer
+pub(crate) const DEFAULT_LINE_COLOR: u32 = 65280;
+
+impl Default for AnnotationBuilder {
+    /// A user-drawn (type 4) layer with ImageScope's default green outline
+    fn default() -> Self {
+        Self { id: None, name: String::new(), annotation_type: String::from("4"), line_color: DEFAULT_LINE_COLOR, regions: Vec::new() }
+    }
+}
+
+impl AnnotationBuilder {
+    /// Start an empty user-drawn layer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the layer ID instead of using its position
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Set the layer name
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Set the layer type (4 = user-drawn, 3 = analysis results)
+    pub fn annotation_type(mut self, annotation_type: impl Into<String>) -> Self {
+        self.annotation_type = annotation_type.into();
+        self
+    }
+
+    /// Set outline color as an ImageScope BGR integer (0xBBGGRR)
+    pub fn line_color(mut self, line_color: u32) -> Self {
+        self.line_color = line_color;
+        self
+    }
+
+    /// Add a region
+    pub fn region(mut self, region: RegionBuilder) -> Self {
+        self.regions.push(region);
+        self
+    }
+
+    /// Assemble the layer, `position` is its 1-based index in the document and `regions_before` the number of
+    /// regions in earlier layers
+    fn build(self, position: usize, regions_before: usize, microns_per_pixel: Option<f64>) -> Annotation {
+        // Attributes ImageScope writes on every layer
+        let mut extra = Extra::new();
+        for (name, value) in [("@ReadOnly", "0"), ("@NameReadOnly", "0"), ("@LineColorReadOnly", "0"), ("@Incremental", "0"), ("@Visible", "1"), ("@Selected", "0"), ("@MarkupImagePath", ""), ("@MacroName", "")] {
+            extra.insert(name.to_string(), XmlValue::Text(value.to_string()));
+        }
+        extra.insert(String::from("@LineColor"), XmlValue::Text(self.line_color.to_string()));
+        extra.insert(String::from("Plots"), XmlValue::Element(Extra::new()));
+        Annotation {
+            id: self.id.unwrap_or_else(|| position.to_string()),
+            name: self.name,
+            annotation_type: self.annotation_type,
+            attributes: AnnotationAttributes::default(),
+            regions: Regions {
+                region: self.regions.into_iter().enumerate().map(|(i, region)| region.build(regions_before + i + 1, i + 1, microns_per_pixel)).collect(),
+                ..Regions::default()
+            },
+            extra,
+        }
+    }
+}
+
+/// Build a single region from its outline
+#[derive(Debug, Default)]
+pub struct RegionBuilder {
+    id: Option<String>,
+    region_type: RegionType,
+    text: String,
+    negative_roa: bool,
+    vertices: Vec<(f64, f64)>,
+}
+
+impl RegionBuilder {
+    /// Start an empty freehand region
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the region ID instead of using its position
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Set the region shape
+    pub fn region_type(mut self, region_type: RegionType) -> Self {
+        self.region_type = region_type;
+        self
+    }
+
+    /// Set the text label
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.text = text.into();
+        self
+    }
+
+    /// Mark the region as an exclusion (negative) region
+    pub fn negative_roa(mut self, negative_roa: bool) -> Self {
+        self.negative_roa = negative_roa;
+        self
+    }
+
+    /// Append one vertex in level-0 pixel coordinates
+    pub fn vertex(mut self, x: f64, y: f64) -> Self {
+        self.vertices.push((x, y));
+        self
+    }
+
+    /// Append several vertices in level-0 pixel coordinates
+    pub fn vertices<I: IntoIterator<Item = (f64, f64)>>(mut self, vertices: I) -> Self {
+        self.vertices.extend(vertices);
+        self
+    }
+
+    /// Assemble the region, `number` is its 1-based index in the document and `position` in the layer
+    fn build(self, number: usize, position: usize, microns_per_pixel: Option<f64>) -> Region {
+        let (length, area) = match (&self.region_type, self.vertices.as_slice()) {
+            // Stored as two opposite corners of the bounding box
+            (RegionType::Rectangle, &[(x1, y1), (x2, y2)]) => {
+                let (width, height) = ((x2 - x1).abs(), (y2 - y1).abs());
+                (2.0 * (width + height), width * height)
+            },
+            (RegionType::Ellipse, &[(x1, y1), (x2, y2)]) => {
+                let (a, b) = ((x2 - x1).abs() / 2.0, (y2 - y1).abs() / 2.0);
+                // Ramanujan's approximation of the circumference
+                let length = std::f64::consts::PI * (3.0 * (a + b) - ((3.0 * a + b) * (a + 3.0 * b)).sqrt());
+                (length, std::f64::consts::PI * a * b)
+            },
+            _ => {
+                let length = outline_length(&self.vertices, self.region_type.is_closed());
+                (length, if self.region_type.is_closed() { shoelace_area(&self.vertices) } else { 0.0 })
+            },
+        };
+        let mut extra = Extra::new();
+        for (name, value) in [("@Zoom", "1"), ("@Selected", "0"), ("@ImageFocus", "-1")] {
+            extra.insert(name.to_string(), XmlValue::Text(value.to_string()));
+        }
+        extra.insert(String::from("@DisplayId"), XmlValue::Text(position.to_string()));
+        Region {
+            id: self.id.unwrap_or_else(|| number.to_string()),
+            region_type: self.region_type,
+            length: Some(length.to_string()),
+            area: Some(area.to_string()),
+            length_microns: Some(microns_per_pixel.map(|m| length * m).unwrap_or(0.0).to_string()),
+            area_microns: Some(microns_per_pixel.map(|m| area * m * m).unwrap_or(0.0).to_string()),
+            text: Some(self.text),
+            negative_roa: Some(String::from(if self.negative_roa { "1" } else { "0" })),
+            analyze: Some(String::from("1")),
+            attributes: RegionAttributes::default(),
+            image_location: Some(String::new()),
+            input_region_id: Some(String::from("0")),
+            vertices: Some(Vertices { vertex: self.vertices.iter().map(|&(x, y)| Vertex::new(x, y)).collect(), extra: Extra::new() }),
+            extra,
+        }
+    }
+}
+
+/// Length of the outline through all vertices, including the closing edge if `closed`
+fn outline_length(vertices: &[(f64, f64)], closed: bool) -> f64 {
+    let mut length: f64 = vertices.windows(2).map(|w| (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1)).sum();
+    if closed && vertices.len() > 2 {
+        let (first, last) = (vertices[0], vertices[vertices.len() - 1]);
+        length += (first.0 - last.0).hypot(first.1 - last.1);
+    }
+    length
+}
+
+/// Area enclosed by the polygon through all vertices (shoelace formula)
+fn shoelace_area(vertices: &[(f64, f64)]) -> f64 {
+    let n = vertices.len();
+    if n < 3 {
+        return 0.0;
+    }
+    let twice_area: f64 = (0..n).map(|i| {
+        let (x1, y1) = vertices[i];
+        let (x2, y2) = vertices[(i + 1) % n];
+        x1 * y2 - x2 * y1
+    }).sum();
+    twice_area.abs() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_xml_str;
+
+    /// Numeric value of an optional measurement attribute
+    fn number(value: &Option<String>) -> f64 {
+        value.as_deref().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn built_document_round_trips_through_xml() {
+        let built = AnnotationsBuilder::new()
+            .microns_per_pixel(0.5)
+            .layer(AnnotationBuilder::new().name("Tumor")
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("box").vertex(10.0, 20.0).vertex(50.0, 50.0))
+                .region(RegionBuilder::new().region_type(RegionType::Ellipse).vertex(0.0, 0.0).vertex(20.0, 20.0)))
+            .layer(AnnotationBuilder::new().id("7").name("Stroma").line_color(255)
+                .region(RegionBuilder::new().vertices([(0.0, 0.0), (4.0, 0.0), (4.0, 3.0)]).negative_roa(true))
+                .region(RegionBuilder::new().id("custom").region_type(RegionType::Ruler).vertex(0.0, 0.0).vertex(3.0, 4.0)))
+            .build();
+        let parsed = parse_xml_str(&built.to_xml_string().unwrap()).unwrap();
+        assert_eq!(parsed, built);
+
+        assert_eq!(parsed.microns_per_pixel.as_deref(), Some("0.500000"));
+        let ids: Vec<(&str, Vec<&str>)> = parsed.annotation.iter()
+            .map(|layer| (layer.id.as_str(), layer.regions.region.iter().map(|r| r.id.as_str()).collect()))
+            .collect();
+        // Region IDs continue across layers unless set explicitly
+        assert_eq!(ids, [("1", vec!["1", "2"]), ("7", vec!["3", "custom"])]);
+        assert_eq!(parsed.annotation[1].extra.get("@LineColor"), Some(&XmlValue::Text(String::from("255"))));
+
+        let [rectangle, ellipse] = &parsed.annotation[0].regions.region[..] else { panic!("expected two regions") };
+        assert_eq!((number(&rectangle.length), number(&rectangle.area)), (140.0, 1200.0));
+        assert_eq!((number(&rectangle.length_microns), number(&rectangle.area_microns)), (70.0, 300.0));
+        assert_eq!(rectangle.text.as_deref(), Some("box"));
+        // A circle of radius 10, where Ramanujan's approximation is exact
+        assert!((number(&ellipse.length) - 20.0 * std::f64::consts::PI).abs() < 1e-9);
+        assert!((number(&ellipse.area) - 100.0 * std::f64::consts::PI).abs() < 1e-9);
+        assert!((number(&ellipse.area_microns) - 25.0 * std::f64::consts::PI).abs() < 1e-9);
+
+        let [triangle, ruler] = &parsed.annotation[1].regions.region[..] else { panic!("expected two regions") };
+        assert_eq!((number(&triangle.length), number(&triangle.area)), (12.0, 6.0));
+        assert_eq!(triangle.negative_roa.as_deref(), Some("1"));
+        // Open shapes have a length but no area
+        assert_eq!((number(&ruler.length), number(&ruler.area), number(&ruler.length_microns)), (5.0, 0.0, 2.5));
+    }
+
+    #[test]
+    fn micron_fields_are_zero_without_a_resolution() {
+        let built = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new().region(RegionBuilder::new().region_type(RegionType::Rectangle).vertex(0.0, 0.0).vertex(2.0, 3.0)))
+            .build();
+        let region = &built.annotation[0].regions.region[0];
+        assert!(built.microns_per_pixel.is_none());
+        assert_eq!((number(&region.length), number(&region.area)), (10.0, 6.0));
+        assert_eq!((number(&region.length_microns), number(&region.area_microns)), (0.0, 0.0));
+    }
+}
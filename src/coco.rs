@@ -0,0 +1,125 @@
+use serde::Serialize;
+use crate::convert::ExportRegion;
+use crate::extract::UNLABELED;
+use crate::geometry::BoundingBox;
+
+/// COCO object detection dataset built up one slide at a time
+/// Every slide is an image, every distinct text label a category, and every closed region an annotation
+/// with its outline as polygon segmentation. COCO polygons cannot have holes, so NegativeROA regions are
+/// left out, as are lines and points.
+#[derive(Serialize, Debug, Default)]
+pub struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CocoImage {
+    pub id: usize,
+    pub file_name: String,
+    /// Extent of the annotations, as the XML does not record the slide size
+    pub width: u64,
+    pub height: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CocoAnnotation {
+    pub id: usize,
+    pub image_id: usize,
+    pub category_id: usize,
+    /// Outline as `[x1, y1, x2, y2, ...]` in level-0 pixels
+    pub segmentation: Vec<Vec<f64>>,
+    pub area: f64,
+    /// `[x, y, width, height]`
+    pub bbox: [f64; 4],
+    pub iscrowd: u8,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CocoCategory {
+    pub id: usize,
+    pub name: String,
+    pub supercategory: String,
+}
+
+impl CocoDataset {
+    /// Start an empty dataset
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a slide named `file_name` with its regions
+    pub fn add_image(&mut self, file_name: &str, regions: &[ExportRegion]) {
+        let image_id = self.images.len() + 1;
+        let mut extent = (0.0f64, 0.0f64);
+        for r in regions {
+            if r.region.is_negative() || !r.region.region_type.is_closed() {
+                continue;
+            }
+            let outline = r.region.outline();
+            let Some(bbox) = BoundingBox::of(&outline).filter(|_| outline.len() >= 3) else { continue };
+            extent = (extent.0.max(bbox.max_x), extent.1.max(bbox.max_y));
+            let label = r.region.label();
+            let name = if label.is_empty() { UNLABELED } else { label.as_str() };
+            let category_id = match self.categories.iter().find(|c| c.name == name) {
+                Some(category) => category.id,
+                None => {
+                    let id = self.categories.len() + 1;
+                    self.categories.push(CocoCategory { id, name: name.to_string(), supercategory: String::new() });
+                    id
+                },
+            };
+            self.annotations.push(CocoAnnotation {
+                id: self.annotations.len() + 1,
+                image_id,
+                category_id,
+                segmentation: vec![outline.iter().flat_map(|&(x, y)| [x, y]).collect()],
+                area: r.region.computed_area(),
+                bbox: [bbox.min_x, bbox.min_y, bbox.width(), bbox.height()],
+                iscrowd: 0,
+            });
+        }
+        self.images.push(CocoImage { id: image_id, file_name: file_name.to_string(), width: extent.0.max(0.0).ceil() as u64, height: extent.1.max(0.0).ceil() as u64 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::model::RegionType;
+    use super::{CocoDataset, CocoImage};
+
+    #[test]
+    fn closed_regions_become_annotations_of_their_label() {
+        let first = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Tumor").vertices([(10.0, 20.0), (40.0, 60.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Necrosis").negative_roa(true).vertices([(15.0, 25.0), (20.0, 30.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Polyline).text("Margin").vertices([(0.0, 0.0), (90.0, 90.0)])))
+            .build();
+        let second = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(RegionBuilder::new().vertices([(0.0, 0.0), (6.0, 0.0), (0.0, 8.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Tumor").vertices([(0.0, 0.0), (2.0, 2.0)])))
+            .build();
+        let mut dataset = CocoDataset::new();
+        dataset.add_image("case1.svs", &export_regions(&first, &[]));
+        dataset.add_image("case2.svs", &export_regions(&second, &[]));
+        assert_eq!(dataset.images, [
+            CocoImage { id: 1, file_name: String::from("case1.svs"), width: 40, height: 60 },
+            CocoImage { id: 2, file_name: String::from("case2.svs"), width: 6, height: 8 },
+        ]);
+        let names: Vec<&str> = dataset.categories.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Tumor", "unlabeled"]);
+        // The exclusion and the line are left out
+        let annotations: Vec<(usize, usize, usize, [f64; 4], f64)> = dataset.annotations.iter().map(|a| (a.id, a.image_id, a.category_id, a.bbox, a.area)).collect();
+        assert_eq!(annotations, [
+            (1, 1, 1, [10.0, 20.0, 30.0, 40.0], 1200.0),
+            (2, 2, 2, [0.0, 0.0, 6.0, 8.0], 24.0),
+            (3, 2, 1, [0.0, 0.0, 2.0, 2.0], 4.0),
+        ]);
+        assert_eq!(dataset.annotations[0].segmentation, [vec![10.0, 20.0, 40.0, 20.0, 40.0, 60.0, 10.0, 60.0]]);
+    }
+}
@@ -0,0 +1,181 @@
+use std::{error, io::{self, Write}, path};
+use crate::extract::{FileSummary, RegionRecord};
+use crate::input::xml_sources;
+use crate::options::Options;
+use crate::process_source;
+use crate::report::csv_field;
+
+/// Column names of the completeness report
+pub const COMPLETENESS_HEADER: &str = "Filename,drawn layers,analysis layers,regions,positivity,num weak positive,num positive,num strong positive,num total,missing";
+
+/// What was found in one file, to chase incomplete analyses
+/// Metric counts are the number of regions with a value for the metric.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Completeness {
+    pub path: path::PathBuf,
+    pub drawn_layers: usize,
+    pub analysis_layers: usize,
+    pub regions: usize,
+    pub positivity: usize,
+    pub num_wpositive: usize,
+    pub num_positive: usize,
+    pub num_spositive: usize,
+    pub num_total: usize,
+    /// Why the file could not be processed, None if it was
+    pub error: Option<String>,
+    /// True if the algorithm should have counted weak and strong positives
+    pub intensity_bins: bool,
+    /// True if the algorithm should have reported a positivity and a total
+    pub ratios: bool,
+}
+
+impl Completeness {
+    /// Completeness of a file from its records and summary
+    pub fn of(records: &[RegionRecord], summary: &FileSummary) -> Self {
+        let count = |value: fn(&RegionRecord) -> Option<f64>| records.iter().filter(|r| value(r).is_some()).count();
+        Self {
+            path: summary.path.clone(),
+            drawn_layers: summary.drawn_layers,
+            analysis_layers: summary.analysis_layers,
+            regions: records.len(),
+            positivity: count(|r| r.positivity),
+            num_wpositive: count(|r| r.num_wpositive),
+            num_positive: count(|r| r.num_positive),
+            num_spositive: count(|r| r.num_spositive),
+            num_total: count(|r| r.num_total),
+            error: summary.error.clone(),
+            intensity_bins: summary.intensity_bins,
+            ratios: summary.ratios,
+        }
+    }
+
+    /// What is missing, empty for a complete file
+    /// A metric is missing when some region lacks it. Weak and strong counts are only expected from algorithms
+    /// that count them, and positivity and total counts not from those only counting events.
+    pub fn missing(&self) -> Vec<&'static str> {
+        if self.error.is_some() {
+            return vec!["unreadable"];
+        }
+        let mut missing = Vec::new();
+        if self.drawn_layers == 0 {
+            missing.push("no drawn layer");
+        }
+        if self.analysis_layers == 0 {
+            missing.push("no analysis layer");
+        }
+        if self.regions == 0 {
+            missing.push("no regions");
+        }
+        let mut metrics = vec![("positivity", self.positivity), ("num positive", self.num_positive), ("num total", self.num_total)];
+        if !self.ratios {
+            metrics.retain(|&(name, _)| name == "num positive");
+        }
+        if self.intensity_bins {
+            metrics.extend([("num weak positive", self.num_wpositive), ("num strong positive", self.num_spositive)]);
+        }
+        missing.extend(metrics.into_iter().filter(|&(_, n)| n < self.regions).map(|(name, _)| name));
+        missing
+    }
+
+    /// True if nothing is missing
+    pub fn complete(&self) -> bool {
+        self.missing().is_empty()
+    }
+}
+
+/// Write one CSV line per file, without a header
+pub fn write_completeness_records<W: Write>(files: &[Completeness], writer: &mut W) -> io::Result<()> {
+    for c in files {
+        writeln!(writer, "{},{},{},{},{},{},{},{},{},{}",
+            csv_field(c.path.file_name().and_then(|n| n.to_str()).unwrap_or_default()),
+            c.drawn_layers,
+            c.analysis_layers,
+            c.regions,
+            c.positivity,
+            c.num_wpositive,
+            c.num_positive,
+            c.num_spositive,
+            c.num_total,
+            csv_field(&c.missing().join(";")))?;
+    }
+    Ok(())
+}
+
+/// Process every XML file in `search_path` and write the completeness report into `writer`
+/// Returns the completeness of each file
+pub fn run_completeness_to_writer<W: Write>(search_path: &path::Path, options: &Options, writer: &mut W) -> Result<Vec<Completeness>, Box<dyn error::Error>> {
+    let mut files = Vec::new();
+    for source in xml_sources(search_path, options)? {
+        let (records, summary) = process_source(&source, options);
+        files.push(Completeness::of(&records, &summary));
+    }
+    writeln!(writer, "{}", COMPLETENESS_HEADER)?;
+    write_completeness_records(&files, writer)?;
+    Ok(files)
+}
+
+/// Process every XML file in `search_path` and print the completeness report to stdout
+/// Returns the number of incomplete files
+pub fn run_completeness(search_path: &path::Path, options: &Options) -> Result<usize, Box<dyn error::Error>> {
+    let files = run_completeness_to_writer(search_path, options, &mut io::stdout().lock())?;
+    Ok(files.iter().filter(|c| !c.complete()).count())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+    use crate::extract::{FileSummary, RegionRecord};
+    use crate::metadata::parse_csv_line;
+    use super::{Completeness, write_completeness_records};
+
+    fn record(positivity: Option<f64>, num_wpositive: Option<f64>, num_total: Option<f64>) -> RegionRecord {
+        RegionRecord {
+            filename: String::from("case1.xml"),
+            slide_name: String::from("case1.svs"),
+            region_id: String::from("1"),
+            text_label: String::new(),
+            positivity,
+            num_wpositive,
+            num_positive: Some(1.0),
+            num_spositive: num_wpositive,
+            num_all_positive: 1.0,
+            num_total,
+            algorithm: String::new(),
+            columns: Vec::new(),
+            text_columns: Vec::new(),
+        }
+    }
+
+    fn summary(intensity_bins: bool, ratios: bool) -> FileSummary {
+        FileSummary { drawn_layers: 1, analysis_layers: 1, intensity_bins, ratios, ..FileSummary::new(path::Path::new("case 1, block A.xml")) }
+    }
+
+    #[test]
+    fn metrics_some_region_lacks_are_missing() {
+        let records = [record(Some(0.5), Some(1.0), Some(2.0)), record(None, None, Some(2.0))];
+        let completeness = Completeness::of(&records, &summary(true, true));
+        assert_eq!((completeness.regions, completeness.positivity, completeness.num_wpositive, completeness.num_total), (2, 1, 1, 2));
+        assert_eq!(completeness.missing(), ["positivity", "num weak positive", "num strong positive"]);
+        // Weak and strong counts are only expected from algorithms counting them
+        assert_eq!(Completeness::of(&records, &summary(false, true)).missing(), ["positivity"]);
+        // Event counts have neither a positivity nor a total
+        assert!(Completeness::of(&records, &summary(false, false)).complete());
+    }
+
+    #[test]
+    fn files_without_layers_or_regions_are_incomplete() {
+        let empty = Completeness::of(&[], &FileSummary::new(path::Path::new("case1.xml")));
+        assert_eq!(empty.missing(), ["no drawn layer", "no analysis layer", "no regions"]);
+        let unreadable = FileSummary { error: Some(String::from("Error parsing XML")), ..summary(true, true) };
+        assert_eq!(Completeness::of(&[], &unreadable).missing(), ["unreadable"]);
+    }
+
+    #[test]
+    fn rows_quote_the_file_name() {
+        let records = [record(None, None, Some(2.0))];
+        let mut csv = Vec::new();
+        write_completeness_records(&[Completeness::of(&records, &summary(true, true))], &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(parse_csv_line(csv.trim_end()), ["case 1, block A.xml", "1", "1", "1", "0", "0", "1", "0", "1", "positivity;num weak positive;num strong positive"]);
+    }
+}
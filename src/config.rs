@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::{error, fs, path};
+use serde::Deserialize;
+use crate::options::Options;
+use crate::patterns::{ColumnType, CustomColumn};
+use crate::scores::AllredBins;
+use crate::metadata::FilenamePattern;
+use crate::aggregate::LabelFilter;
+use crate::qc::QcRules;
+
+/// Contents of a TOML configuration file, everything is optional
+///
+/// ```toml
+/// # Named groups of a regular expression matched against file names become extra output columns
+/// filename_pattern = '(?P<case>\d+)-(?P<block>[A-Z]\d)-(?P<stain>\w+)'
+///
+/// # Patterns replace the built-in ones for the columns listed
+/// [patterns]
+/// np = ["Np =", "Number of Positive ="]
+///
+/// # Extra output columns holding the attribute whose header name matches a regular expression, given by the
+/// # pattern alone or with the type of its values (number by default, or text) and whether files need it
+/// [columns]
+/// avg_intensity = "Iavg.*"
+/// tissue_class = { pattern = "^Class", type = "text", required = true }
+///
+/// # Regions counting towards slide positivity in summaries, see `LabelFilter`
+/// [slide_positivity]
+/// include = ["Tumor*"]
+/// exclude = ["Control*"]
+///
+/// # Thresholds for automated batch gating, see `QcRules`
+/// [qc_rules]
+/// min_ntotal = 1000
+/// min_area_mm2 = 0.5
+/// min_positivity = 0
+/// max_positivity = 1
+/// max_failures = 10
+///
+/// # Local conventions for binning Allred scores, see `AllredBins`
+/// [allred]
+/// proportion_cutoffs = [0, 1, 10, 33.3, 66.7]
+/// intensity_cutoffs = [0, 1.5, 2.5]
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    filename_pattern: Option<String>,
+    #[serde(default)]
+    patterns: BTreeMap<String, Vec<String>>,
+    /// Kept as a table to preserve the order of the columns
+    #[serde(default)]
+    columns: toml::Table,
+    allred: Option<AllredBins>,
+    slide_positivity: Option<LabelFilter>,
+    qc_rules: Option<QcRules>,
+}
+
+/// Custom column given as a table, see `CustomColumn`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ColumnSpec {
+    pattern: String,
+    #[serde(default, rename = "type")]
+    kind: ColumnType,
+    #[serde(default)]
+    required: bool,
+}
+
+/// Read a TOML configuration file into `options`
+pub fn load_config(path: &path::Path, options: &mut Options) -> Result<(), Box<dyn error::Error>> {
+    let config: ConfigFile = toml::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| format!("Error reading config from {}: {}", path.display(), e))?;
+    for (column, patterns) in config.patterns {
+        *options.attribute_patterns.column_mut(&column).ok_or(format!("Unknown column {} in {}", column, path.display()))? = patterns;
+    }
+    for (name, value) in config.columns {
+        let spec = match value {
+            toml::Value::String(pattern) => ColumnSpec { pattern, kind: ColumnType::default(), required: false },
+            toml::Value::Table(table) => table.try_into().map_err(|e| format!("Invalid column {} in {}: {}", name, path.display(), e))?,
+            _ => return Err(format!("Column {} in {} should be a pattern or a table with a pattern", name, path.display()).into()),
+        };
+        let pattern = regex::Regex::new(&spec.pattern).map_err(|e| format!("Invalid pattern for column {} in {}: {}", name, path.display(), e))?;
+        options.columns.push(CustomColumn { name, pattern, kind: spec.kind, required: spec.required });
+    }
+    if let Some(pattern) = config.filename_pattern {
+        options.filename_pattern = Some(FilenamePattern::new(&pattern).map_err(|e| format!("In {}: {}", path.display(), e))?);
+    }
+    if let Some(rules) = config.qc_rules {
+        options.qc_rules = rules;
+    }
+    if let Some(filter) = config.slide_positivity {
+        options.slide_labels = filter;
+    }
+    if let Some(allred) = config.allred {
+        for cutoffs in [&allred.proportion_cutoffs, &allred.intensity_cutoffs] {
+            if cutoffs.windows(2).any(|w| w[0] >= w[1]) {
+                return Err(format!("Allred cutoffs in {} should be increasing", path.display()).into());
+            }
+        }
+        options.allred = allred;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::options::Options;
+    use crate::patterns::ColumnType;
+    use super::load_config;
+
+    /// Options after loading `contents` as a config file, or the error
+    fn load(name: &str, contents: &str) -> Result<Options, String> {
+        let path = std::env::temp_dir().join(format!("read_imagescope_xml_config_{}_{}.toml", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let mut options = Options::default();
+        let loaded = load_config(&path, &mut options).map_err(|e| e.to_string());
+        fs::remove_file(&path).unwrap();
+        loaded.map(|_| options)
+    }
+
+    #[test]
+    fn settings_are_read_into_the_options() {
+        let options = load("full", r#"
+            filename_pattern = '(?P<case>\d+)-(?P<block>[A-Z]\d)'
+            [patterns]
+            np = ["Np =", "Number of Positive ="]
+            [columns]
+            avg_intensity = "Iavg.*"
+            tissue_class = { pattern = "^Class", type = "text", required = true }
+            [slide_positivity]
+            include = ["Tumor*"]
+            [qc_rules]
+            min_ntotal = 1000
+            [allred]
+            proportion_cutoffs = [0, 1, 10, 33.3, 66.7]
+            intensity_cutoffs = [0, 1.5, 2.5]
+        "#).unwrap();
+        assert_eq!(options.filename_pattern.unwrap().columns(), ["case", "block"]);
+        assert_eq!(options.attribute_patterns.num_positive, ["Np =", "Number of Positive ="]);
+        // Columns keep the order of the file
+        let columns: Vec<(&str, &str, ColumnType, bool)> = options.columns.iter().map(|c| (c.name.as_str(), c.pattern.as_str(), c.kind, c.required)).collect();
+        assert_eq!(columns, [("avg_intensity", "Iavg.*", ColumnType::Number, false), ("tissue_class", "^Class", ColumnType::Text, true)]);
+        assert_eq!(options.slide_labels.include, ["Tumor*"]);
+        assert_eq!(options.qc_rules.min_ntotal, Some(1000.0));
+        assert_eq!(options.allred.intensity_cutoffs, [0.0, 1.5, 2.5]);
+        // Nothing configured leaves the defaults
+        let options = load("empty", "").unwrap();
+        assert!(options.columns.is_empty() && options.filename_pattern.is_none());
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        let errors = [
+            ("unknown_key", "filename_patern = 'x'", "unknown field"),
+            ("unknown_column_key", "[columns]\nx = { pattern = 'x', kind = 'text' }", "Invalid column x"),
+            ("column_type", "[columns]\nx = 3", "should be a pattern"),
+            ("column_regex", "[columns]\nx = '(unclosed'", "Invalid pattern for column x"),
+            ("filename_regex", "filename_pattern = '(unclosed'", "In "),
+            ("pattern_column", "[patterns]\nnope = ['x']", "Unknown column nope"),
+            ("allred", "[allred]\nproportion_cutoffs = [0, 10, 10]\nintensity_cutoffs = [0, 1.5, 2.5]", "should be increasing"),
+        ];
+        for (name, contents, expected) in errors {
+            let error = load(name, contents).unwrap_err();
+            assert!(error.contains(expected), "{}: {}", name, error);
+        }
+    }
+}
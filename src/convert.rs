@@ -0,0 +1,425 @@
+use std::{error, fmt, fs, path, str::FromStr};
+use std::collections::HashMap;
+use crate::model::{Annotation, Annotations, Region};
+use crate::extract::{RegionRecord, extract_regions_with_summary, region_keys, region_records_with_columns};
+use crate::geometry::prepare_for_export;
+use crate::import::{InputFormat, detect_input_format, import_annotations, import_files};
+use crate::input::{XmlSource, is_s3_location, is_tar_file, is_url, is_xml_file, is_zip_file, xml_sources};
+use crate::options::Options;
+use crate::read_source;
+use crate::coco::CocoDataset;
+
+/// Formats annotations can be converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    ImageScopeXml,
+    AsapXml,
+    OmeXml,
+    Coco,
+    CvatXml,
+    LabelMe,
+    NapariCsv,
+    GeoJson,
+    Wkt,
+    Svg,
+    TilesCsv,
+    TilesJson,
+    HeatmapCsv,
+    HeatmapSvg,
+    CropsCsv,
+    CropsJson,
+    #[cfg(feature = "raster")]
+    MaskPng,
+    #[cfg(feature = "raster")]
+    MaskTiff,
+}
+
+impl Format {
+    /// Extension of the files written in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::ImageScopeXml => "xml",
+            Format::AsapXml => "asap.xml",
+            Format::OmeXml => "ome.xml",
+            Format::Coco => "coco.json",
+            Format::CvatXml => "cvat.xml",
+            Format::LabelMe => "labelme.json",
+            Format::NapariCsv => "napari.csv",
+            Format::GeoJson => "geojson",
+            Format::Wkt => "wkt.csv",
+            Format::Svg => "svg",
+            Format::TilesCsv => "tiles.csv",
+            Format::TilesJson => "tiles.json",
+            Format::HeatmapCsv => "heatmap.csv",
+            Format::HeatmapSvg => "heatmap.svg",
+            Format::CropsCsv => "crops.csv",
+            Format::CropsJson => "crops.json",
+            #[cfg(feature = "raster")]
+            Format::MaskPng => "mask.png",
+            #[cfg(feature = "raster")]
+            Format::MaskTiff => "mask.tif",
+        }
+    }
+
+    /// True for formats written as a single dataset file covering every input
+    pub fn is_dataset(&self) -> bool {
+        matches!(self, Format::Coco | Format::CvatXml)
+    }
+}
+
+impl FromStr for Format {
+    type Err = Box<dyn error::Error>;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "imagescope-xml" => Ok(Format::ImageScopeXml),
+            "asap-xml" => Ok(Format::AsapXml),
+            "ome-xml" => Ok(Format::OmeXml),
+            "coco" => Ok(Format::Coco),
+            "cvat-xml" => Ok(Format::CvatXml),
+            "labelme" => Ok(Format::LabelMe),
+            "napari-csv" => Ok(Format::NapariCsv),
+            "geojson" => Ok(Format::GeoJson),
+            "wkt" => Ok(Format::Wkt),
+            "svg" => Ok(Format::Svg),
+            "tiles-csv" => Ok(Format::TilesCsv),
+            "tiles-json" => Ok(Format::TilesJson),
+            "heatmap-csv" => Ok(Format::HeatmapCsv),
+            "heatmap-svg" => Ok(Format::HeatmapSvg),
+            "crops-csv" => Ok(Format::CropsCsv),
+            "crops-json" => Ok(Format::CropsJson),
+            #[cfg(feature = "raster")]
+            "mask-png" => Ok(Format::MaskPng),
+            #[cfg(feature = "raster")]
+            "mask-tiff" => Ok(Format::MaskTiff),
+            #[cfg(not(feature = "raster"))]
+            "mask-png" | "mask-tiff" => Err("Cannot write masks: rebuild with the `raster` feature".into()),
+            other => Err(format!("Unknown format {}", other).into()),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::ImageScopeXml => write!(f, "imagescope-xml"),
+            Format::AsapXml => write!(f, "asap-xml"),
+            Format::OmeXml => write!(f, "ome-xml"),
+            Format::Coco => write!(f, "coco"),
+            Format::CvatXml => write!(f, "cvat-xml"),
+            Format::LabelMe => write!(f, "labelme"),
+            Format::NapariCsv => write!(f, "napari-csv"),
+            Format::GeoJson => write!(f, "geojson"),
+            Format::Wkt => write!(f, "wkt"),
+            Format::Svg => write!(f, "svg"),
+            Format::TilesCsv => write!(f, "tiles-csv"),
+            Format::TilesJson => write!(f, "tiles-json"),
+            Format::HeatmapCsv => write!(f, "heatmap-csv"),
+            Format::HeatmapSvg => write!(f, "heatmap-svg"),
+            Format::CropsCsv => write!(f, "crops-csv"),
+            Format::CropsJson => write!(f, "crops-json"),
+            #[cfg(feature = "raster")]
+            Format::MaskPng => write!(f, "mask-png"),
+            #[cfg(feature = "raster")]
+            Format::MaskTiff => write!(f, "mask-tiff"),
+        }
+    }
+}
+
+/// A drawn region to export, with the results reported for it if any
+#[derive(Debug, Clone, Copy)]
+pub struct ExportRegion<'a> {
+    pub layer: &'a Annotation,
+    pub region: &'a Region,
+    pub record: Option<&'a RegionRecord>,
+}
+
+/// Regions of the drawn layers matched with their records by region ID
+/// Analysis (type 3) layers are left out since their regions repeat the drawn ones.
+pub fn export_regions<'a>(annotations: &'a Annotations, records: &'a [RegionRecord]) -> Vec<ExportRegion<'a>> {
+    let by_id: HashMap<&str, &RegionRecord> = records.iter().map(|r| (r.region_id.as_str(), r)).collect();
+    annotations.annotation.iter()
+        .filter(|layer| layer.annotation_type != "3")
+        .flat_map(|layer| {
+            let keys = region_keys(layer.regions.region.iter().map(|r| r.id.as_str()));
+            layer.regions.region.iter().zip(keys)
+                .map(|(region, key)| ExportRegion { layer, region, record: by_id.get(key.as_str()).copied() })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Write annotations and their records in another format
+pub fn convert_annotations(annotations: &Annotations, records: &[RegionRecord], format: Format, options: &Options) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let regions = export_regions(annotations, records);
+    match format {
+        Format::ImageScopeXml => Ok(annotations.to_xml_string()?.into_bytes()),
+        Format::AsapXml => Ok(crate::asap::to_asap_xml(&regions)?.into_bytes()),
+        Format::OmeXml => Ok(crate::ome::to_ome_xml(&regions).into_bytes()),
+        Format::Coco | Format::CvatXml => convert_dataset(&[DatasetSlide { name: "", annotations, records }], format),
+        Format::LabelMe => {
+            let image_path = records.first().map(|r| r.slide_name.as_str()).unwrap_or_default();
+            Ok(serde_json::to_vec_pretty(&crate::labelme::to_labelme(&regions, image_path))?)
+        },
+        Format::NapariCsv => Ok(crate::napari::napari_shapes_csv(&regions, options.downsample).into_bytes()),
+        Format::GeoJson => Ok(serde_json::to_vec(&crate::geojson::to_geojson(&regions))?),
+        Format::Wkt => Ok(crate::wkt::to_wkt_csv(&regions).into_bytes()),
+        Format::Svg => Ok(crate::render::to_svg(&regions, options.downsample, options.color_by).into_bytes()),
+        Format::TilesCsv => Ok(crate::tiles::tiles_csv(&crate::tiles::tile_regions(&regions, options.tile_size, &options.tile_labels)).into_bytes()),
+        Format::TilesJson => Ok(serde_json::to_vec(&crate::tiles::tile_regions(&regions, options.tile_size, &options.tile_labels))?),
+        Format::HeatmapCsv => {
+            let heatmap = crate::heatmap::heatmap_grid(&regions, options.downsample)?;
+            eprintln!("Heatmap starts at level-0 pixel {}, {}", heatmap.x, heatmap.y);
+            Ok(heatmap.to_csv().into_bytes())
+        },
+        Format::HeatmapSvg => Ok(crate::heatmap::heatmap_svg(&regions, options.downsample).into_bytes()),
+        Format::CropsCsv => Ok(crate::crops::crops_csv(&crate::crops::crop_boxes(&regions, options.crop_margin)).into_bytes()),
+        Format::CropsJson => Ok(serde_json::to_vec(&crate::crops::crop_boxes(&regions, options.crop_margin))?),
+        #[cfg(feature = "raster")]
+        Format::MaskPng | Format::MaskTiff => {
+            let mask = crate::mask::rasterize(&regions, options.downsample, &options.mask_labels)?;
+            eprintln!("Mask starts at level-0 pixel {}, {}", mask.x, mask.y);
+            for (value, label) in mask.labels.iter().enumerate() {
+                eprintln!("Mask value {} is {}", value + 1, label);
+            }
+            if format == Format::MaskPng { mask.to_png() } else { mask.to_tiff() }
+        },
+    }
+}
+
+/// One slide of a dataset export
+#[derive(Debug, Clone, Copy)]
+pub struct DatasetSlide<'a> {
+    pub name: &'a str,
+    pub annotations: &'a Annotations,
+    pub records: &'a [RegionRecord],
+}
+
+/// Write several slides into a single dataset file
+pub fn convert_dataset(slides: &[DatasetSlide], format: Format) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    match format {
+        Format::Coco => {
+            let mut dataset = CocoDataset::new();
+            for slide in slides {
+                dataset.add_image(slide.name, &export_regions(slide.annotations, slide.records));
+            }
+            Ok(serde_json::to_vec(&dataset)?)
+        },
+        Format::CvatXml => Ok(crate::cvat::to_cvat_xml(slides).into_bytes()),
+        _ => Err(format!("{} is not a dataset format", format).into()),
+    }
+}
+
+/// Folder output files are written into unless another one is given, the current folder
+pub const DEFAULT_OUTPUT_DIR: &str = ".";
+
+/// Name of the file written for `filename`, e.g. `slide1.geojson` for `slide1.xml.gz`
+/// Only the last component of an archive entry (`batch.zip/slide1.xml`) or URL is used, without any query.
+fn output_name(filename: &str, format: Format) -> String {
+    let path = filename.split(['?', '#']).next().unwrap_or(filename).trim_end_matches(['/', '\\']);
+    let mut stem = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    for extension in [".gz", ".xml", ".asap", ".session", ".annotations", ".geojson", ".json"] {
+        if stem.len() > extension.len() && stem[stem.len() - extension.len()..].eq_ignore_ascii_case(extension) {
+            stem = &stem[..stem.len() - extension.len()];
+        }
+    }
+    format!("{}.{}", stem, format.extension())
+}
+
+/// Convert every file of format `from` in `search_path` into `format`, writing one file per input into `output_dir`,
+/// or a single `dataset.<extension>` file for dataset formats. With `InputFormat::Auto` the format of each file
+/// is recognized from its contents.
+/// Returns the number of files that could not be converted
+pub fn run_convert(search_path: &path::Path, from: InputFormat, format: Format, output_dir: &path::Path, options: &Options) -> Result<usize, Box<dyn error::Error>> {
+    fs::create_dir_all(output_dir)?;
+    let mut failed = 0;
+    let mut dataset = Vec::new();
+    let sources = match from {
+        InputFormat::ImageScopeXml => xml_sources(search_path, options)?,
+        // XML files, archives and URLs as when extracting data, plus the files of the other formats
+        InputFormat::Auto => {
+            let mut sources = xml_sources(search_path, options)?;
+            // Only local folders can be listed for other files
+            let remote = search_path.to_str().is_some_and(|l| is_url(l) || is_s3_location(l));
+            if !remote {
+                let others = import_files(search_path, from)?.into_iter().filter(|p| !is_xml_file(p) && !is_zip_file(p) && !is_tar_file(p));
+                sources.extend(others.map(|p| XmlSource::file(&p)));
+            }
+            sources
+        },
+        _ => import_files(search_path, from)?.iter().map(|p| XmlSource::file(p)).collect(),
+    };
+    for source in sources {
+        let mut summary = source.summary();
+        let from = match from {
+            InputFormat::Auto => match source.contents() {
+                Ok(contents) => detect_input_format(&contents),
+                Err(e) => {
+                    eprintln!("Error reading {}: {}", source.path.display(), e);
+                    failed += 1;
+                    continue;
+                },
+            },
+            from => from,
+        };
+        let annotations = match from {
+            InputFormat::ImageScopeXml => read_source(&source, options, &mut summary),
+            _ => match source.contents().and_then(|contents| import_annotations(&contents, from)) {
+                Ok((annotations, skipped)) => {
+                    for s in skipped {
+                        summary.warn(format!("In {}: {}", source.path.display(), s));
+                    }
+                    Some(annotations)
+                },
+                Err(e) => {
+                    eprintln!("Error reading {} as {}: {}", source.path.display(), from, e);
+                    None
+                },
+            },
+        };
+        let Some(mut annotations) = annotations else {
+            failed += 1;
+            continue;
+        };
+        if summary.failed() {
+            failed += 1;
+            continue;
+        }
+        let regions_info = extract_regions_with_summary(&annotations, options, &mut summary);
+        let records = region_records_with_columns(&source.filename, &source.slide_name, &regions_info, &options.column_names());
+        let output = prepare_for_export(&mut annotations, options)
+            .and_then(|_| if format.is_dataset() { Ok(Vec::new()) } else { convert_annotations(&annotations, &records, format, options) });
+        match output {
+            // Dataset formats are written once every file has been read
+            Ok(_) if format.is_dataset() => dataset.push((source.slide_name.clone(), annotations, records)),
+            Ok(output) => {
+                let output_path = output_dir.join(output_name(&source.filename, format));
+                match fs::write(&output_path, output) {
+                    Ok(()) => eprintln!("Wrote {}", output_path.display()),
+                    Err(e) => {
+                        eprintln!("Error writing {}: {}", output_path.display(), e);
+                        failed += 1;
+                    },
+                }
+            },
+            Err(e) => {
+                eprintln!("Error converting {} to {}: {}", source.path.display(), format, e);
+                failed += 1;
+            },
+        }
+    }
+    if format.is_dataset() && !dataset.is_empty() {
+        let slides: Vec<DatasetSlide> = dataset.iter().map(|(name, annotations, records)| DatasetSlide { name, annotations, records }).collect();
+        let output_path = output_dir.join(format!("dataset.{}", format.extension()));
+        fs::write(&output_path, convert_dataset(&slides, format)?)?;
+        eprintln!("Wrote {}", output_path.display());
+    }
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::import::InputFormat;
+    use crate::model::RegionType;
+    use crate::options::Options;
+    use super::{DEFAULT_OUTPUT_DIR, Format, output_name, run_convert};
+
+    #[test]
+    fn format_names_round_trip() {
+        let formats = [
+            Format::ImageScopeXml, Format::AsapXml, Format::OmeXml, Format::Coco, Format::CvatXml, Format::LabelMe,
+            Format::NapariCsv, Format::GeoJson, Format::Wkt, Format::Svg, Format::TilesCsv, Format::TilesJson,
+            Format::HeatmapCsv, Format::HeatmapSvg, Format::CropsCsv, Format::CropsJson,
+            #[cfg(feature = "raster")]
+            Format::MaskPng,
+            #[cfg(feature = "raster")]
+            Format::MaskTiff,
+        ];
+        for format in formats {
+            assert_eq!(format.to_string().parse::<Format>().unwrap(), format);
+        }
+        assert_eq!(" GeoJSON ".parse::<Format>().unwrap(), Format::GeoJson);
+        assert!("shapefile".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn output_names_drop_the_input_extensions() {
+        assert_eq!(output_name("slide1.xml.gz", Format::GeoJson), "slide1.geojson");
+        assert_eq!(output_name("slide1.XML", Format::Wkt), "slide1.wkt.csv");
+        assert_eq!(output_name("slide1.session.xml", Format::AsapXml), "slide1.asap.xml");
+        assert_eq!(output_name("slide1.annotations", Format::ImageScopeXml), "slide1.xml");
+        // A name that is nothing but an extension is kept
+        assert_eq!(output_name(".xml", Format::Svg), ".xml.svg");
+        // Archive entries and URLs are named after their last component
+        assert_eq!(output_name("batch.zip/cases/case1.xml", Format::GeoJson), "case1.geojson");
+        assert_eq!(output_name("batch.tar.gz/case1.xml.gz", Format::Wkt), "case1.wkt.csv");
+        assert_eq!(output_name("https://host/slides/a.xml?version=2#top", Format::GeoJson), "a.geojson");
+        assert_eq!(output_name(r"C:\exports\b.xml", Format::GeoJson), "b.geojson");
+        assert_eq!(path::Path::new(DEFAULT_OUTPUT_DIR).join(output_name("slide1.xml", Format::GeoJson)), path::Path::new("./slide1.geojson"));
+    }
+
+    #[test]
+    fn slides_are_written_one_file_each_or_into_one_dataset() {
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_convert_{}", std::process::id()));
+        let (input, output) = (dir.join("input"), dir.join("output"));
+        fs::create_dir_all(&input).unwrap();
+        for name in ["case1", "case2"] {
+            let annotations = AnnotationsBuilder::new()
+                .layer(AnnotationBuilder::new().region(RegionBuilder::new().region_type(RegionType::Rectangle).text(name).vertices([(0.0, 0.0), (10.0, 10.0)])))
+                .build();
+            fs::write(input.join(format!("{}.xml", name)), annotations.to_xml_string().unwrap()).unwrap();
+        }
+        let options = Options::default();
+        let failed = run_convert(&input, InputFormat::ImageScopeXml, Format::Wkt, &output, &options).unwrap();
+        let failed_dataset = run_convert(&input, InputFormat::ImageScopeXml, Format::Coco, &output, &options).unwrap();
+        let mut written: Vec<String> = fs::read_dir(&output).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+        written.sort();
+        let case1 = fs::read_to_string(output.join("case1.wkt.csv")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((failed, failed_dataset), (0, 0));
+        assert_eq!(written, ["case1.wkt.csv", "case2.wkt.csv", "dataset.coco.json"]);
+        assert!(case1.lines().nth(1).unwrap().starts_with("1,1,case1,"), "{}", case1);
+    }
+
+    #[test]
+    fn a_file_that_cannot_be_written_fails_alone() {
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_convert_unwritable_{}", std::process::id()));
+        let (input, output) = (dir.join("input"), dir.join("output"));
+        fs::create_dir_all(&input).unwrap();
+        for name in ["case1", "case2"] {
+            let annotations = AnnotationsBuilder::new().layer(AnnotationBuilder::new().region(RegionBuilder::new().vertices([(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]))).build();
+            fs::write(input.join(format!("{}.xml", name)), annotations.to_xml_string().unwrap()).unwrap();
+        }
+        // A folder in the way of the first output file
+        fs::create_dir_all(output.join("case1.wkt.csv")).unwrap();
+        let failed = run_convert(&input, InputFormat::ImageScopeXml, Format::Wkt, &output, &Options::default()).unwrap();
+        let written = output.join("case2.wkt.csv").is_file();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(failed, 1);
+        assert!(written);
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn archive_entries_are_written_under_their_own_name() {
+        use std::io::Write;
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_convert_zip_{}", std::process::id()));
+        let (input, output) = (dir.join("input"), dir.join("output"));
+        fs::create_dir_all(&input).unwrap();
+        let mut archive = zip::ZipWriter::new(fs::File::create(input.join("batch.zip")).unwrap());
+        for name in ["cases/case1.xml", "case2.xml"] {
+            let annotations = AnnotationsBuilder::new().layer(AnnotationBuilder::new().region(RegionBuilder::new().vertices([(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]))).build();
+            archive.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            archive.write_all(annotations.to_xml_string().unwrap().as_bytes()).unwrap();
+        }
+        archive.finish().unwrap();
+        let failed = run_convert(&input, InputFormat::Auto, Format::GeoJson, &output, &Options::default()).unwrap();
+        let mut written: Vec<String> = fs::read_dir(&output).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+        written.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(failed, 0);
+        assert_eq!(written, ["case1.geojson", "case2.geojson"]);
+    }
+}
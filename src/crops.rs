@@ -0,0 +1,85 @@
+use serde::Serialize;
+use crate::convert::ExportRegion;
+use crate::report::csv_field;
+
+/// Column names of the crop box export
+pub const CROPS_HEADER: &str = "Layer ID,Region ID,text label,x,y,width,height";
+
+/// Level-0 rectangle to read from the slide for a region, e.g. with OpenSlide's `read_region`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CropBox {
+    pub layer_id: String,
+    pub region_id: String,
+    pub label: String,
+    /// Top left corner
+    pub x: i64,
+    pub y: i64,
+    pub width: i64,
+    pub height: i64,
+}
+
+/// Bounding box of each region padded by `margin` pixels on every side, in whole pixels and clipped at the
+/// slide origin. NegativeROA regions are left out.
+pub fn crop_boxes(regions: &[ExportRegion], margin: f64) -> Vec<CropBox> {
+    regions.iter()
+        .filter(|r| !r.region.is_negative())
+        .filter_map(|r| {
+            let bbox = r.region.bounding_box()?;
+            let (x, y) = (((bbox.min_x - margin).floor() as i64).max(0), ((bbox.min_y - margin).floor() as i64).max(0));
+            let (right, bottom) = ((bbox.max_x + margin).ceil() as i64, (bbox.max_y + margin).ceil() as i64);
+            Some(CropBox {
+                layer_id: r.layer.id.clone(),
+                region_id: r.region.id.clone(),
+                label: r.region.label(),
+                x,
+                y,
+                width: (right - x).max(0),
+                height: (bottom - y).max(0),
+            })
+        })
+        .collect()
+}
+
+/// CSV with one line per crop box
+pub fn crops_csv(crops: &[CropBox]) -> String {
+    let mut csv = format!("{}\n", CROPS_HEADER);
+    for c in crops {
+        csv.push_str(&format!("{},{},{},{},{},{},{}\n", csv_field(&c.layer_id), csv_field(&c.region_id), csv_field(&c.label), c.x, c.y, c.width, c.height));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::model::RegionType;
+    use super::{CropBox, crop_boxes, crops_csv};
+
+    fn rectangle(text: &str, from: (f64, f64), to: (f64, f64)) -> RegionBuilder {
+        RegionBuilder::new().region_type(RegionType::Rectangle).text(text).vertices([from, to])
+    }
+
+    #[test]
+    fn boxes_are_padded_and_clipped_at_the_origin() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(rectangle("Tumor", (5.0, 3.0), (20.0, 10.5)))
+                .region(rectangle("Stroma", (100.2, 50.7), (110.0, 60.0)))
+                .region(rectangle("", (101.0, 51.0), (102.0, 52.0)).negative_roa(true)))
+            .build();
+        let crops = crop_boxes(&export_regions(&annotations, &[]), 2.0);
+        let boxes: Vec<(&str, i64, i64, i64, i64)> = crops.iter().map(|c| (c.region_id.as_str(), c.x, c.y, c.width, c.height)).collect();
+        // Outward to whole pixels
+        assert_eq!(boxes, [("1", 3, 1, 19, 12), ("2", 98, 48, 14, 14)]);
+        let crops = crop_boxes(&export_regions(&annotations, &[]), 10.0);
+        assert_eq!((crops[0].x, crops[0].y, crops[0].width, crops[0].height), (0, 0, 30, 21));
+        assert_eq!((crops[0].layer_id.as_str(), crops[0].label.as_str()), ("1", "Tumor"));
+    }
+
+    #[test]
+    fn boxes_are_written_one_per_line() {
+        let crop = CropBox { layer_id: String::from("1"), region_id: String::from("2"), label: String::from("Tumor, A"), x: 3, y: 1, width: 19, height: 12 };
+        assert_eq!(crops_csv(&[crop]), "Layer ID,Region ID,text label,x,y,width,height\n1,2,\"Tumor, A\",3,1,19,12\n");
+    }
+}
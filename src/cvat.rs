@@ -0,0 +1,84 @@
+use quick_xml::escape::escape;
+use crate::convert::{DatasetSlide, export_regions};
+use crate::extract::UNLABELED;
+use crate::geometry::BoundingBox;
+use crate::model::RegionType;
+use crate::render::layer_color;
+
+/// Label of NegativeROA regions, CVAT has no exclusions
+const EXCLUSION_LABEL: &str = "Exclusion";
+
+/// CVAT for images 1.1 annotation XML with one image per slide
+/// Rectangles become boxes, ellipses ellipses, other closed regions polygons, lines polylines and single
+/// vertices points. Labels are the region texts, colored after the layer where they first appear.
+pub fn to_cvat_xml(slides: &[DatasetSlide]) -> String {
+    let mut labels: Vec<(String, String)> = Vec::new();
+    let mut images = String::new();
+    for (id, slide) in slides.iter().enumerate() {
+        let regions = export_regions(slide.annotations, slide.records);
+        let mut shapes = String::new();
+        let mut extent = (0.0f64, 0.0f64);
+        for r in &regions {
+            let outline = r.region.outline();
+            let Some(bbox) = BoundingBox::of(&outline) else { continue };
+            extent = (extent.0.max(bbox.max_x), extent.1.max(bbox.max_y));
+            let label = match r.region.label() {
+                _ if r.region.is_negative() => EXCLUSION_LABEL.to_string(),
+                label if label.is_empty() => UNLABELED.to_string(),
+                label => label,
+            };
+            if !labels.iter().any(|(name, _)| *name == label) {
+                labels.push((label.clone(), layer_color(r.layer).unwrap_or_else(|| String::from("#00ff00"))));
+            }
+            let common = format!("label=\"{}\" source=\"manual\" occluded=\"0\" z_order=\"0\"", escape(label.as_str()));
+            let points = outline.iter().map(|(x, y)| format!("{:.2},{:.2}", x, y)).collect::<Vec<_>>().join(";");
+            let shape = match (&r.region.region_type, outline.len()) {
+                (_, 1) => format!("<points {} points=\"{}\"/>", common, points),
+                (RegionType::Rectangle, _) => format!("<box {} xtl=\"{:.2}\" ytl=\"{:.2}\" xbr=\"{:.2}\" ybr=\"{:.2}\"/>", common, bbox.min_x, bbox.min_y, bbox.max_x, bbox.max_y),
+                (RegionType::Ellipse, _) => format!("<ellipse {} cx=\"{:.2}\" cy=\"{:.2}\" rx=\"{:.2}\" ry=\"{:.2}\"/>", common, (bbox.min_x + bbox.max_x) / 2.0, (bbox.min_y + bbox.max_y) / 2.0, bbox.width() / 2.0, bbox.height() / 2.0),
+                (region_type, n) if n >= 3 && region_type.is_closed() => format!("<polygon {} points=\"{}\"/>", common, points),
+                _ => format!("<polyline {} points=\"{}\"/>", common, points),
+            };
+            shapes.push_str(&format!("    {}\n", shape));
+        }
+        images.push_str(&format!("  <image id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\">\n{}  </image>\n", id, escape(slide.name), extent.0.max(0.0).ceil(), extent.1.max(0.0).ceil(), shapes));
+    }
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<annotations>\n  <version>1.1</version>\n  <meta>\n    <task>\n      <size>");
+    xml.push_str(&format!("{}</size>\n      <labels>\n", slides.len()));
+    for (name, color) in &labels {
+        xml.push_str(&format!("        <label>\n          <name>{}</name>\n          <color>{}</color>\n          <type>any</type>\n          <attributes/>\n        </label>\n", escape(name.as_str()), color));
+    }
+    xml.push_str("      </labels>\n    </task>\n  </meta>\n");
+    xml.push_str(&images);
+    xml.push_str("</annotations>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::DatasetSlide;
+    use crate::model::RegionType;
+    use super::to_cvat_xml;
+
+    #[test]
+    fn slides_become_images_with_labelled_shapes() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new().line_color(255)
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Tumor").vertices([(10.0, 20.0), (40.0, 60.0)]))
+                .region(RegionBuilder::new().negative_roa(true).vertices([(0.0, 0.0), (4.0, 0.0), (4.0, 4.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Polyline).vertices([(0.0, 0.0), (5.5, 5.0)]))
+                .region(RegionBuilder::new().vertex(1.0, 2.0)))
+            .build();
+        let xml = to_cvat_xml(&[DatasetSlide { name: "case1.svs", annotations: &annotations, records: &[] }]);
+        assert!(xml.contains("<size>1</size>"));
+        assert!(xml.contains("<name>Tumor</name>\n          <color>#ff0000</color>"), "{}", xml);
+        assert!(xml.contains("<name>Exclusion</name>") && xml.contains("<name>unlabeled</name>"), "{}", xml);
+        assert!(xml.contains(r#"<image id="0" name="case1.svs" width="40" height="60">"#), "{}", xml);
+        assert!(xml.contains(r#"<box label="Tumor" source="manual" occluded="0" z_order="0" xtl="10.00" ytl="20.00" xbr="40.00" ybr="60.00"/>"#), "{}", xml);
+        assert!(xml.contains(r#"<polygon label="Exclusion" source="manual" occluded="0" z_order="0" points="0.00,0.00;4.00,0.00;4.00,4.00"/>"#), "{}", xml);
+        assert!(xml.contains(r#"<polyline label="unlabeled" source="manual" occluded="0" z_order="0" points="0.00,0.00;5.50,5.00"/>"#), "{}", xml);
+        assert!(xml.contains(r#"<points label="unlabeled" source="manual" occluded="0" z_order="0" points="1.00,2.00"/>"#), "{}", xml);
+    }
+}
@@ -0,0 +1,173 @@
+use std::borrow::Cow;
+use std::{error, fmt};
+
+/// Character encodings we are able to convert into UTF-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Windows-1252, also used for ISO-8859-1 (Latin-1) which it extends
+    Windows1252,
+}
+
+/// Reasons XML bytes could not be converted into UTF-8
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodingError {
+    /// The declaration names an encoding we cannot convert
+    Unsupported(String),
+    /// The bytes are not valid in the detected encoding
+    Invalid(Encoding),
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodingError::Unsupported(name) => write!(f, "unsupported XML encoding \"{}\"", name),
+            EncodingError::Invalid(encoding) => write!(f, "XML is not valid {:?}", encoding),
+        }
+    }
+}
+
+impl error::Error for EncodingError {}
+
+/// Work out the encoding of XML bytes
+/// A byte-order mark wins, then the layout of the leading `<?`, then the encoding named in the declaration.
+/// Returns the encoding and the length of the byte-order mark to skip.
+pub fn detect_encoding(bytes: &[u8]) -> Result<(Encoding, usize), EncodingError> {
+    match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => return Ok((Encoding::Utf8, 3)),
+        [0xFF, 0xFE, ..] => return Ok((Encoding::Utf16Le, 2)),
+        [0xFE, 0xFF, ..] => return Ok((Encoding::Utf16Be, 2)),
+        [b'<', 0, b'?', 0, ..] => return Ok((Encoding::Utf16Le, 0)),
+        [0, b'<', 0, b'?', ..] => return Ok((Encoding::Utf16Be, 0)),
+        _ => {},
+    }
+    let encoding = match declared_encoding(bytes) {
+        None => Encoding::Utf8,
+        Some(name) => match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Encoding::Utf8,
+            // Declared as UTF-16 but without BOM or 16-bit layout, the file was re-saved as 8-bit
+            "utf-16" | "utf-16le" | "utf-16be" => Encoding::Utf8,
+            "windows-1252" | "cp1252" | "iso-8859-1" | "iso8859-1" | "latin1" | "latin-1" | "us-ascii" | "ascii" => Encoding::Windows1252,
+            _ => return Err(EncodingError::Unsupported(name)),
+        },
+    };
+    Ok((encoding, 0))
+}
+
+/// Find the encoding named in the XML declaration of an 8-bit encoded document
+fn declared_encoding(bytes: &[u8]) -> Option<String> {
+    // The declaration is always near the start of the file
+    let head = &bytes[..bytes.len().min(200)];
+    let start = head.windows(5).position(|w| w == b"<?xml")?;
+    let end = start + head[start..].windows(2).position(|w| w == b"?>")?;
+    let declaration = String::from_utf8_lossy(&head[start..end]);
+    let after = &declaration[declaration.find("encoding")? + "encoding".len()..];
+    let after = after.trim_start().strip_prefix('=')?.trim_start();
+    let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &after[1..];
+    Some(value[..value.find(quote)?].to_string())
+}
+
+/// Convert XML bytes into UTF-8 text, detecting their encoding first
+/// Undeclared 8-bit files that are not valid UTF-8 are read as Windows-1252, which is what ImageScope uses on Western Windows systems
+pub fn decode_xml(bytes: &[u8]) -> Result<Cow<'_, str>, EncodingError> {
+    let (encoding, bom) = detect_encoding(bytes)?;
+    let bytes = &bytes[bom..];
+    match encoding {
+        Encoding::Utf8 => match std::str::from_utf8(bytes) {
+            Ok(text) => Ok(Cow::Borrowed(text)),
+            Err(_) if bom == 0 && declared_encoding(bytes).is_none() => Ok(Cow::Owned(decode_windows_1252(bytes))),
+            Err(_) => Err(EncodingError::Invalid(Encoding::Utf8)),
+        },
+        Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes).map(Cow::Owned).ok_or(EncodingError::Invalid(encoding)),
+        Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes).map(Cow::Owned).ok_or(EncodingError::Invalid(encoding)),
+        Encoding::Windows1252 => Ok(Cow::Owned(decode_windows_1252(bytes))),
+    }
+}
+
+/// Decode UTF-16 using `unit` to assemble each pair of bytes
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    char::decode_utf16(units).collect::<Result<String, _>>().ok()
+}
+
+/// Characters for bytes 0x80 to 0x9F in Windows-1252, other bytes map directly onto Unicode
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// Decode Windows-1252, every byte is a valid character
+fn decode_windows_1252(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| match b {
+        0x80..=0x9F => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+        _ => b as char,
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode text as UTF-16 with a byte-order mark, using `bytes` to lay out each unit
+    fn utf16(text: &str, bom: [u8; 2], bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        let mut encoded = bom.to_vec();
+        encoded.extend(text.encode_utf16().flat_map(bytes));
+        encoded
+    }
+
+    #[test]
+    fn utf16_with_bom_decodes() {
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-16\"?><Annotations MicronsPerPixel=\"0.5\">µm é</Annotations>";
+        let le = utf16(xml, [0xFF, 0xFE], u16::to_le_bytes);
+        let be = utf16(xml, [0xFE, 0xFF], u16::to_be_bytes);
+        assert_eq!(detect_encoding(&le), Ok((Encoding::Utf16Le, 2)));
+        assert_eq!(detect_encoding(&be), Ok((Encoding::Utf16Be, 2)));
+        assert_eq!(decode_xml(&le).unwrap(), xml);
+        assert_eq!(decode_xml(&be).unwrap(), xml);
+        assert_eq!(decode_xml(&le[..le.len() - 1]), Err(EncodingError::Invalid(Encoding::Utf16Le)));
+    }
+
+    #[test]
+    fn utf16_without_bom_is_detected_from_the_declaration_layout() {
+        let xml = "<?xml version=\"1.0\"?><Annotations/>";
+        let le: Vec<u8> = xml.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<u8> = xml.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(decode_xml(&le).unwrap(), xml);
+        assert_eq!(decode_xml(&be).unwrap(), xml);
+    }
+
+    #[test]
+    fn windows_1252_decodes() {
+        // µ is 0xB5 and é is 0xE9 in Windows-1252, € is 0x80 in its high block
+        let declared = b"<?xml version=\"1.0\" encoding=\"windows-1252\"?><Region Text=\"\xB5m \xE9 \x80\"/>";
+        assert_eq!(detect_encoding(declared), Ok((Encoding::Windows1252, 0)));
+        assert_eq!(decode_xml(declared).unwrap(), "<?xml version=\"1.0\" encoding=\"windows-1252\"?><Region Text=\"µm é €\"/>");
+        // Undeclared bytes that are not valid UTF-8 fall back to Windows-1252
+        assert_eq!(decode_xml(b"<Region Text=\"\xB5m \xE9\"/>").unwrap(), "<Region Text=\"µm é\"/>");
+    }
+
+    #[test]
+    fn utf8_is_borrowed_and_checked() {
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Region Text=\"µm é\"/>";
+        assert!(matches!(decode_xml(xml.as_bytes()), Ok(Cow::Borrowed(text)) if text == xml));
+        let mut with_bom = vec![0xEF, 0xBB, 0xBF];
+        with_bom.extend(xml.as_bytes());
+        assert_eq!(decode_xml(&with_bom).unwrap(), xml);
+        // Declared UTF-8 that is not valid UTF-8 is an error rather than a guess
+        assert_eq!(decode_xml(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><a>\xE9</a>"), Err(EncodingError::Invalid(Encoding::Utf8)));
+    }
+
+    #[test]
+    fn unknown_declared_encoding_is_unsupported() {
+        let xml = b"<?xml version='1.0' encoding='Shift_JIS'?><a/>";
+        assert_eq!(detect_encoding(xml), Err(EncodingError::Unsupported("Shift_JIS".to_string())));
+    }
+}
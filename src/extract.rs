@@ -0,0 +1,1071 @@
+use std::path;
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use crate::model::{Annotation, Annotations, AttributeHeader};
+use crate::number::{DecimalSeparator, parse_number, parse_number_with_warning};
+use crate::options::Options;
+use crate::scores::Score;
+use crate::qc::{QC_RULE_COLUMNS, qc_rule_values};
+use crate::patterns::{AttributePatterns, find_match, matches_pattern};
+use crate::algorithm::{AlgorithmKind, detect_algorithm, positivity_scale};
+#[cfg(feature = "config")]
+use crate::patterns::{ColumnType, CustomColumn};
+
+/// Label given to regions without a text where one is required, e.g. in column names or other formats
+pub(crate) const UNLABELED: &str = "unlabeled";
+
+/// Name of the column listing implausible values of a region
+pub const QC_COLUMN: &str = "qc";
+
+/// Information we wish to collect about a region
+#[derive(Debug)]
+pub struct RegionInfo {
+    text_label: Option<String>,
+    image_location: Option<String>,
+    num_positive: Option<f64>,
+    num_spositive: Option<f64>,
+    num_wpositive: Option<f64>,
+    num_total: Option<f64>,
+    positivity: Option<f64>,
+    /// Algorithm and version of the analysis layer the values come from
+    algorithm: Option<String>,
+    /// Values of the configured extra columns, by column name
+    columns: BTreeMap<String, Option<f64>>,
+    /// Extra text columns, in output order
+    text_columns: Vec<(String, String)>,
+    /// Values of the configured text columns, by column name, until they are added to the text columns
+    #[cfg(feature = "config")]
+    custom_text: BTreeMap<String, String>,
+    /// Declared area of the drawn region, None for exclusions and analysis-only regions
+    area: Option<f64>,
+    /// Declared area of the drawn region in square microns, None for exclusions and analysis-only regions
+    area_microns: Option<f64>,
+    /// Area of the drawn region in square pixels without the parts inside NegativeROA regions, only worked out
+    /// for the density score
+    #[cfg(feature = "geometry")]
+    effective_area: Option<f64>,
+    warnings: Vec<String>,
+}
+
+impl RegionInfo {
+    /// Make new RegionInfo with fully specified Options
+    fn new() -> Self {
+        Self { text_label: None, positivity: None, num_positive: None, num_spositive: None, num_wpositive: None, num_total: None, image_location: None, algorithm: None, columns: BTreeMap::new(), text_columns: Vec::new(), #[cfg(feature = "config")] custom_text: BTreeMap::new(), area: None, area_microns: None, #[cfg(feature = "geometry")] effective_area: None, warnings: Vec::new()}
+    }
+    
+    /// Get text label
+    pub fn text_label(&self) -> Option<&String> {
+        self.text_label.as_ref()
+    }
+    
+    /// Get positivity
+    pub fn positivity(&self) -> Option<f64> {
+        self.positivity
+    }
+    
+    /// Get total number of positive pixels, use 0 for missing data
+    pub fn get_total_positive(&self) -> f64 {
+        self.num_wpositive.unwrap_or(0.0)+self.num_positive.unwrap_or(0.0)+self.num_spositive.unwrap_or(0.0)
+    }
+    /// Get number pixels positive
+    pub fn num_positive(&self) -> Option<f64> {
+        self.num_positive
+    }
+
+    /// Get total number of non-background pixels
+    pub fn num_total(&self) -> Option<f64> {
+        self.num_total
+    }
+
+    /// Set new text label
+    fn set_text_label(&mut self, text_label: Option<String>) {
+        // Warn if over-write
+        if self.text_label.is_some() {
+            self.warn("Over-writing region text label");
+        }
+        self.text_label = text_label;
+    }
+    
+    /// Set number positive
+    fn set_num_positive(&mut self, num_pos: Option<f64>) {
+        // Warn if over-write
+        if let Some(_n_pos) = self.num_positive {
+            self.warn("Over-writing number positive for region");
+        }
+        self.num_positive = num_pos;
+    }
+
+    /// Set number total
+    fn set_num_total(&mut self, num_total: Option<f64>) {
+        // Warn if over-write
+        if let Some(_n_total) = self.num_total {
+            self.warn("Over-writing number total for region");
+        }
+        self.num_total = num_total;
+    }
+    /// Set positivity
+    fn set_positivity(&mut self, positivity: Option<f64>) {
+        // Warn if over-write
+        if let Some(_n_pos) = self.positivity {
+            self.warn("Over-writing positivity for region");
+        }
+        self.positivity = positivity;
+    }
+    
+    /* We don't use image location
+    fn image_location(&self) -> Option<&String> {
+        self.image_location.as_ref()
+    } 
+    */
+    
+    fn set_image_location(&mut self, image_location: Option<String>) {
+        self.image_location = image_location;
+    }
+    
+    /// Set number strong positive
+    fn set_num_spositive(&mut self, num_spositive: Option<f64>) {
+        self.num_spositive = num_spositive;
+    }
+    
+    /// Set number weak positive
+    fn set_num_wpositive(&mut self, num_wpositive: Option<f64>) {
+        self.num_wpositive = num_wpositive;
+    }
+    
+    pub fn num_spositive(&self) -> Option<f64> {
+        self.num_spositive
+    }
+    
+    pub fn num_wpositive(&self) -> Option<f64> {
+        self.num_wpositive
+    } 
+
+    /// Get the algorithm and version of the analysis layer
+    pub fn algorithm(&self) -> Option<&String> {
+        self.algorithm.as_ref()
+    }
+
+    /// Set the algorithm and version of the analysis layer
+    fn set_algorithm(&mut self, algorithm: String) {
+        self.algorithm = Some(algorithm);
+    }
+
+    /// Get the value of a configured extra column
+    pub fn column(&self, name: &str) -> Option<f64> {
+        self.columns.get(name).copied().flatten()
+    }
+
+    /// Set the value of a configured extra column
+    fn set_column(&mut self, name: &str, value: Option<f64>) {
+        if self.columns.insert(name.to_string(), value).is_some() {
+            self.warn(&format!("Over-writing {} for region", name));
+        }
+    }
+
+    /// True if the region has a value for a configured extra column
+    #[cfg(feature = "config")]
+    fn has_custom_value(&self, column: &CustomColumn) -> bool {
+        match column.kind {
+            ColumnType::Number => self.column(&column.name).is_some(),
+            ColumnType::Text => self.custom_text.get(&column.name).is_some_and(|v| !v.is_empty()),
+        }
+    }
+
+    /// Get the extra text columns, in output order
+    pub fn text_columns(&self) -> &[(String, String)] {
+        &self.text_columns
+    }
+
+    /// Add an extra text column
+    fn push_text_column(&mut self, name: &str, value: String) {
+        self.text_columns.push((name.to_string(), value));
+    }
+
+    /// Get the declared area of the drawn region
+    pub fn area(&self) -> Option<f64> {
+        self.area
+    }
+
+    /// Get the declared area of the drawn region in square microns
+    pub fn area_microns(&self) -> Option<f64> {
+        self.area_microns
+    }
+
+    /// Get the area of the drawn region without its excluded parts, in square pixels
+    #[cfg(feature = "geometry")]
+    pub fn effective_area(&self) -> Option<f64> {
+        self.effective_area
+    }
+
+    /// Implausible values a reviewer should look at, empty if none
+    pub fn qc_flags(&self) -> Vec<&'static str> {
+        let mut flags = Vec::new();
+        if self.positivity.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+            flags.push("positivity out of range");
+        }
+        if self.num_total.unwrap_or(0.0) == 0.0 && self.get_total_positive() > 0.0 {
+            flags.push("positives without total");
+        }
+        if [self.num_wpositive, self.num_positive, self.num_spositive, self.num_total].iter().flatten().any(|&n| n < 0.0) {
+            flags.push("negative count");
+        }
+        if self.area.is_some_and(|a| a > 0.0) && self.positivity.is_none() && self.num_total.is_none() {
+            flags.push("not analyzed");
+        }
+        flags
+    }
+
+    /// Get warnings raised while collecting information for this region
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Read a numeric attribute value, warning if it had to be cleaned up first
+    fn number(&mut self, value: &str, separator: DecimalSeparator) -> Option<f64> {
+        let (number, warning) = parse_number_with_warning(value, separator);
+        if let Some(warning) = warning {
+            self.warn(&warning);
+        }
+        number
+    }
+
+    /// Print a warning and remember it
+    fn warn(&mut self, message: &str) {
+        eprintln!("Warning: {}", message);
+        self.warnings.push(message.to_string());
+    }
+
+}
+
+/// What was found while processing a single file
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct FileSummary {
+    /// XML file processed
+    pub path: path::PathBuf,
+    /// Number of annotation layers
+    pub layers: usize,
+    /// Number of user-drawn (type 4) layers
+    pub drawn_layers: usize,
+    /// Number of analysis (type 3) layers read, only the selected one if a layer was chosen
+    pub analysis_layers: usize,
+    /// True if an analysis layer came from an algorithm counting weak and strong positives separately
+    pub intensity_bins: bool,
+    /// True if an analysis layer came from an algorithm reporting a positivity and a total, not only counting events
+    pub ratios: bool,
+    /// Number of user-drawn (type 4) regions
+    pub regions_drawn: usize,
+    /// Number of analysis (type 3) regions
+    pub regions_analyzed: usize,
+    /// Warnings raised while reading the file and its regions
+    pub warnings: Vec<String>,
+    /// Why the file could not be processed, None if it was
+    pub error: Option<String>,
+    /// Number of regions failing the configured QC rules
+    pub qc_failures: usize,
+    /// Keys of regions whose ID was already used in the same layer, as reported in the Region ID column
+    pub duplicate_ids: Vec<String>,
+}
+
+impl FileSummary {
+    /// Make an empty summary for `path`
+    pub fn new(path: &path::Path) -> Self {
+        Self { path: path.to_path_buf(), ..Self::default() }
+    }
+
+    /// Print a warning and remember it
+    pub(crate) fn warn(&mut self, message: String) {
+        eprintln!("{}", message);
+        self.warnings.push(message);
+    }
+
+    /// Record that the file could not be processed, the message is also kept as a warning
+    pub(crate) fn fail(&mut self, message: String) {
+        self.warn(message.clone());
+        self.error = Some(message);
+    }
+
+    /// Record a region reported under `key` because its ID was used before, with a warning
+    pub(crate) fn duplicate(&mut self, message: String, key: String) {
+        self.warn(message);
+        self.duplicate_ids.push(key);
+    }
+
+    /// True if the file could not be processed
+    pub fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// Collect information about each region in a parsed file, keyed by region ID
+/// `filepath` is only used to identify the file in warnings
+pub fn extract_regions(annotations: &Annotations, filepath: &path::Path) -> HashMap<String, RegionInfo> {
+    let mut summary = FileSummary::new(filepath);
+    extract_regions_with_summary(annotations, &Options::default(), &mut summary)
+}
+
+/// Collect information about each region in a parsed file, keyed by region ID
+/// Numbers are read as configured in `options`, layer and region counts and all warnings are recorded in `summary`
+pub fn extract_regions_with_summary(annotations: &Annotations, options: &Options, summary: &mut FileSummary) -> HashMap<String, RegionInfo> {
+    let filepath = summary.path.clone();
+    summary.layers += annotations.annotation.len();
+
+    // Collect information about each region
+    let mut regions_info: HashMap<String, RegionInfo> = HashMap::new();
+    
+    // Warn if we have more than one type 3 annotation layer
+    let mut analysis_layer = false;
+
+    // Which regions lie inside which, only worked out if wanted
+    #[cfg(feature = "geometry")]
+    let tree = options.nested_labels.then(|| crate::geometry::RegionTree::new(annotations));
+
+    // Process each annotation layer
+    for layer in &annotations.annotation {
+        // Flag regions whose declared area does not match their outline, if asked to
+        #[cfg(feature = "geometry")]
+        if let Some(tolerance) = options.area_tolerance {
+            for r in &layer.regions.region {
+                for problem in r.check_area(annotations.microns_per_pixel(), tolerance) {
+                    summary.warn(format!("In {}: Region {} of layer {}: {}", filepath.display(), r.id, &layer.id, problem));
+                }
+            }
+        }
+        match layer.annotation_type.as_str() {                
+            "4" => {
+                //dbg!(&layer);
+                // Type "4" are user-drawn regions
+                // We will extract the text label for each region identified by 'Id'
+                summary.drawn_layers += 1;
+                summary.regions_drawn += layer.regions.region.len();
+                let keys = region_keys(layer.regions.region.iter().map(|r| r.id.as_str()));
+                for (r, rid) in layer.regions.region.iter().zip(keys) {           
+                    //dbg!(&r);     
+                    if rid != r.id {
+                        summary.duplicate(format!("In {}: Duplicate region ID {} in layer {}, reported as {}", filepath.display(), r.id, &layer.id, rid), rid.clone());
+                    }
+                    // Find the correct region Id to store information                   
+                    let info = regions_info.entry(rid)
+                    // Or make a new region Id entry if missing
+                    .or_insert(RegionInfo::new());
+                    // Store the label
+                    #[cfg(feature = "geometry")]
+                    let label = tree.as_ref().and_then(|t| t.nested_label(r)).unwrap_or_else(|| r.label());
+                    #[cfg(not(feature = "geometry"))]
+                    let label = r.label();
+                    info.set_text_label(Some(label));
+                    if !r.is_negative() {
+                        info.area = r.area.as_deref().and_then(|a| parse_number(a, options.decimal_separator));
+                        info.area_microns = r.area_microns.as_deref().and_then(|a| parse_number(a, options.decimal_separator));
+                        #[cfg(feature = "geometry")]
+                        if options.scores.contains(&Score::Density) && r.region_type.is_closed() {
+                            info.effective_area = Some(annotations.effective_area(r));
+                        }
+                    }
+                    // Store the requested shape measurements
+                    #[cfg(feature = "geometry")]
+                    for measurement in &options.measurements {
+                        for (column, value) in measurement.columns().iter().zip(measurement.values(r, annotations)) {
+                            info.set_column(column, value);
+                        }
+                    }
+                }
+            },
+            "3" => {
+                // Pick the extraction rules for whichever algorithm made the layer
+                let algorithm = detect_algorithm(layer);
+                // Only use the selected analysis layer, if one was chosen
+                let selected = options.analysis_layer.as_ref().is_none_or(|s| layer.id == *s || layer.name == *s);
+                // but report every layer made by a chosen algorithm in its own columns
+                let own_columns = options.algorithm_columns.contains(&algorithm.kind);
+                // along with its specific results, if asked for
+                let results = options.results.contains(&algorithm.kind);
+                // and the classes asked for of a GENIE classifier
+                let classes = algorithm.kind == AlgorithmKind::Genie && !options.genie_classes.is_empty();
+                if !selected && !own_columns && !results && !classes {
+                    continue;
+                }
+                let patterns = algorithm.patterns(&options.attribute_patterns);
+                // Drawn region each analysis region belongs to
+                let input_ids = input_region_ids(annotations, layer, summary);
+                if own_columns {
+                    let columns = [&patterns.positivity, &patterns.num_wpositive, &patterns.num_positive, &patterns.num_spositive, &patterns.num_total];
+                    let columns: Vec<(&str, Vec<String>)> = algorithm.kind.columns().iter().zip(columns).map(|(name, p)| (*name, p.clone())).collect();
+                    extract_pattern_columns(layer, &input_ids, &columns, options, &mut regions_info);
+                }
+                if results {
+                    let columns: Vec<(&str, Vec<String>)> = algorithm.kind.result_columns().iter()
+                        .map(|(name, names)| (*name, names.iter().map(|n| n.to_string()).collect()))
+                        .collect();
+                    extract_pattern_columns(layer, &input_ids, &columns, options, &mut regions_info);
+                }
+                if classes {
+                    extract_pattern_columns(layer, &input_ids, &options.genie_classes.patterns(), options, &mut regions_info);
+                }
+                if !selected {
+                    continue;
+                }
+                summary.analysis_layers += 1;
+                if !algorithm.recognizes(layer, &patterns) {
+                    summary.warn(format!("In {}: No extractor recognizes analysis layer {} ({}), add attribute patterns or custom columns for its results", filepath.display(), &layer.id, &layer.name));
+                }
+                // Algorithms without a positivity of their own have it added up from percentages
+                let percentages = algorithm.positive_percentages();
+                summary.intensity_bins |= algorithm.has_intensity_bins();
+                summary.ratios |= !algorithm.counts_only();
+                // Extra columns do not depend on the attributes below being present
+                #[cfg(feature = "config")]
+                extract_columns(layer, &input_ids, options, &mut regions_info);
+                // Ensure an attribute header exists
+                if let Some(attribute_header) = &layer.regions.region_attribute_headers.attribute_header {
+                    // Locate specific attributes of interest
+                    let positivity_match = find_match(&patterns.positivity, attribute_header, |a| &a.name);
+                    let num_wpositive_match = find_match(&patterns.num_wpositive, attribute_header, |a| &a.name);
+                    let num_positive_match = find_match(&patterns.num_positive, attribute_header, |a| &a.name);
+                    let num_spositive_match = find_match(&patterns.num_spositive, attribute_header, |a| &a.name);
+                    let num_total_match = find_match(&patterns.num_total, attribute_header, |a| &a.name);
+                    // Log which pattern found each attribute
+                    for (column, found) in [("positivity", positivity_match), ("nwp", num_wpositive_match), ("np", num_positive_match), ("nsp", num_spositive_match), ("ntotal", num_total_match)] {
+                        if let Some((header, pattern)) = found.filter(|_| options.verbose) {
+                            eprintln!("In {}: layer {} {} is \"{}\" (matched \"{}\")", filepath.display(), &layer.id, column, header.name, pattern);
+                        }
+                    }
+                    let positivity_attrib = positivity_match.map(|(a, _)| a);
+                    let num_wpositive_attrib = num_wpositive_match.map(|(a, _)| a);
+                    let num_positive_attrib = num_positive_match.map(|(a, _)| a);
+                    let num_spositive_attrib = num_spositive_match.map(|(a, _)| a);
+                    let num_total_attrib = num_total_match.map(|(a, _)| a);
+                    // If any element is missing, we will skip the file
+                    // Algorithms only counting events need neither a positivity nor a total
+                    if positivity_attrib.is_none() && !algorithm.counts_only() && percentages.is_empty() {
+                        summary.warn(format!("Missing positivity in {}", filepath.display()));
+                        continue;
+                    }
+                    if num_positive_attrib.is_none() && algorithm.counts_positives() {
+                        summary.warn(format!("Missing number positive in {}", filepath.display()));
+                        continue;
+                    }
+                    // Only some algorithms count weak and strong positives
+                    if num_wpositive_attrib.is_none() && algorithm.has_intensity_bins() {
+                        summary.warn(format!("Missing number weak positive in {}", filepath.display()));
+                        continue;
+                    }
+                    if num_spositive_attrib.is_none() && algorithm.has_intensity_bins() {
+                        summary.warn(format!("Missing number strong positive in {}", filepath.display()));
+                        continue;
+                    }
+                    if num_total_attrib.is_none() && algorithm.requires_total() {
+                        summary.warn(format!("Missing number total in {}", filepath.display()));
+                        continue;
+                    } 
+                    // By now we know all selected variables are valid so unwrap them
+                    let positivity_name=positivity_attrib.map(|a| a.id.clone());
+                    let positivity_factor = positivity_match.map_or(1.0, |(_, pattern)| positivity_scale(pattern));
+                    let num_positive_name=num_positive_attrib.map(|a| a.id.clone());
+                    let num_wpositive_name=num_wpositive_attrib.map(|a| a.id.clone());
+                    let num_spositive_name=num_spositive_attrib.map(|a| a.id.clone());
+                    let num_total_name=num_total_attrib.map(|a| a.id.clone());
+                    // Warn if there is more than one type 3 layer
+                    if analysis_layer {
+                        summary.warn(format!("Warning! Multiple type 3 analysis layers found - last one will be used, choose one with --layer. Currently processing layer id {} ({})", &layer.id, &layer.name));
+                    } else {
+                        analysis_layer=true;
+                    }
+                    summary.regions_analyzed += layer.regions.region.len();
+                    // Now scan through each region looking for specified attributes and store the value
+                    // Get the region IDs to be used as the keys
+                    let keys = region_keys(input_ids.iter().map(String::as_str));
+                    for ((r, rid), input_id) in layer.regions.region.iter().zip(keys).zip(&input_ids) {
+                        //dbg!(&r);
+                        if rid != *input_id {
+                            summary.duplicate(format!("In {}: Duplicate input region ID {} in analysis layer {}, reported as {}", filepath.display(), input_id, &layer.id, rid), rid.clone());
+                        }
+                        regions_info.entry(rid.clone()).or_insert(RegionInfo::new()).set_algorithm(algorithm.to_string());
+                        // Get image location for this region (stripped down to just the filename)
+                        if let Some(loc) = path::Path::new(r.image_location.as_deref().unwrap_or("")).file_name() {
+                            // Try to convert OsStr to String
+                            if let Some(lp) = loc.to_str() {
+                                // Start by locating a region info for this region
+                                regions_info.entry(rid.clone())
+                                // or alternatively make a new entry
+                                .or_insert(RegionInfo::new())
+                                // Convert result into String and return "" if unable
+                                .set_image_location(Some(lp.to_string()));
+                            }                                
+                        }
+                        // Check first if there exists a Region Attributes section for this region
+                        if let Some(region_attrib) = &r.attributes.attribute {
+                            // Now search through each atttribute to find the positivity attribute
+                            for attrib in region_attrib {
+                                if Some(&attrib.name)==positivity_name.as_ref() {
+                                    // Find the correct region Id to store information, or make a new entry if missing
+                                    let info = regions_info.entry(rid.clone()).or_insert(RegionInfo::new());
+                                    // Convert result into a number, missing if unable
+                                    let number = info.number(&attrib.value, options.decimal_separator);
+                                    info.set_positivity(number.map(|n| n * positivity_factor));
+                                }
+                                if Some(&attrib.name)==num_positive_name.as_ref() {
+                                    // Find the correct region Id to store information, or make a new entry if missing
+                                    let info = regions_info.entry(rid.clone()).or_insert(RegionInfo::new());
+                                    // Convert result into a number, missing if unable
+                                    let number = info.number(&attrib.value, options.decimal_separator);
+                                    info.set_num_positive(number);
+                                }
+                                if Some(&attrib.name)==num_wpositive_name.as_ref() {
+                                    // Find the correct region Id to store information, or make a new entry if missing
+                                    let info = regions_info.entry(rid.clone()).or_insert(RegionInfo::new());
+                                    // Convert result into a number, missing if unable
+                                    let number = info.number(&attrib.value, options.decimal_separator);
+                                    info.set_num_wpositive(number);
+                                }
+                                if Some(&attrib.name)==num_spositive_name.as_ref() {
+                                    // Find the correct region Id to store information, or make a new entry if missing
+                                    let info = regions_info.entry(rid.clone()).or_insert(RegionInfo::new());
+                                    // Convert result into a number, missing if unable
+                                    let number = info.number(&attrib.value, options.decimal_separator);
+                                    info.set_num_spositive(number);
+                                }
+                                if Some(&attrib.name)==num_total_name.as_ref() {
+                                    // Find the correct region Id to store information, or make a new entry if missing
+                                    let info = regions_info.entry(rid.clone()).or_insert(RegionInfo::new());
+                                    // Convert result into a number, missing if unable
+                                    let number = info.number(&attrib.value, options.decimal_separator);
+                                    info.set_num_total(number);
+                                }
+                            }                                
+                        }                                
+                    }
+                } else {
+                    summary.warn(format!("In {}: Type 3 annotation layer {} is missing Region Attribute header, matching region attribute names instead", filepath.display(), &layer.id));
+                    // Warn if there is more than one type 3 layer
+                    if analysis_layer {
+                        summary.warn(format!("Warning! Multiple type 3 analysis layers found - last one will be used, choose one with --layer. Currently processing layer id {} ({})", &layer.id, &layer.name));
+                    } else {
+                        analysis_layer=true;
+                    }
+                    summary.regions_analyzed += layer.regions.region.len();
+                    extract_by_attribute_name(layer, &input_ids, options, &patterns, &algorithm.to_string(), &mut regions_info, summary);
+                }
+                if !percentages.is_empty() {
+                    derive_positivity(layer, &input_ids, &percentages, options, &mut regions_info);
+                }
+            },
+            // Ignore other annotation types
+            &_ => {},
+        }            
+    }
+    if let Some(selected) = &options.analysis_layer {
+        if !annotations.annotation.iter().any(|l| l.annotation_type == "3" && (l.id == *selected || l.name == *selected)) {
+            summary.warn(format!("In {}: Analysis layer {} not found", filepath.display(), selected));
+        }
+    }
+
+    // Scores need the final counts, so are worked out last
+    for info in regions_info.values_mut() {
+        for score in &options.scores {
+            for (column, value) in score.columns().iter().zip(score.values(info, options, annotations.microns_per_pixel())) {
+                // Counts that do not add up to the positivity hint at mis-mapped attributes
+                if let Some(delta) = value.filter(|d| *score == Score::PositivityDelta && d.abs() > options.positivity_tolerance) {
+                    info.warn(&format!("Positivity recomputed from the counts differs from the reported positivity by {}, check the attribute mapping", delta));
+                }
+                info.set_column(column, value);
+            }
+        }
+    }
+
+    #[cfg(feature = "config")]
+    for column in options.columns.iter().filter(|c| c.required) {
+        let missing = regions_info.values().filter(|info| !info.has_custom_value(column)).count();
+        if missing > 0 {
+            summary.fail(format!("In {}: Required column {} is missing for {} of {} regions", filepath.display(), column.name, missing, regions_info.len()));
+        }
+    }
+
+    // Text columns are written in the order of Options::text_column_names
+    #[cfg(feature = "config")]
+    for info in regions_info.values_mut() {
+        for column in options.columns.iter().filter(|c| c.kind == ColumnType::Text) {
+            let value = info.custom_text.remove(&column.name).unwrap_or_default();
+            info.push_text_column(&column.name, value);
+        }
+    }
+    if options.qc {
+        for info in regions_info.values_mut() {
+            let flags = info.qc_flags().join(";");
+            info.push_text_column(QC_COLUMN, flags);
+        }
+    }
+
+    if options.qc_rules.is_active() {
+        for info in regions_info.values_mut() {
+            let failed = options.qc_rules.region_failures(info, annotations.microns_per_pixel());
+            if !failed.is_empty() {
+                summary.qc_failures += 1;
+            }
+            for (column, value) in QC_RULE_COLUMNS.iter().zip(qc_rule_values(&failed)) {
+                info.push_text_column(column, value);
+            }
+        }
+    }
+
+    // Collect warnings raised for individual regions
+    for (rid, info) in &regions_info {
+        for w in info.warnings() {
+            summary.warnings.push(format!("Region {}: {}", rid, w));
+        }
+    }
+
+    regions_info
+}
+
+/// Region of a user-drawn layer each analysis region of a layer belongs to, in order
+/// Older exports lack `InputRegionId`; with the `geometry` feature those regions are matched to the drawn
+/// region they overlap most instead.
+fn input_region_ids(annotations: &Annotations, layer: &Annotation, summary: &mut FileSummary) -> Vec<String> {
+    // Only indexed once a region needs matching, current exports always have InputRegionId
+    #[cfg(feature = "geometry")]
+    let mut index = None;
+    #[cfg(not(feature = "geometry"))]
+    let _ = annotations;
+    layer.regions.region.iter().map(|r| {
+        if let Some(id) = &r.input_region_id {
+            return id.clone();
+        }
+        #[cfg(feature = "geometry")]
+        if let Some((_, drawn)) = index.get_or_insert_with(|| crate::geometry::RegionIndex::drawn(annotations)).best_match(r) {
+            summary.warn(format!("In {}: Region {} of analysis layer {} has no InputRegionId, matched to drawn region {} by position", summary.path.display(), r.id, &layer.id, drawn.id));
+            return drawn.id.clone();
+        }
+        summary.warn(format!("In {}: Region {} of analysis layer {} has no InputRegionId, reported under its own ID", summary.path.display(), r.id, &layer.id));
+        r.id.clone()
+    }).collect()
+}
+
+/// Key for each region ID of a layer, in order
+/// Repeated IDs get a `#2`, `#3`, ... suffix so the regions are reported separately instead of merged.
+pub(crate) fn region_keys<'a>(ids: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    ids.into_iter().map(|id| {
+        let count = seen.entry(id).or_insert(0);
+        *count += 1;
+        if *count == 1 { id.to_string() } else { format!("{}#{}", id, count) }
+    }).collect()
+}
+
+/// Fallback for analysis layers without a Region Attribute header
+/// Results are found by the start of the region attribute names themselves, with the value taken from
+/// the Value attribute or, if that is empty, from after the `=` in the name (e.g. `Positivity = 0.43`).
+fn extract_by_attribute_name(layer: &Annotation, input_ids: &[String], options: &Options, patterns: &AttributePatterns, algorithm: &str, regions_info: &mut HashMap<String, RegionInfo>, summary: &mut FileSummary) {
+    // Get the region IDs to be used as the keys
+    let keys = region_keys(input_ids.iter().map(String::as_str));
+    for ((r, rid), input_id) in layer.regions.region.iter().zip(keys).zip(input_ids) {
+        if rid != *input_id {
+            summary.duplicate(format!("In {}: Duplicate input region ID {} in analysis layer {}, reported as {}", summary.path.display(), input_id, &layer.id, rid), rid.clone());
+        }
+        let info = regions_info.entry(rid).or_insert(RegionInfo::new());
+        info.set_algorithm(algorithm.to_string());
+        // Get image location for this region (stripped down to just the filename)
+        if let Some(loc) = path::Path::new(r.image_location.as_deref().unwrap_or("")).file_name().and_then(|l| l.to_str()) {
+            info.set_image_location(Some(loc.to_string()));
+        }
+        for attrib in r.attributes.attribute.iter().flatten() {
+            let value = if attrib.value.trim().is_empty() {
+                attrib.name.split_once('=').map(|(_, v)| v).unwrap_or("")
+            } else {
+                &attrib.value
+            };
+            let matches = |column: &[String]| column.iter().any(|p| matches_pattern(&attrib.name, p));
+            if let Some(pattern) = patterns.positivity.iter().find(|p| matches_pattern(&attrib.name, p)) {
+                let number = info.number(value, options.decimal_separator);
+                info.set_positivity(number.map(|n| n * positivity_scale(pattern)));
+            } else if matches(&patterns.num_wpositive) {
+                let number = info.number(value, options.decimal_separator);
+                info.set_num_wpositive(number);
+            } else if matches(&patterns.num_positive) {
+                let number = info.number(value, options.decimal_separator);
+                info.set_num_positive(number);
+            } else if matches(&patterns.num_spositive) {
+                let number = info.number(value, options.decimal_separator);
+                info.set_num_spositive(number);
+            } else if matches(&patterns.num_total) {
+                let number = info.number(value, options.decimal_separator);
+                info.set_num_total(number);
+            }
+        }
+    }
+}
+
+/// Set the positivity of the regions of a layer that have none to the sum of the `percentages` read from it, e.g.
+/// the shares of 1+, 2+ and 3+ cells, missing unless all of them are found
+fn derive_positivity(layer: &Annotation, input_ids: &[String], percentages: &[(&str, Vec<String>)], options: &Options, regions_info: &mut HashMap<String, RegionInfo>) {
+    extract_pattern_columns(layer, input_ids, percentages, options, regions_info);
+    for rid in region_keys(input_ids.iter().map(String::as_str)) {
+        if let Some(info) = regions_info.get_mut(&rid).filter(|info| info.positivity().is_none()) {
+            let sum: Option<f64> = percentages.iter().map(|(name, _)| info.column(name)).sum();
+            info.set_positivity(sum.map(|s| s / 100.0));
+        }
+    }
+}
+
+/// Store the results of an analysis layer in extra columns, each given by its name and attribute name patterns
+/// Results are found through the attribute headers like for the main columns, or by attribute name without
+/// headers. Problems with the layer are only warned about when it is read for the main columns.
+/// Positivity columns are scaled to fractions when read from a percentage.
+fn extract_pattern_columns(layer: &Annotation, input_ids: &[String], columns: &[(&str, Vec<String>)], options: &Options, regions_info: &mut HashMap<String, RegionInfo>) {
+    // Attribute header ID of each result with the pattern that found it, if the layer has headers
+    let header_ids: Option<Vec<Option<(String, &str)>>> = layer.regions.region_attribute_headers.attribute_header.as_ref()
+        .map(|headers| columns.iter().enumerate().map(|(i, (_, column))| {
+            let own: Vec<&AttributeHeader> = headers.iter().filter(|h| best_column(&h.name, columns).is_some_and(|(c, _)| c == i)).collect();
+            find_match(column, &own, |a| &a.name).map(|(a, pattern)| (a.id.clone(), pattern))
+        }).collect());
+    let keys = region_keys(input_ids.iter().map(String::as_str));
+    for (r, rid) in layer.regions.region.iter().zip(keys) {
+        let info = regions_info.entry(rid).or_insert(RegionInfo::new());
+        for attrib in r.attributes.attribute.iter().flatten() {
+            let found = match &header_ids {
+                Some(ids) => ids.iter().enumerate().find_map(|(i, id)| id.as_ref().filter(|(id, _)| *id == attrib.name).map(|(_, pattern)| (i, *pattern))),
+                None => best_column(&attrib.name, columns),
+            };
+            let Some((i, pattern)) = found else { continue };
+            let value = if attrib.value.trim().is_empty() && header_ids.is_none() {
+                attrib.name.split_once('=').map(|(_, v)| v).unwrap_or("")
+            } else {
+                &attrib.value
+            };
+            let number = info.number(value, options.decimal_separator);
+            let factor = if columns[i].0.ends_with("_positivity") { positivity_scale(pattern) } else { 1.0 };
+            info.set_column(columns[i].0, number.map(|n| n * factor));
+        }
+    }
+}
+
+/// Column of `columns` an attribute name belongs to, with the pattern it matched
+/// Where patterns of several columns match, the longest decides, so `Tumor Area (%)` belongs to the column
+/// read from `Tumor Area (%)` rather than to the one read from `Tumor Area`.
+fn best_column<'c>(name: &str, columns: &'c [(&str, Vec<String>)]) -> Option<(usize, &'c str)> {
+    let length = |pattern: &str| pattern.chars().filter(|c| !c.is_whitespace()).count();
+    columns.iter().enumerate()
+        .flat_map(|(i, (_, patterns))| patterns.iter().filter(|p| matches_pattern(name, p)).map(move |p| (i, p.as_str())))
+        .max_by_key(|&(i, pattern)| (length(pattern), std::cmp::Reverse(i)))
+}
+
+/// Collect the configured extra columns from an analysis layer
+/// Each column takes the first attribute header whose name matches its regular expression, or without
+/// a header the region attributes whose names match.
+#[cfg(feature = "config")]
+fn extract_columns(layer: &Annotation, input_ids: &[String], options: &Options, regions_info: &mut HashMap<String, RegionInfo>) {
+    let headers = layer.regions.region_attribute_headers.attribute_header.as_deref();
+    for column in &options.columns {
+        // Attribute name to look for in each region, None to match the names themselves
+        let attribute_name = match headers {
+            Some(headers) => match headers.iter().find(|h| column.pattern.is_match(&h.name)) {
+                Some(header) => Some(header.id.as_str()),
+                None => continue,
+            },
+            None => None,
+        };
+        let keys = region_keys(input_ids.iter().map(String::as_str));
+        for (r, rid) in layer.regions.region.iter().zip(keys) {
+            for attrib in r.attributes.attribute.iter().flatten() {
+                let value = match attribute_name {
+                    Some(name) if attrib.name == name => attrib.value.as_str(),
+                    None if column.pattern.is_match(&attrib.name) && attrib.value.trim().is_empty() => attrib.name.split_once('=').map(|(_, v)| v).unwrap_or(""),
+                    None if column.pattern.is_match(&attrib.name) => attrib.value.as_str(),
+                    _ => continue,
+                };
+                let info = regions_info.entry(rid.clone()).or_insert(RegionInfo::new());
+                match column.kind {
+                    ColumnType::Number => {
+                        let number = info.number(value, options.decimal_separator);
+                        info.set_column(&column.name, number);
+                    },
+                    ColumnType::Text => {
+                        info.custom_text.insert(column.name.clone(), value.trim().to_string());
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// One output row describing a single region of a single file
+/// Serializes with the field names below, missing values become `null`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RegionRecord {
+    /// XML file the region was read from
+    pub filename: String,
+    /// Slide the XML file annotates
+    pub slide_name: String,
+    /// Region ID
+    pub region_id: String,
+    /// Text label of the user-drawn region
+    pub text_label: String,
+    pub positivity: Option<f64>,
+    pub num_wpositive: Option<f64>,
+    pub num_positive: Option<f64>,
+    pub num_spositive: Option<f64>,
+    /// Sum of weak, normal and strong positive counts
+    pub num_all_positive: f64,
+    pub num_total: Option<f64>,
+    /// Algorithm and version of the analysis layer, empty without one
+    pub algorithm: String,
+    /// Configured extra columns, in the order they are configured
+    pub columns: Vec<(String, Option<f64>)>,
+    /// Extra text columns written after the numeric ones, such as the QC flags
+    pub text_columns: Vec<(String, String)>,
+}
+
+/// Slide name for an XML file, assumed to match the file name with an `.svs` extension
+/// A `.gz` extension is dropped first, so `case1.xml.gz` annotates `case1.svs`
+pub fn slide_name(filepath: &path::Path) -> String {
+    let mut slidename = filepath.to_path_buf();
+    if slidename.extension().is_some_and(|e| e.eq_ignore_ascii_case("gz")) {
+        slidename.set_extension("");
+    }
+    // Sedeen keeps its annotations next to the slide as `<slide>.session.xml`
+    let name = slidename.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    match name.len().checked_sub(".session.xml".len()).filter(|&n| n > 0 && name[n..].eq_ignore_ascii_case(".session.xml")) {
+        Some(n) => slidename.set_file_name(format!("{}.svs", &name[..n])),
+        None => {
+            slidename.set_extension("svs");
+        },
+    }
+    slidename.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// Turn the collected region information for one file into output records
+pub fn region_records(filename: &str, slidename: &str, regions_info: &HashMap<String, RegionInfo>) -> Vec<RegionRecord> {
+    region_records_with_columns(filename, slidename, regions_info, &[])
+}
+
+/// Turn the collected region information for one file into output records, including the extra `columns`
+pub fn region_records_with_columns(filename: &str, slidename: &str, regions_info: &HashMap<String, RegionInfo>, columns: &[&str]) -> Vec<RegionRecord> {
+    regions_info.iter().map(|(rid, info)| RegionRecord {
+        filename: filename.to_string(),
+        slide_name: slidename.to_string(),
+        region_id: rid.clone(),
+        text_label: info.text_label().map(|t| t.trim().to_string()).unwrap_or_default(),
+        positivity: info.positivity(),
+        num_wpositive: info.num_wpositive(),
+        num_positive: info.num_positive(),
+        num_spositive: info.num_spositive(),
+        num_all_positive: info.get_total_positive(),
+        num_total: info.num_total(),
+        algorithm: info.algorithm().cloned().unwrap_or_default(),
+        columns: columns.iter().map(|name| (name.to_string(), info.column(name))).collect(),
+        text_columns: info.text_columns().to_vec(),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::algorithm::GenieClasses;
+    use crate::options::Options;
+    use crate::parse::parse_xml_str;
+    use super::{extract_pattern_columns, extract_regions, extract_regions_with_summary, input_region_ids, region_keys, FileSummary};
+
+    /// GENIE columns of the only region of a layer
+    fn genie_columns(layer: &str) -> (Option<f64>, Option<f64>) {
+        let xml = format!("<Annotations><Annotation Id=\"2\" Type=\"3\">{}</Annotation></Annotations>", layer);
+        let annotations = parse_xml_str(&xml).unwrap();
+        let classes = GenieClasses::parse_list("Tumor").unwrap();
+        let mut regions_info = HashMap::new();
+        extract_pattern_columns(&annotations.annotation[0], &[String::from("1")], &classes.patterns(), &Options::default(), &mut regions_info);
+        let info = &regions_info["1"];
+        (info.column("genie_tumor_area"), info.column("genie_tumor_percent"))
+    }
+
+    #[test]
+    fn genie_percent_is_not_read_as_area_with_headers() {
+        let layer = r#"<Regions>
+            <RegionAttributeHeaders>
+                <AttributeHeader Id="1" Name="Tumor Area (%)"/>
+                <AttributeHeader Id="2" Name="Tumor Area (mm2)"/>
+            </RegionAttributeHeaders>
+            <Region Id="1"><Attributes>
+                <Attribute Name="1" Id="1" Value="40"/>
+                <Attribute Name="2" Id="2" Value="0.004"/>
+            </Attributes></Region>
+        </Regions>"#;
+        assert_eq!(genie_columns(layer), (Some(0.004), Some(40.0)));
+    }
+
+    #[test]
+    fn genie_percent_is_not_read_as_area_without_headers() {
+        let layer = r#"<Regions>
+            <Region Id="1"><Attributes>
+                <Attribute Name="Tumor Area (%)" Id="1" Value="40"/>
+                <Attribute Name="Tumor Area (mm2)" Id="2" Value="0.004"/>
+            </Attributes></Region>
+        </Regions>"#;
+        assert_eq!(genie_columns(layer), (Some(0.004), Some(40.0)));
+        let layer = r#"<Regions><Region Id="1"><Attributes><Attribute Name="Tumor Area (%)" Id="1" Value="40"/></Attributes></Region></Regions>"#;
+        assert_eq!(genie_columns(layer), (None, Some(40.0)));
+    }
+
+    #[test]
+    fn membrane_positivity_is_the_share_of_positive_cells() {
+        let xml = r#"<Annotations><Annotation Id="2" Name="Membrane v9" Type="3"><Regions>
+            <RegionAttributeHeaders>
+                <AttributeHeader Id="1" Name="%(3+) Cells"/>
+                <AttributeHeader Id="2" Name="%(2+) Cells"/>
+                <AttributeHeader Id="3" Name="%(1+) Cells"/>
+                <AttributeHeader Id="4" Name="(%) Complete"/>
+                <AttributeHeader Id="5" Name="Num Complete"/>
+                <AttributeHeader Id="6" Name="Num Cells"/>
+            </RegionAttributeHeaders>
+            <Region Id="1" InputRegionId="1"><Attributes>
+                <Attribute Name="1" Id="1" Value="10"/>
+                <Attribute Name="2" Id="2" Value="20"/>
+                <Attribute Name="3" Id="3" Value="5"/>
+                <Attribute Name="4" Id="4" Value="80"/>
+                <Attribute Name="5" Id="5" Value="160"/>
+                <Attribute Name="6" Id="6" Value="200"/>
+            </Attributes></Region>
+        </Regions></Annotation></Annotations>"#;
+        let regions = extract_regions(&parse_xml_str(xml).unwrap(), std::path::Path::new("membrane.xml"));
+        let info = &regions["1"];
+        assert!((info.positivity().unwrap() - 0.35).abs() < 1e-12);
+        assert_eq!((info.num_positive(), info.num_total()), (None, Some(200.0)));
+    }
+
+    #[test]
+    fn analysis_regions_without_input_region_id() {
+        let xml = r#"<Annotations>
+            <Annotation Id="1" Type="4"><Regions>
+                <Region Id="5"><Vertices><Vertex X="0" Y="0"/><Vertex X="10" Y="0"/><Vertex X="10" Y="10"/><Vertex X="0" Y="10"/></Vertices></Region>
+            </Regions></Annotation>
+            <Annotation Id="2" Type="3"><Regions>
+                <Region Id="1" InputRegionId="5"/>
+                <Region Id="2"><Vertices><Vertex X="1" Y="1"/><Vertex X="9" Y="1"/><Vertex X="9" Y="9"/><Vertex X="1" Y="9"/></Vertices></Region>
+                <Region Id="3"><Vertices><Vertex X="50" Y="50"/><Vertex X="60" Y="50"/><Vertex X="60" Y="60"/></Vertices></Region>
+            </Regions></Annotation>
+        </Annotations>"#;
+        let annotations = parse_xml_str(xml).unwrap();
+        let mut summary = FileSummary::new(std::path::Path::new("old.xml"));
+        let ids = input_region_ids(&annotations, &annotations.annotation[1], &mut summary);
+        // Without the geometry feature there is nothing to match against
+        let matched = if cfg!(feature = "geometry") { "5" } else { "2" };
+        assert_eq!(ids, ["5", matched, "3"]);
+        assert_eq!(summary.warnings.len(), 2);
+        assert!(summary.warnings[1].ends_with("Region 3 of analysis layer 2 has no InputRegionId, reported under its own ID"));
+    }
+
+    #[cfg(feature = "geometry")]
+    #[test]
+    fn declared_areas_are_only_checked_with_a_tolerance() {
+        use super::extract_regions_with_summary;
+        // The outline encloses 100 square pixels, or 25 square microns
+        let xml = r#"<Annotations MicronsPerPixel="0.5"><Annotation Id="1" Type="4"><Regions>
+            <Region Id="1" Area="150" AreaMicrons="25.1"><Vertices><Vertex X="0" Y="0"/><Vertex X="10" Y="0"/><Vertex X="10" Y="10"/><Vertex X="0" Y="10"/></Vertices></Region>
+        </Regions></Annotation></Annotations>"#;
+        let annotations = parse_xml_str(xml).unwrap();
+        let warnings = |area_tolerance| {
+            let options = Options { area_tolerance, ..Options::default() };
+            let mut summary = FileSummary::new(std::path::Path::new("areas.xml"));
+            extract_regions_with_summary(&annotations, &options, &mut summary);
+            summary.warnings
+        };
+        assert!(warnings(None).is_empty());
+        assert_eq!(warnings(Some(0.01)), ["In areas.xml: Region 1 of layer 1: Area is 150 but the outline encloses 100.0 square pixels"]);
+        assert_eq!(warnings(Some(0.001)).len(), 2);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn custom_columns_are_read_and_required_ones_checked() {
+        use crate::patterns::{ColumnType, CustomColumn};
+        use super::extract_regions_with_summary;
+        let xml = r#"<Annotations><Annotation Id="2" Type="3"><Regions>
+            <RegionAttributeHeaders>
+                <AttributeHeader Id="1" Name="Iavg (0-255)"/>
+                <AttributeHeader Id="2" Name="Class"/>
+            </RegionAttributeHeaders>
+            <Region Id="1" InputRegionId="1"><Attributes>
+                <Attribute Name="1" Id="1" Value="181.5"/>
+                <Attribute Name="2" Id="2" Value=" Tumor "/>
+            </Attributes></Region>
+            <Region Id="2" InputRegionId="2"><Attributes>
+                <Attribute Name="1" Id="1" Value="90"/>
+            </Attributes></Region>
+        </Regions></Annotation></Annotations>"#;
+        let annotations = parse_xml_str(xml).unwrap();
+        let column = |name: &str, pattern: &str, kind, required| CustomColumn { name: String::from(name), pattern: regex::Regex::new(pattern).unwrap(), kind, required };
+        let extract = |required| {
+            let options = Options { columns: vec![column("avg_intensity", "^Iavg", ColumnType::Number, false), column("tissue_class", "^Class", ColumnType::Text, required)], ..Options::default() };
+            let mut summary = FileSummary::new(std::path::Path::new("classes.xml"));
+            let regions = extract_regions_with_summary(&annotations, &options, &mut summary);
+            (regions, summary)
+        };
+        let (regions, summary) = extract(false);
+        assert!(!summary.failed());
+        assert_eq!((regions["1"].column("avg_intensity"), regions["2"].column("avg_intensity")), (Some(181.5), Some(90.0)));
+        // Regions without a text value get an empty one so the columns line up
+        assert_eq!(regions["1"].text_columns(), [(String::from("tissue_class"), String::from("Tumor"))]);
+        assert_eq!(regions["2"].text_columns(), [(String::from("tissue_class"), String::new())]);
+        let (_, summary) = extract(true);
+        assert!(summary.failed());
+        assert_eq!(summary.error.as_deref(), Some("In classes.xml: Required column tissue_class is missing for 1 of 2 regions"));
+    }
+
+
+    /// Positive Pixel Count layer with a region for each of `input_ids`, all with `positivity`
+    fn ppc_layer(id: &str, name: &str, input_ids: &[&str], positivity: f64) -> String {
+        let values = [positivity, 0.0, positivity * 100.0, 0.0, 100.0];
+        let attributes: String = values.iter().enumerate().map(|(i, value)| format!(r#"<Attribute Name="{}" Id="{}" Value="{}"/>"#, i + 1, i + 1, value)).collect();
+        let regions: String = input_ids.iter().enumerate()
+            .map(|(i, input_id)| format!(r#"<Region Id="{}" InputRegionId="{}"><Attributes>{}</Attributes></Region>"#, i + 1, input_id, attributes))
+            .collect();
+        format!(r#"<Annotation Id="{}" Name="{}" Type="3"><Regions><RegionAttributeHeaders>
+            <AttributeHeader Id="1" Name="Positivity = Np / (Nwp + Np + Nsp)"/>
+            <AttributeHeader Id="2" Name="Nwp = Number of Weak Positive"/>
+            <AttributeHeader Id="3" Name="Np = Number of Positive"/>
+            <AttributeHeader Id="4" Name="Nsp = Number of Strong Positive"/>
+            <AttributeHeader Id="5" Name="NTotal = Number of Total"/>
+            </RegionAttributeHeaders>{}</Regions></Annotation>"#, id, name, regions)
+    }
+
+    #[test]
+    fn repeated_region_ids_are_reported_separately() {
+        assert_eq!(region_keys(["1", "1", "2", "1"]), ["1", "1#2", "2", "1#3"]);
+        let xml = format!(r#"<Annotations>
+            <Annotation Id="1" Type="4"><Regions><Region Id="1" Text="Tumor"/><Region Id="1" Text="Stroma"/><Region Id="2" Text="Tumor"/></Regions></Annotation>
+            {}</Annotations>"#, ppc_layer("2", "Positive Pixel Count v9", &["1", "1", "2"], 0.5));
+        let mut summary = FileSummary::new(std::path::Path::new("repeated.xml"));
+        let regions = extract_regions_with_summary(&parse_xml_str(&xml).unwrap(), &Options::default(), &mut summary);
+        let mut labels: Vec<(&str, Option<&str>, Option<f64>)> = regions.iter().map(|(key, info)| (key.as_str(), info.text_label().map(String::as_str), info.positivity())).collect();
+        labels.sort_by_key(|(key, _, _)| *key);
+        assert_eq!(labels, [("1", Some("Tumor"), Some(0.5)), ("1#2", Some("Stroma"), Some(0.5)), ("2", Some("Tumor"), Some(0.5))]);
+        // Each repeat is warned about once per layer
+        assert_eq!(summary.duplicate_ids, ["1#2", "1#2"]);
+        assert_eq!(summary.warnings, [
+            "In repeated.xml: Duplicate region ID 1 in layer 1, reported as 1#2",
+            "In repeated.xml: Duplicate input region ID 1 in analysis layer 2, reported as 1#2",
+        ]);
+    }
+
+    #[test]
+    fn one_of_several_analysis_layers_is_chosen() {
+        let xml = format!(r#"<Annotations>
+            <Annotation Id="1" Type="4"><Regions><Region Id="1" Text="Tumor"/></Regions></Annotation>
+            {}{}</Annotations>"#, ppc_layer("2", "Positive Pixel Count v9", &["1"], 0.25), ppc_layer("3", "Rerun", &["1"], 0.75));
+        let annotations = parse_xml_str(&xml).unwrap();
+        let extract = |layer: Option<&str>| {
+            let options = Options { analysis_layer: layer.map(String::from), ..Options::default() };
+            let mut summary = FileSummary::new(std::path::Path::new("layers.xml"));
+            let positivity = extract_regions_with_summary(&annotations, &options, &mut summary)["1"].positivity();
+            (positivity, summary.analysis_layers, summary.warnings)
+        };
+        // The last layer wins, with a warning and one for each value it overwrites
+        let (positivity, layers, warnings) = extract(None);
+        assert_eq!((positivity, layers), (Some(0.75), 2));
+        assert!(warnings[0].starts_with("Warning! Multiple type 3 analysis layers found"), "{}", warnings[0]);
+        assert_eq!(warnings[1..], ["Region 1: Over-writing positivity for region", "Region 1: Over-writing number positive for region", "Region 1: Over-writing number total for region"]);
+        // A layer is chosen by ID or name
+        assert_eq!(extract(Some("2")), (Some(0.25), 1, Vec::new()));
+        assert_eq!(extract(Some("Rerun")), (Some(0.75), 1, Vec::new()));
+        let (positivity, layers, warnings) = extract(Some("9"));
+        assert_eq!((positivity, layers), (None, 0));
+        assert_eq!(warnings, ["In layers.xml: Analysis layer 9 not found"]);
+    }
+}
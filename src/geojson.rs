@@ -0,0 +1,97 @@
+use serde_json::{json, Map, Value};
+use crate::convert::ExportRegion;
+use crate::model::Region;
+
+/// GeoJSON geometry of a region: a polygon for closed shapes, a line string for lines and a point for
+/// single vertices. Null without any vertices.
+pub fn geojson_geometry(region: &Region) -> Value {
+    let outline = region.outline();
+    let coordinates = |points: &[(f64, f64)]| points.iter().map(|&(x, y)| json!([x, y])).collect::<Vec<Value>>();
+    match outline.len() {
+        0 => Value::Null,
+        1 => json!({ "type": "Point", "coordinates": [outline[0].0, outline[0].1] }),
+        n if n >= 3 && region.region_type.is_closed() => {
+            // Rings end where they start
+            let mut ring = coordinates(&outline);
+            ring.push(ring[0].clone());
+            json!({ "type": "Polygon", "coordinates": [ring] })
+        },
+        _ => json!({ "type": "LineString", "coordinates": coordinates(&outline) }),
+    }
+}
+
+/// Feature collection with one feature per region
+/// Properties follow the CSV report, with `classification` set to the label as QuPath expects.
+pub fn to_geojson(regions: &[ExportRegion]) -> Value {
+    let features: Vec<Value> = regions.iter().map(|r| {
+        let label = r.region.label();
+        let mut properties = Map::new();
+        properties.insert(String::from("objectType"), json!("annotation"));
+        properties.insert(String::from("name"), json!(label));
+        properties.insert(String::from("classification"), json!({ "name": label }));
+        properties.insert(String::from("layer_id"), json!(r.layer.id));
+        properties.insert(String::from("layer_name"), json!(r.layer.name));
+        properties.insert(String::from("region_id"), json!(r.region.id));
+        properties.insert(String::from("region_type"), json!(r.region.region_type.code()));
+        if let Some(record) = r.record {
+            properties.insert(String::from("positivity"), json!(record.positivity));
+            properties.insert(String::from("num_wpositive"), json!(record.num_wpositive));
+            properties.insert(String::from("num_positive"), json!(record.num_positive));
+            properties.insert(String::from("num_spositive"), json!(record.num_spositive));
+            properties.insert(String::from("num_all_positive"), json!(record.num_all_positive));
+            properties.insert(String::from("num_total"), json!(record.num_total));
+            properties.insert(String::from("algorithm"), json!(record.algorithm));
+            for (name, value) in &record.columns {
+                properties.insert(name.clone(), json!(value));
+            }
+        }
+        json!({ "type": "Feature", "id": r.region.id, "geometry": geojson_geometry(r.region), "properties": properties })
+    }).collect();
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::extract::RegionRecord;
+    use crate::model::RegionType;
+    use super::to_geojson;
+
+    #[test]
+    fn regions_become_features_with_their_results() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new().name("Tumor")
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Tumor 1").vertices([(0.0, 0.0), (10.0, 20.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Polyline).vertices([(0.0, 0.0), (5.0, 5.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Polyline).vertex(3.0, 4.0)))
+            .build();
+        let record = RegionRecord {
+            filename: String::from("case1.xml"),
+            slide_name: String::from("case1.svs"),
+            region_id: String::from("1"),
+            text_label: String::from("Tumor 1"),
+            positivity: Some(0.25),
+            num_wpositive: None,
+            num_positive: Some(10.0),
+            num_spositive: None,
+            num_all_positive: 10.0,
+            num_total: Some(40.0),
+            algorithm: String::from("Positive Pixel Count 9.1"),
+            columns: vec![(String::from("perimeter"), Some(60.0))],
+            text_columns: Vec::new(),
+        };
+        let geojson = to_geojson(&export_regions(&annotations, std::slice::from_ref(&record)));
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0]["geometry"], json!({ "type": "Polygon", "coordinates": [[[0.0, 0.0], [10.0, 0.0], [10.0, 20.0], [0.0, 20.0], [0.0, 0.0]]] }));
+        let properties = &features[0]["properties"];
+        assert_eq!(properties["classification"], json!({ "name": "Tumor 1" }));
+        assert_eq!((&properties["layer_name"], &properties["positivity"], &properties["num_wpositive"]), (&json!("Tumor"), &json!(0.25), &json!(null)));
+        assert_eq!(properties["perimeter"], json!(60.0));
+        assert_eq!(features[1]["geometry"]["type"], json!("LineString"));
+        assert!(features[1]["properties"].get("positivity").is_none());
+        assert_eq!(features[2]["geometry"], json!({ "type": "Point", "coordinates": [3.0, 4.0] }));
+    }
+}
@@ -0,0 +1,1245 @@
+use std::collections::HashMap;
+use std::{error, str::FromStr};
+use rstar::{RTree, AABB};
+use rstar::primitives::{GeomWithData, Rectangle};
+use crate::model::{Annotation, Annotations, Region, RegionType, Vertex};
+use crate::options::Options;
+
+/// A point in level-0 pixel coordinates
+pub type Point = (f64, f64);
+
+/// Area enclosed by a polygon (shoelace formula), the closing edge is implied
+pub fn polygon_area(points: &[Point]) -> f64 {
+    let n = points.len();
+    if n < 3 {
+        return 0.0;
+    }
+    let twice_area: f64 = (0..n).map(|i| {
+        let (x1, y1) = points[i];
+        let (x2, y2) = points[(i + 1) % n];
+        x1 * y2 - x2 * y1
+    }).sum();
+    twice_area.abs() / 2.0
+}
+
+/// Axis-aligned rectangle enclosing a shape, in level-0 pixel coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl BoundingBox {
+    /// Smallest box holding all of `points`, None if there are none
+    pub fn of(points: &[Point]) -> Option<BoundingBox> {
+        let (&(x, y), rest) = points.split_first()?;
+        Some(rest.iter().fold(BoundingBox { min_x: x, min_y: y, max_x: x, max_y: y }, |b, &(x, y)| BoundingBox {
+            min_x: b.min_x.min(x),
+            min_y: b.min_y.min(y),
+            max_x: b.max_x.max(x),
+            max_y: b.max_y.max(y),
+        }))
+    }
+
+    pub fn width(&self) -> f64 {
+        self.max_x - self.min_x
+    }
+
+    pub fn height(&self) -> f64 {
+        self.max_y - self.min_y
+    }
+
+    /// True if the two boxes share any point
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.min_x <= other.max_x && other.min_x <= self.max_x && self.min_y <= other.max_y && other.min_y <= self.max_y
+    }
+}
+
+/// Mean of the points, None if there are none
+fn mean_point(points: &[Point]) -> Option<Point> {
+    if points.is_empty() {
+        return None;
+    }
+    let count = points.len() as f64;
+    Some((points.iter().map(|p| p.0).sum::<f64>() / count, points.iter().map(|p| p.1).sum::<f64>() / count))
+}
+
+/// Centroid of the area enclosed by a polygon, the mean of the points if it encloses no area
+pub fn polygon_centroid(points: &[Point]) -> Option<Point> {
+    let n = points.len();
+    let (mut cx, mut cy, mut twice_area) = (0.0, 0.0, 0.0);
+    for i in 0..n {
+        let (x1, y1) = points[i];
+        let (x2, y2) = points[(i + 1) % n];
+        let cross = x1 * y2 - x2 * y1;
+        twice_area += cross;
+        cx += (x1 + x2) * cross;
+        cy += (y1 + y2) * cross;
+    }
+    if twice_area.abs() < f64::EPSILON {
+        return mean_point(points);
+    }
+    Some((cx / (3.0 * twice_area), cy / (3.0 * twice_area)))
+}
+
+/// Convex hull of the points in counter-clockwise order (monotone chain), collinear points are dropped
+pub fn convex_hull(points: &[Point]) -> Vec<Point> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    sorted.dedup();
+    if sorted.len() < 3 {
+        return sorted;
+    }
+    let cross = |o: Point, a: Point, b: Point| (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0);
+    let mut hull: Vec<Point> = Vec::with_capacity(sorted.len() * 2);
+    // Lower hull left to right, then upper hull right to left
+    for pass in [sorted.clone(), sorted.into_iter().rev().collect()] {
+        let start = hull.len();
+        for p in pass {
+            while hull.len() >= start + 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0 {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        // The last point starts the other half
+        hull.pop();
+    }
+    hull
+}
+
+/// True if `point` lies inside the polygon (even-odd rule)
+pub fn polygon_contains(points: &[Point], (x, y): Point) -> bool {
+    let n = points.len();
+    if n < 3 {
+        return false;
+    }
+    let mut inside = false;
+    for i in 0..n {
+        let (x1, y1) = points[i];
+        let (x2, y2) = points[(i + n - 1) % n];
+        if (y1 > y) != (y2 > y) && x < (x2 - x1) * (y - y1) / (y2 - y1) + x1 {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Signed area of a polygon, positive if the points run counter-clockwise
+fn signed_area(points: &[Point]) -> f64 {
+    let n = points.len();
+    (0..n).map(|i| {
+        let (x1, y1) = points[i];
+        let (x2, y2) = points[(i + 1) % n];
+        x1 * y2 - x2 * y1
+    }).sum::<f64>() / 2.0
+}
+
+/// Clip a polygon against a counter-clockwise convex polygon (Sutherland-Hodgman)
+fn clip_convex(subject: &[Point], clip: &[Point]) -> Vec<Point> {
+    let mut output = subject.to_vec();
+    for i in 0..clip.len() {
+        if output.is_empty() {
+            break;
+        }
+        let (a, b) = (clip[i], clip[(i + 1) % clip.len()]);
+        let side = |p: Point| (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0);
+        let input = std::mem::take(&mut output);
+        for j in 0..input.len() {
+            let (p, q) = (input[j], input[(j + 1) % input.len()]);
+            let (sp, sq) = (side(p), side(q));
+            if sp >= 0.0 {
+                output.push(p);
+            }
+            if (sp >= 0.0) != (sq >= 0.0) {
+                let t = sp / (sp - sq);
+                output.push((p.0 + t * (q.0 - p.0), p.1 + t * (q.1 - p.1)));
+            }
+        }
+    }
+    output
+}
+
+/// Fan of triangles from the first point, each counter-clockwise with the sign of its original orientation
+/// Summing the signed triangles gives back the polygon, which lets any simple polygon be handled as convex pieces.
+fn signed_triangles(points: &[Point]) -> Vec<([Point; 3], f64)> {
+    (1..points.len().saturating_sub(1)).filter_map(|i| {
+        let triangle = [points[0], points[i], points[i + 1]];
+        let area = signed_area(&triangle);
+        if area > 0.0 {
+            Some((triangle, 1.0))
+        } else if area < 0.0 {
+            Some(([triangle[0], triangle[2], triangle[1]], -1.0))
+        } else {
+            None
+        }
+    }).collect()
+}
+
+/// Area of overlap between two simple polygons
+pub fn intersection_area(a: &[Point], b: &[Point]) -> f64 {
+    match (BoundingBox::of(a), BoundingBox::of(b)) {
+        (Some(ba), Some(bb)) if ba.intersects(&bb) => {},
+        _ => return 0.0,
+    }
+    let (ta, tb) = (signed_triangles(a), signed_triangles(b));
+    let mut area = 0.0;
+    for (triangle_a, sign_a) in &ta {
+        for (triangle_b, sign_b) in &tb {
+            area += sign_a * sign_b * polygon_area(&clip_convex(triangle_a, triangle_b));
+        }
+    }
+    // The signs of the fans are relative to the orientation of each polygon
+    area.abs()
+}
+
+/// Area of `subject` inside any of `others`, where the others overlap counting once
+/// Between consecutive vertex heights and edge crossings the covered width along a horizontal line changes
+/// linearly, so measuring it halfway through each such slab gives the exact area.
+fn covered_area(subject: &[Point], others: &[Vec<Point>]) -> f64 {
+    let Some(bbox) = BoundingBox::of(subject) else { return 0.0 };
+    let others: Vec<&[Point]> = others.iter()
+        .filter(|other| BoundingBox::of(other).is_some_and(|b| b.intersects(&bbox)))
+        .map(Vec::as_slice)
+        .collect();
+    if others.is_empty() {
+        return 0.0;
+    }
+    let polygons: Vec<Vec<(Point, Point)>> = std::iter::once(subject).chain(others.iter().copied()).map(|p| edges(p, true)).collect();
+    let mut heights: Vec<f64> = polygons.iter().flatten().map(|(a, _)| a.1).collect();
+    for (i, first) in polygons.iter().enumerate() {
+        for second in &polygons[i + 1..] {
+            for &(a, b) in first {
+                heights.extend(second.iter().filter_map(|&(c, d)| crossing_height(a, b, c, d)));
+            }
+        }
+    }
+    heights.retain(|y| (bbox.min_y..=bbox.max_y).contains(y));
+    heights.sort_by(f64::total_cmp);
+    heights.dedup();
+    heights.windows(2).map(|w| {
+        let y = (w[0] + w[1]) / 2.0;
+        let covered = merge_spans(others.iter().flat_map(|other| scanline(other, y)).collect());
+        let inside: f64 = scanline(subject, y).iter()
+            .flat_map(|&(start, end)| covered.iter().map(move |&(from, to)| (end.min(to) - start.max(from)).max(0.0)))
+            .sum();
+        inside * (w[1] - w[0])
+    }).sum()
+}
+
+/// Where a horizontal line at `y` is inside a polygon, as sorted x ranges
+fn scanline(points: &[Point], y: f64) -> Vec<(f64, f64)> {
+    let mut xs: Vec<f64> = edges(points, true).into_iter()
+        .filter(|(a, b)| (a.1 <= y) != (b.1 <= y))
+        .map(|(a, b)| a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1))
+        .collect();
+    xs.sort_by(f64::total_cmp);
+    xs.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+/// Join overlapping x ranges
+fn merge_spans(mut spans: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut merged: Vec<(f64, f64)> = Vec::new();
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Height at which the segments from `a` to `b` and from `c` to `d` cross, None if they do not
+fn crossing_height(a: Point, b: Point, c: Point, d: Point) -> Option<f64> {
+    if a.1.max(b.1) < c.1.min(d.1) || c.1.max(d.1) < a.1.min(b.1) || !segments_intersect(a, b, c, d) {
+        return None;
+    }
+    let denominator = (b.0 - a.0) * (d.1 - c.1) - (b.1 - a.1) * (d.0 - c.0);
+    if denominator == 0.0 {
+        return None;
+    }
+    let t = ((c.0 - a.0) * (d.1 - c.1) - (c.1 - a.1) * (d.0 - c.0)) / denominator;
+    Some(a.1 + t * (b.1 - a.1))
+}
+
+/// Distance from `p` to the segment from `a` to `b`
+fn segment_point_distance(p: Point, a: Point, b: Point) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 { (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0) } else { 0.0 };
+    (p.0 - (a.0 + t * dx)).hypot(p.1 - (a.1 + t * dy))
+}
+
+/// True if the segments from `a` to `b` and from `c` to `d` cross or touch
+fn segments_intersect(a: Point, b: Point, c: Point, d: Point) -> bool {
+    let cross = |o: Point, p: Point, q: Point| (p.0 - o.0) * (q.1 - o.1) - (p.1 - o.1) * (q.0 - o.0);
+    let (d1, d2, d3, d4) = (cross(c, d, a), cross(c, d, b), cross(a, b, c), cross(a, b, d));
+    // Collinear segments pass the orientation test even when apart, their bounding boxes must overlap too
+    let overlap = |p: f64, q: f64, r: f64, s: f64| p.min(q) <= r.max(s) && r.min(s) <= p.max(q);
+    (d1 * d2 <= 0.0) && (d3 * d4 <= 0.0) && overlap(a.0, b.0, c.0, d.0) && overlap(a.1, b.1, c.1, d.1)
+}
+
+/// Edges of a path, including the one back to the start if `closed`
+fn edges(points: &[Point], closed: bool) -> Vec<(Point, Point)> {
+    let mut edges: Vec<(Point, Point)> = points.windows(2).map(|w| (w[0], w[1])).collect();
+    if closed && points.len() > 2 {
+        edges.push((points[points.len() - 1], points[0]));
+    }
+    if points.len() == 1 {
+        edges.push((points[0], points[0]));
+    }
+    edges
+}
+
+/// Positions of the points kept by Douglas-Peucker simplification of an open path
+/// Points closer than `tolerance` to the line through the points kept either side of them are dropped.
+fn simplify_path(points: &[Point], tolerance: f64, keep: &mut Vec<usize>, offset: usize) {
+    if points.len() < 3 {
+        return;
+    }
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let (farthest, distance) = points[1..points.len() - 1].iter().enumerate()
+        .map(|(i, &p)| (i + 1, segment_point_distance(p, first, last)))
+        .fold((0, 0.0), |best, candidate| if candidate.1 > best.1 { candidate } else { best });
+    if distance > tolerance {
+        simplify_path(&points[..=farthest], tolerance, keep, offset);
+        keep.push(offset + farthest);
+        simplify_path(&points[farthest..], tolerance, keep, offset + farthest);
+    }
+}
+
+/// Positions of the points to keep when simplifying a path with tolerance `tolerance` (Douglas-Peucker)
+/// Closed outlines are split at the point farthest from the first so both halves keep their shape.
+pub fn simplify(points: &[Point], tolerance: f64, closed: bool) -> Vec<usize> {
+    let n = points.len();
+    if n < 3 {
+        return (0..n).collect();
+    }
+    let mut keep = vec![0];
+    if closed {
+        let start = points[0];
+        let (split, _) = points.iter().enumerate()
+            .map(|(i, p)| (i, (p.0 - start.0).hypot(p.1 - start.1)))
+            .fold((0, 0.0), |best, candidate| if candidate.1 > best.1 { candidate } else { best });
+        let mut ring = points.to_vec();
+        ring.push(start);
+        simplify_path(&ring[..=split], tolerance, &mut keep, 0);
+        if split > 0 {
+            keep.push(split);
+        }
+        simplify_path(&ring[split..], tolerance, &mut keep, split);
+    } else {
+        simplify_path(points, tolerance, &mut keep, 0);
+        keep.push(n - 1);
+    }
+    keep
+}
+
+/// True if the rectangle lies entirely inside the polygon, touching its outline counts as inside
+pub fn polygon_contains_box(points: &[Point], bbox: &BoundingBox) -> bool {
+    let center = ((bbox.min_x + bbox.max_x) / 2.0, (bbox.min_y + bbox.max_y) / 2.0);
+    if !polygon_contains(points, center) {
+        return false;
+    }
+    let corners = [(bbox.min_x, bbox.min_y), (bbox.max_x, bbox.min_y), (bbox.max_x, bbox.max_y), (bbox.min_x, bbox.max_y)];
+    let area = bbox.width() * bbox.height();
+    // Allow for rounding in the overlap
+    intersection_area(points, &corners) >= area * (1.0 - 1e-9)
+}
+
+/// Pixels of a `width` by `height` grid whose centers lie inside the polygon (even-odd rule), as runs of
+/// columns `start..end` in each row. The polygon is in level-0 pixels and scaled down by `downsample`.
+pub fn polygon_spans(points: &[Point], downsample: f64, width: u32, height: u32) -> Vec<(u32, u32, u32)> {
+    let points: Vec<Point> = points.iter().map(|(x, y)| (x / downsample, y / downsample)).collect();
+    let Some(bbox) = BoundingBox::of(&points) else { return Vec::new() };
+    let n = points.len();
+    let first_row = bbox.min_y.floor().max(0.0) as u32;
+    let last_row = (bbox.max_y.ceil().max(0.0) as u32).min(height);
+    let mut spans = Vec::new();
+    for row in first_row..last_row {
+        let y = row as f64 + 0.5;
+        // Where the edges cross this row
+        let mut crossings: Vec<f64> = (0..n).filter_map(|i| {
+            let ((x1, y1), (x2, y2)) = (points[i], points[(i + 1) % n]);
+            ((y1 > y) != (y2 > y)).then(|| x1 + (y - y1) * (x2 - x1) / (y2 - y1))
+        }).collect();
+        crossings.sort_by(f64::total_cmp);
+        for pair in crossings.chunks_exact(2) {
+            // Pixel centers from the first crossing up to, but not including, the second
+            let start = (pair[0] - 0.5).ceil().max(0.0) as u32;
+            let end = (pair[1] - 0.5).ceil().clamp(0.0, width as f64) as u32;
+            if start < end {
+                spans.push((row, start, end));
+            }
+        }
+    }
+    spans
+}
+
+/// Length of a path through `points`, including the edge back to the start if `closed`
+pub fn path_length(points: &[Point], closed: bool) -> f64 {
+    let mut length: f64 = points.windows(2).map(|w| (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1)).sum();
+    if closed && points.len() > 2 {
+        let (first, last) = (points[0], points[points.len() - 1]);
+        length += (first.0 - last.0).hypot(first.1 - last.1);
+    }
+    length
+}
+
+/// True if `a` and `b` differ by more than `tolerance` relative to the larger of the two
+fn disagree(a: f64, b: f64, tolerance: f64) -> bool {
+    (a - b).abs() > tolerance * a.abs().max(b.abs())
+}
+
+impl Region {
+    /// Outline of the region as a polygon in level-0 pixel coordinates
+    /// ImageScope stores rectangles and ellipses as two opposite corners of their bounding box; those become
+    /// the four corners of the rectangle and an ellipse approximated by a polygon.
+    pub fn outline(&self) -> Vec<Point> {
+        let points = self.points();
+        match self.region_type {
+            RegionType::Rectangle if points.len() == 2 => {
+                let ((x1, y1), (x2, y2)) = (points[0], points[1]);
+                vec![(x1, y1), (x2, y1), (x2, y2), (x1, y2)]
+            },
+            RegionType::Ellipse if (2..=4).contains(&points.len()) => {
+                let Some(bbox) = BoundingBox::of(&points) else { return points };
+                let (cx, cy) = ((bbox.min_x + bbox.max_x) / 2.0, (bbox.min_y + bbox.max_y) / 2.0);
+                let (rx, ry) = (bbox.width() / 2.0, bbox.height() / 2.0);
+                (0..ELLIPSE_SEGMENTS).map(|i| {
+                    let angle = i as f64 * std::f64::consts::TAU / ELLIPSE_SEGMENTS as f64;
+                    (cx + rx * angle.cos(), cy + ry * angle.sin())
+                }).collect()
+            },
+            _ => points,
+        }
+    }
+
+    /// Store the outline as the vertices, so rectangles and ellipses keep their shape under any transform
+    pub fn expand_outline(&mut self) {
+        if !matches!(self.region_type, RegionType::Rectangle | RegionType::Ellipse) {
+            return;
+        }
+        let outline = self.outline();
+        if let Some(vertices) = &mut self.vertices {
+            if outline.len() != vertices.vertex.len() {
+                vertices.vertex = outline.into_iter().map(|(x, y)| Vertex::new(x, y)).collect();
+            }
+        }
+    }
+
+    /// Area in square pixels computed from the vertices, 0 for open shapes such as rulers
+    pub fn computed_area(&self) -> f64 {
+        if self.region_type.is_closed() { polygon_area(&self.outline()) } else { 0.0 }
+    }
+
+    /// Length of the outline in pixels, for open shapes the length of the line
+    pub fn perimeter(&self) -> f64 {
+        path_length(&self.outline(), self.region_type.is_closed())
+    }
+
+    /// Centroid in level-0 pixel coordinates, for open shapes the mean of the vertices
+    /// None if the region has no vertices.
+    pub fn centroid(&self) -> Option<Point> {
+        if self.region_type.is_closed() {
+            polygon_centroid(&self.outline())
+        } else {
+            mean_point(&self.outline())
+        }
+    }
+
+    /// Rectangle enclosing all vertices, None if the region has no vertices
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        BoundingBox::of(&self.outline())
+    }
+
+    /// Area of the convex hull of the vertices in square pixels
+    pub fn hull_area(&self) -> f64 {
+        polygon_area(&convex_hull(&self.outline()))
+    }
+
+    /// Area divided by the area of its convex hull, 1 for convex shapes, None for open or degenerate shapes
+    pub fn solidity(&self) -> Option<f64> {
+        let hull_area = self.hull_area();
+        if !self.region_type.is_closed() || hull_area <= 0.0 {
+            return None;
+        }
+        Some(self.computed_area() / hull_area)
+    }
+
+    /// True if the level-0 pixel location lies inside the region, always false for open shapes
+    pub fn contains_point(&self, x: f64, y: f64) -> bool {
+        self.region_type.is_closed() && polygon_contains(&self.outline(), (x, y))
+    }
+
+    /// Area in square pixels shared with another region, 0 if either is an open shape
+    pub fn overlap_area(&self, other: &Region) -> f64 {
+        if !self.region_type.is_closed() || !other.region_type.is_closed() {
+            return 0.0;
+        }
+        intersection_area(&self.outline(), &other.outline())
+    }
+
+    /// Intersection over union with another region, None if neither encloses any area
+    pub fn iou(&self, other: &Region) -> Option<f64> {
+        let overlap = self.overlap_area(other);
+        let union = self.computed_area() + other.computed_area() - overlap;
+        if union <= 0.0 { None } else { Some(overlap / union) }
+    }
+
+    /// Distance between the centroids of two regions in pixels, None if either has no vertices
+    pub fn centroid_distance(&self, other: &Region) -> Option<f64> {
+        let ((x1, y1), (x2, y2)) = (self.centroid()?, other.centroid()?);
+        Some((x2 - x1).hypot(y2 - y1))
+    }
+
+    /// Shortest distance between the outlines of two regions in pixels, 0 if they touch, overlap or one
+    /// lies inside the other. None if either has no vertices.
+    pub fn edge_distance(&self, other: &Region) -> Option<f64> {
+        let (a, b) = (self.outline(), other.outline());
+        if a.is_empty() || b.is_empty() {
+            return None;
+        }
+        if self.contains_point(b[0].0, b[0].1) || other.contains_point(a[0].0, a[0].1) {
+            return Some(0.0);
+        }
+        let (edges_a, edges_b) = (edges(&a, self.region_type.is_closed()), edges(&b, other.region_type.is_closed()));
+        let mut distance = f64::INFINITY;
+        for &(p, q) in &edges_a {
+            for &(r, s) in &edges_b {
+                if segments_intersect(p, q, r, s) {
+                    return Some(0.0);
+                }
+                distance = distance
+                    .min(segment_point_distance(p, r, s))
+                    .min(segment_point_distance(q, r, s))
+                    .min(segment_point_distance(r, p, q))
+                    .min(segment_point_distance(s, p, q));
+            }
+        }
+        Some(distance)
+    }
+
+    /// Drop vertices that change the outline by less than `tolerance` pixels (Douglas-Peucker)
+    pub fn simplify(&mut self, tolerance: f64) {
+        let closed = self.region_type.is_closed();
+        let Some(vertices) = &mut self.vertices else { return };
+        // Vertices that are not numbers are left alone
+        if vertices.vertex.iter().any(|v| v.point().is_none()) {
+            return;
+        }
+        let points: Vec<Point> = vertices.vertex.iter().filter_map(Vertex::point).collect();
+        let mut keep = vec![false; points.len()];
+        for i in simplify(&points, tolerance, closed) {
+            keep[i] = true;
+        }
+        let mut kept = keep.into_iter();
+        vertices.vertex.retain(|_| kept.next().unwrap_or(true));
+    }
+
+    /// Compare the area computed from the outline with the declared `Area` and `AreaMicrons`
+    /// Returns a description of each disagreement beyond `tolerance` (a fraction, e.g. 0.01 for 1%).
+    /// Regions without enough vertices to enclose an area are not checked.
+    pub fn check_area(&self, microns_per_pixel: Option<f64>, tolerance: f64) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.region_type.is_closed() || self.outline().len() < 3 {
+            return problems;
+        }
+        let computed = self.computed_area();
+        let declared = |value: &Option<String>| value.as_deref().and_then(|v| v.trim().parse::<f64>().ok());
+        if let Some(area) = declared(&self.area) {
+            if disagree(computed, area, tolerance) {
+                problems.push(format!("Area is {} but the outline encloses {:.1} square pixels", area, computed));
+            }
+        }
+        if let (Some(area_microns), Some(mpp)) = (declared(&self.area_microns), microns_per_pixel) {
+            let computed_microns = computed * mpp * mpp;
+            if disagree(computed_microns, area_microns, tolerance) {
+                problems.push(format!("AreaMicrons is {} but the outline encloses {:.1} square microns", area_microns, computed_microns));
+            }
+        }
+        problems
+    }
+}
+
+impl Annotations {
+    /// Regions of all layers containing the level-0 pixel location, with the layer each belongs to
+    pub fn regions_containing(&self, x: f64, y: f64) -> Vec<(&str, &Region)> {
+        self.annotation.iter()
+            .flat_map(|layer| layer.regions.region.iter().map(move |r| (layer.id.as_str(), r)))
+            .filter(|(_, r)| r.contains_point(x, y))
+            .collect()
+    }
+}
+
+/// Bounding box of a region in the index, with the layer Id and the region
+type IndexEntry<'a> = GeomWithData<Rectangle<[f64; 2]>, (&'a str, &'a Region)>;
+
+/// R-tree over the bounding boxes of all regions of a file, for fast point and overlap queries
+pub struct RegionIndex<'a> {
+    tree: RTree<IndexEntry<'a>>,
+}
+
+impl<'a> RegionIndex<'a> {
+    /// Index the regions of all layers, regions without vertices are left out
+    pub fn new(annotations: &'a Annotations) -> Self {
+        Self::with_layers(annotations, |_| true)
+    }
+
+    /// Index the regions of the drawn layers, leaving out the analysis (type 3) layers that repeat them
+    pub fn drawn(annotations: &'a Annotations) -> Self {
+        Self::with_layers(annotations, |layer| layer.annotation_type != "3")
+    }
+
+    fn with_layers(annotations: &'a Annotations, include: impl Fn(&Annotation) -> bool) -> Self {
+        let entries = annotations.annotation.iter()
+            .filter(|layer| include(layer))
+            .flat_map(|layer| layer.regions.region.iter().map(move |r| (layer.id.as_str(), r)))
+            .filter_map(|(layer, r)| {
+                let b = r.bounding_box()?;
+                Some(GeomWithData::new(Rectangle::from_corners([b.min_x, b.min_y], [b.max_x, b.max_y]), (layer, r)))
+            })
+            .collect();
+        Self { tree: RTree::bulk_load(entries) }
+    }
+
+    /// Number of indexed regions
+    pub fn len(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.size() == 0
+    }
+
+    /// Regions containing the level-0 pixel location, with the layer each belongs to
+    pub fn regions_containing(&self, x: f64, y: f64) -> Vec<(&'a str, &'a Region)> {
+        self.tree.locate_all_at_point(&[x, y])
+            .map(|entry| entry.data)
+            .filter(|(_, r)| r.contains_point(x, y))
+            .collect()
+    }
+
+    /// Regions whose bounding boxes overlap the bounding box, candidates for an exact overlap test
+    pub fn regions_near(&self, bbox: &BoundingBox) -> Vec<(&'a str, &'a Region)> {
+        let envelope = AABB::from_corners([bbox.min_x, bbox.min_y], [bbox.max_x, bbox.max_y]);
+        self.tree.locate_in_envelope_intersecting(&envelope).map(|entry| entry.data).collect()
+    }
+
+    /// Region most likely to be the same shape as `region`: the one with the highest intersection over union,
+    /// or failing that the smallest one containing its centroid
+    pub fn best_match(&self, region: &Region) -> Option<(&'a str, &'a Region)> {
+        let bbox = region.bounding_box()?;
+        let candidates = self.regions_near(&bbox);
+        candidates.iter()
+            .filter_map(|&(layer, other)| Some((layer, other, region.iou(other).filter(|iou| *iou > 0.0)?)))
+            .max_by(|a, b| a.2.total_cmp(&b.2))
+            .map(|(layer, other, _)| (layer, other))
+            .or_else(|| {
+                let (x, y) = region.centroid()?;
+                self.regions_containing(x, y).into_iter().min_by(|a, b| a.1.computed_area().total_cmp(&b.1.computed_area()))
+            })
+    }
+
+    /// Closest other region by edge-to-edge distance in pixels, optionally only among regions with the label
+    /// Regions are visited outwards from the centre of its bounding box and the search stops once no further
+    /// region can be closer.
+    pub fn nearest(&self, region: &Region, label: Option<&str>) -> Option<(&'a str, &'a Region, f64)> {
+        let bbox = region.bounding_box()?;
+        let centre = [(bbox.min_x + bbox.max_x) / 2.0, (bbox.min_y + bbox.max_y) / 2.0];
+        // Outlines lie within their bounding boxes, so a region is at least its box's distance from the centre,
+        // less this, away from the outline
+        let half_diagonal = (bbox.max_x - bbox.min_x).hypot(bbox.max_y - bbox.min_y) / 2.0;
+        let mut nearest: Option<(&'a str, &'a Region, f64)> = None;
+        for (entry, distance_2) in self.tree.nearest_neighbor_iter_with_distance_2(&centre) {
+            if nearest.is_some_and(|(_, _, best)| distance_2.sqrt() - half_diagonal > best) {
+                break;
+            }
+            let (layer, other) = entry.data;
+            if std::ptr::eq(other, region) || label.is_some_and(|label| other.label() != label) {
+                continue;
+            }
+            if let Some(distance) = region.edge_distance(other) {
+                if nearest.is_none_or(|(_, _, best)| distance < best) {
+                    nearest = Some((layer, other, distance));
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Other regions overlapping `region`, with the overlap area in square pixels
+    pub fn overlapping(&self, region: &Region) -> Vec<(&'a str, &'a Region, f64)> {
+        let Some(bbox) = region.bounding_box() else {
+            return Vec::new();
+        };
+        self.regions_near(&bbox).into_iter()
+            .filter(|(_, other)| !std::ptr::eq(*other, region))
+            .map(|(layer, other)| (layer, other, region.overlap_area(other)))
+            .filter(|(_, _, area)| *area > 0.0)
+            .collect()
+    }
+}
+
+/// Number of edges of the polygon standing in for an ellipse
+const ELLIPSE_SEGMENTS: usize = 128;
+
+/// Fraction of a region's area that must lie inside another region for it to count as nested
+const NESTED_FRACTION: f64 = 0.99;
+
+/// 2D affine transform taking (x, y) to (a x + b y + c, d x + e y + f), e.g. from one scan of a slide to a re-scan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Affine {
+    pub matrix: [f64; 6],
+}
+
+impl Affine {
+    pub fn apply(&self, (x, y): Point) -> Point {
+        let [a, b, c, d, e, f] = self.matrix;
+        (a * x + b * y + c, d * x + e * y + f)
+    }
+}
+
+impl FromStr for Affine {
+    type Err = Box<dyn error::Error>;
+
+    /// Parse the six numbers `a,b,c,d,e,f` of the first two rows of the matrix
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let numbers = value.split(',').map(|n| n.trim().parse::<f64>()).collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| format!("Invalid affine matrix {}: {}", value, e))?;
+        let matrix: [f64; 6] = numbers.try_into().map_err(|_| format!("Affine matrix {} does not have six numbers a,b,c,d,e,f", value))?;
+        Ok(Self { matrix })
+    }
+}
+
+impl Vertex {
+    /// Move the vertex to where `f` maps it, vertices that are not numbers are left alone
+    pub fn map(&mut self, f: impl Fn(Point) -> Point) {
+        if let Some((x, y)) = self.point().map(f) {
+            self.x = x.to_string();
+            self.y = y.to_string();
+        }
+    }
+}
+
+impl Annotations {
+    /// Move every vertex of every region to where `f` maps it
+    pub fn map_points(&mut self, f: impl Fn(Point) -> Point) {
+        let vertices = self.annotation.iter_mut()
+            .flat_map(|layer| layer.regions.region.iter_mut())
+            .flat_map(|region| region.vertices.iter_mut().flat_map(|v| v.vertex.iter_mut()));
+        for vertex in vertices {
+            vertex.map(&f);
+        }
+    }
+
+    /// NegativeROA (exclusion) regions of the drawn layers
+    pub fn exclusions(&self) -> Vec<&Region> {
+        self.annotation.iter()
+            .filter(|layer| layer.annotation_type != "3")
+            .flat_map(|layer| layer.regions.region.iter())
+            .filter(|r| r.is_negative())
+            .collect()
+    }
+
+    /// Area in square pixels of a region that the analysis covers, i.e. without the parts inside the NegativeROA
+    /// regions of its own layer, counting overlapping exclusions once. Exclusion regions themselves have none.
+    /// `region` must be one of the regions of these annotations, otherwise nothing is excluded.
+    pub fn effective_area(&self, region: &Region) -> f64 {
+        if region.is_negative() {
+            return 0.0;
+        }
+        if !region.region_type.is_closed() {
+            return region.computed_area();
+        }
+        let layer = self.annotation.iter().find(|layer| layer.regions.region.iter().any(|r| std::ptr::eq(r, region)));
+        let exclusions: Vec<Vec<Point>> = layer.iter()
+            .flat_map(|layer| layer.regions.region.iter())
+            .filter(|r| r.is_negative() && r.region_type.is_closed())
+            .map(Region::outline)
+            .collect();
+        (region.computed_area() - covered_area(&region.outline(), &exclusions)).max(0.0)
+    }
+
+    /// Convert all coordinates from level-0 pixels to microns using the scan resolution
+    /// MicronsPerPixel becomes 1 so measurements in microns stay correct afterwards.
+    pub fn to_microns(&mut self) -> Result<(), Box<dyn error::Error>> {
+        let mpp = self.microns_per_pixel().ok_or("Cannot convert to microns without MicronsPerPixel")?;
+        self.map_points(|(x, y)| (x * mpp, y * mpp));
+        self.microns_per_pixel = Some(String::from("1"));
+        Ok(())
+    }
+
+    /// Simplify every region with a tolerance in microns, converted to pixels with the scan resolution
+    pub fn simplify(&mut self, tolerance_microns: f64) -> Result<(), Box<dyn error::Error>> {
+        let mpp = self.microns_per_pixel().ok_or("Cannot simplify by a distance in microns without MicronsPerPixel")?;
+        for region in self.annotation.iter_mut().flat_map(|layer| layer.regions.region.iter_mut()) {
+            region.simplify(tolerance_microns / mpp);
+        }
+        Ok(())
+    }
+}
+
+/// Apply the geometry settings of `options` to annotations about to be exported
+/// Outlines are simplified first, then transformed, and only then converted to microns.
+pub fn prepare_for_export(annotations: &mut Annotations, options: &Options) -> Result<(), Box<dyn error::Error>> {
+    if let Some(tolerance) = options.simplify {
+        annotations.simplify(tolerance)?;
+    }
+    if let Some(affine) = &options.affine {
+        // Two corners cannot describe a rotated or sheared rectangle
+        for region in annotations.annotation.iter_mut().flat_map(|layer| layer.regions.region.iter_mut()) {
+            region.expand_outline();
+        }
+        annotations.map_points(|p| affine.apply(p));
+    }
+    if options.microns {
+        annotations.to_microns()?;
+    }
+    Ok(())
+}
+
+/// A region in the containment hierarchy
+#[derive(Debug, Clone)]
+pub struct RegionNode<'a> {
+    pub layer: &'a str,
+    pub region: &'a Region,
+    /// Position of the smallest region containing this one, None at the top level
+    pub parent: Option<usize>,
+    /// Positions of the regions directly inside this one
+    pub children: Vec<usize>,
+}
+
+/// Which drawn regions lie inside which, e.g. tumor within tissue
+/// Analysis (type 3) layers are left out since they repeat the drawn regions.
+#[derive(Debug, Clone)]
+pub struct RegionTree<'a> {
+    pub nodes: Vec<RegionNode<'a>>,
+    /// Position of each node keyed by the address of its region
+    positions: HashMap<usize, usize>,
+}
+
+/// Address of a region, identifying it within the annotations it belongs to
+/// Kept as a number rather than a pointer so the tree can still be sent between threads.
+fn address(region: &Region) -> usize {
+    region as *const Region as usize
+}
+
+impl<'a> RegionTree<'a> {
+    pub fn new(annotations: &'a Annotations) -> Self {
+        let regions: Vec<(&str, &Region)> = annotations.annotation.iter()
+            .filter(|layer| layer.annotation_type != "3")
+            .flat_map(|layer| layer.regions.region.iter().map(move |r| (layer.id.as_str(), r)))
+            .filter(|(_, r)| r.computed_area() > 0.0)
+            .collect();
+        let index = RegionIndex::drawn(annotations);
+        let positions: HashMap<usize, usize> = regions.iter().enumerate().map(|(i, (_, r))| (address(r), i)).collect();
+        let mut nodes: Vec<RegionNode> = regions.iter()
+            .map(|&(layer, region)| RegionNode { layer, region, parent: None, children: Vec::new() })
+            .collect();
+        for (i, &(_, region)) in regions.iter().enumerate() {
+            let area = region.computed_area();
+            let Some(bbox) = region.bounding_box() else { continue };
+            // The smallest larger region holding nearly all of this one
+            nodes[i].parent = index.regions_near(&bbox).into_iter()
+                .filter_map(|(_, other)| positions.get(&address(other)).copied())
+                .filter(|&j| j != i)
+                .map(|j| (j, regions[j].1.computed_area()))
+                .filter(|&(j, other_area)| other_area > area && region.overlap_area(regions[j].1) >= NESTED_FRACTION * area)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(j, _)| j);
+        }
+        for i in 0..nodes.len() {
+            if let Some(parent) = nodes[i].parent {
+                nodes[parent].children.push(i);
+            }
+        }
+        Self { nodes, positions }
+    }
+
+    /// Position of the node for a region of the annotations the tree was built from
+    pub fn position(&self, region: &Region) -> Option<usize> {
+        self.positions.get(&address(region)).copied()
+    }
+
+    /// Labels from the outermost region down to this one, e.g. `Tissue / Tumor`
+    pub fn nested_label(&self, region: &Region) -> Option<String> {
+        let mut labels = Vec::new();
+        let mut current = self.position(region);
+        while let Some(i) = current {
+            labels.push(self.nodes[i].region.label());
+            current = self.nodes[i].parent;
+        }
+        if labels.is_empty() {
+            return None;
+        }
+        labels.reverse();
+        Some(labels.join(" / "))
+    }
+}
+
+/// Shape measurements that can be reported as extra output columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measurement {
+    Perimeter,
+    Centroid,
+    BoundingBox,
+    Hull,
+    /// Area left for analysis once NegativeROA regions are cut out
+    EffectiveArea,
+}
+
+impl Measurement {
+    /// Parse a comma separated list of measurement names, e.g. `perimeter,centroid`
+    pub fn parse_list(names: &str) -> Result<Vec<Measurement>, Box<dyn error::Error>> {
+        names.split(',').map(|name| match name.trim().to_ascii_lowercase().as_str() {
+            "perimeter" => Ok(Measurement::Perimeter),
+            "centroid" => Ok(Measurement::Centroid),
+            "bbox" => Ok(Measurement::BoundingBox),
+            "hull" => Ok(Measurement::Hull),
+            "effective-area" => Ok(Measurement::EffectiveArea),
+            other => Err(format!("Unknown measurement {}", other).into()),
+        }).collect()
+    }
+
+    /// Names of the output columns holding this measurement
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            Measurement::Perimeter => &["perimeter", "perimeter_microns"],
+            Measurement::Centroid => &["centroid_x", "centroid_y", "centroid_x_microns", "centroid_y_microns"],
+            Measurement::BoundingBox => &["bbox_min_x", "bbox_min_y", "bbox_max_x", "bbox_max_y"],
+            Measurement::Hull => &["hull_area", "solidity"],
+            Measurement::EffectiveArea => &["effective_area", "effective_area_microns"],
+        }
+    }
+
+    /// Values for each of `columns()`, micron values are None without a scan resolution
+    /// Bounding boxes are in pixels, ready for cropping the slide.
+    pub fn values(&self, region: &Region, annotations: &Annotations) -> Vec<Option<f64>> {
+        let microns_per_pixel = annotations.microns_per_pixel();
+        match self {
+            Measurement::Perimeter => {
+                let perimeter = region.perimeter();
+                vec![Some(perimeter), microns_per_pixel.map(|m| perimeter * m)]
+            },
+            Measurement::Centroid => {
+                let centroid = region.centroid();
+                let microns = centroid.zip(microns_per_pixel).map(|((x, y), m)| (x * m, y * m));
+                vec![centroid.map(|c| c.0), centroid.map(|c| c.1), microns.map(|c| c.0), microns.map(|c| c.1)]
+            },
+            Measurement::BoundingBox => {
+                let bbox = region.bounding_box();
+                vec![bbox.map(|b| b.min_x), bbox.map(|b| b.min_y), bbox.map(|b| b.max_x), bbox.map(|b| b.max_y)]
+            },
+            Measurement::Hull => vec![Some(region.hull_area()), region.solidity()],
+            Measurement::EffectiveArea => {
+                let area = annotations.effective_area(region);
+                vec![Some(area), microns_per_pixel.map(|m| area * m * m)]
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Affine, BoundingBox, ELLIPSE_SEGMENTS, RegionIndex, RegionTree, convex_hull, intersection_area, path_length, polygon_centroid, polygon_contains, simplify};
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::model::{Region, RegionType};
+
+    fn rectangle(x1: f64, y1: f64, x2: f64, y2: f64) -> RegionBuilder {
+        RegionBuilder::new().vertices([(x1, y1), (x2, y1), (x2, y2), (x1, y2)])
+    }
+
+    /// The region built on its own
+    fn region(builder: RegionBuilder) -> Region {
+        AnnotationsBuilder::new().layer(AnnotationBuilder::new().region(builder)).build().annotation.remove(0).regions.region.remove(0)
+    }
+
+    #[test]
+    fn effective_area_subtracts_the_union_of_the_layer_exclusions() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(rectangle(0.0, 0.0, 100.0, 100.0))
+                .region(rectangle(0.0, 0.0, 50.0, 100.0).negative_roa(true))
+                .region(rectangle(25.0, 0.0, 75.0, 100.0).negative_roa(true))
+                .region(RegionBuilder::new().vertices([(60.0, 60.0), (100.0, 60.0), (100.0, 100.0)]).negative_roa(true)))
+            .layer(AnnotationBuilder::new().region(rectangle(75.0, 0.0, 100.0, 50.0).negative_roa(true)))
+            .build();
+        let layer = &annotations.annotation[0].regions.region;
+        // 25 x 100 left of the overlapping strips, less the part of the triangle right of them
+        assert!((annotations.effective_area(&layer[0]) - (2500.0 - (800.0 - 112.5))).abs() < 1e-9);
+        assert_eq!(annotations.effective_area(&layer[1]), 0.0);
+    }
+
+    #[test]
+    fn nearest_matches_a_full_scan() {
+        // A grid of squares of varying size, every third one a vessel, and a long thin strip across the grid
+        let mut layer = AnnotationBuilder::new().region(rectangle(-500.0, 415.0, 1500.0, 418.0).text("vessel"));
+        for i in 0..100 {
+            let (x, y, size) = ((i % 10) as f64 * 100.0, (i / 10) as f64 * 90.0, 10.0 + (i * 7 % 30) as f64);
+            let label = if i % 3 == 0 { "vessel" } else { "tumor" };
+            layer = layer.region(rectangle(x, y, x + size, y + size).text(label));
+        }
+        let annotations = AnnotationsBuilder::new().layer(layer).build();
+        let index = RegionIndex::new(&annotations);
+        let regions = &annotations.annotation[0].regions.region;
+        // Squares in the same column have collinear sides but do not touch
+        assert_eq!(regions[1].edge_distance(&regions[91]), Some(800.0));
+        for region in regions {
+            for label in [None, Some("vessel"), Some("tumor")] {
+                let expected = regions.iter()
+                    .filter(|other| !std::ptr::eq(*other, region) && label.is_none_or(|label| other.label() == label))
+                    .filter_map(|other| region.edge_distance(other))
+                    .min_by(|a, b| a.total_cmp(b));
+                assert_eq!(index.nearest(region, label).map(|(_, _, distance)| distance), expected);
+            }
+        }
+        assert!(index.nearest(&regions[0], Some("stroma")).is_none());
+    }
+
+    #[test]
+    fn nested_labels_run_from_the_outermost_region() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(rectangle(0.0, 0.0, 100.0, 100.0).text("Tissue"))
+                .region(rectangle(10.0, 10.0, 50.0, 50.0).text("Tumor"))
+                .region(rectangle(20.0, 20.0, 30.0, 30.0).text("Necrosis"))
+                .region(rectangle(90.0, 90.0, 150.0, 150.0).text("Margin")))
+            .layer(AnnotationBuilder::new().annotation_type("3").region(rectangle(20.0, 20.0, 30.0, 30.0).text("Analysis")))
+            .build();
+        let tree = RegionTree::new(&annotations);
+        let regions = &annotations.annotation[0].regions.region;
+        let labels: Vec<Option<String>> = regions.iter().map(|r| tree.nested_label(r)).collect();
+        assert_eq!(labels, [
+            Some(String::from("Tissue")),
+            Some(String::from("Tissue / Tumor")),
+            Some(String::from("Tissue / Tumor / Necrosis")),
+            Some(String::from("Margin")),
+        ]);
+        assert_eq!(tree.nodes[0].children, [1]);
+        // Analysis layers are left out of the tree
+        let analysis = &annotations.annotation[1].regions.region[0];
+        assert_eq!(tree.position(analysis), None);
+        assert_eq!(tree.nested_label(analysis), None);
+    }
+
+    #[test]
+    fn path_length_and_perimeter() {
+        let triangle = [(0.0, 0.0), (3.0, 0.0), (3.0, 4.0)];
+        assert_eq!(path_length(&triangle, false), 7.0);
+        assert_eq!(path_length(&triangle, true), 12.0);
+        // A line is not closed back on itself
+        assert_eq!(path_length(&triangle[..2], true), 3.0);
+        assert_eq!(path_length(&[], true), 0.0);
+        assert_eq!(region(RegionBuilder::new().vertices(triangle)).perimeter(), 12.0);
+        assert_eq!(region(RegionBuilder::new().region_type(RegionType::Polyline).vertices(triangle)).perimeter(), 7.0);
+        assert_eq!(region(RegionBuilder::new().region_type(RegionType::Rectangle).vertex(0.0, 0.0).vertex(3.0, 4.0)).perimeter(), 14.0);
+    }
+
+    #[test]
+    fn centroid_is_that_of_the_enclosed_area() {
+        // An L of a 2 x 1 and a 1 x 1 square: (2 * (1, 0.5) + (0.5, 1.5)) / 3
+        let l_shape = [(0.0, 0.0), (2.0, 0.0), (2.0, 1.0), (1.0, 1.0), (1.0, 2.0), (0.0, 2.0)];
+        let (x, y) = polygon_centroid(&l_shape).unwrap();
+        assert!((x - 2.5 / 3.0).abs() < 1e-12 && (y - 2.5 / 3.0).abs() < 1e-12);
+        // Orientation does not matter
+        let reversed: Vec<_> = l_shape.iter().rev().copied().collect();
+        assert_eq!(polygon_centroid(&reversed), polygon_centroid(&l_shape));
+        // Without an area, the mean of the points
+        assert_eq!(polygon_centroid(&[(0.0, 0.0), (4.0, 2.0)]), Some((2.0, 1.0)));
+        assert_eq!(polygon_centroid(&[]), None);
+        // Open shapes use the mean of their vertices
+        let ruler = region(RegionBuilder::new().region_type(RegionType::Polyline).vertices([(0.0, 0.0), (6.0, 0.0), (6.0, 3.0)]));
+        assert_eq!(ruler.centroid(), Some((4.0, 1.0)));
+    }
+
+    #[test]
+    fn bounding_boxes_enclose_every_point() {
+        let bbox = BoundingBox::of(&[(3.0, -1.0), (-2.0, 4.0), (1.0, 1.0)]).unwrap();
+        assert_eq!(bbox, BoundingBox { min_x: -2.0, min_y: -1.0, max_x: 3.0, max_y: 4.0 });
+        assert_eq!((bbox.width(), bbox.height()), (5.0, 5.0));
+        assert_eq!(BoundingBox::of(&[]), None);
+        // Sharing only an edge counts as intersecting
+        let touching = BoundingBox { min_x: 3.0, min_y: 0.0, max_x: 5.0, max_y: 1.0 };
+        let apart = BoundingBox { min_x: 3.5, min_y: 0.0, max_x: 5.0, max_y: 1.0 };
+        assert!(bbox.intersects(&touching) && touching.intersects(&bbox));
+        assert!(!bbox.intersects(&apart));
+        // Rectangles are given by two corners in any order
+        let rectangle = region(RegionBuilder::new().region_type(RegionType::Rectangle).vertex(10.0, 8.0).vertex(2.0, 4.0));
+        assert_eq!(rectangle.bounding_box(), Some(BoundingBox { min_x: 2.0, min_y: 4.0, max_x: 10.0, max_y: 8.0 }));
+    }
+
+    #[test]
+    fn convex_hull_runs_counter_clockwise_without_inner_points() {
+        // A square with a point inside, a repeated corner and a point halfway along an edge
+        let points = [(0.0, 0.0), (2.0, 2.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0), (4.0, 0.0), (2.0, 0.0), (1.0, 3.0)];
+        assert_eq!(convex_hull(&points), [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)]);
+        assert_eq!(convex_hull(&[(1.0, 1.0), (0.0, 0.0), (1.0, 1.0)]), [(0.0, 0.0), (1.0, 1.0)]);
+        // A concave arrow head fills in to its triangle
+        let arrow = region(RegionBuilder::new().vertices([(0.0, 0.0), (4.0, 2.0), (0.0, 4.0), (2.0, 2.0)]));
+        assert_eq!(arrow.hull_area(), 8.0);
+        assert_eq!(arrow.solidity(), Some(0.5));
+    }
+
+    #[test]
+    fn points_inside_concave_polygons() {
+        // A U opening upwards, its notch from x 1 to 3 above y 1
+        let u_shape = [(0.0, 0.0), (4.0, 0.0), (4.0, 3.0), (3.0, 3.0), (3.0, 1.0), (1.0, 1.0), (1.0, 3.0), (0.0, 3.0)];
+        assert!(polygon_contains(&u_shape, (0.5, 2.0)));
+        assert!(polygon_contains(&u_shape, (2.0, 0.5)));
+        assert!(!polygon_contains(&u_shape, (2.0, 2.0)));
+        assert!(!polygon_contains(&u_shape, (5.0, 0.5)));
+        // Points level with a vertex are counted once
+        assert!(polygon_contains(&u_shape, (0.5, 1.0)));
+        assert!(!polygon_contains(&u_shape, (-1.0, 1.0)));
+        // On the outline the even-odd rule is half-open: the low sides are inside, the high sides are not
+        let square = [(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)];
+        assert!(polygon_contains(&square, (0.0, 1.0)) && polygon_contains(&square, (1.0, 0.0)));
+        assert!(!polygon_contains(&square, (2.0, 1.0)) && !polygon_contains(&square, (1.0, 2.0)));
+        assert!(!polygon_contains(&square[..2], (1.0, 0.0)));
+        // Open shapes contain nothing
+        let polyline = region(RegionBuilder::new().region_type(RegionType::Polyline).vertices(square));
+        assert!(!polyline.contains_point(1.0, 1.0));
+        assert!(region(RegionBuilder::new().vertices(square)).contains_point(1.0, 1.0));
+    }
+
+    #[test]
+    fn index_finds_regions_by_point_and_box() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(rectangle(0.0, 0.0, 10.0, 10.0).text("Tissue"))
+                // A triangle whose bounding box holds (9, 1) but the triangle itself does not
+                .region(RegionBuilder::new().vertices([(0.0, 0.0), (10.0, 10.0), (0.0, 10.0)]).text("Tumor"))
+                .region(RegionBuilder::new().text("Empty")))
+            .layer(AnnotationBuilder::new().annotation_type("3").region(rectangle(20.0, 20.0, 30.0, 30.0).text("Analysis")))
+            .build();
+        let labels = |found: Vec<(&str, &Region)>| -> Vec<(String, String)> {
+            let mut labels: Vec<(String, String)> = found.into_iter().map(|(layer, r)| (layer.to_string(), r.label())).collect();
+            labels.sort();
+            labels
+        };
+        let index = RegionIndex::new(&annotations);
+        // Regions without vertices are left out
+        assert_eq!(index.len(), 3);
+        assert_eq!(labels(index.regions_containing(2.0, 8.0)), [("1".into(), "Tissue".into()), ("1".into(), "Tumor".into())]);
+        assert_eq!(labels(index.regions_containing(9.0, 1.0)), [("1".into(), "Tissue".into())]);
+        assert!(index.regions_containing(15.0, 15.0).is_empty());
+        // Candidates by bounding box only, touching boxes included
+        let bbox = BoundingBox { min_x: 10.0, min_y: 10.0, max_x: 20.0, max_y: 20.0 };
+        assert_eq!(index.regions_near(&bbox).len(), 3);
+        let bbox = BoundingBox { min_x: 11.0, min_y: 11.0, max_x: 19.0, max_y: 19.0 };
+        assert!(index.regions_near(&bbox).is_empty());
+        // Drawn layers only
+        let drawn = RegionIndex::drawn(&annotations);
+        assert_eq!(drawn.len(), 2);
+        assert!(drawn.regions_containing(25.0, 25.0).is_empty());
+        assert_eq!(labels(index.regions_containing(25.0, 25.0)), [("2".into(), "Analysis".into())]);
+    }
+
+    #[test]
+    fn overlap_of_concave_and_disjoint_polygons() {
+        let square = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)];
+        let shifted = [(2.0, 2.0), (6.0, 2.0), (6.0, 6.0), (2.0, 6.0)];
+        assert!((intersection_area(&square, &shifted) - 4.0).abs() < 1e-9);
+        // A U whose notch (x 1 to 3, y 1 to 3) holds most of a 2 x 2 square that also covers 1 x 2 of the arms
+        let u_shape = [(0.0, 0.0), (4.0, 0.0), (4.0, 3.0), (3.0, 3.0), (3.0, 1.0), (1.0, 1.0), (1.0, 3.0), (0.0, 3.0)];
+        let in_notch = [(1.0, 1.0), (3.0, 1.0), (3.0, 3.0), (1.0, 3.0)];
+        assert!(intersection_area(&u_shape, &in_notch).abs() < 1e-9);
+        let across = [(0.5, 0.5), (3.5, 0.5), (3.5, 1.5), (0.5, 1.5)];
+        assert!((intersection_area(&u_shape, &across) - 2.0).abs() < 1e-9);
+        // Clockwise input gives the same area
+        let clockwise: Vec<_> = u_shape.iter().rev().copied().collect();
+        assert!((intersection_area(&clockwise, &across) - 2.0).abs() < 1e-9);
+        // Disjoint, and touching only along an edge
+        assert_eq!(intersection_area(&square, &[(10.0, 10.0), (11.0, 10.0), (11.0, 11.0)]), 0.0);
+        assert!(intersection_area(&square, &[(4.0, 0.0), (8.0, 0.0), (8.0, 4.0), (4.0, 4.0)]).abs() < 1e-9);
+
+        let a = region(RegionBuilder::new().vertices(square));
+        let b = region(RegionBuilder::new().vertices(shifted));
+        // 4 shared out of 16 + 16 - 4
+        assert!((a.iou(&b).unwrap() - 4.0 / 28.0).abs() < 1e-9);
+        assert!((a.iou(&a).unwrap() - 1.0).abs() < 1e-9);
+        let far = region(RegionBuilder::new().vertices([(10.0, 10.0), (11.0, 10.0), (11.0, 11.0)]));
+        assert_eq!(a.iou(&far), Some(0.0));
+        let line = region(RegionBuilder::new().region_type(RegionType::Ruler).vertices([(0.0, 0.0), (4.0, 4.0)]));
+        assert_eq!(line.iou(&line), None);
+    }
+
+    #[test]
+    fn simplify_keeps_the_corners() {
+        // Jitter along the bottom, then straight up
+        let path = [(0.0, 0.0), (1.0, 0.1), (2.0, 0.0), (3.0, 0.1), (4.0, 0.0), (4.0, 1.0), (4.0, 2.0), (4.3, 3.0), (4.0, 4.0)];
+        assert_eq!(simplify(&path, 0.5, false), [0, 4, 8]);
+        assert_eq!(simplify(&path, 0.05, false), [0, 1, 2, 3, 4, 6, 7, 8]);
+        assert_eq!(simplify(&path[..2], 10.0, false), [0, 1]);
+        // A closed outline keeps its far side, which an open path through the same points would lose
+        let square = [(0.0, 0.0), (2.0, 0.0), (4.0, 0.0), (4.0, 2.0), (4.0, 4.0), (2.0, 4.0), (0.0, 4.0), (0.0, 2.0)];
+        assert_eq!(simplify(&square, 0.5, true), [0, 2, 4, 6]);
+
+        let mut annotations = AnnotationsBuilder::new().microns_per_pixel(0.25)
+            .layer(AnnotationBuilder::new().region(RegionBuilder::new().vertices(path)))
+            .build();
+        // 0.05 microns is 0.2 pixels, which drops the jitter but not the bend at (4.3, 3)
+        annotations.simplify(0.05).unwrap();
+        let kept: Vec<(&str, &str)> = annotations.annotation[0].regions.region[0].vertices.as_ref().unwrap().vertex.iter().map(|v| (v.x.as_str(), v.y.as_str())).collect();
+        assert_eq!(kept, [("0", "0"), ("4", "0"), ("4.3", "3"), ("4", "4")]);
+        annotations.microns_per_pixel = None;
+        assert!(annotations.simplify(0.05).is_err());
+    }
+
+    #[test]
+    fn to_microns_scales_every_vertex() {
+        let mut annotations = AnnotationsBuilder::new().microns_per_pixel(0.5)
+            .layer(AnnotationBuilder::new().region(rectangle(0.0, 0.0, 10.0, 20.0)))
+            .layer(AnnotationBuilder::new().region(RegionBuilder::new().region_type(RegionType::Ruler).vertices([(4.0, 6.0), (8.0, 6.0)])))
+            .build();
+        annotations.to_microns().unwrap();
+        let points: Vec<Vec<(f64, f64)>> = annotations.annotation.iter().map(|layer| layer.regions.region[0].outline()).collect();
+        assert_eq!(points, [vec![(0.0, 0.0), (5.0, 0.0), (5.0, 10.0), (0.0, 10.0)], vec![(2.0, 3.0), (4.0, 3.0)]]);
+        // Measurements in microns stay correct
+        assert_eq!(annotations.microns_per_pixel(), Some(1.0));
+        assert_eq!(annotations.annotation[0].regions.region[0].computed_area(), 50.0);
+        annotations.microns_per_pixel = None;
+        assert!(annotations.to_microns().is_err());
+    }
+
+    #[test]
+    fn affine_transforms_parse_and_apply() {
+        let affine: Affine = " 2, 0, 10, 0, 3, -5 ".parse().unwrap();
+        assert_eq!(affine.matrix, [2.0, 0.0, 10.0, 0.0, 3.0, -5.0]);
+        assert_eq!(affine.apply((1.0, 2.0)), (12.0, 1.0));
+        // A quarter turn about the origin
+        let rotation: Affine = "0,-1,0,1,0,0".parse().unwrap();
+        assert_eq!(rotation.apply((3.0, 4.0)), (-4.0, 3.0));
+        assert!("1,0,0,0,1".parse::<Affine>().unwrap_err().to_string().contains("does not have six numbers"));
+        assert!("1,0,0,0,1,x".parse::<Affine>().unwrap_err().to_string().starts_with("Invalid affine matrix"));
+    }
+
+    #[test]
+    fn two_corner_shapes_become_full_outlines() {
+        let rectangle = region(RegionBuilder::new().region_type(RegionType::Rectangle).vertex(10.0, 20.0).vertex(4.0, 2.0));
+        assert_eq!(rectangle.outline(), [(10.0, 20.0), (4.0, 20.0), (4.0, 2.0), (10.0, 2.0)]);
+        assert_eq!(rectangle.computed_area(), 108.0);
+
+        let mut ellipse = region(RegionBuilder::new().region_type(RegionType::Ellipse).vertex(0.0, 0.0).vertex(20.0, 10.0));
+        let outline = ellipse.outline();
+        assert_eq!(outline.len(), ELLIPSE_SEGMENTS);
+        assert_eq!(outline[0], (20.0, 5.0));
+        // Every point lies on the ellipse centered at (10, 5) with radii 10 and 5
+        assert!(outline.iter().all(|(x, y)| (((x - 10.0) / 10.0).powi(2) + ((y - 5.0) / 5.0).powi(2) - 1.0).abs() < 1e-9));
+        let exact = std::f64::consts::PI * 50.0;
+        assert!((ellipse.computed_area() - exact).abs() < 0.001 * exact);
+        assert!((ellipse.centroid().unwrap().0 - 10.0).abs() < 1e-9);
+        // Storing the outline keeps the shape
+        ellipse.expand_outline();
+        assert_eq!(ellipse.vertices.as_ref().unwrap().vertex.len(), ELLIPSE_SEGMENTS);
+        assert_eq!(ellipse.outline(), outline);
+
+        // Other shapes and vertex counts are used as they are
+        let polygon = [(0.0, 0.0), (4.0, 0.0), (4.0, 3.0)];
+        assert_eq!(region(RegionBuilder::new().vertices(polygon)).outline(), polygon);
+        assert_eq!(region(RegionBuilder::new().region_type(RegionType::Rectangle).vertices(polygon)).outline(), polygon);
+    }
+}
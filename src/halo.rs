@@ -0,0 +1,141 @@
+use std::error;
+use serde::Deserialize;
+use crate::builder::{AnnotationBuilder, AnnotationsBuilder, DEFAULT_LINE_COLOR, RegionBuilder};
+use crate::encoding::decode_xml;
+use crate::model::{Annotations, RegionType};
+use crate::number::{DecimalSeparator, parse_number};
+use crate::parse::skip_leading_junk;
+
+/// Root `Annotations` element of a HALO `.annotations` file
+#[derive(Deserialize, Debug, Default)]
+struct HaloDocument {
+    #[serde(rename = "Annotation", default)]
+    annotation: Vec<HaloLayer>,
+}
+
+#[derive(Deserialize, Debug)]
+struct HaloLayer {
+    #[serde(rename = "@Name", default)]
+    name: String,
+    #[serde(rename = "@LineColor", default)]
+    line_color: String,
+    #[serde(rename = "Regions", default)]
+    regions: HaloRegions,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct HaloRegions {
+    #[serde(rename = "Region", default)]
+    region: Vec<HaloRegion>,
+}
+
+#[derive(Deserialize, Debug)]
+struct HaloRegion {
+    #[serde(rename = "@Type", default)]
+    region_type: String,
+    #[serde(rename = "@NegativeROA", default)]
+    negative_roa: String,
+    #[serde(rename = "@Text", default)]
+    text: String,
+    #[serde(rename = "Vertices", default)]
+    vertices: HaloVertices,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct HaloVertices {
+    #[serde(rename = "V", default)]
+    vertex: Vec<HaloVertex>,
+}
+
+#[derive(Deserialize, Debug)]
+struct HaloVertex {
+    #[serde(rename = "@X")]
+    x: String,
+    #[serde(rename = "@Y")]
+    y: String,
+}
+
+/// Read an Indica Labs HALO `.annotations` file
+/// HALO layers keep their names and BGR line colors. Their regions carry the region text if any, otherwise
+/// the layer name. Rectangles and ellipses keep their two corners as in ImageScope, and pins are skipped.
+/// Returns the annotations and a description of every region skipped.
+pub fn import_halo_annotations(contents: &[u8]) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+    let xml = decode_xml(contents)?;
+    let document: HaloDocument = quick_xml::de::from_str(skip_leading_junk(&xml))?;
+    let mut builder = AnnotationsBuilder::new();
+    let mut skipped = Vec::new();
+    for halo_layer in document.annotation {
+        let color = halo_layer.line_color.trim().parse().unwrap_or(DEFAULT_LINE_COLOR);
+        let mut layer = AnnotationBuilder::new().name(halo_layer.name.clone()).line_color(color);
+        for region in halo_layer.regions.region {
+            let region_type = match region.region_type.as_str() {
+                "Polygon" => RegionType::Freehand,
+                "Rectangle" => RegionType::Rectangle,
+                "Ellipse" => RegionType::Ellipse,
+                "Ruler" => RegionType::Ruler,
+                other => {
+                    skipped.push(format!("Skipping HALO {} region in layer {:?}, ImageScope has no matching region type", other, halo_layer.name));
+                    continue;
+                },
+            };
+            let mut points = Vec::with_capacity(region.vertices.vertex.len());
+            for vertex in &region.vertices.vertex {
+                match (parse_number(&vertex.x, DecimalSeparator::Auto), parse_number(&vertex.y, DecimalSeparator::Auto)) {
+                    (Some(x), Some(y)) => points.push((x, y)),
+                    _ => return Err(format!("Invalid vertex ({}, {}) in HALO layer {:?}", vertex.x, vertex.y, halo_layer.name).into()),
+                }
+            }
+            // HALO writes booleans as 0/1 or False/True
+            let negative = region.negative_roa.trim() == "1" || region.negative_roa.trim().eq_ignore_ascii_case("true");
+            let text = if region.text.is_empty() { halo_layer.name.as_str() } else { region.text.as_str() };
+            layer = layer.region(RegionBuilder::new().region_type(region_type).text(text).negative_roa(negative).vertices(points));
+        }
+        builder = builder.layer(layer);
+    }
+    Ok((builder.build(), skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::RegionType;
+    use crate::render::layer_color;
+    use super::import_halo_annotations;
+
+    #[test]
+    fn layers_keep_their_names_and_colors() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+            <Annotations>
+                <Annotation Name="Tumor" LineColor="255" Visible="True">
+                    <Regions>
+                        <Region Type="Polygon" HasEndcaps="0" NegativeROA="0">
+                            <Vertices><V X="0" Y="0"/><V X="10.5" Y="0"/><V X="10.5" Y="10"/></Vertices>
+                        </Region>
+                        <Region Type="Rectangle" NegativeROA="True" Text="Necrosis">
+                            <Vertices><V X="2" Y="2"/><V X="4" Y="4"/></Vertices>
+                        </Region>
+                        <Region Type="Pin" NegativeROA="0">
+                            <Vertices><V X="1" Y="1"/></Vertices>
+                        </Region>
+                    </Regions>
+                </Annotation>
+                <Annotation Name="Stroma">
+                    <Regions>
+                        <Region Type="Ellipse" NegativeROA="0"><Vertices><V X="0" Y="0"/><V X="8" Y="4"/></Vertices></Region>
+                    </Regions>
+                </Annotation>
+            </Annotations>"#;
+        let (annotations, skipped) = import_halo_annotations(xml.as_bytes()).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert!(skipped[0].contains("Pin"), "{}", skipped[0]);
+        assert_eq!(layer_color(&annotations.annotation[0]).as_deref(), Some("#ff0000"));
+        // Layers without a color get ImageScope's default green
+        assert_eq!(layer_color(&annotations.annotation[1]).as_deref(), Some("#00ff00"));
+        let tumor = &annotations.annotation[0].regions.region;
+        assert_eq!(tumor.len(), 2);
+        assert_eq!((&tumor[0].region_type, tumor[0].label(), tumor[0].is_negative()), (&RegionType::Freehand, String::from("Tumor"), false));
+        assert_eq!(tumor[0].outline(), [(0.0, 0.0), (10.5, 0.0), (10.5, 10.0)]);
+        assert_eq!((&tumor[1].region_type, tumor[1].label(), tumor[1].is_negative()), (&RegionType::Rectangle, String::from("Necrosis"), true));
+        let stroma = &annotations.annotation[1].regions.region[0];
+        assert_eq!((&stroma.region_type, stroma.label()), (&RegionType::Ellipse, String::from("Stroma")));
+    }
+}
@@ -0,0 +1,169 @@
+use std::error;
+use quick_xml::escape::escape;
+use crate::convert::ExportRegion;
+use crate::geometry::{BoundingBox, Point, polygon_spans};
+
+/// Closed regions with their positivity, NegativeROA regions have none
+fn footprints<'a>(regions: &'a [ExportRegion]) -> Vec<(&'a ExportRegion<'a>, Vec<Point>, Option<f64>)> {
+    regions.iter()
+        .filter(|r| r.region.region_type.is_closed())
+        .map(|r| (r, r.region.outline(), r.record.and_then(|record| record.positivity)))
+        .filter(|(_, outline, _)| outline.len() >= 3)
+        .collect()
+}
+
+/// Largest heatmap we make, in cells, as each takes 16 bytes of memory
+const MAX_HEATMAP_CELLS: u64 = 1 << 26;
+
+/// Positivity per cell of a grid over the regions, row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heatmap {
+    pub width: u32,
+    pub height: u32,
+    /// Level-0 pixel position of the top left corner of the grid on the slide
+    pub x: f64,
+    pub y: f64,
+    pub cells: Vec<Option<f64>>,
+}
+
+/// First column and row and size of a grid holding all regions, on the grid of a map of the whole slide
+fn grid_extent(footprints: &[(&ExportRegion, Vec<Point>, Option<f64>)], downsample: f64) -> (f64, f64, f64, f64) {
+    let all_points: Vec<Point> = footprints.iter()
+        .filter(|(r, _, _)| !r.region.is_negative())
+        .flat_map(|(_, outline, _)| outline.iter().copied())
+        .collect();
+    let (first_column, first_row, last_column, last_row) = BoundingBox::of(&all_points)
+        .map(|b| ((b.min_x / downsample).floor().max(0.0), (b.min_y / downsample).floor().max(0.0), (b.max_x / downsample).ceil().max(0.0), (b.max_y / downsample).ceil().max(0.0)))
+        .unwrap_or((0.0, 0.0, 0.0, 0.0));
+    (first_column, first_row, (last_column - first_column).max(1.0), (last_row - first_row).max(1.0))
+}
+
+/// Color for a positivity from 0 (blue) through 0.5 (grey) to 1 (red)
+fn heat_color(positivity: f64) -> String {
+    let p = positivity.clamp(0.0, 1.0);
+    let (low, middle, high) = ((59.0, 76.0, 192.0), (221.0, 221.0, 221.0), (180.0, 4.0, 38.0));
+    let (from, to, t) = if p < 0.5 { (low, middle, p * 2.0) } else { (middle, high, p * 2.0 - 1.0) };
+    let mix = |a: f64, b: f64| (a + (b - a) * t).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+/// Grid of positivity per cell of `downsample` level-0 pixels
+/// Cells take the positivity of the last region covering their center, none outside regions and inside
+/// NegativeROA regions. The grid covers the bounding box of the regions and is refused when it would be too
+/// large to hold in memory.
+pub fn heatmap_grid(regions: &[ExportRegion], downsample: f64) -> Result<Heatmap, Box<dyn error::Error>> {
+    let footprints = footprints(regions);
+    let (first_column, first_row, width, height) = grid_extent(&footprints, downsample);
+    if width * height > MAX_HEATMAP_CELLS as f64 {
+        return Err(format!("A heatmap of {:.0} x {:.0} cells is too large, raise --downsample", width, height).into());
+    }
+    let (x, y) = (first_column * downsample, first_row * downsample);
+    let mut heatmap = Heatmap { width: width as u32, height: height as u32, x, y, cells: vec![None; width as usize * height as usize] };
+    // Cut-outs are applied after all regions are drawn
+    let ordered = footprints.iter().filter(|(r, _, _)| !r.region.is_negative()).chain(footprints.iter().filter(|(r, _, _)| r.region.is_negative()));
+    for (r, outline, positivity) in ordered {
+        let value = if r.region.is_negative() { None } else { *positivity };
+        let shifted: Vec<Point> = outline.iter().map(|(px, py)| (px - x, py - y)).collect();
+        for (row, start, end) in polygon_spans(&shifted, downsample, heatmap.width, heatmap.height) {
+            let offset = (row * heatmap.width) as usize;
+            heatmap.cells[offset + start as usize..offset + end as usize].fill(value);
+        }
+    }
+    Ok(heatmap)
+}
+
+/// Heatmap grid as CSV, see [`heatmap_grid`]
+pub fn heatmap_csv(regions: &[ExportRegion], downsample: f64) -> Result<String, Box<dyn error::Error>> {
+    Ok(heatmap_grid(regions, downsample)?.to_csv())
+}
+
+impl Heatmap {
+    /// One line per row of cells, blank for cells without a positivity
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for row in self.cells.chunks(self.width as usize) {
+            let cells: Vec<String> = row.iter().map(|cell| cell.map(|p| p.to_string()).unwrap_or_default()).collect();
+            csv.push_str(&cells.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+/// SVG with each region filled by the color of its positivity, scaled down by `downsample`
+/// Regions without a positivity are only outlined, NegativeROA regions are drawn white on top. The view covers
+/// the same cells as [`heatmap_grid`].
+pub fn heatmap_svg(regions: &[ExportRegion], downsample: f64) -> String {
+    let footprints = footprints(regions);
+    let (first_column, first_row, width, height) = grid_extent(&footprints, downsample);
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"{x} {y} {w} {h}\">\n", x = first_column, y = first_row, w = width, h = height);
+    let ordered = footprints.iter().filter(|(r, _, _)| !r.region.is_negative()).chain(footprints.iter().filter(|(r, _, _)| r.region.is_negative()));
+    for (r, outline, positivity) in ordered {
+        let points = outline.iter().map(|(x, y)| format!("{},{}", x / downsample, y / downsample)).collect::<Vec<_>>().join(" ");
+        let (fill, title) = match (r.region.is_negative(), positivity) {
+            (true, _) => (String::from("#ffffff"), String::from("excluded")),
+            (false, Some(p)) => (heat_color(*p), format!("positivity {}", p)),
+            (false, None) => (String::from("none"), String::from("no positivity")),
+        };
+        let title = escape(format!("Region {}: {} ({})", r.region.id, r.region.label(), title).as_str()).to_string();
+        svg.push_str(&format!("  <polygon points=\"{}\" fill=\"{}\" stroke=\"#555555\" stroke-width=\"1\"><title>{}</title></polygon>\n", points, fill, title));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::extract::RegionRecord;
+    use crate::model::RegionType;
+    use super::heatmap_grid;
+
+    fn rectangle(text: &str, from: (f64, f64), to: (f64, f64)) -> RegionBuilder {
+        RegionBuilder::new().region_type(RegionType::Rectangle).text(text).vertices([from, to])
+    }
+
+    fn record(region_id: &str, positivity: f64) -> RegionRecord {
+        RegionRecord {
+            filename: String::from("case1.xml"),
+            slide_name: String::from("case1.svs"),
+            region_id: String::from(region_id),
+            text_label: String::new(),
+            positivity: Some(positivity),
+            num_wpositive: None,
+            num_positive: None,
+            num_spositive: None,
+            num_all_positive: 0.0,
+            num_total: None,
+            algorithm: String::new(),
+            columns: Vec::new(),
+            text_columns: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn cells_take_the_positivity_of_their_region() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(rectangle("Tumor", (10.0, 10.0), (50.0, 30.0)))
+                .region(rectangle("", (30.0, 10.0), (50.0, 20.0)).negative_roa(true))
+                .region(rectangle("Stroma", (10.0, 20.0), (20.0, 30.0)))
+                .region(rectangle("Other", (40.0, 20.0), (50.0, 30.0))))
+            .build();
+        let records = [record("1", 0.25), record("3", 0.75)];
+        let heatmap = heatmap_grid(&export_regions(&annotations, &records), 10.0).unwrap();
+        // The grid starts at the cell holding the top left corner of the regions
+        assert_eq!((heatmap.width, heatmap.height, heatmap.x, heatmap.y), (4, 2, 10.0, 10.0));
+        assert_eq!(heatmap.cells, [Some(0.25), Some(0.25), None, None, Some(0.75), Some(0.25), Some(0.25), None]);
+        assert_eq!(heatmap.to_csv(), "0.25,0.25,,\n0.75,0.25,0.25,\n");
+        assert!(heatmap_grid(&export_regions(&annotations, &records), 0.001).is_err());
+    }
+
+    #[test]
+    fn an_empty_slide_has_one_empty_cell() {
+        let annotations = AnnotationsBuilder::new().layer(AnnotationBuilder::new()).build();
+        let heatmap = heatmap_grid(&export_regions(&annotations, &[]), 10.0).unwrap();
+        assert_eq!((heatmap.width, heatmap.height, heatmap.cells), (1, 1, vec![None]));
+    }
+}
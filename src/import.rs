@@ -0,0 +1,139 @@
+use std::{error, fmt, fs, path, str::FromStr};
+use crate::encoding::decode_xml;
+use crate::model::Annotations;
+use crate::parse::{decompress, root_element};
+
+/// Formats annotations can be converted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputFormat {
+    /// Recognize the format of every file from its contents
+    #[default]
+    Auto,
+    ImageScopeXml,
+    QupathGeoJson,
+    AsapXml,
+    HaloAnnotations,
+    SedeenSession,
+}
+
+impl InputFormat {
+    /// Extensions of the files read in this format, besides XML files for `Auto`
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Auto => &["geojson", "json", "annotations"],
+            InputFormat::ImageScopeXml => &["xml"],
+            InputFormat::QupathGeoJson => &["geojson", "json"],
+            InputFormat::AsapXml => &["xml"],
+            InputFormat::HaloAnnotations => &["annotations"],
+            InputFormat::SedeenSession => &["xml"],
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = Box<dyn error::Error>;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(InputFormat::Auto),
+            "imagescope-xml" | "imagescope" => Ok(InputFormat::ImageScopeXml),
+            "qupath-geojson" | "qupath" => Ok(InputFormat::QupathGeoJson),
+            "asap-xml" | "asap" => Ok(InputFormat::AsapXml),
+            "halo-annotations" | "halo" => Ok(InputFormat::HaloAnnotations),
+            "sedeen-session" | "sedeen" => Ok(InputFormat::SedeenSession),
+            other => Err(format!("Unknown input format {}", other).into()),
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputFormat::Auto => write!(f, "auto"),
+            InputFormat::ImageScopeXml => write!(f, "imagescope-xml"),
+            InputFormat::QupathGeoJson => write!(f, "qupath-geojson"),
+            InputFormat::AsapXml => write!(f, "asap-xml"),
+            InputFormat::HaloAnnotations => write!(f, "halo-annotations"),
+            InputFormat::SedeenSession => write!(f, "sedeen-session"),
+        }
+    }
+}
+
+/// Read annotations written by another tool into the ImageScope model
+/// Returns the annotations and a description of every shape skipped for lack of a matching ImageScope region type.
+pub fn import_annotations(contents: &[u8], format: InputFormat) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+    match format {
+        InputFormat::Auto => import_annotations(contents, detect_input_format(contents)),
+        InputFormat::ImageScopeXml => crate::parse::parse_xml_bytes_reporting(contents),
+        InputFormat::QupathGeoJson => crate::qupath::import_qupath_geojson(contents),
+        InputFormat::AsapXml => crate::asap::import_asap_xml(contents),
+        InputFormat::HaloAnnotations => crate::halo::import_halo_annotations(contents),
+        InputFormat::SedeenSession => crate::sedeen::import_sedeen_session(contents),
+    }
+}
+
+/// Recognize the format of file contents: JSON is taken for QuPath GeoJSON and XML is told apart by its root
+/// element, and by HALO's `V` vertices since HALO and ImageScope share the `Annotations` root.
+/// Anything else is left to the ImageScope XML parser.
+pub fn detect_input_format(contents: &[u8]) -> InputFormat {
+    let Ok(bytes) = decompress(contents) else { return InputFormat::ImageScopeXml };
+    let Ok(text) = decode_xml(&bytes) else { return InputFormat::ImageScopeXml };
+    let text = text.trim_start_matches(|c: char| c == '\u{FEFF}' || c.is_whitespace());
+    if text.starts_with('{') || text.starts_with('[') {
+        return InputFormat::QupathGeoJson;
+    }
+    match root_element(text) {
+        Some("ASAP_Annotations") => InputFormat::AsapXml,
+        Some("session") => InputFormat::SedeenSession,
+        Some("Annotations") if text.contains("<V ") && !text.contains("<Vertex") => InputFormat::HaloAnnotations,
+        _ => InputFormat::ImageScopeXml,
+    }
+}
+
+/// Files in `search_path` with one of the extensions of `format`, or `search_path` itself if it is a file
+pub fn import_files(search_path: &path::Path, format: InputFormat) -> Result<Vec<path::PathBuf>, Box<dyn error::Error>> {
+    if search_path.is_file() {
+        return Ok(vec![search_path.to_path_buf()]);
+    }
+    let mut files: Vec<path::PathBuf> = fs::read_dir(search_path)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().and_then(|e| e.to_str()).is_some_and(|e| format.extensions().iter().any(|x| e.eq_ignore_ascii_case(x))))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputFormat, detect_input_format};
+
+    #[test]
+    fn formats_are_recognized_from_contents() {
+        let detected = |contents: &str| detect_input_format(contents.as_bytes());
+        assert_eq!(detected(r#"{"type": "FeatureCollection", "features": []}"#), InputFormat::QupathGeoJson);
+        assert_eq!(detected("\u{FEFF}\n [{\"type\": \"Feature\"}]"), InputFormat::QupathGeoJson);
+        assert_eq!(detected(r#"<?xml version="1.0"?><!-- exported --><ASAP_Annotations><Annotations/></ASAP_Annotations>"#), InputFormat::AsapXml);
+        assert_eq!(detected(r#"<?xml version="1.0"?><session software="Sedeen Viewer"/>"#), InputFormat::SedeenSession);
+        assert_eq!(detected(r#"<Annotations><Annotation><Regions><Region><Vertices><V X="1" Y="2"/></Vertices></Region></Regions></Annotation></Annotations>"#), InputFormat::HaloAnnotations);
+        assert_eq!(detected(r#"<Annotations MicronsPerPixel="0.5"><Annotation Id="1"><Regions><Region><Vertices><Vertex X="1" Y="2"/></Vertices></Region></Regions></Annotation></Annotations>"#), InputFormat::ImageScopeXml);
+        // Anything unrecognized is left to the ImageScope parser to report
+        assert_eq!(detected("<Other/>"), InputFormat::ImageScopeXml);
+        assert_eq!(detected("not annotations"), InputFormat::ImageScopeXml);
+    }
+
+    #[test]
+    fn utf16_contents_are_decoded_first() {
+        let mut contents = vec![0xFF, 0xFE];
+        contents.extend("<ASAP_Annotations/>".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(detect_input_format(&contents), InputFormat::AsapXml);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_contents_are_decompressed_first() {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"<session/>").unwrap();
+        assert_eq!(detect_input_format(&encoder.finish().unwrap()), InputFormat::SedeenSession);
+    }
+}
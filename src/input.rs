@@ -0,0 +1,647 @@
+use std::{error, fs, io, path, str::FromStr, time};
+use std::collections::HashMap;
+#[cfg(feature = "zip")]
+use std::sync::{Arc, Mutex};
+use crate::extract::{FileSummary, slide_name};
+use crate::model::Annotations;
+use crate::options::Options;
+use crate::parse::{parse_xml_bytes_lenient, parse_xml_bytes_reporting, with_file_bytes};
+
+/// What to do when several files annotate the same slide, e.g. re-exports or copies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateSlides {
+    /// Report all files, warning about slides annotated more than once
+    #[default]
+    Warn,
+    /// Only report the most recently modified file of each slide
+    Newest,
+}
+
+impl FromStr for DuplicateSlides {
+    type Err = Box<dyn error::Error>;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "warn" => Ok(DuplicateSlides::Warn),
+            "newest" => Ok(DuplicateSlides::Newest),
+            other => Err(format!("Unknown duplicate slide policy {}, expected warn or newest", other).into()),
+        }
+    }
+}
+
+/// A single XML document to process, either a file on disk or contents already in memory
+#[derive(Debug, Clone)]
+pub struct XmlSource {
+    /// Where the document came from, used in warnings and summaries
+    pub path: path::PathBuf,
+    /// Value for the Filename column
+    pub filename: String,
+    /// Value for the Slide Name column
+    pub slide_name: String,
+    /// When the document was last modified, None if unknown
+    pub modified: Option<time::SystemTime>,
+    /// Warnings about the document found before it is read, recorded in its summary
+    pub warnings: Vec<String>,
+    /// Where to read the contents from
+    contents: Contents,
+}
+
+/// Where the contents of a XmlSource are read from
+#[derive(Debug, Clone)]
+enum Contents {
+    /// Read the file at `XmlSource::path`
+    File,
+    /// Already in memory
+    Memory(Vec<u8>),
+    /// An entry of a ZIP archive, extracted when parsed
+    /// The entries of an archive share it, so its central directory is only read once.
+    #[cfg(feature = "zip")]
+    ZipEntry { archive: Arc<Mutex<zip::ZipArchive<fs::File>>>, index: usize },
+    /// Bytes of an uncompressed archive, e.g. a tar entry, read when parsed
+    #[cfg(feature = "tar")]
+    FileRange { file: path::PathBuf, offset: u64, size: u64 },
+    /// Fetch an object from S3 when parsed
+    #[cfg(feature = "s3")]
+    S3 { bucket: Box<s3::Bucket>, key: String },
+    /// An archive that could not be read, so that it is reported as failed rather than stopping the batch
+    Unreadable(String),
+}
+
+impl XmlSource {
+    /// A XML file on disk
+    pub fn file(filepath: &path::Path) -> Self {
+        Self {
+            path: filepath.to_path_buf(),
+            // A name that is not valid UTF-8 must not stop the batch, it is reported with replacement characters
+            filename: filepath.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| filepath.display().to_string()),
+            slide_name: slide_name(filepath),
+            modified: fs::metadata(filepath).and_then(|m| m.modified()).ok(),
+            warnings: Vec::new(),
+            contents: Contents::File,
+        }
+    }
+
+    /// A XML document held in memory, e.g. extracted from an archive
+    /// `filename` is reported as is, the slide name is derived from its last component
+    pub fn in_memory(path: path::PathBuf, filename: String, contents: Vec<u8>) -> Self {
+        Self::archive_entry(path, filename, Contents::Memory(contents))
+    }
+
+    /// A XML document inside an archive, read from `contents`
+    fn archive_entry(path: path::PathBuf, filename: String, contents: Contents) -> Self {
+        let slide_name = slide_name(path::Path::new(&filename));
+        Self { path, filename, slide_name, modified: None, warnings: Vec::new(), contents }
+    }
+
+    /// An archive whose documents could not be listed, failing with `error` when read
+    pub fn unreadable(filepath: &path::Path, error: String) -> Self {
+        Self { contents: Contents::Unreadable(error), ..Self::file(filepath) }
+    }
+
+    /// Make a summary for the document holding its warnings so far
+    pub fn summary(&self) -> FileSummary {
+        let mut summary = FileSummary::new(&self.path);
+        for warning in &self.warnings {
+            summary.warn(warning.clone());
+        }
+        summary
+    }
+
+    /// Raw contents of the document, which may still be compressed
+    pub fn contents(&self) -> Result<Vec<u8>, Box<dyn error::Error>> {
+        match &self.contents {
+            Contents::File => Ok(std::fs::read(&self.path)?),
+            Contents::Memory(contents) => Ok(contents.clone()),
+            #[cfg(feature = "zip")]
+            Contents::ZipEntry { archive, index } => {
+                use std::io::Read;
+                let mut contents = Vec::new();
+                // A panic while another entry was read leaves nothing half done, entries are looked up afresh
+                let mut archive = archive.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                archive.by_index(*index)?.read_to_end(&mut contents)?;
+                Ok(contents)
+            },
+            #[cfg(feature = "tar")]
+            Contents::FileRange { file, offset, size } => {
+                use std::io::{Read, Seek};
+                let mut file = fs::File::open(file)?;
+                file.seek(io::SeekFrom::Start(*offset))?;
+                let mut contents = Vec::new();
+                file.take(*size).read_to_end(&mut contents)?;
+                Ok(contents)
+            },
+            #[cfg(feature = "s3")]
+            Contents::S3 { bucket, key } => {
+                let response = bucket.get_object(key)?;
+                if response.status_code() != 200 {
+                    return Err(format!("Fetching {} failed with status {}", self.path.display(), response.status_code()).into());
+                }
+                Ok(response.bytes().to_vec())
+            },
+            Contents::Unreadable(error) => Err(error.clone().into()),
+        }
+    }
+
+    /// Parse the document
+    /// Returns the annotations and a description of anything skipped in lenient mode
+    pub fn parse(&self, options: &Options) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+        let parse = |bytes: &[u8]| if options.lenient {
+            parse_xml_bytes_lenient(bytes)
+        } else {
+            parse_xml_bytes_reporting(bytes)
+        };
+        match &self.contents {
+            Contents::File => with_file_bytes(&self.path, parse)?,
+            Contents::Memory(contents) => parse(contents),
+            _ => parse(&self.contents()?),
+        }
+    }
+}
+
+/// True if the file name ends in `.xml` or `.xml.gz` (any case)
+pub fn is_xml_file(filepath: &path::Path) -> bool {
+    let name = filepath.file_name().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    name.ends_with(".xml") || name.ends_with(".xml.gz")
+}
+
+/// True if the file name ends in `.zip` (any case)
+pub fn is_zip_file(filepath: &path::Path) -> bool {
+    filepath.extension().is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
+/// True if the file name ends in `.tar`, `.tar.gz` or `.tgz` (any case)
+pub fn is_tar_file(filepath: &path::Path) -> bool {
+    let name = filepath.file_name().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// List the XML files (plain or gzip-compressed) directly inside `search_path`
+pub fn xml_files(search_path: &path::Path) -> io::Result<Vec<path::PathBuf>> {
+    let mut files = Vec::new();
+    // Iterate through list of files in search path looking for XML files only
+    for entry in search_path.read_dir()? {
+        let filepath = entry?.path();
+        if is_xml_file(&filepath) {
+            files.push(filepath);
+        }
+    }
+    Ok(files)
+}
+
+/// True if `location` is a `s3://bucket/prefix` location rather than a local path
+pub fn is_s3_location(location: &str) -> bool {
+    location.to_ascii_lowercase().starts_with("s3://")
+}
+
+/// True if `location` is a HTTP or HTTPS URL rather than a local path
+pub fn is_url(location: &str) -> bool {
+    let lower = location.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Find every XML document in `search_path`
+/// A folder is searched for XML files and, with the `zip` and `tar` features, the XML files inside archives.
+/// An archive that cannot be read becomes a single source failing when read, so the rest are still processed.
+/// A single file or archive may also be given directly, with the `http` feature a URL,
+/// and with the `s3` feature a `s3://bucket/prefix` location.
+pub fn xml_sources(search_path: &path::Path, options: &Options) -> Result<Vec<XmlSource>, Box<dyn error::Error>> {
+    if let Some(location) = search_path.to_str().filter(|l| is_s3_location(l)) {
+        #[cfg(feature = "s3")]
+        return s3_sources(location).map(|sources| handle_duplicate_slides(sources, options.duplicate_slides));
+        #[cfg(not(feature = "s3"))]
+        return Err(format!("Cannot read {}: rebuild with the `s3` feature to read object storage", location).into());
+    }
+    if let Some(location) = search_path.to_str().filter(|l| is_url(l)) {
+        #[cfg(feature = "http")]
+        return Ok(vec![url_source(location, options)?]);
+        #[cfg(not(feature = "http"))]
+        return Err(format!("Cannot read {}: rebuild with the `http` feature to read URLs", location).into());
+    }
+    let mut sources = Vec::new();
+    let files = if search_path.is_dir() {
+        let mut files = Vec::new();
+        for entry in search_path.read_dir()? {
+            files.push(entry?.path());
+        }
+        files
+    } else {
+        vec![search_path.to_path_buf()]
+    };
+    for filepath in files {
+        if is_xml_file(&filepath) {
+            sources.push(XmlSource::file(&filepath));
+        } else if is_zip_file(&filepath) {
+            #[cfg(feature = "zip")]
+            match zip_sources(&filepath) {
+                Ok(found) => sources.extend(found),
+                Err(e) => sources.push(XmlSource::unreadable(&filepath, format!("Cannot read ZIP archive: {}", e))),
+            }
+            #[cfg(not(feature = "zip"))]
+            eprintln!("Skipping {}: rebuild with the `zip` feature to read ZIP archives", filepath.display());
+        } else if is_tar_file(&filepath) {
+            #[cfg(feature = "tar")]
+            match tar_sources(&filepath) {
+                Ok(found) => sources.extend(found),
+                Err(e) => sources.push(XmlSource::unreadable(&filepath, format!("Cannot read tar archive: {}", e))),
+            }
+            #[cfg(not(feature = "tar"))]
+            eprintln!("Skipping {}: rebuild with the `tar` feature to read tar archives", filepath.display());
+        }
+    }
+    Ok(handle_duplicate_slides(sources, options.duplicate_slides))
+}
+
+/// Warn about slides annotated by several sources, and with `DuplicateSlides::Newest` keep only the most
+/// recently modified source of each
+/// The warning is added to each source reported, so it ends up in their summaries.
+/// Sources are returned sorted by path. Those without a modification time count as the oldest, ties go to the
+/// last path.
+pub fn handle_duplicate_slides(mut sources: Vec<XmlSource>, policy: DuplicateSlides) -> Vec<XmlSource> {
+    // Folders are listed in no particular order
+    sources.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut slides: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, source) in sources.iter().enumerate() {
+        slides.entry(source.slide_name.as_str()).or_default().push(i);
+    }
+    let mut skipped = vec![false; sources.len()];
+    let mut warnings = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        let same = &slides[source.slide_name.as_str()];
+        // Each slide is handled at its first source
+        if same.len() < 2 || same[0] != i {
+            continue;
+        }
+        let names = same.iter().map(|&j| sources[j].path.display().to_string()).collect::<Vec<_>>().join(", ");
+        let (warning, kept) = match policy {
+            DuplicateSlides::Warn => (format!("Warning: Slide {} is annotated by {} files ({}), its regions are reported once per file", source.slide_name, same.len(), names), same.clone()),
+            DuplicateSlides::Newest => {
+                let newest = *same.iter().max_by_key(|&&j| (sources[j].modified.unwrap_or(time::UNIX_EPOCH), j)).expect("Duplicate slides have at least two sources");
+                for &j in same {
+                    skipped[j] = j != newest;
+                }
+                (format!("Warning: Slide {} is annotated by {} files ({}), only reporting the newest {}", source.slide_name, same.len(), names, sources[newest].path.display()), vec![newest])
+            },
+        };
+        warnings.extend(kept.into_iter().map(|j| (j, warning.clone())));
+    }
+    for (j, warning) in warnings {
+        sources[j].warnings.push(warning);
+    }
+    sources.into_iter().zip(skipped).filter(|(_, skipped)| !skipped).map(|(source, _)| source).collect()
+}
+
+/// List every XML document inside a ZIP archive
+/// Entries are only extracted when parsed, so one document at a time is held in memory. Each is reported as
+/// `<archive name>/<path inside archive>`, modified when the archive was since ZIP entries only record a local time.
+#[cfg(feature = "zip")]
+pub fn zip_sources(archive_path: &path::Path) -> Result<Vec<XmlSource>, Box<dyn error::Error>> {
+    let archive_name = archive_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let file = std::fs::File::open(archive_path)?;
+    let modified = file.metadata().and_then(|m| m.modified()).ok();
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut entries = Vec::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index)?;
+        if entry.is_file() && is_xml_file(path::Path::new(entry.name())) {
+            entries.push((index, entry.name().to_string()));
+        }
+    }
+    // Opened once for all entries rather than once per entry read
+    let archive = Arc::new(Mutex::new(archive));
+    let sources = entries.into_iter().map(|(index, name)| {
+        let contents = Contents::ZipEntry { archive: Arc::clone(&archive), index };
+        let mut source = XmlSource::archive_entry(archive_path.join(&name), format!("{}/{}", archive_name, name), contents);
+        source.modified = modified;
+        source
+    }).collect();
+    Ok(sources)
+}
+
+/// List every XML document inside a tar archive, optionally gzip-compressed
+/// Entries of an uncompressed archive are only read when parsed. Those of a compressed archive can only be reached
+/// by decompressing everything before them, so all its XML documents are read into memory up front.
+/// Each is reported as `<archive name>/<path inside archive>`, modified at the time recorded in the entry.
+#[cfg(feature = "tar")]
+pub fn tar_sources(archive_path: &path::Path) -> Result<Vec<XmlSource>, Box<dyn error::Error>> {
+    use std::io::{BufRead, Read};
+    let archive_name = archive_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let file = std::fs::File::open(archive_path)?;
+    let archive_modified = file.metadata().and_then(|m| m.modified()).ok();
+    let mut file = io::BufReader::new(file);
+    // Detect compression from the content rather than trusting the extension
+    let compressed = crate::parse::is_gzip(file.fill_buf()?);
+    let reader: Box<dyn Read> = if compressed {
+        Box::new(flate2::read::MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut archive = tar::Archive::new(reader);
+    let mut sources = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_path_buf();
+        if !entry.header().entry_type().is_file() || !is_xml_file(&entry_path) {
+            continue;
+        }
+        let contents = if compressed {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            Contents::Memory(contents)
+        } else {
+            Contents::FileRange { file: archive_path.to_path_buf(), offset: entry.raw_file_position(), size: entry.size() }
+        };
+        let filename = format!("{}/{}", archive_name, entry_path.display());
+        let mut source = XmlSource::archive_entry(archive_path.join(&entry_path), filename, contents);
+        source.modified = entry.header().mtime().ok()
+            .map(|seconds| time::UNIX_EPOCH + time::Duration::from_secs(seconds))
+            .or(archive_modified);
+        sources.push(source);
+    }
+    Ok(sources)
+}
+
+/// Download a XML document, retrying failed downloads as configured in `options`
+/// The URL is reported as the file name, the slide name comes from the last part of the URL path
+#[cfg(feature = "http")]
+pub fn url_source(url: &str, options: &Options) -> Result<XmlSource, Box<dyn error::Error>> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(options.http_timeout))
+        .build()?;
+    let mut attempt = 0;
+    let contents = loop {
+        let error: Box<dyn error::Error> = match client.get(url).send() {
+            Ok(response) if response.status().is_success() => break response.bytes()?.to_vec(),
+            // Client errors will not go away by asking again
+            Ok(response) if response.status().is_client_error() => return Err(format!("Downloading {} failed: {}", url, response.status()).into()),
+            Ok(response) => format!("Downloading {} failed: {}", url, response.status()).into(),
+            Err(e) => e.into(),
+        };
+        if attempt >= options.http_retries {
+            return Err(error);
+        }
+        attempt += 1;
+        eprintln!("{}, retrying ({} of {})", error, attempt, options.http_retries);
+        std::thread::sleep(backoff(attempt));
+    };
+    // Name the slide after the last part of the path, ignoring any query
+    let url_path = url.split(['?', '#']).next().unwrap_or(url);
+    let last = url_path.trim_end_matches('/').rsplit('/').next().unwrap_or(url_path);
+    let mut source = XmlSource::in_memory(path::PathBuf::from(url), url.to_string(), contents);
+    source.slide_name = slide_name(path::Path::new(last));
+    Ok(source)
+}
+
+/// How long to wait before retry number `attempt`, a little longer each time up to half a minute
+#[cfg(feature = "http")]
+fn backoff(attempt: u32) -> time::Duration {
+    time::Duration::from_secs(1 << attempt.min(5))
+}
+
+/// List the XML objects under a `s3://bucket/prefix` location
+/// Objects are only fetched when parsed, so a large prefix is never held in memory at once.
+/// Region and credentials come from the usual AWS environment variables and profile, and
+/// `AWS_ENDPOINT` selects an S3-compatible service such as MinIO.
+#[cfg(feature = "s3")]
+pub fn s3_sources(location: &str) -> Result<Vec<XmlSource>, Box<dyn error::Error>> {
+    let without_scheme = &location["s3://".len()..];
+    let (bucket_name, prefix) = without_scheme.split_once('/').unwrap_or((without_scheme, ""));
+    let region = s3::Region::from_default_env().unwrap_or(s3::Region::UsEast1);
+    let custom_endpoint = matches!(region, s3::Region::Custom { .. });
+    let mut bucket = s3::Bucket::new(bucket_name, region, s3::creds::Credentials::default()?)?;
+    // S3-compatible services generally expect the bucket in the path rather than the host name
+    if custom_endpoint {
+        bucket = bucket.with_path_style();
+    }
+    let mut sources = Vec::new();
+    for page in bucket.list(prefix.to_string(), None)? {
+        for object in page.contents {
+            if !is_xml_file(path::Path::new(&object.key)) {
+                continue;
+            }
+            let name = format!("s3://{}/{}", bucket_name, object.key);
+            sources.push(XmlSource {
+                path: path::PathBuf::from(&name),
+                slide_name: slide_name(path::Path::new(&object.key)),
+                filename: name,
+                modified: parse_utc_timestamp(&object.last_modified),
+                warnings: Vec::new(),
+                contents: Contents::S3 { bucket: bucket.clone(), key: object.key },
+            });
+        }
+    }
+    Ok(sources)
+}
+
+/// Parse a UTC timestamp such as `2024-05-01T12:34:56.000Z`, as S3 lists the objects
+#[cfg(feature = "s3")]
+fn parse_utc_timestamp(timestamp: &str) -> Option<time::SystemTime> {
+    let (date, clock) = timestamp.trim().trim_end_matches('Z').split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut clock = clock.splitn(3, ':');
+    let hours: u64 = clock.next()?.parse().ok()?;
+    let minutes: u64 = clock.next()?.parse().ok()?;
+    let seconds: f64 = clock.next()?.parse().ok()?;
+    // Days since 1970-01-01, counting years from March so leap days come last
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + (153 * month + 2) / 5 + day - 1;
+    let days = u64::try_from(era * 146097 + day_of_era - 719468).ok()?;
+    let since_epoch = time::Duration::from_secs(days * 86400 + hours * 3600 + minutes * 60) + time::Duration::try_from_secs_f64(seconds).ok()?;
+    Some(time::UNIX_EPOCH + since_epoch)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path, time};
+    #[cfg(any(feature = "zip", feature = "tar"))]
+    use crate::parse::parse_xml_str;
+    use super::{DuplicateSlides, XmlSource, handle_duplicate_slides};
+
+    #[cfg(any(feature = "zip", feature = "tar"))]
+    const EXPORT: &str = include_str!("../tests/data/imagescope_export.xml");
+
+    fn source(path: &str, modified: u64) -> XmlSource {
+        let mut source = XmlSource::in_memory(path::PathBuf::from(path), path.to_string(), Vec::new());
+        source.modified = Some(time::UNIX_EPOCH + time::Duration::from_secs(modified));
+        source
+    }
+
+    #[test]
+    fn duplicate_slides_are_recorded_in_summaries() {
+        let sources = vec![source("b/case1.xml", 2), source("a/case1.xml", 1), source("case2.xml", 1)];
+        let kept = handle_duplicate_slides(sources.clone(), DuplicateSlides::Warn);
+        let warned: Vec<usize> = kept.iter().map(|s| s.summary().warnings.len()).collect();
+        assert_eq!(warned, [1, 1, 0]);
+        let kept = handle_duplicate_slides(sources, DuplicateSlides::Newest);
+        let paths: Vec<&str> = kept.iter().map(|s| s.filename.as_str()).collect();
+        assert_eq!(paths, ["b/case1.xml", "case2.xml"]);
+        assert!(kept[0].summary().warnings[0].contains("only reporting the newest b/case1.xml"));
+    }
+
+    /// Temporary folder for the archive tests, emptied first
+    #[cfg(any(feature = "zip", feature = "tar"))]
+    fn archive_dir(name: &str) -> path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip_entries_are_read_from_one_shared_archive() {
+        use std::{fs, io::Write};
+        use crate::options::Options;
+        use super::{xml_sources, zip_sources};
+        let dir = archive_dir("zip_sources");
+        let archive_path = dir.join("batch.zip");
+        let mut archive = zip::ZipWriter::new(fs::File::create(&archive_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        archive.add_directory("cases/", options).unwrap();
+        for (name, contents) in [("cases/case1.xml", EXPORT), ("notes.txt", "not annotations"), ("case2.XML", EXPORT)] {
+            archive.start_file(name, options).unwrap();
+            archive.write_all(contents.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap();
+        fs::write(dir.join("broken.zip"), "not a zip archive").unwrap();
+        let sources = zip_sources(&archive_path);
+        let modified = fs::metadata(&archive_path).unwrap().modified().ok();
+        let listed = xml_sources(&dir, &Options::default());
+        let sources = sources.unwrap();
+        // Entries are read in parallel in a batch
+        let contents: Vec<Vec<u8>> = std::thread::scope(|scope| {
+            let reads: Vec<_> = sources.iter().map(|source| scope.spawn(|| source.contents().unwrap())).collect();
+            reads.into_iter().map(|read| read.join().unwrap()).collect()
+        });
+        let parsed = sources[1].parse(&Options::default());
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<(&str, &str)> = sources.iter().map(|s| (s.filename.as_str(), s.slide_name.as_str())).collect();
+        assert_eq!(names, [("batch.zip/cases/case1.xml", "case1.svs"), ("batch.zip/case2.XML", "case2.svs")]);
+        assert_eq!(sources[0].path, archive_path.join("cases/case1.xml"));
+        assert!(sources.iter().all(|s| s.modified == modified));
+        assert!(contents.iter().all(|c| c == EXPORT.as_bytes()));
+        assert_eq!(parsed.unwrap().0, parse_xml_str(EXPORT).unwrap());
+        // An archive that cannot be read fails alone
+        let listed = listed.unwrap();
+        assert_eq!(listed.len(), 3);
+        let broken = listed.iter().find(|s| s.filename == "broken.zip").unwrap();
+        assert!(broken.contents().unwrap_err().to_string().starts_with("Cannot read ZIP archive"));
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn tar_entries_are_read_plain_or_compressed() {
+        use std::fs;
+        use crate::options::Options;
+        use super::tar_sources;
+        let dir = archive_dir("tar_sources");
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, contents) in [("cases/case1.xml", EXPORT), ("notes.txt", "not annotations"), ("case2.xml", EXPORT)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mtime(1_700_000_000);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, contents.as_bytes()).unwrap();
+        }
+        let plain = builder.into_inner().unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &plain).unwrap();
+        // Compression is told from the contents, whatever the extension
+        let archives = [("batch.tar", plain), ("batch.tgz", encoder.finish().unwrap())];
+        for (name, contents) in &archives {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        let found: Vec<_> = archives.iter().map(|(name, _)| tar_sources(&dir.join(name)).unwrap()).collect();
+        let contents: Vec<Vec<Vec<u8>>> = found.iter().map(|sources| sources.iter().map(|s| s.contents().unwrap()).collect()).collect();
+        let parsed = found[0][0].parse(&Options::default());
+        fs::write(dir.join("broken.tar"), [0x1F, 0x8B, 0x08, 0x00]).unwrap();
+        let broken = tar_sources(&dir.join("broken.tar"));
+        fs::remove_dir_all(&dir).unwrap();
+        for (sources, (archive, _)) in found.iter().zip(&archives) {
+            let names: Vec<(String, &str)> = sources.iter().map(|s| (s.filename.clone(), s.slide_name.as_str())).collect();
+            assert_eq!(names, [(format!("{}/cases/case1.xml", archive), "case1.svs"), (format!("{}/case2.xml", archive), "case2.svs")]);
+            assert!(sources.iter().all(|s| s.modified == Some(time::UNIX_EPOCH + time::Duration::from_secs(1_700_000_000))));
+        }
+        assert!(contents.iter().flatten().all(|c| c == EXPORT.as_bytes()));
+        assert_eq!(parsed.unwrap().0, parse_xml_str(EXPORT).unwrap());
+        assert!(broken.is_err());
+    }
+
+    /// Serve `responses` to one request each on a local port, returning the URL and the number of requests served
+    #[cfg(feature = "http")]
+    fn serve(responses: &'static [&'static str]) -> (String, std::thread::JoinHandle<usize>) {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/slides/case1.xml?token=abc", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            listener.set_nonblocking(true).unwrap();
+            let mut served = 0;
+            let deadline = time::Instant::now() + time::Duration::from_secs(10);
+            while served < responses.len() && time::Instant::now() < deadline {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    std::thread::sleep(time::Duration::from_millis(10));
+                    continue;
+                };
+                stream.set_nonblocking(false).unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend(&buffer[..read]);
+                }
+                let (status, body) = responses[served].split_once('|').unwrap();
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body).unwrap();
+                served += 1;
+            }
+            served
+        });
+        (url, server)
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn failed_downloads_are_retried_with_backoff() {
+        use crate::options::Options;
+        use super::{backoff, url_source};
+        let backoffs: Vec<u64> = (1..8).map(|attempt| backoff(attempt).as_secs()).collect();
+        assert_eq!(backoffs, [2, 4, 8, 16, 32, 32, 32]);
+        let options = Options { http_retries: 1, http_timeout: 5, ..Options::default() };
+        // A server error is retried
+        let (url, server) = serve(&["503 Service Unavailable|", "200 OK|<Annotations/>"]);
+        let source = url_source(&url, &options).unwrap();
+        assert_eq!(server.join().unwrap(), 2);
+        assert_eq!((source.filename.as_str(), source.slide_name.as_str()), (url.as_str(), "case1.svs"));
+        assert_eq!(source.contents().unwrap(), b"<Annotations/>");
+        // Until the retries run out
+        let (url, server) = serve(&["503 Service Unavailable|", "502 Bad Gateway|"]);
+        let error = url_source(&url, &options).unwrap_err().to_string();
+        assert_eq!(server.join().unwrap(), 2);
+        assert!(error.ends_with("failed: 502 Bad Gateway"), "{}", error);
+        // Client errors are not
+        let (url, server) = serve(&["404 Not Found|"]);
+        let error = url_source(&url, &options).unwrap_err().to_string();
+        assert_eq!(server.join().unwrap(), 1);
+        assert!(error.ends_with("failed: 404 Not Found"), "{}", error);
+    }
+}
+
+#[cfg(all(test, feature = "s3"))]
+mod s3_tests {
+    use std::time;
+    use super::parse_utc_timestamp;
+
+    #[test]
+    fn s3_timestamps() {
+        let seconds = |t: &str| parse_utc_timestamp(t).map(|t| t.duration_since(time::UNIX_EPOCH).unwrap().as_secs_f64());
+        assert_eq!(seconds("1970-01-01T00:00:00.000Z"), Some(0.0));
+        assert_eq!(seconds("2000-03-01T00:00:00Z"), Some(951868800.0));
+        assert_eq!(seconds("2024-05-01T12:34:56.500Z"), Some(1714566896.5));
+        assert_eq!(seconds("yesterday"), None);
+    }
+}
@@ -0,0 +1,77 @@
+use serde_json::{json, Value};
+use crate::convert::ExportRegion;
+use crate::extract::UNLABELED;
+use crate::geometry::BoundingBox;
+use crate::model::RegionType;
+
+/// LabelMe version whose file layout is written
+const LABELME_VERSION: &str = "5.2.1";
+
+/// LabelMe JSON for one slide, with a shape per region labelled with its text
+/// Rectangles keep their two corners, rulers and arrows become lines, polylines line strips and single
+/// vertices points. Ellipses and other closed regions become polygons. NegativeROA regions are labelled
+/// `Exclusion`, and the image size is the extent of the annotations.
+pub fn to_labelme(regions: &[ExportRegion], image_path: &str) -> Value {
+    let mut extent = (0.0f64, 0.0f64);
+    let shapes: Vec<Value> = regions.iter().filter_map(|r| {
+        let outline = r.region.outline();
+        let bbox = BoundingBox::of(&outline)?;
+        extent = (extent.0.max(bbox.max_x), extent.1.max(bbox.max_y));
+        let label = match r.region.label() {
+            _ if r.region.is_negative() => String::from("Exclusion"),
+            label if label.is_empty() => UNLABELED.to_string(),
+            label => label,
+        };
+        let (shape_type, points) = match (&r.region.region_type, outline.len()) {
+            (_, 1) => ("point", outline),
+            (RegionType::Rectangle, _) => ("rectangle", vec![(bbox.min_x, bbox.min_y), (bbox.max_x, bbox.max_y)]),
+            (RegionType::Ruler | RegionType::Arrow, 2) => ("line", outline),
+            (region_type, n) if n >= 3 && region_type.is_closed() => ("polygon", outline),
+            _ => ("linestrip", outline),
+        };
+        Some(json!({
+            "label": label,
+            "points": points.iter().map(|&(x, y)| json!([x, y])).collect::<Vec<_>>(),
+            "group_id": null,
+            "description": "",
+            "shape_type": shape_type,
+            "flags": {},
+        }))
+    }).collect();
+    json!({
+        "version": LABELME_VERSION,
+        "flags": {},
+        "shapes": shapes,
+        "imagePath": image_path,
+        "imageData": null,
+        "imageHeight": extent.1.max(0.0).ceil() as u64,
+        "imageWidth": extent.0.max(0.0).ceil() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::model::RegionType;
+    use super::to_labelme;
+
+    #[test]
+    fn regions_become_labelled_shapes() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Tumor").vertices([(10.0, 20.0), (40.0, 60.0)]))
+                .region(RegionBuilder::new().negative_roa(true).vertices([(0.0, 0.0), (4.0, 0.0), (4.0, 4.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Ruler).vertices([(0.0, 0.0), (5.0, 5.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Polyline).vertices([(0.0, 0.0), (5.0, 5.0), (9.0, 1.0)])))
+            .build();
+        let labelme = to_labelme(&export_regions(&annotations, &[]), "case1.svs");
+        assert_eq!((&labelme["imagePath"], &labelme["imageWidth"], &labelme["imageHeight"]), (&json!("case1.svs"), &json!(40), &json!(60)));
+        let shapes: Vec<(&str, &str)> = labelme["shapes"].as_array().unwrap().iter()
+            .map(|s| (s["label"].as_str().unwrap(), s["shape_type"].as_str().unwrap()))
+            .collect();
+        assert_eq!(shapes, [("Tumor", "rectangle"), ("Exclusion", "polygon"), ("unlabeled", "line"), ("unlabeled", "linestrip")]);
+        assert_eq!(labelme["shapes"][0]["points"], json!([[10.0, 20.0], [40.0, 60.0]]));
+    }
+}
@@ -1,428 +1,474 @@
-use std::{error, path};
-use serde::{Deserialize, Serialize};
-use std::fs::read_to_string;
-use std::collections::HashMap;
-use quick_xml::DeError;
+use std::{error, io, panic, path, thread, time};
+use std::sync::mpsc;
 
-/// Information we wish to collect about a region
-#[derive(Debug)]
-struct RegionInfo {
-    text_label: Option<String>,
-    image_location: Option<String>,
-    num_positive: Option<f32>,
-    num_spositive: Option<f32>,
-    num_wpositive: Option<f32>,
-    num_total: Option<f32>,
-    positivity: Option<f32>,
+mod model;
+mod parse;
+mod encoding;
+mod extract;
+mod report;
+mod write;
+mod builder;
+mod recover;
+mod strict;
+mod validate;
+mod options;
+mod number;
+mod patterns;
+mod algorithm;
+mod aggregate;
+mod completeness;
+mod qc;
+mod scores;
+mod metadata;
+#[cfg(feature = "geometry")]
+mod geometry;
+#[cfg(feature = "config")]
+mod config;
+#[cfg(feature = "export")]
+mod convert;
+#[cfg(feature = "export")]
+mod import;
+#[cfg(feature = "export")]
+mod qupath;
+#[cfg(feature = "export")]
+mod asap;
+#[cfg(feature = "export")]
+mod halo;
+#[cfg(feature = "export")]
+mod sedeen;
+#[cfg(feature = "export")]
+mod ome;
+#[cfg(feature = "export")]
+mod coco;
+#[cfg(feature = "export")]
+mod cvat;
+#[cfg(feature = "export")]
+mod labelme;
+#[cfg(feature = "export")]
+mod napari;
+#[cfg(feature = "export")]
+mod geojson;
+#[cfg(feature = "export")]
+mod wkt;
+#[cfg(feature = "export")]
+mod render;
+#[cfg(feature = "export")]
+mod tiles;
+#[cfg(feature = "export")]
+mod heatmap;
+#[cfg(feature = "export")]
+mod crops;
+#[cfg(feature = "raster")]
+mod mask;
+mod input;
+#[cfg(feature = "async")]
+mod stream;
+
+pub use model::{Annotations, Annotation, AnnotationAttributes, AnnotationAttributesAttribute, Regions, RegionAttributeHeaders, AttributeHeader, Region, RegionAttributes, RegionAttributesAttribute, RegionType, Vertices, Vertex, Extra, XmlValue};
+pub use builder::{AnnotationsBuilder, AnnotationBuilder, RegionBuilder};
+pub use encoding::{Encoding, EncodingError, detect_encoding, decode_xml};
+pub use options::Options;
+pub use patterns::{AttributePatterns, matches_pattern, matches_glob, find_match};
+#[cfg(feature = "config")]
+pub use patterns::{ColumnType, CustomColumn};
+#[cfg(feature = "config")]
+pub use config::load_config;
+#[cfg(feature = "geometry")]
+pub use geometry::{Point, Affine, BoundingBox, Measurement, RegionIndex, RegionTree, RegionNode, polygon_area, polygon_centroid, path_length, convex_hull, polygon_contains, polygon_contains_box, polygon_spans, intersection_area, simplify, prepare_for_export};
+#[cfg(feature = "export")]
+pub use convert::{DEFAULT_OUTPUT_DIR, Format, ExportRegion, DatasetSlide, export_regions, convert_annotations, convert_dataset, run_convert};
+#[cfg(feature = "export")]
+pub use import::{InputFormat, detect_input_format, import_annotations, import_files};
+#[cfg(feature = "export")]
+pub use qupath::import_qupath_geojson;
+#[cfg(feature = "export")]
+pub use asap::{import_asap_xml, to_asap_xml};
+#[cfg(feature = "export")]
+pub use halo::import_halo_annotations;
+#[cfg(feature = "export")]
+pub use sedeen::import_sedeen_session;
+#[cfg(feature = "export")]
+pub use ome::to_ome_xml;
+#[cfg(feature = "export")]
+pub use coco::{CocoDataset, CocoImage, CocoAnnotation, CocoCategory};
+#[cfg(feature = "export")]
+pub use cvat::to_cvat_xml;
+#[cfg(feature = "export")]
+pub use labelme::to_labelme;
+#[cfg(feature = "export")]
+pub use napari::{NAPARI_HEADER, napari_shapes_csv};
+#[cfg(feature = "export")]
+pub use geojson::{to_geojson, geojson_geometry};
+#[cfg(feature = "export")]
+pub use wkt::{WKT_HEADER, to_wkt_csv, wkt_geometry};
+#[cfg(feature = "export")]
+pub use render::{ColorBy, to_svg};
+#[cfg(feature = "export")]
+pub use tiles::{TILES_HEADER, Tile, tile_regions, tiles_csv};
+#[cfg(feature = "export")]
+pub use heatmap::{Heatmap, heatmap_csv, heatmap_grid, heatmap_svg};
+#[cfg(feature = "export")]
+pub use crops::{CROPS_HEADER, CropBox, crop_boxes, crops_csv};
+#[cfg(feature = "raster")]
+pub use mask::{Mask, rasterize};
+pub use aggregate::{SLIDE_SUMMARY_HEADER, LABEL_SUMMARY_HEADER, COHORT_SUMMARY_HEADER, COMPARISON_HEADER, ALL_LABELS, GroupBy, Aggregate, LabelFilter, CohortSummary, GroupComparison, aggregate, aggregate_slides, aggregate_slides_filtered, aggregate_labels, cohort_summary, compare_groups, write_aggregate_records, write_checked_aggregate_records, write_cohort_records, write_comparison_records, run_summarize, run_summarize_to_writer, run_compare, run_compare_to_writer, PIVOT_METRICS, write_pivot, run_pivot, run_pivot_to_writer};
+pub use metadata::{LabelMap, Metadata};
+#[cfg(feature = "config")]
+pub use metadata::FilenamePattern;
+pub use scores::{Score, AllredBins};
+pub use qc::{QC_RULE_COLUMNS, QcRules};
+pub use completeness::{COMPLETENESS_HEADER, Completeness, write_completeness_records, run_completeness, run_completeness_to_writer};
+pub use algorithm::{Algorithm, AlgorithmKind, GenieClasses, detect_algorithm};
+pub use number::{DecimalSeparator, parse_number, parse_number_with_warning};
+pub use input::{XmlSource, DuplicateSlides, handle_duplicate_slides, is_xml_file, is_zip_file, is_tar_file, is_url, is_s3_location, xml_files, xml_sources};
+#[cfg(feature = "zip")]
+pub use input::zip_sources;
+#[cfg(feature = "tar")]
+pub use input::tar_sources;
+#[cfg(feature = "http")]
+pub use input::url_source;
+#[cfg(feature = "s3")]
+pub use input::s3_sources;
+pub use parse::{parse_xml, try_parse_xml, try_parse_xml_lenient, parse_xml_bytes, parse_xml_bytes_lenient, parse_xml_str, is_gzip};
+pub use recover::parse_xml_lenient;
+pub use strict::check_strict;
+pub use validate::{Schema, Violation, validate_xml, validate_bytes, run_validate};
+#[cfg(feature = "mmap")]
+pub use parse::parse_xml_mmap;
+pub use extract::{QC_COLUMN, RegionInfo, RegionRecord, FileSummary, extract_regions, extract_regions_with_summary, region_records, region_records_with_columns, slide_name};
+pub use report::{OutputFormat, read_records, is_csv_file, CSV_HEADER, VERTICES_HEADER, TIDY_HEADER, write_vertex_records, write_tidy_records, write_csv_header, write_csv_header_with_columns, write_csv_records, write_csv_records_with_columns, report_csv, report_csv_string};
+#[cfg(feature = "async")]
+pub use stream::{file_stream, extract_stream, run_async};
+
+/// Read a single XML document and return its records together with a summary of what was found
+/// A document that cannot be read, makes processing panic or takes longer than `options.timeout_per_file`
+/// has no records and is marked as failed in its summary.
+pub fn process_source(source: &XmlSource, options: &Options) -> (Vec<RegionRecord>, FileSummary) {
+    let Some(timeout) = options.timeout_per_file else {
+        return process_source_guarded(source, options);
+    };
+    let thread_options = options.clone();
+    give_up_after(source, timeout, move |source| process_source_guarded(source, &thread_options))
 }
 
-impl RegionInfo {
-    /// Make new RegionInfo with fully specified Options
-    fn new() -> Self {
-        Self { text_label: None, positivity: None, num_positive: None, num_spositive: None, num_wpositive: None, num_total: None, image_location: None}
-    }
-    
-    /// Get text label
-    fn text_label(&self) -> Option<&String> {
-        self.text_label.as_ref()
-    }
-    
-    /// Get positivity
-    fn positivity(&self) -> Option<f32> {
-        self.positivity
-    }
-    
-    /// Get total number of positive pixels, use 0 for missing data
-    fn get_total_positive(&self) -> f32 {
-        self.num_wpositive.unwrap_or(0.0)+self.num_positive.unwrap_or(0.0)+self.num_spositive.unwrap_or(0.0)
-    }
-    /// Get number pixels positive
-    fn num_positive(&self) -> Option<f32> {
-        self.num_positive
+/// Run `process` on a copy of `source`, marking the source as failed if it takes longer than `timeout` seconds
+fn give_up_after<F>(source: &XmlSource, timeout: u64, process: F) -> (Vec<RegionRecord>, FileSummary)
+where
+    F: FnOnce(&XmlSource) -> (Vec<RegionRecord>, FileSummary) + Send + 'static,
+{
+    // Work on a separate thread so we can stop waiting for it
+    let (sender, receiver) = mpsc::channel();
+    let thread_source = source.clone();
+    thread::spawn(move || {
+        // Nobody is listening any more if we gave up on the file
+        let _ = sender.send(process(&thread_source));
+    });
+    match receiver.recv_timeout(time::Duration::from_secs(timeout)) {
+        Ok(result) => result,
+        Err(_) => {
+            // The thread cannot be stopped, it is abandoned and ends with the process at the latest
+            let mut summary = source.summary();
+            summary.fail(format!("Gave up on {} after {} seconds", source.path.display(), timeout));
+            (Vec::new(), summary)
+        },
     }
+}
 
-    /// Get total number of non-background pixels
-    fn num_total(&self) -> Option<f32> {
-        self.num_total
-    }
+/// Body of `process_source`, catching panics but without a time limit
+fn process_source_guarded(source: &XmlSource, options: &Options) -> (Vec<RegionRecord>, FileSummary) {
+    catch_panics(source, || process_source_unguarded(source, options))
+}
 
-    /// Set new text label
-    fn set_text_label(&mut self, text_label: Option<String>) {
-        // Warn if over-write
-        if self.text_label.is_some() {
-            eprintln!("Warning: Over-writing region text label");
-        }
-        self.text_label = text_label;
-    }
-    
-    /// Set number positive
-    fn set_num_positive(&mut self, num_pos: Option<f32>) {
-        // Warn if over-write
-        if let Some(_n_pos) = self.num_positive {
-            eprintln!("Warning: Over-writing number positive for region");
-        }
-        self.num_positive = num_pos;
+/// Run `process`, marking `source` as failed if it panics
+fn catch_panics(source: &XmlSource, process: impl FnOnce() -> (Vec<RegionRecord>, FileSummary)) -> (Vec<RegionRecord>, FileSummary) {
+    match panic::catch_unwind(panic::AssertUnwindSafe(process)) {
+        Ok(result) => result,
+        Err(payload) => {
+            // Panic payloads are almost always a message
+            let message = payload.downcast_ref::<&str>().map(|m| m.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("unknown panic"));
+            let mut summary = source.summary();
+            summary.fail(format!("Error processing {}: {}", source.path.display(), message));
+            (Vec::new(), summary)
+        },
     }
+}
 
-    /// Set number total
-    fn set_num_total(&mut self, num_total: Option<f32>) {
-        // Warn if over-write
-        if let Some(_n_total) = self.num_total {
-            eprintln!("Warning: Over-writing number total for region");
-        }
-        self.num_total = num_total;
+/// Read the annotations of a source, recording anything that went wrong in `summary`
+/// An unreadable document simply has no regions, a document rejected by `--strict` gives None.
+pub(crate) fn read_source(source: &XmlSource, options: &Options, summary: &mut FileSummary) -> Option<Annotations> {
+    match source.parse(options) {
+        Ok((annotations, skipped)) => {
+            for s in skipped {
+                summary.warn(format!("In {}: {}", source.path.display(), s));
+            }
+            // Anything out of the ordinary makes the whole file fail in strict mode
+            let violations = if options.strict { check_strict(&annotations) } else { Vec::new() };
+            if !violations.is_empty() {
+                summary.fail(format!("{} is not canonical ImageScope XML: {}", source.path.display(), violations.join("; ")));
+                return None;
+            }
+            Some(annotations)
+        },
+        Err(e) => {
+            summary.fail(format!("Error parsing XML from {}: {}", source.path.display(), e));
+            Some(Annotations::default())
+        },
     }
-    /// Set positivity
-    fn set_positivity(&mut self, positivity: Option<f32>) {
-        // Warn if over-write
-        if let Some(_n_pos) = self.positivity {
-            eprintln!("Warning: Over-writing positivity for region");
+}
+
+/// Body of `process_source`, without catching panics
+fn process_source_unguarded(source: &XmlSource, options: &Options) -> (Vec<RegionRecord>, FileSummary) {
+    let mut summary = source.summary();
+    // Read XML into annotations structure
+    let Some(annotations) = read_source(source, options, &mut summary) else {
+        return (Vec::new(), summary);
+    };
+    // Collect information about each region
+    let regions_info = extract_regions_with_summary(&annotations, options, &mut summary);
+    let mut records = region_records_with_columns(&source.filename, &source.slide_name, &regions_info, &options.column_names());
+    if let Some(map) = &options.label_map {
+        for record in &mut records {
+            record.text_label = map.normalize(&record.text_label);
         }
-        self.positivity = positivity;
-    }
-    
-    /* We don't use image location
-    fn image_location(&self) -> Option<&String> {
-        self.image_location.as_ref()
-    } 
-    */
-    
-    fn set_image_location(&mut self, image_location: Option<String>) {
-        self.image_location = image_location;
     }
-    
-    /// Set number strong positive
-    fn set_num_spositive(&mut self, num_spositive: Option<f32>) {
-        self.num_spositive = num_spositive;
+    #[cfg(feature = "config")]
+    if let Some(pattern) = &options.filename_pattern {
+        pattern.join(&source.filename, &mut records, &mut summary);
     }
-    
-    /// Set number weak positive
-    fn set_num_wpositive(&mut self, num_wpositive: Option<f32>) {
-        self.num_wpositive = num_wpositive;
+    if let Some(metadata) = &options.metadata {
+        metadata.join(&source.slide_name, &mut records, &mut summary);
     }
-    
-    fn num_spositive(&self) -> Option<f32> {
-        self.num_spositive
-    }
-    
-    fn num_wpositive(&self) -> Option<f32> {
-        self.num_wpositive
-    } 
+    (records, summary)
+}
 
+/// Read a single XML file and return its records together with a summary of what was found
+pub fn process_file(filepath: &path::Path, options: &Options) -> (Vec<RegionRecord>, FileSummary) {
+    process_source(&XmlSource::file(filepath), options)
 }
 
-/// Try to open and real a XML file using pre-defined structure
-pub fn parse_xml(path: &path::Path) -> Annotations {
-    dbg!(path);
-    // Read file into string and ignore any errors
-    let xml = read_to_string(path).unwrap_or_default();
-    // Now convert the XML into Rust data structure 
-    let annotations: Result<Annotations, DeError> = quick_xml::de::from_str(&xml);
-    match annotations {
-        Ok(annotations) => return annotations,
-        Err(e) => eprintln!("Error parsing XML from {}: {}", path.display(), e),
-    }
-    // Error parsing so return empty
-    Annotations { microns_per_pixel: String::from(""), annotation: Vec::new()}
+/// Process every XML file in `search_path` and print the CSV report to stdout
+/// Returns the number of files that could not be processed, fails if more regions fail the QC rules than their budget
+pub fn run(search_path: &path::Path, options: &Options) -> Result<usize, Box<dyn error::Error>> {
+    let summaries = run_to_writer(search_path, options, &mut io::stdout().lock())?;
+    options.qc_rules.check_budget(summaries.iter().map(|s| s.qc_failures).sum(), 0)?;
+    Ok(summaries.iter().filter(|s| s.failed()).count())
 }
 
-pub fn run(search_path: &path::Path) -> Result<(), Box<dyn error::Error>> {
+/// Process every XML file in `search_path` and write the report into `writer`
+/// Returns a summary for each file processed
+pub fn run_to_writer<W: io::Write>(search_path: &path::Path, options: &Options, writer: &mut W) -> Result<Vec<FileSummary>, Box<dyn error::Error>> {
+    if options.format == OutputFormat::VerticesCsv {
+        return run_vertices_to_writer(search_path, options, writer);
+    }
+    let mut summaries = Vec::new();
+    let tidy = options.format == OutputFormat::Tidy;
     // Setup header
-    println!("Filename,Slide Name,Region ID,text label,positivity,num weak positive,num positive,num strong positive,num all positive,num total");
-    // Iterate through list of files in search path looking for XML files only
-    for entry in search_path.read_dir().expect("Invalid search path").filter(|dirent| {
-        dirent.as_ref().is_ok_and(|d|  {
-            return d.path().as_path().extension().is_some_and(|e| e.to_ascii_lowercase()==*"xml");
-        })
-    }) {        
-        let filepath = entry?.path(); // Since this is filtered, all values of the entry iterator should have valid path() so safe to use unwrap()
-        //dbg!(&filepath);
-
-        // Read XML file into annotations structure     
-        let annotations = parse_xml(&filepath);
-        //dbg!(&annotations);
-
-        // Collect information about each region
-        let mut regions_info: HashMap<String, RegionInfo> = HashMap::new();
-        
-        // Warn if we have more than one type 3 annotation layer
-        let mut analysis_layer = false;
-
-        // Process each annotation layer
-        for layer in annotations.annotation {
-            match layer.annotation_type.as_str() {                
-                "4" => {
-                    //dbg!(&layer);
-                    // Type "4" are user-drawn regions
-                    // We will extract the text label for each region identified by 'Id'
-                    for r in layer.regions.region {           
-                        //dbg!(&r);     
-                        // Find the correct region Id to store information                   
-                        regions_info.entry(r.id.clone())
-                        // Or make a new region Id entry if missing
-                        .or_insert(RegionInfo::new())
-                        // Store the label
-                        .set_text_label(Some(r.text));
-                    }
-                },
-                "3" => {
-                    // Ensure an attribute header exists
-                    if let Some(attribute_header) = layer.regions.region_attribute_headers.attribute_header {
-                        // Locate specific attributes of interest
-                        let positivity_attrib = attribute_header.iter().find(|a| a.name.starts_with("Positivity ="));
-                        let num_wpositive_attrib = attribute_header.iter().find(|a| a.name.starts_with("Nwp ="));
-                        let num_positive_attrib = attribute_header.iter().find(|a| a.name.starts_with("Np  ="));
-                        let num_spositive_attrib=attribute_header.iter().find(|a| a.name.starts_with("Nsp ="));
-                        let num_total_attrib = attribute_header.iter().find(|a| a.name.starts_with("NTotal ="));
-                        // If any element is missing, we will skip the file
-                        if positivity_attrib.is_none() {
-                            eprintln!("Missing positivity in {}", filepath.display());
-                            continue;
-                        }
-                        if num_positive_attrib.is_none() {
-                            eprintln!("Missing number positive in {}", filepath.display());
-                            continue;
-                        }
-                        if num_wpositive_attrib.is_none() {
-                            eprintln!("Missing number weak positive in {}", filepath.display());
-                            continue;
-                        }
-                        if num_spositive_attrib.is_none() {
-                            eprintln!("Missing number strong positive in {}", filepath.display());
-                            continue;
-                        }
-                        if num_total_attrib.is_none() {
-                            eprintln!("Missing number total in {}", filepath.display());
-                            continue;
-                        } 
-                        // By now we know all selected variables are valid so unwrap them
-                        let positivity_name=positivity_attrib.expect("Missing positivity attribute after is_none is false").id.clone();
-                        let num_positive_name=num_positive_attrib.expect("Missing number positive attribute after is_none is false").id.clone();
-                        let num_wpositive_name=num_wpositive_attrib.expect("Missing number weak positive after is_none is false").id.clone();
-                        let num_spositive_name=num_spositive_attrib.expect("Missing number strong positive after is_none is false").id.clone();
-                        let num_total_name=num_total_attrib.expect("Missing total number attribute after is_none is false").id.clone();
-                        // Warn if there is more than one type 3 layer
-                        if analysis_layer {
-                            eprintln!("Warning! Multiple type 3 analysis layers found - last one will be used. Currently processing layer id {}", &layer.id);
-                        } else {
-                            analysis_layer=true;
-                        }
-                        // Now scan through each region looking for specified attributes and store the value
-                        for r in layer.regions.region {
-                            //dbg!(&r);
-                            // Get the region ID to be used as the key
-                            let rid = r.input_region_id.expect("Missing input region ID for analysis region");
-                            // Get image location for this region (stripped down to just the filename)
-                            if let Some(loc) = path::Path::new(&r.image_location.unwrap_or(String::from(""))).file_name() {
-                                // Try to convert OsStr to String
-                                if let Some(lp) = loc.to_str() {
-                                    // Start by locating a region info for this region
-                                    regions_info.entry(rid.clone())
-                                    // or alternatively make a new entry
-                                    .or_insert(RegionInfo::new())
-                                    // Convert result into String and return "" if unable
-                                    .set_image_location(Some(lp.to_string()));
-                                }                                
-                            }
-                            // Check first if there exists a Region Attributes section for this region
-                            if let Some(region_attrib) = r.attributes.attribute {
-                                // Now search through each atttribute to find the positivity attribute
-                                for attrib in region_attrib {
-                                    if attrib.name==positivity_name {
-                                        // Find the correct region Id to store information
-                                        regions_info.entry(rid.clone())
-                                        // Or make a new entry if missing
-                                        .or_insert(RegionInfo::new())
-                                        // Convert result into f32 and return NAN if unable
-                                        .set_positivity(attrib.value.trim().parse::<f32>().ok());
-                                    }
-                                    if attrib.name==num_positive_name {
-                                        // Find the correct region Id to store information
-                                        regions_info.entry(rid.clone())
-                                        // Or make a new entry if missing
-                                        .or_insert(RegionInfo::new())
-                                        // Convert result into f32 and return 0 if unable
-                                        .set_num_positive(attrib.value.trim().parse::<f32>().ok());
-                                    }
-                                    if attrib.name==num_wpositive_name {
-                                        // Find the correct region Id to store information
-                                        regions_info.entry(rid.clone())
-                                        // Or make a new entry if missing
-                                        .or_insert(RegionInfo::new())
-                                        // Convert result into f32 and return 0 if unable
-                                        .set_num_wpositive(attrib.value.trim().parse::<f32>().ok());
-                                    }
-                                    if attrib.name==num_spositive_name {
-                                        // Find the correct region Id to store information
-                                        regions_info.entry(rid.clone())
-                                        // Or make a new entry if missing
-                                        .or_insert(RegionInfo::new())
-                                        // Convert result into f32 and return 0 if unable
-                                        .set_num_spositive(attrib.value.trim().parse::<f32>().ok());
-                                    }
-                                    if attrib.name==num_total_name {
-                                        // Find the correct region Id to store information
-                                        regions_info.entry(rid.clone())
-                                        // Or make a new entry if missing
-                                        .or_insert(RegionInfo::new())
-                                        // Convert result into f32 and return 0 if unable
-                                        .set_num_total(attrib.value.trim().parse::<f32>().ok());
-                                    }
-                                }                                
-                            }                                
-                        }
-                    } else {
-                        eprintln!("In {}: Type 3 annotation layer {} is missing Region Attribute header", filepath.display(), &layer.id);
-                        continue;
-                    }
-                },
-                // Ignore other annotation types
-                &_ => {},
-            }            
-        }
+    if tidy {
+        writeln!(writer, "{}", TIDY_HEADER)?;
+    } else {
+        write_csv_header_with_columns(writer, &options.output_column_names())?;
+    }
+    for source in xml_sources(search_path, options)? {
+        let (records, summary) = process_source(&source, options);
 
         // Report filename, region id, and information about each region
-        for r in &regions_info {
-            let mut slidename = filepath.clone();
-            slidename.set_extension("svs");
-            println!("{},{},{},{},{},{},{},{},{},{}", &filepath.file_name().expect("Error parsing filename from full path").to_str().expect("Unable to convert filename to string"), 
-                slidename.file_name().expect("Missing SVS slide filename").to_str().expect("Error converting SVS filename to string"), 
-                r.0, 
-                r.1.text_label().unwrap_or(&String::from("")).trim(), 
-                r.1.positivity().unwrap_or(f32::NAN), 
-                r.1.num_wpositive().unwrap_or(0.0),
-                r.1.num_positive().unwrap_or(0.0), 
-                r.1.num_spositive().unwrap_or(0.0),
-                r.1.get_total_positive(),
-                r.1.num_total().unwrap_or(0.0));
+        if tidy {
+            write_tidy_records(&records, writer)?;
+        } else {
+            write_csv_records_with_columns(&records, &options.text_column_names(), writer)?;
         }
+        summaries.push(summary);
     } 
 
-    // Return Ok    
-    Ok(())
+    // Return summaries
+    Ok(summaries)
 }
 
-/// List of annotations
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Annotations {
-    /// Scale in microns per pixel (assuming square aspect ratio)
-    #[serde(rename = "@MicronsPerPixel")]
-    pub microns_per_pixel: String,
-    /// List of annotations
-    #[serde(rename = "Annotation")]
-    pub annotation: Vec<Annotation>,
+/// Write the vertices of every XML file in `search_path` into `writer`
+fn run_vertices_to_writer<W: io::Write>(search_path: &path::Path, options: &Options, writer: &mut W) -> Result<Vec<FileSummary>, Box<dyn error::Error>> {
+    let mut summaries = Vec::new();
+    writeln!(writer, "{}", VERTICES_HEADER)?;
+    for source in xml_sources(search_path, options)? {
+        let mut summary = source.summary();
+        if let Some(annotations) = read_source(&source, options, &mut summary) {
+            write_vertex_records(&source.filename, &annotations, writer)?;
+        }
+        summaries.push(summary);
+    }
+    Ok(summaries)
 }
 
-/// An annotation layer
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Annotation {
-    /// Annotation ID
-    #[serde(rename = "@Id")]
-    pub id: String,
-    /// Name of annotation layer
-    #[serde(rename = "@Name")]
-    pub name: String,
-    /// Annotation type
-    /// 4 = user-defined drawn regions
-    /// 3 = calculated data from analysis
-    #[serde(rename = "@Type")]
-    pub annotation_type: String,
-    /// List of annotation attributes
-    #[serde(rename = "Attributes")]
-    pub attributes: AnnotationAttributes,
-    /// List of regions
-    #[serde(rename = "Regions")]
-    pub regions: Regions
-}
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time};
+    use crate::algorithm::AlgorithmKind;
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::input::XmlSource;
+    use crate::metadata::parse_csv_line;
+    use crate::options::Options;
+    use super::{catch_panics, give_up_after, run_to_writer};
 
-/// A specific attribute for an annotation
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AnnotationAttributes {
-    #[serde(rename = "Attribute")]
-    pub attribute: Option<Vec<AnnotationAttributesAttribute>>,
-}
+    #[test]
+    fn a_bad_file_does_not_stop_the_batch() {
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_batch_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let annotations = AnnotationsBuilder::new().layer(AnnotationBuilder::new().region(RegionBuilder::new().text("Tumor").vertices([(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]))).build();
+        let xml = annotations.to_xml_string().unwrap();
+        fs::write(dir.join("good.xml"), &xml).unwrap();
+        fs::write(dir.join("broken.xml"), &xml[..xml.len() / 2]).unwrap();
+        // A name that is not valid UTF-8
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            fs::write(dir.join(std::ffi::OsStr::from_bytes(b"bad\xff.xml")), &xml).unwrap();
+        }
+        let mut output = Vec::new();
+        let summaries = run_to_writer(&dir, &Options::default(), &mut output);
+        fs::remove_dir_all(&dir).unwrap();
+        let summaries = summaries.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let failed: Vec<bool> = summaries.iter().map(|s| s.failed()).collect();
+        #[cfg(unix)]
+        {
+            // Sources are sorted by path
+            assert_eq!(failed, [false, true, false]);
+            assert!(output.contains("bad\u{FFFD}.xml,bad\u{FFFD}.svs,1,Tumor,"), "{}", output);
+        }
+        #[cfg(not(unix))]
+        assert_eq!(failed, [true, false]);
+        assert!(output.contains("good.xml,good.svs,1,Tumor,"), "{}", output);
+    }
 
-/// Annotation attribute details
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AnnotationAttributesAttribute {
-    #[serde(rename = "@Name")]
-    pub name: String,
-    #[serde(rename = "@Id")]
-    pub id: String,
-    #[serde(rename = "@Value")]
-    pub value: String,
-}
+    #[test]
+    fn a_panic_fails_only_its_source() {
+        let source = XmlSource::in_memory("case1.xml".into(), String::from("case1.xml"), Vec::new());
+        let (records, summary) = catch_panics(&source, || panic!("vertex out of range"));
+        assert!(records.is_empty());
+        assert_eq!(summary.error.as_deref(), Some("Error processing case1.xml: vertex out of range"));
+        let (_, summary) = catch_panics(&source, || (Vec::new(), source.summary()));
+        assert!(!summary.failed());
+    }
 
-/// List of regions in an annotation layer
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Regions {
-    #[serde(rename = "RegionAttributeHeaders")]
-    pub region_attribute_headers: RegionAttributeHeaders,
-    #[serde(rename = "Region")]
-    pub region: Vec<Region>,
-}
+    #[test]
+    fn a_slow_source_is_given_up_on() {
+        let source = XmlSource::in_memory("slow.xml".into(), String::from("slow.xml"), Vec::new());
+        let started = time::Instant::now();
+        let (records, summary) = give_up_after(&source, 1, |source| {
+            thread::sleep(time::Duration::from_secs(5));
+            (Vec::new(), source.summary())
+        });
+        assert!(started.elapsed() < time::Duration::from_secs(4));
+        assert!(records.is_empty());
+        assert_eq!(summary.error.as_deref(), Some("Gave up on slow.xml after 1 seconds"));
+        let (_, summary) = give_up_after(&source, 1, |source| (Vec::new(), source.summary()));
+        assert!(!summary.failed());
+    }
 
-/// Meta-information about region attributes common across regions (header)
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RegionAttributeHeaders {
-    #[serde(rename = "AttributeHeader")]
-    pub attribute_header: Option<Vec<AttributeHeader>>,
-}
 
-/// Region attribute header details
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AttributeHeader {
-    #[serde(rename = "@Id")]
-    pub id: String,
-    #[serde(rename = "@Name")]
-    pub name: String,
-}
+    /// Columns of the only row reported for a slide with a drawn region and an analysis layer named `name`,
+    /// holding `results` as attribute header name and value
+    fn report_row(name: &str, results: &[(&str, &str)], options: &Options) -> Vec<(String, String)> {
+        let headers: String = results.iter().enumerate().map(|(i, (header, _))| format!(r#"<AttributeHeader Id="{}" Name="{}"/>"#, i + 1, header)).collect();
+        let values: String = results.iter().enumerate().map(|(i, (_, value))| format!(r#"<Attribute Name="{}" Id="{}" Value="{}"/>"#, i + 1, i + 1, value)).collect();
+        let xml = format!(r#"<Annotations>
+            <Annotation Id="1" Type="4"><Regions><Region Id="1" Text="Tumor"/></Regions></Annotation>
+            <Annotation Id="2" Name="{}" Type="3"><Regions><RegionAttributeHeaders>{}</RegionAttributeHeaders>
+                <Region Id="1" InputRegionId="1"><Attributes>{}</Attributes></Region>
+            </Regions></Annotation>
+        </Annotations>"#, name, headers, values);
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_results_{}_{}", name.replace(' ', "_"), std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("case1.xml"), xml).unwrap();
+        let mut output = Vec::new();
+        let summaries = run_to_writer(&dir, options, &mut output).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(summaries[0].warnings.is_empty(), "{:?}", summaries[0].warnings);
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<Vec<String>> = output.lines().map(parse_csv_line).collect();
+        assert_eq!(lines.len(), 2, "{}", output);
+        lines[0].iter().cloned().zip(lines[1].iter().cloned()).collect()
+    }
 
-/// Details about each region
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Region {
-    #[serde(rename = "@Id")]
-    pub id: String,
-    #[serde(rename = "@Type")]
-    pub region_type: String,
-    #[serde(rename = "@Length")]
-    pub length: String,
-    #[serde(rename = "@Area")]
-    pub area: String,
-    #[serde(rename = "@LengthMicrons")]
-    pub length_microns: String,
-    #[serde(rename = "@AreaMicrons")]
-    pub area_microns: String,
-    #[serde(rename = "@Text")]
-    pub text: String,
-    #[serde(rename = "@NegativeROA")]
-    pub negative_roa: String,
-    #[serde(rename = "@Analyze")]
-    pub analyze: String,
-    #[serde(rename = "Attributes")]
-    pub attributes: RegionAttributes,
-    #[serde(rename="@ImageLocation")]
-    pub image_location: Option<String>,
-    #[serde(rename="@InputRegionId")]
-    pub input_region_id: Option<String>,
-}
+    /// Values of `columns` in a row from `report_row`
+    fn values<'a>(row: &'a [(String, String)], columns: &[&str]) -> Vec<&'a str> {
+        columns.iter().map(|column| row.iter().find(|(name, _)| name == column).map(|(_, value)| value.as_str()).unwrap_or_else(|| panic!("No column {} in {:?}", column, row))).collect()
+    }
 
-/// Region attribute
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RegionAttributes {
-    #[serde(rename = "Attribute")]
-    pub attribute: Option<Vec<RegionAttributesAttribute>>,
-}
+    #[test]
+    fn legacy_positive_pixel_count_names_are_read() {
+        let row = report_row("Layer 2", &[
+            ("Positivity", "0.6"),
+            ("NumberWeakPositive", "10"),
+            ("NumberPositive", "20"),
+            ("NumberStrongPositive", "30"),
+            ("NumberTotal", "100"),
+        ], &Options::default());
+        let columns = ["positivity", "num weak positive", "num positive", "num strong positive", "num all positive", "num total", "algorithm"];
+        assert_eq!(values(&row, &columns), ["0.6", "10", "20", "30", "60", "100", "Positive Pixel Count"]);
+        let row = report_row("Layer 2", &[
+            ("Positivity: Np / (Nwp + Np + Nsp)", "0.5"),
+            ("Weak Positive Pixels", "1"),
+            ("Positive Pixels", "2"),
+            ("Strong Positive Pixels", "3"),
+            ("Total Pixels", "12"),
+        ], &Options::default());
+        assert_eq!(values(&row, &columns), ["0.5", "1", "2", "3", "6", "12", "Positive Pixel Count"]);
+    }
+
+
+    #[test]
+    fn nuclear_results_are_reported() {
+        let results = [
+            ("(%) Positive Nuclei", "45"),
+            ("Num Positive Nuclei", "90"),
+            ("Total Nuclei", "200"),
+            ("(0+) Percent Nuclei", "55"),
+            ("(1+) Percent Nuclei", "25"),
+            ("(2+) Percent Nuclei", "15"),
+            ("(3+) Percent Nuclei", "5"),
+            ("Average Positive Intensity", "142.5"),
+        ];
+        let options = Options { results: vec![AlgorithmKind::Nuclear], ..Options::default() };
+        let row = report_row("Nuclear v9", &results, &options);
+        assert_eq!(values(&row, &["positivity", "num positive", "num total", "algorithm"]), ["0.45", "90", "200", "Nuclear 9"]);
+        let columns = ["nuclear_percent_0", "nuclear_percent_1", "nuclear_percent_2", "nuclear_percent_3", "nuclear_total", "nuclear_average_intensity"];
+        assert_eq!(values(&row, &columns), ["55", "25", "15", "5", "200", "142.5"]);
+        // Only reported when asked for
+        let row = report_row("Nuclear v9", &results, &Options::default());
+        assert!(row.iter().all(|(name, _)| !name.starts_with("nuclear_")), "{:?}", row);
+    }
 
-/// Region attribute detail
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RegionAttributesAttribute {
-    #[serde(rename = "@Name")]
-    pub name: String,
-    #[serde(rename = "@Id")]
-    pub id: String,
-    #[serde(rename = "@Value")]
-    pub value: String,
-    #[serde(rename = "@DisplayColor")]
-    pub display_color: String,
-}
\ No newline at end of file
+
+    #[test]
+    fn rare_events_are_reported_without_a_positivity() {
+        let options = Options { results: vec![AlgorithmKind::RareEvent], ..Options::default() };
+        let row = report_row("Rare Event Detection", &[("Number of Events", "12"), ("Events/mm^2", "4.8"), ("Area Analyzed", "2.5")], &options);
+        // Missing values are written as for any layer
+        assert_eq!(values(&row, &["positivity", "num positive", "num total", "algorithm"]), ["NaN", "12", "0", "Rare Event"]);
+        assert_eq!(values(&row, &["rare_event_count", "rare_event_density", "rare_event_area"]), ["12", "4.8", "2.5"]);
+        // Events out of the cells counted give a positivity
+        let row = report_row("Rare Event Detection", &[("Percent Events", "1.5"), ("Num Events", "3"), ("Num Cells", "200")], &Options::default());
+        assert_eq!(values(&row, &["positivity", "num positive", "num total"]), ["0.015", "3", "200"]);
+    }
+
+
+    #[test]
+    fn cytoplasmic_results_are_reported() {
+        let options = Options { results: vec![AlgorithmKind::Cytoplasmic], ..Options::default() };
+        let row = report_row("Cytoplasmic v2", &[
+            ("(%) Positive Cells", "30"),
+            ("Num Positive Cells", "60"),
+            ("Num Cells", "200"),
+            ("(0+) Percent Cells", "70"),
+            ("(1+) Percent Cells", "20"),
+            ("(2+) Percent Cells", "8"),
+            ("(3+) Percent Cells", "2"),
+            ("Average Cytoplasmic Intensity", "181.2"),
+        ], &options);
+        assert_eq!(values(&row, &["positivity", "num positive", "num total", "algorithm"]), ["0.3", "60", "200", "Cytoplasmic 2"]);
+        let columns = ["cytoplasmic_percent_0", "cytoplasmic_percent_1", "cytoplasmic_percent_2", "cytoplasmic_percent_3", "cytoplasmic_average_intensity"];
+        assert_eq!(values(&row, &columns), ["70", "20", "8", "2", "181.2"]);
+    }
+}
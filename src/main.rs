@@ -1,20 +1,264 @@
-use std::{env, path, error};
+use std::{env, path, error, process, slice};
+use read_imagescope_xml::{DecimalSeparator, GroupBy, Options};
 
 fn main() -> Result<(), Box<dyn error::Error>> {
     // Start by collecting command line arguments
     let args: Vec<String> = env::args().collect();
     dbg!(&args);
 
+    // Validating files against a schema is separate from extracting data
+    if args.get(1).map(String::as_str) == Some("validate") {
+        return validate(&args);
+    }
+    // As is converting files to other formats, and rendering is a conversion to an image format
+    if matches!(args.get(1).map(String::as_str), Some("convert" | "render")) {
+        return convert(&args);
+    }
+    // Summaries roll the regions up instead of listing them
+    let summarize = args.get(1).map(String::as_str) == Some("summarize");
+    // And completeness reports list what each file is missing
+    let completeness = args.get(1).map(String::as_str) == Some("completeness");
+    let mut group_by: Option<GroupBy> = None;
+    let mut compare: Option<&String> = None;
+    let mut pivot = false;
+
+    // Separate options from the search path
+    let mut options = Options::default();
+    let mut metadata: Option<&String> = None;
+    let mut positional: Vec<&String> = Vec::new();
+    let mut rest = args[if summarize || completeness { 2 } else { 1 }..].iter();
+    while let Some(arg) = rest.next() {
+        if parse_option(arg, &mut rest, &mut options, &mut metadata)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--input" => positional.push(rest.next().ok_or("Missing value for --input")?),
+            "--by" if summarize => group_by = Some(rest.next().ok_or("Missing value for --by")?.parse()?),
+            "--pivot" if summarize => match rest.next().map(String::as_str) {
+                Some("label") => pivot = true,
+                Some(other) => return Err(format!("Unknown pivot {}, expected label", other).into()),
+                None => return Err("Missing value for --pivot".into()),
+            },
+            "--compare" if summarize => compare = Some(rest.next().ok_or("Missing value for --compare")?),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag).into()),
+            _ => positional.push(arg),
+        }
+    }
+    // Pivots and comparisons have their own grouping, so only one kind of summary can be asked for
+    let summaries = [(pivot, "--pivot"), (compare.is_some(), "--compare"), (group_by.is_some(), "--by")];
+    let chosen: Vec<&str> = summaries.iter().filter(|(given, _)| *given).map(|(_, flag)| *flag).collect();
+    if chosen.len() > 1 {
+        return Err(format!("Options {} cannot be combined, choose one kind of summary", chosen.join(", ")).into());
+    }
+    read_metadata(metadata, &mut options)?;
+
     // Default is use executable folder as search path
     let mut search_path = path::Path::new(&args[0]).parent().expect("Parent folder of executable should always be available and valid");
     // If an argument is specified, use that directly instead
-    if args.len()>=2 {
+    if let Some(arg) = positional.first() {
         // Create a search Path from provided argument directly
-        search_path = path::Path::new(&args[1]);
+        search_path = path::Path::new(arg);
     } 
     
     dbg!(&search_path);
 
     // Return the results from parsing the XML files
-    read_imagescope_xml::run(search_path)        
+    if completeness {
+        // Exit code is the number of incomplete files
+        let incomplete = read_imagescope_xml::run_completeness(search_path, &options)?;
+        if incomplete > 0 {
+            eprintln!("{} file(s) are incomplete", incomplete);
+            process::exit(incomplete.min(255) as i32);
+        }
+        return Ok(());
+    }
+    let failed = if pivot {
+        read_imagescope_xml::run_pivot(search_path, &options)?
+    } else if let Some(column) = compare {
+        read_imagescope_xml::run_compare(search_path, column, &options)?
+    } else if summarize {
+        read_imagescope_xml::run_summarize(search_path, group_by.unwrap_or_default(), &options)?
+    } else {
+        read_imagescope_xml::run(search_path, &options)?
+    };
+    // Exit code is the number of files that could not be processed
+    if failed > 0 {
+        eprintln!("{} file(s) could not be processed", failed);
+        process::exit(failed.min(255) as i32);
+    }
+    Ok(())
+}
+
+/// Check files against the bundled or a given XML schema, e.g. `validate --schema my.xsd folder`
+/// Exit code is the number of files that are not valid
+fn validate(args: &[String]) -> Result<(), Box<dyn error::Error>> {
+    let mut schema: Option<&String> = None;
+    let mut positional: Vec<&String> = Vec::new();
+    let mut rest = args[2..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--schema" => schema = Some(rest.next().ok_or("Missing value for --schema")?),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag).into()),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() > 1 {
+        return Err(format!("Expected a single folder or file to validate, got {}", positional.iter().map(|p| p.as_str()).collect::<Vec<_>>().join(", ")).into());
+    }
+    // Same default search path as extracting data
+    let search_path = match positional.first() {
+        Some(arg) => path::Path::new(arg.as_str()),
+        None => path::Path::new(&args[0]).parent().expect("Parent folder of executable should always be available and valid"),
+    };
+    let invalid = read_imagescope_xml::run_validate(search_path, schema.map(path::Path::new), &Options::default())?;
+    if invalid > 0 {
+        eprintln!("{} file(s) are not valid", invalid);
+        process::exit(invalid.min(255) as i32);
+    }
+    Ok(())
+}
+
+/// Convert files to another format, e.g. `convert --to geojson --output out folder`, or render them, e.g.
+/// `render --svg --downsample 32 folder`. The input format is recognized from each file unless given with `--from`, e.g. `--from qupath-geojson`
+/// Takes the same options as extracting data and a single folder or file. Output files are written into the
+/// current folder unless given with `--output`. Exit code is the number of files that could not be converted.
+fn convert(args: &[String]) -> Result<(), Box<dyn error::Error>> {
+    let mut options = Options::default();
+    let mut format: Option<String> = None;
+    let mut from: Option<String> = None;
+    let mut output: Option<&String> = None;
+    let mut metadata: Option<&String> = None;
+    let mut positional: Vec<&String> = Vec::new();
+    let mut rest = args[2..].iter();
+    while let Some(arg) = rest.next() {
+        if parse_option(arg, &mut rest, &mut options, &mut metadata)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--from" => from = Some(rest.next().ok_or("Missing value for --from")?.clone()),
+            "--to" => format = Some(rest.next().ok_or("Missing value for --to")?.clone()),
+            "--svg" => format = Some(String::from("svg")),
+            "--mask" => format = Some(String::from("mask-png")),
+            "--heatmap" => format = Some(String::from("heatmap-svg")),
+            "--output" => output = Some(rest.next().ok_or("Missing value for --output")?),
+            "--input" => positional.push(rest.next().ok_or("Missing value for --input")?),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag).into()),
+            _ => positional.push(arg),
+        }
+    }
+    read_metadata(metadata, &mut options)?;
+    let format = format.ok_or(if args[1] == "render" { "Missing image format, e.g. --svg" } else { "Missing --to <format>" })?;
+    if positional.len() > 1 {
+        return Err(format!("Expected a single folder or file to convert, got {}", positional.iter().map(|p| p.as_str()).collect::<Vec<_>>().join(", ")).into());
+    }
+    // Same default search path as extracting data
+    let search_path = match positional.first() {
+        Some(arg) => path::Path::new(arg.as_str()),
+        None => path::Path::new(&args[0]).parent().expect("Parent folder of executable should always be available and valid"),
+    };
+    #[cfg(feature = "export")]
+    {
+        let output_dir = path::Path::new(output.map(String::as_str).unwrap_or(read_imagescope_xml::DEFAULT_OUTPUT_DIR));
+        let from = from.as_deref().map(str::parse).transpose()?.unwrap_or_default();
+        let failed = read_imagescope_xml::run_convert(search_path, from, format.parse()?, output_dir, &options)?;
+        if failed > 0 {
+            eprintln!("{} file(s) could not be converted", failed);
+            process::exit(failed.min(255) as i32);
+        }
+        Ok(())
+    }
+    #[cfg(not(feature = "export"))]
+    {
+        let _ = (search_path, output, from);
+        Err(format!("Cannot convert to {}: rebuild with the `export` feature", format).into())
+    }
+}
+
+/// Apply an option shared by extracting and converting, false if `arg` is not one of them
+/// The path of the metadata file is kept in `metadata`, to be read with `read_metadata`.
+fn parse_option<'a>(arg: &str, rest: &mut slice::Iter<'a, String>, options: &mut Options, metadata: &mut Option<&'a String>) -> Result<bool, Box<dyn error::Error>> {
+    match arg {
+        "--lenient" => options.lenient = true,
+        "--strict" => options.strict = true,
+        "--verbose" => options.verbose = true,
+        "--http-timeout" => options.http_timeout = option_value(arg, rest.next())?,
+        "--http-retries" => options.http_retries = option_value(arg, rest.next())?,
+        "--timeout-per-file" => options.timeout_per_file = Some(option_value(arg, rest.next())?),
+        "--decimal-comma" => options.decimal_separator = DecimalSeparator::Comma,
+        "--decimal-point" => options.decimal_separator = DecimalSeparator::Point,
+        "--duplicate-slides" => options.duplicate_slides = rest.next().ok_or("Missing value for --duplicate-slides")?.parse()?,
+        "--format" => options.format = rest.next().ok_or("Missing value for --format")?.parse()?,
+        "--positivity-tolerance" => options.positivity_tolerance = option_value(arg, rest.next())?,
+        "--layer" => options.analysis_layer = Some(option_value(arg, rest.next())?),
+        "--pattern" => options.attribute_patterns.add(rest.next().ok_or("Missing value for --pattern")?)?,
+        "--genie-classes" => options.genie_classes = read_imagescope_xml::GenieClasses::parse_list(rest.next().ok_or("Missing value for --genie-classes")?)?,
+        "--results" => options.results.extend(read_imagescope_xml::AlgorithmKind::parse_results(rest.next().ok_or("Missing value for --results")?)?),
+        "--algorithm-columns" => options.algorithm_columns.extend(read_imagescope_xml::AlgorithmKind::parse_list(rest.next().ok_or("Missing value for --algorithm-columns")?)?),
+        "--qc" => options.qc = true,
+        "--label-map" => options.label_map = Some(read_imagescope_xml::LabelMap::read(path::Path::new(rest.next().ok_or("Missing value for --label-map")?))?),
+        // Read once all options are parsed, as the join column may follow
+        "--metadata" => *metadata = Some(rest.next().ok_or("Missing value for --metadata")?),
+        "--join-on" => options.join_on = option_value(arg, rest.next())?,
+        "--score" => options.scores.extend(read_imagescope_xml::Score::parse_list(rest.next().ok_or("Missing value for --score")?)?),
+        #[cfg(feature = "config")]
+        "--config" => read_imagescope_xml::load_config(path::Path::new(rest.next().ok_or("Missing value for --config")?), options)?,
+        #[cfg(feature = "config")]
+        "--filename-pattern" => options.filename_pattern = Some(read_imagescope_xml::FilenamePattern::new(rest.next().ok_or("Missing value for --filename-pattern")?)?),
+        #[cfg(not(feature = "config"))]
+        "--filename-pattern" => return Err("Cannot use --filename-pattern: rebuild with the `config` feature".into()),
+        #[cfg(not(feature = "config"))]
+        "--config" => return Err("Cannot read a config file: rebuild with the `config` feature".into()),
+        #[cfg(feature = "geometry")]
+        "--area-tolerance" => options.area_tolerance = Some(option_value(arg, rest.next())?),
+        #[cfg(feature = "geometry")]
+        "--measure" => options.measurements.extend(read_imagescope_xml::Measurement::parse_list(rest.next().ok_or("Missing value for --measure")?)?),
+        #[cfg(feature = "geometry")]
+        "--nested-labels" => options.nested_labels = true,
+        #[cfg(feature = "geometry")]
+        "--simplify" => options.simplify = Some(option_value(arg, rest.next())?),
+        #[cfg(feature = "geometry")]
+        "--microns" => options.microns = true,
+        #[cfg(feature = "geometry")]
+        "--affine" => options.affine = Some(rest.next().ok_or("Missing value for --affine")?.parse()?),
+        #[cfg(feature = "export")]
+        "--downsample" => {
+            options.downsample = option_value(arg, rest.next())?;
+            if options.downsample <= 0.0 {
+                return Err("--downsample must be a positive number".into());
+            }
+        },
+        #[cfg(feature = "export")]
+        "--color-by" => options.color_by = rest.next().ok_or("Missing value for --color-by")?.parse()?,
+        #[cfg(feature = "export")]
+        "--tile-size" => {
+            options.tile_size = option_value(arg, rest.next())?;
+            if options.tile_size <= 0.0 {
+                return Err("--tile-size must be a positive number".into());
+            }
+        },
+        #[cfg(feature = "export")]
+        "--tile-labels" => options.tile_labels.extend(rest.next().ok_or("Missing value for --tile-labels")?.split(',').map(|l| l.trim().to_string())),
+        #[cfg(feature = "export")]
+        "--crop-margin" => options.crop_margin = option_value(arg, rest.next())?,
+        #[cfg(feature = "raster")]
+        "--mask-labels" => options.mask_labels.extend(rest.next().ok_or("Missing value for --mask-labels")?.split(',').map(|l| l.trim().to_string())),
+        #[cfg(not(feature = "geometry"))]
+        flag @ ("--area-tolerance" | "--measure" | "--nested-labels" | "--simplify" | "--microns" | "--affine") => return Err(format!("Cannot use {}: rebuild with the `geometry` feature", flag).into()),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Read the metadata file given with `--metadata`, joining on the column given with `--join-on`
+fn read_metadata(metadata: Option<&String>, options: &mut Options) -> Result<(), Box<dyn error::Error>> {
+    if let Some(path) = metadata {
+        options.metadata = Some(read_imagescope_xml::Metadata::read(path::Path::new(path), &options.join_on)?);
+    }
+    Ok(())
+}
+
+/// Parse the value following an option
+fn option_value<T: std::str::FromStr>(option: &str, value: Option<&String>) -> Result<T, Box<dyn error::Error>> {
+    let value = value.ok_or(format!("Missing value for {}", option))?;
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", option, value).into())
 }
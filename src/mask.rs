@@ -0,0 +1,145 @@
+use std::error;
+use crate::convert::ExportRegion;
+use crate::geometry::{BoundingBox, Point, polygon_spans};
+
+/// Largest mask we make, in pixels, as each takes a byte of memory
+const MAX_MASK_PIXELS: u64 = 1 << 30;
+
+/// Label mask of the regions, one byte per pixel, row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mask {
+    pub width: u32,
+    pub height: u32,
+    /// Level-0 pixel position of the top left corner of the mask on the slide
+    pub x: f64,
+    pub y: f64,
+    pub pixels: Vec<u8>,
+    /// Label of each mask value, value 1 is the first label and 0 is background
+    pub labels: Vec<String>,
+}
+
+/// Fill the pixels whose centers lie inside the polygon with `value`
+fn fill_polygon(mask: &mut Mask, points: &[Point], downsample: f64, value: u8) {
+    for (row, start, end) in polygon_spans(points, downsample, mask.width, mask.height) {
+        let offset = (row * mask.width) as usize;
+        mask.pixels[offset + start as usize..offset + end as usize].fill(value);
+    }
+}
+
+/// Burn closed regions into a label mask scaled down by `downsample`
+/// Only regions with one of `labels` are drawn, in that order of mask values; all labels in order of
+/// appearance if empty. NegativeROA regions are cleared back to background after all others are drawn.
+/// The mask covers the bounding box of the drawn regions, on the grid of a mask of the whole slide, and is refused
+/// when it would be too large to hold in memory.
+pub fn rasterize(regions: &[ExportRegion], downsample: f64, labels: &[String]) -> Result<Mask, Box<dyn error::Error>> {
+    let closed: Vec<(&ExportRegion, Vec<Point>)> = regions.iter()
+        .filter(|r| r.region.region_type.is_closed())
+        .map(|r| (r, r.region.outline()))
+        .filter(|(_, outline)| outline.len() >= 3)
+        .collect();
+    let mut mask_labels: Vec<String> = labels.to_vec();
+    if mask_labels.is_empty() {
+        for (r, _) in closed.iter().filter(|(r, _)| !r.region.is_negative()) {
+            let label = r.region.label();
+            if !mask_labels.contains(&label) {
+                mask_labels.push(label);
+            }
+        }
+    }
+    if mask_labels.len() > u8::MAX as usize {
+        return Err(format!("Too many labels for a mask: {}, at most {}", mask_labels.len(), u8::MAX).into());
+    }
+    // Whole mask pixels from the slide origin, so the mask can be placed back onto the slide
+    let all_points: Vec<Point> = closed.iter()
+        .filter(|(r, _)| !r.region.is_negative() && mask_labels.contains(&r.region.label()))
+        .flat_map(|(_, outline)| outline.iter().copied())
+        .collect();
+    let (first_column, first_row, last_column, last_row) = BoundingBox::of(&all_points)
+        .map(|b| ((b.min_x / downsample).floor().max(0.0), (b.min_y / downsample).floor().max(0.0), (b.max_x / downsample).ceil().max(0.0), (b.max_y / downsample).ceil().max(0.0)))
+        .unwrap_or((0.0, 0.0, 0.0, 0.0));
+    let (width, height) = ((last_column - first_column).max(1.0), (last_row - first_row).max(1.0));
+    if width * height > MAX_MASK_PIXELS as f64 {
+        return Err(format!("A mask of {:.0} x {:.0} pixels is too large, raise --downsample or choose fewer --mask-labels", width, height).into());
+    }
+    let (x, y) = (first_column * downsample, first_row * downsample);
+    let mut mask = Mask { width: width as u32, height: height as u32, x, y, pixels: vec![0; width as usize * height as usize], labels: Vec::new() };
+    let shifted = |outline: &[Point]| outline.iter().map(|(px, py)| (px - x, py - y)).collect::<Vec<Point>>();
+    for (r, outline) in closed.iter().filter(|(r, _)| !r.region.is_negative()) {
+        if let Some(position) = mask_labels.iter().position(|l| *l == r.region.label()) {
+            fill_polygon(&mut mask, &shifted(outline), downsample, position as u8 + 1);
+        }
+    }
+    for (_, outline) in closed.iter().filter(|(r, _)| r.region.is_negative()) {
+        fill_polygon(&mut mask, &shifted(outline), downsample, 0);
+    }
+    mask.labels = mask_labels;
+    Ok(mask)
+}
+
+impl Mask {
+    /// Encode as an 8-bit grayscale PNG
+    pub fn to_png(&self) -> Result<Vec<u8>, Box<dyn error::Error>> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(bytes)
+    }
+
+    /// Encode as an 8-bit grayscale TIFF
+    pub fn to_tiff(&self) -> Result<Vec<u8>, Box<dyn error::Error>> {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        tiff::encoder::TiffEncoder::new(&mut bytes)?
+            .write_image::<tiff::encoder::colortype::Gray8>(self.width, self.height, &self.pixels)?;
+        Ok(bytes.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use super::rasterize;
+
+    fn square(x1: f64, y1: f64, x2: f64, y2: f64) -> RegionBuilder {
+        RegionBuilder::new().vertices([(x1, y1), (x2, y1), (x2, y2), (x1, y2)])
+    }
+
+    #[test]
+    fn regions_are_burnt_in_with_holes_cleared() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(square(10.0, 10.0, 30.0, 30.0).text("Tumor"))
+                .region(square(20.0, 20.0, 40.0, 40.0).text("Stroma"))
+                .region(square(22.0, 22.0, 26.0, 26.0).negative_roa(true)))
+            .build();
+        let regions = export_regions(&annotations, &[]);
+        let mask = rasterize(&regions, 2.0, &[]).unwrap();
+        // The mask starts at the drawn regions, on the grid of the downsampled slide
+        assert_eq!((mask.width, mask.height, mask.x, mask.y), (15, 15, 10.0, 10.0));
+        assert_eq!(mask.labels, ["Tumor", "Stroma"]);
+        let expected: Vec<u8> = (0..15).flat_map(|row| (0..15).map(move |column| {
+            let inside = |from: u32, to: u32| (from..to).contains(&row) && (from..to).contains(&column);
+            if inside(6, 8) { 0 } else if inside(5, 15) { 2 } else if inside(0, 10) { 1 } else { 0 }
+        })).collect();
+        assert_eq!(mask.pixels, expected);
+
+        // Values follow the order of the labels asked for, other labels are left out of the mask and its extent
+        let mask = rasterize(&regions, 2.0, &[String::from("Stroma"), String::from("Tumor")]).unwrap();
+        assert_eq!((mask.width, mask.x), (15, 10.0));
+        assert_eq!((mask.pixels[0], mask.pixels[14 * 15 + 14]), (2, 1));
+        let mask = rasterize(&regions, 2.0, &[String::from("Stroma")]).unwrap();
+        assert_eq!((mask.width, mask.height, mask.x, mask.y), (10, 10, 20.0, 20.0));
+        assert_eq!(mask.pixels.iter().filter(|&&value| value == 1).count(), 100 - 4);
+    }
+
+    #[test]
+    fn masks_encode_as_images() {
+        let annotations = AnnotationsBuilder::new().layer(AnnotationBuilder::new().region(square(0.0, 0.0, 4.0, 2.0))).build();
+        let mask = rasterize(&export_regions(&annotations, &[]), 1.0, &[]).unwrap();
+        assert_eq!((mask.width, mask.height), (4, 2));
+        assert!(mask.to_png().unwrap().starts_with(b"\x89PNG"));
+        assert!(mask.to_tiff().unwrap().starts_with(b"II*\0"));
+    }
+}
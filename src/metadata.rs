@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::{error, fs, path};
+use crate::extract::{FileSummary, RegionRecord};
+
+/// Study manifest whose columns are added to the records of matching slides
+/// Rows are matched on the join column holding the slide name, with or without its extension, so both
+/// `case1.svs` and `case1` match slide `case1.svs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    /// Index of the join column in `header`
+    key: usize,
+    /// Row of each join value
+    index: HashMap<String, usize>,
+}
+
+impl Metadata {
+    /// Read a CSV file with a header line, joining on the column named `join_on`
+    pub fn read(path: &path::Path, join_on: &str) -> Result<Self, Box<dyn error::Error>> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Error reading metadata from {}: {}", path.display(), e))?;
+        let mut lines = contents.trim_start_matches('\u{feff}').lines().filter(|l| !l.trim().is_empty());
+        let header = parse_csv_line(lines.next().ok_or(format!("Metadata file {} is empty", path.display()))?);
+        let rows: Vec<Vec<String>> = lines.map(|line| {
+            let mut row = parse_csv_line(line);
+            row.resize(header.len(), String::new());
+            row
+        }).collect();
+        let key = header.iter().position(|h| h == join_on).ok_or(format!("In {}: Missing join column {}", path.display(), join_on))?;
+        let mut index = HashMap::new();
+        for (i, row) in rows.iter().enumerate() {
+            if index.insert(row[key].clone(), i).is_some() {
+                eprintln!("Warning: {} {} appears more than once in the metadata, using the last row", join_on, row[key]);
+            }
+        }
+        Ok(Self { header, rows, key, index })
+    }
+
+    /// Names of the columns added to the records, all but the join column
+    pub fn columns(&self) -> Vec<&str> {
+        self.header.iter().enumerate().filter(|&(i, _)| i != self.key).map(|(_, h)| h.as_str()).collect()
+    }
+
+    /// Values of `columns()` for a slide, None without a matching row
+    pub fn values(&self, slide_name: &str) -> Option<Vec<&str>> {
+        let stem = path::Path::new(slide_name).file_stem().and_then(|s| s.to_str()).unwrap_or(slide_name);
+        let row = &self.rows[*self.index.get(slide_name).or_else(|| self.index.get(stem))?];
+        Some(row.iter().enumerate().filter(|&(i, _)| i != self.key).map(|(_, v)| v.as_str()).collect())
+    }
+
+    /// Add the metadata columns to the records of a slide, left empty with a warning without a matching row
+    pub fn join(&self, slide_name: &str, records: &mut [RegionRecord], summary: &mut FileSummary) {
+        let values = self.values(slide_name).unwrap_or_else(|| {
+            summary.warn(format!("In {}: No metadata for slide {}", summary.path.display(), slide_name));
+            vec![""; self.header.len() - 1]
+        });
+        for record in records {
+            record.text_columns.extend(self.columns().into_iter().zip(&values).map(|(c, v)| (c.to_string(), v.to_string())));
+        }
+    }
+}
+
+/// Fields encoded in file names, such as case, block and stain, given by the named groups of a regular expression
+#[cfg(feature = "config")]
+#[derive(Debug, Clone)]
+pub struct FilenamePattern {
+    pattern: regex::Regex,
+}
+
+#[cfg(feature = "config")]
+impl FilenamePattern {
+    /// Compile `pattern`, e.g. `(?P<case>\d+)-(?P<block>[A-Z]\d)-(?P<stain>\w+)`, which needs at least one named group
+    pub fn new(pattern: &str) -> Result<Self, Box<dyn error::Error>> {
+        let pattern = regex::Regex::new(pattern).map_err(|e| format!("Invalid file name pattern: {}", e))?;
+        if pattern.capture_names().flatten().next().is_none() {
+            return Err(format!("File name pattern {} has no named groups such as (?P<case>...)", pattern).into());
+        }
+        Ok(Self { pattern })
+    }
+
+    /// Names of the groups, one column each
+    pub fn columns(&self) -> Vec<&str> {
+        self.pattern.capture_names().flatten().collect()
+    }
+
+    /// Add the fields found in the file name to its records, left empty with a warning if the name does not match
+    /// Groups that take no part in the match are left empty too.
+    pub fn join(&self, filename: &str, records: &mut [RegionRecord], summary: &mut FileSummary) {
+        let captures = self.pattern.captures(filename);
+        if captures.is_none() {
+            summary.warn(format!("In {}: File name does not match {}", summary.path.display(), self.pattern));
+        }
+        let values: Vec<(String, String)> = self.columns().into_iter().map(|name| {
+            let value = captures.as_ref().and_then(|c| c.name(name)).map(|m| m.as_str()).unwrap_or_default();
+            (name.to_string(), value.to_string())
+        }).collect();
+        for record in records {
+            record.text_columns.extend(values.iter().cloned());
+        }
+    }
+}
+
+/// Mapping of free-text region labels onto normalized ones, e.g. `tumour`, `Tumor ` and `TUMOR` onto `tumor`
+/// Labels are looked up ignoring case and surrounding or repeated whitespace, labels not in the map are kept.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LabelMap {
+    labels: HashMap<String, String>,
+}
+
+impl LabelMap {
+    /// Read a CSV file with a header line and two columns, the label as written and its normalized form
+    pub fn read(path: &path::Path) -> Result<Self, Box<dyn error::Error>> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Error reading label map from {}: {}", path.display(), e))?;
+        let mut labels = HashMap::new();
+        for (i, line) in contents.trim_start_matches('\u{feff}').lines().enumerate().skip(1).filter(|(_, l)| !l.trim().is_empty()) {
+            let fields = parse_csv_line(line);
+            let [label, normalized] = fields.as_slice() else {
+                return Err(format!("Line {} of {} should have two columns, the label and its normalized form", i + 1, path.display()).into());
+            };
+            labels.insert(label_key(label), normalized.clone());
+        }
+        Ok(Self { labels })
+    }
+
+    /// Normalized form of `label`, or `label` itself if it is not in the map
+    pub fn normalize(&self, label: &str) -> String {
+        self.labels.get(&label_key(label)).cloned().unwrap_or_else(|| label.to_string())
+    }
+}
+
+/// Lower case label with single spaces between words
+fn label_key(label: &str) -> String {
+    label.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Split a CSV line into its fields, removing the quotes around quoted fields
+pub(crate) fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path};
+    use crate::extract::{FileSummary, RegionRecord};
+    use super::{LabelMap, Metadata};
+
+    /// Read `contents` as a metadata file joined on `join_on`
+    fn read(name: &str, contents: &str, join_on: &str) -> Result<Metadata, String> {
+        let path = std::env::temp_dir().join(format!("read_imagescope_xml_metadata_{}_{}.csv", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let metadata = Metadata::read(&path, join_on).map_err(|e| e.to_string());
+        fs::remove_file(&path).unwrap();
+        metadata
+    }
+
+    fn record() -> RegionRecord {
+        RegionRecord {
+            filename: String::from("case1.xml"),
+            slide_name: String::from("case1.svs"),
+            region_id: String::from("1"),
+            text_label: String::new(),
+            positivity: None,
+            num_wpositive: None,
+            num_positive: None,
+            num_spositive: None,
+            num_all_positive: 0.0,
+            num_total: None,
+            algorithm: String::new(),
+            columns: Vec::new(),
+            text_columns: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn join_adds_the_columns_of_the_matching_row() {
+        let metadata = read("join", "patient,slide,stain\n\"Doe, J\",case1.svs,HER2\nRoe,case2.svs,ER\n", "slide").unwrap();
+        assert_eq!(metadata.columns(), ["patient", "stain"]);
+        let mut records = vec![record()];
+        let mut summary = FileSummary::new(path::Path::new("case1.xml"));
+        metadata.join("case1.svs", &mut records, &mut summary);
+        assert_eq!(records[0].text_columns, [(String::from("patient"), String::from("Doe, J")), (String::from("stain"), String::from("HER2"))]);
+        assert!(summary.warnings.is_empty());
+        // Slides missing from the manifest get empty columns and a warning
+        let mut records = vec![record()];
+        metadata.join("case3.svs", &mut records, &mut summary);
+        assert_eq!(records[0].text_columns, [(String::from("patient"), String::new()), (String::from("stain"), String::new())]);
+        assert_eq!(summary.warnings.len(), 1);
+    }
+
+    #[test]
+    fn slide_names_match_without_their_extension() {
+        let metadata = read("stem", "slide_name,arm\ncase1,treated\n", "slide_name").unwrap();
+        assert_eq!(metadata.values("case1.svs"), Some(vec!["treated"]));
+        assert_eq!(metadata.values("case1"), Some(vec!["treated"]));
+        assert_eq!(metadata.values("case2.svs"), None);
+    }
+
+    #[test]
+    fn missing_join_column_is_an_error() {
+        let error = read("missing", "slide,arm\ncase1,treated\n", "slide_name").unwrap_err();
+        assert!(error.ends_with("Missing join column slide_name"), "{}", error);
+    }
+
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn file_name_fields_become_columns() {
+        use super::FilenamePattern;
+        let pattern = FilenamePattern::new(r"(?P<case>\d+)-(?P<block>[A-Z]\d)(-(?P<stain>[A-Z]+))?").unwrap();
+        assert_eq!(pattern.columns(), ["case", "block", "stain"]);
+        let mut records = vec![record(), record()];
+        let mut summary = FileSummary::new(path::Path::new("1043-B2-ER.xml"));
+        pattern.join("1043-B2-ER.xml", &mut records, &mut summary);
+        let fields = |case: &str, block: &str, stain: &str| vec![(String::from("case"), String::from(case)), (String::from("block"), String::from(block)), (String::from("stain"), String::from(stain))];
+        assert!(records.iter().all(|r| r.text_columns == fields("1043", "B2", "ER")));
+        // Groups left out of the match are empty
+        let mut records = vec![record()];
+        pattern.join("1043-B2.xml", &mut records, &mut summary);
+        assert_eq!(records[0].text_columns, fields("1043", "B2", ""));
+        assert!(summary.warnings.is_empty());
+        // A name that does not match gets empty columns and a warning
+        let mut summary = FileSummary::new(path::Path::new("scan.xml"));
+        let mut records = vec![record()];
+        pattern.join("scan.xml", &mut records, &mut summary);
+        assert_eq!(records[0].text_columns, fields("", "", ""));
+        assert_eq!(summary.warnings.len(), 1);
+        assert!(summary.warnings[0].starts_with("In scan.xml: File name does not match"), "{}", summary.warnings[0]);
+        assert!(FilenamePattern::new(r"\d+-[A-Z]\d").is_err());
+        assert!(FilenamePattern::new(r"(?P<case>\d+").is_err());
+    }
+
+
+    #[test]
+    fn labels_are_normalized_ignoring_case_and_spacing() {
+        let path = std::env::temp_dir().join(format!("read_imagescope_xml_label_map_{}.csv", std::process::id()));
+        fs::write(&path, "\u{feff}label,normalized\ntumour,Tumor\n\"Stroma,  reactive\",Stroma\n\n").unwrap();
+        let labels = LabelMap::read(&path);
+        fs::write(&path, "label,normalized\ntumour\n").unwrap();
+        let error = LabelMap::read(&path).unwrap_err().to_string();
+        fs::remove_file(&path).unwrap();
+        let labels = labels.unwrap();
+        assert_eq!(labels.normalize("TUMOUR "), "Tumor");
+        assert_eq!(labels.normalize(" stroma,   Reactive"), "Stroma");
+        // Labels not in the map are kept as written
+        assert_eq!(labels.normalize("Necrosis "), "Necrosis ");
+        assert!(error.starts_with("Line 2 of"), "{}", error);
+    }
+}
@@ -0,0 +1,384 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use indexmap::IndexMap;
+use indexmap::map::Entry;
+
+/// List of annotations
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Annotations {
+    /// Scale in microns per pixel (assuming square aspect ratio)
+    #[serde(rename = "@MicronsPerPixel", skip_serializing_if = "Option::is_none")]
+    pub microns_per_pixel: Option<String>,
+    /// List of annotations
+    #[serde(rename = "Annotation", default)]
+    pub annotation: Vec<Annotation>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl Annotations {
+    /// Scan resolution as a number, None if missing or not a positive number
+    pub fn microns_per_pixel(&self) -> Option<f64> {
+        self.microns_per_pixel.as_deref()?.trim().parse().ok().filter(|m: &f64| *m > 0.0)
+    }
+}
+
+/// An annotation layer
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Annotation {
+    /// Annotation ID
+    #[serde(rename = "@Id")]
+    pub id: String,
+    /// Name of annotation layer
+    #[serde(rename = "@Name", default)]
+    pub name: String,
+    /// Annotation type
+    /// 4 = user-defined drawn regions
+    /// 3 = calculated data from analysis
+    #[serde(rename = "@Type")]
+    pub annotation_type: String,
+    /// List of annotation attributes
+    #[serde(rename = "Attributes", default)]
+    pub attributes: AnnotationAttributes,
+    /// List of regions
+    #[serde(rename = "Regions", default)]
+    pub regions: Regions,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// A specific attribute for an annotation
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct AnnotationAttributes {
+    #[serde(rename = "Attribute", skip_serializing_if = "Option::is_none")]
+    pub attribute: Option<Vec<AnnotationAttributesAttribute>>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// Annotation attribute details
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AnnotationAttributesAttribute {
+    #[serde(rename = "@Name")]
+    pub name: String,
+    #[serde(rename = "@Id", default)]
+    pub id: String,
+    #[serde(rename = "@Value", default)]
+    pub value: String,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// List of regions in an annotation layer
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Regions {
+    #[serde(rename = "RegionAttributeHeaders", default)]
+    pub region_attribute_headers: RegionAttributeHeaders,
+    #[serde(rename = "Region", default)]
+    pub region: Vec<Region>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// Meta-information about region attributes common across regions (header)
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct RegionAttributeHeaders {
+    #[serde(rename = "AttributeHeader", skip_serializing_if = "Option::is_none")]
+    pub attribute_header: Option<Vec<AttributeHeader>>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// Region attribute header details
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AttributeHeader {
+    #[serde(rename = "@Id")]
+    pub id: String,
+    #[serde(rename = "@Name")]
+    pub name: String,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// Details about each region
+/// Only the Id is required, everything else may be absent in files from older ImageScope versions
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Region {
+    #[serde(rename = "@Id")]
+    pub id: String,
+    #[serde(rename = "@Type", default)]
+    pub region_type: RegionType,
+    #[serde(rename = "@Length", skip_serializing_if = "Option::is_none")]
+    pub length: Option<String>,
+    #[serde(rename = "@Area", skip_serializing_if = "Option::is_none")]
+    pub area: Option<String>,
+    #[serde(rename = "@LengthMicrons", skip_serializing_if = "Option::is_none")]
+    pub length_microns: Option<String>,
+    #[serde(rename = "@AreaMicrons", skip_serializing_if = "Option::is_none")]
+    pub area_microns: Option<String>,
+    #[serde(rename = "@Text", skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(rename = "@NegativeROA", skip_serializing_if = "Option::is_none")]
+    pub negative_roa: Option<String>,
+    #[serde(rename = "@Analyze", skip_serializing_if = "Option::is_none")]
+    pub analyze: Option<String>,
+    #[serde(rename = "Attributes", default)]
+    pub attributes: RegionAttributes,
+    #[serde(rename="@ImageLocation", skip_serializing_if = "Option::is_none")]
+    pub image_location: Option<String>,
+    #[serde(rename="@InputRegionId", skip_serializing_if = "Option::is_none")]
+    pub input_region_id: Option<String>,
+    /// Outline of the region, absent in some analysis layers
+    #[serde(rename = "Vertices", default, skip_serializing_if = "Option::is_none")]
+    pub vertices: Option<Vertices>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl Region {
+    /// Outline as level-0 pixel coordinates, skipping vertices that are not numbers
+    pub fn points(&self) -> Vec<(f64, f64)> {
+        self.vertices.iter().flat_map(|v| &v.vertex).filter_map(Vertex::point).collect()
+    }
+
+    /// True for NegativeROA regions, which mark parts of other regions to leave out of the analysis
+    pub fn is_negative(&self) -> bool {
+        self.negative_roa.as_deref().is_some_and(|n| n.trim() == "1")
+    }
+
+    /// Text label with entities and CDATA sections decoded into literal characters
+    /// Some tools write the label as a `<Text>` child element instead of the attribute.
+    pub fn label(&self) -> String {
+        match self.extra.get("Text") {
+            Some(XmlValue::Text(child)) if self.text.as_deref().unwrap_or("").is_empty() => child.as_str(),
+            Some(XmlValue::Element(child)) if self.text.as_deref().unwrap_or("").is_empty() => match child.get("$text") {
+                Some(XmlValue::Text(child)) => child.as_str(),
+                _ => "",
+            },
+            _ => self.text.as_deref().unwrap_or(""),
+        }.to_string()
+    }
+}
+
+/// Outline of a region
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Vertices {
+    #[serde(rename = "Vertex", default)]
+    pub vertex: Vec<Vertex>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// A point of a region outline in level-0 pixel coordinates
+/// Coordinates are kept as written so they survive a round trip unchanged
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Vertex {
+    #[serde(rename = "@X")]
+    pub x: String,
+    #[serde(rename = "@Y")]
+    pub y: String,
+    #[serde(rename = "@Z", skip_serializing_if = "Option::is_none")]
+    pub z: Option<String>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl Vertex {
+    /// Make a vertex at `x`, `y` with Z = 0 as ImageScope writes it
+    pub fn new(x: f64, y: f64) -> Self {
+        Self { x: x.to_string(), y: y.to_string(), z: Some(String::from("0")), extra: Extra::new() }
+    }
+
+    /// Coordinates as numbers, None if either is not a number
+    pub fn point(&self) -> Option<(f64, f64)> {
+        Some((self.x.trim().parse().ok()?, self.y.trim().parse().ok()?))
+    }
+}
+
+/// Region attribute
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct RegionAttributes {
+    #[serde(rename = "Attribute", skip_serializing_if = "Option::is_none")]
+    pub attribute: Option<Vec<RegionAttributesAttribute>>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// Region attribute detail
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RegionAttributesAttribute {
+    #[serde(rename = "@Name")]
+    pub name: String,
+    #[serde(rename = "@Id", default)]
+    pub id: String,
+    #[serde(rename = "@Value", default)]
+    pub value: String,
+    #[serde(rename = "@DisplayColor", skip_serializing_if = "Option::is_none")]
+    pub display_color: Option<String>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// Shape of a region as recorded in the `@Type` attribute of a `Region`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
+pub enum RegionType {
+    /// Type 0: freehand (pen tool) outline, stored as a closed polygon
+    Freehand,
+    /// Type 1: axis-aligned rectangle, stored as two opposite corners
+    Rectangle,
+    /// Type 2: ellipse, stored as two opposite corners of its bounding box
+    Ellipse,
+    /// Type 3: arrow, stored as its tail and head points
+    Arrow,
+    /// Type 4: ruler, stored as its two end points
+    Ruler,
+    /// Type 5: open polyline, stored as an ordered list of points
+    Polyline,
+    /// Any type code we do not recognize, kept verbatim
+    Other(String),
+}
+
+impl Default for RegionType {
+    /// Regions without a type code are drawn with the default pen tool
+    fn default() -> Self {
+        RegionType::Freehand
+    }
+}
+
+impl RegionType {
+    /// Type code as written in the XML
+    pub fn code(&self) -> &str {
+        match self {
+            RegionType::Freehand => "0",
+            RegionType::Rectangle => "1",
+            RegionType::Ellipse => "2",
+            RegionType::Arrow => "3",
+            RegionType::Ruler => "4",
+            RegionType::Polyline => "5",
+            RegionType::Other(code) => code.as_str(),
+        }
+    }
+
+    /// True if the shape encloses an area (as opposed to a line or marker)
+    pub fn is_closed(&self) -> bool {
+        matches!(self, RegionType::Freehand | RegionType::Rectangle | RegionType::Ellipse)
+    }
+}
+
+impl From<String> for RegionType {
+    fn from(code: String) -> Self {
+        match code.trim() {
+            "0" => RegionType::Freehand,
+            "1" => RegionType::Rectangle,
+            "2" => RegionType::Ellipse,
+            "3" => RegionType::Arrow,
+            "4" => RegionType::Ruler,
+            "5" => RegionType::Polyline,
+            _ => RegionType::Other(code),
+        }
+    }
+}
+
+impl From<RegionType> for String {
+    fn from(region_type: RegionType) -> Self {
+        region_type.code().to_string()
+    }
+}
+
+/// Unrecognized attributes (keys starting with `@`) and child elements of an XML element
+/// Every structure keeps its own in an `extra` field, in document order, so they survive a round trip.
+pub type Extra = IndexMap<String, XmlValue>;
+
+/// Generic XML content that does not map onto one of our structures
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum XmlValue {
+    /// Attribute value or text content
+    Text(String),
+    /// Child element with its own attributes and children
+    Element(Extra),
+    /// Repeated child elements sharing the same name
+    List(Vec<XmlValue>),
+}
+
+impl<'de> Deserialize<'de> for XmlValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(XmlValueVisitor)
+    }
+}
+
+/// Builds an XmlValue from whatever quick-xml hands us
+struct XmlValueVisitor;
+
+impl<'de> Visitor<'de> for XmlValueVisitor {
+    type Value = XmlValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("XML text or element")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<XmlValue, E> {
+        Ok(XmlValue::Text(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<XmlValue, E> {
+        Ok(XmlValue::Text(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<XmlValue, E> {
+        Ok(XmlValue::Text(String::new()))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<XmlValue, A::Error> {
+        let mut children = Extra::new();
+        while let Some((key, value)) = map.next_entry::<String, XmlValue>()? {
+            // Repeated child elements are collected into a list in document order, where the first one was
+            match children.entry(key) {
+                Entry::Vacant(entry) => { entry.insert(value); },
+                Entry::Occupied(mut entry) => match entry.get_mut() {
+                    XmlValue::List(list) => list.push(value),
+                    previous => {
+                        let first = std::mem::replace(previous, XmlValue::List(Vec::new()));
+                        *previous = XmlValue::List(vec![first, value]);
+                    },
+                },
+            }
+        }
+        Ok(XmlValue::Element(children))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<XmlValue, A::Error> {
+        let mut list = Vec::new();
+        while let Some(value) = seq.next_element()? {
+            list.push(value);
+        }
+        Ok(XmlValue::List(list))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse_xml_str;
+
+    fn label(region: &str) -> String {
+        let xml = format!("<Annotations><Annotation Id=\"1\" Type=\"4\"><Regions>{}</Regions></Annotation></Annotations>", region);
+        parse_xml_str(&xml).unwrap().annotation[0].regions.region[0].label()
+    }
+
+    #[test]
+    fn label_decodes_entities_once() {
+        assert_eq!(label(r#"<Region Id="1" Text="A &amp; B"/>"#), "A & B");
+        assert_eq!(label(r#"<Region Id="1" Text="A &amp;amp; B"/>"#), "A &amp; B");
+        assert_eq!(label(r#"<Region Id="1" Text="&lt;5%"/>"#), "<5%");
+        assert_eq!(label(r#"<Region Id="1" Text="&#181;m &#x2265; 2"/>"#), "\u{b5}m \u{2265} 2");
+    }
+
+    #[test]
+    fn label_reads_text_child() {
+        assert_eq!(label(r#"<Region Id="1"><Text>A &amp;amp; B</Text></Region>"#), "A &amp; B");
+        assert_eq!(label(r#"<Region Id="1"><Text><![CDATA[A & <B>]]></Text></Region>"#), "A & <B>");
+        assert_eq!(label(r#"<Region Id="1"><Text><![CDATA[&amp;]]></Text></Region>"#), "&amp;");
+    }
+}
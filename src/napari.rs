@@ -0,0 +1,72 @@
+use crate::convert::ExportRegion;
+use crate::extract::UNLABELED;
+use crate::geometry::BoundingBox;
+use crate::model::RegionType;
+use crate::report::csv_field;
+
+/// Column names of napari's shapes CSV, plus the region label
+pub const NAPARI_HEADER: &str = "index,shape-type,vertex-index,axis-0,axis-1,label";
+
+/// Shapes CSV as written and read by napari, one row per vertex with `axis-0` the row (y) and `axis-1` the
+/// column (x), scaled down by `downsample`
+/// Rectangles and ellipses become napari rectangles and ellipses given by their four bounding box corners,
+/// rulers and arrows lines, polylines paths and other closed regions polygons. Single vertices have no napari
+/// shape and are left out. NegativeROA regions are labelled `Exclusion`.
+pub fn napari_shapes_csv(regions: &[ExportRegion], downsample: f64) -> String {
+    let mut csv = String::from(NAPARI_HEADER);
+    csv.push('\n');
+    let mut index = 0;
+    for r in regions {
+        let outline = r.region.outline();
+        let Some(bbox) = BoundingBox::of(&outline).filter(|_| outline.len() >= 2) else { continue };
+        let corners = || vec![(bbox.min_x, bbox.min_y), (bbox.max_x, bbox.min_y), (bbox.max_x, bbox.max_y), (bbox.min_x, bbox.max_y)];
+        let (shape_type, points) = match (&r.region.region_type, outline.len()) {
+            (RegionType::Rectangle, _) => ("rectangle", corners()),
+            (RegionType::Ellipse, _) => ("ellipse", corners()),
+            (RegionType::Ruler | RegionType::Arrow, 2) => ("line", outline),
+            (region_type, n) if n >= 3 && region_type.is_closed() => ("polygon", outline),
+            _ => ("path", outline),
+        };
+        let label = match r.region.label() {
+            _ if r.region.is_negative() => String::from("Exclusion"),
+            label if label.is_empty() => UNLABELED.to_string(),
+            label => label,
+        };
+        let label = csv_field(&label);
+        for (vertex, (x, y)) in points.iter().enumerate() {
+            csv.push_str(&format!("{},{},{},{},{},{}\n", index, shape_type, vertex, y / downsample, x / downsample, label));
+        }
+        index += 1;
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::model::RegionType;
+    use super::{NAPARI_HEADER, napari_shapes_csv};
+
+    #[test]
+    fn vertices_are_written_row_first_and_downsampled() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Tumor, core").vertices([(10.0, 20.0), (40.0, 60.0)]))
+                .region(RegionBuilder::new().vertex(1.0, 2.0))
+                .region(RegionBuilder::new().region_type(RegionType::Ruler).negative_roa(true).vertices([(0.0, 0.0), (4.0, 8.0)])))
+            .build();
+        let csv = napari_shapes_csv(&export_regions(&annotations, &[]), 2.0);
+        let lines: Vec<&str> = csv.lines().collect();
+        // The single vertex has no napari shape, so the ruler is the second shape
+        assert_eq!(lines, [
+            NAPARI_HEADER,
+            "0,rectangle,0,10,5,\"Tumor, core\"",
+            "0,rectangle,1,10,20,\"Tumor, core\"",
+            "0,rectangle,2,30,20,\"Tumor, core\"",
+            "0,rectangle,3,30,5,\"Tumor, core\"",
+            "1,line,0,0,0,Exclusion",
+            "1,line,1,4,2,Exclusion",
+        ]);
+    }
+}
@@ -0,0 +1,109 @@
+/// Which character separates the integer and fractional parts of numbers in attribute values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecimalSeparator {
+    /// Accept both, guessing from the position and number of separators
+    #[default]
+    Auto,
+    /// `0.4321`, with `,` grouping thousands
+    Point,
+    /// `0,4321`, with `.` grouping thousands, as written by ImageScope on many European systems
+    Comma,
+}
+
+/// Parse a numeric attribute value, None if it is not a number
+pub fn parse_number(value: &str, separator: DecimalSeparator) -> Option<f64> {
+    parse_number_with_warning(value, separator).0
+}
+
+/// Parse a numeric attribute value, forgiving thousands separators and percent signs
+/// Percent signs are dropped without rescaling, so `43.2%` is read as 43.2 like `43.2` in the same column.
+/// Returns the number, None if it is not a finite number, and a warning if anything had to be stripped to read it.
+pub fn parse_number_with_warning(value: &str, separator: DecimalSeparator) -> (Option<f64>, Option<String>) {
+    let trimmed = value.trim();
+    // With a decimal comma `1.234` is a thousand, not what Rust makes of it
+    if separator != DecimalSeparator::Comma {
+        if let Ok(number) = trimmed.parse::<f64>() {
+            return (Some(number).filter(|n| n.is_finite()), None);
+        }
+    }
+    let (digits, percent) = match trimmed.strip_suffix('%') {
+        Some(digits) => (digits.trim_end(), true),
+        None => (trimmed, false),
+    };
+    // Spaces and apostrophes are only ever used for grouping
+    let compact: String = digits.chars().filter(|c| !c.is_whitespace() && *c != '\'').collect();
+    let decimal = match separator {
+        DecimalSeparator::Point => '.',
+        DecimalSeparator::Comma => ',',
+        DecimalSeparator::Auto => guess_decimal(&compact),
+    };
+    let group = if decimal == '.' { ',' } else { '.' };
+    let integer = compact.split(decimal).next().unwrap_or("");
+    if compact.matches(decimal).count() > 1 || !valid_grouping(integer, group) {
+        return (None, None);
+    }
+    let Ok(number) = compact.replace(group, "").replace(decimal, ".").parse::<f64>() else {
+        return (None, None);
+    };
+    if !number.is_finite() {
+        return (None, None);
+    }
+    let stripped = percent || compact.contains(group) || compact.len() != digits.len();
+    (Some(number), stripped.then(|| format!("Read \"{}\" as {}", trimmed, number)))
+}
+
+/// Guess the decimal separator of a number written with `,` and/or `.`
+fn guess_decimal(number: &str) -> char {
+    let commas = number.matches(',').count();
+    let points = number.matches('.').count();
+    match (commas, points) {
+        // Whichever comes last separates the fraction
+        (1.., 1..) if number.rfind(',') > number.rfind('.') => ',',
+        (1.., 1..) => '.',
+        // A single comma is a decimal comma unless it is followed by exactly three digits, e.g. `12,345`
+        (1, 0) => {
+            let (integer, fraction) = number.split_once(',').unwrap_or((number, ""));
+            let zero = integer.trim_start_matches(['-', '+']).chars().all(|c| c == '0');
+            if fraction.len() == 3 && !zero { '.' } else { ',' }
+        },
+        (0, 2..) => ',',
+        _ => '.',
+    }
+}
+
+/// True if `group` separates groups of three digits, or is not used at all
+fn valid_grouping(integer: &str, group: char) -> bool {
+    let mut parts = integer.trim_start_matches(['-', '+']).split(group);
+    let first = parts.next().unwrap_or("");
+    if !integer.contains(group) {
+        return true;
+    }
+    (1..=3).contains(&first.len()) && parts.all(|p| p.len() == 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecimalSeparator, parse_number};
+
+    #[test]
+    fn decimal_comma_never_takes_the_point() {
+        assert_eq!(parse_number("1.234", DecimalSeparator::Comma), Some(1234.0));
+        assert_eq!(parse_number("0,4321", DecimalSeparator::Comma), Some(0.4321));
+        assert_eq!(parse_number("1.234", DecimalSeparator::Point), Some(1.234));
+        assert_eq!(parse_number("1.234", DecimalSeparator::Auto), Some(1.234));
+    }
+
+    #[test]
+    fn non_finite_values_are_not_numbers() {
+        for value in ["NaN", "inf", "-infinity", "1e999"] {
+            assert_eq!(parse_number(value, DecimalSeparator::Auto), None, "{}", value);
+            assert_eq!(parse_number(value, DecimalSeparator::Comma), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn percent_sign_is_dropped_without_rescaling() {
+        assert_eq!(parse_number("43.2%", DecimalSeparator::Auto), Some(43.2));
+        assert_eq!(parse_number("43,2 %", DecimalSeparator::Comma), Some(43.2));
+    }
+}
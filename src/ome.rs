@@ -0,0 +1,75 @@
+use quick_xml::escape::escape;
+use crate::convert::ExportRegion;
+use crate::geometry::BoundingBox;
+use crate::model::RegionType;
+use crate::render::layer_color;
+
+/// Namespace of the OME-XML schema the ROIs follow
+const OME_NAMESPACE: &str = "http://www.openmicroscopy.org/Schemas/OME/2016-06";
+
+/// OME-XML document with one ROI per region, in level-0 pixel coordinates
+/// Rectangles and ellipses become OME Rectangle and Ellipse shapes, other closed regions polygons, rulers and
+/// arrows lines, and polylines polylines. Shapes are outlined in their layer's color and labelled with the
+/// region text. OME has no exclusions, so NegativeROA regions are described as `NegativeROA`.
+pub fn to_ome_xml(regions: &[ExportRegion]) -> String {
+    let mut xml = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<OME xmlns=\"{}\">\n", OME_NAMESPACE);
+    for (i, r) in regions.iter().enumerate() {
+        let outline = r.region.outline();
+        let Some(bbox) = BoundingBox::of(&outline) else { continue };
+        let label = escape(r.region.label().as_str()).to_string();
+        let mut common = format!("ID=\"Shape:{}:0\" Text=\"{}\"", i, label);
+        if let Some(color) = layer_color(r.layer) {
+            // OME colors are RGBA packed into a signed 32-bit integer
+            let rgb = u32::from_str_radix(&color[1..], 16).unwrap_or_default();
+            common.push_str(&format!(" StrokeColor=\"{}\"", ((rgb << 8) | 0xff) as i32));
+        }
+        let points = || outline.iter().map(|(x, y)| format!("{},{}", x, y)).collect::<Vec<_>>().join(" ");
+        let shape = match (&r.region.region_type, outline.len()) {
+            (_, 1) => format!("<Point {} X=\"{}\" Y=\"{}\"/>", common, outline[0].0, outline[0].1),
+            (RegionType::Rectangle, _) => format!("<Rectangle {} X=\"{}\" Y=\"{}\" Width=\"{}\" Height=\"{}\"/>", common, bbox.min_x, bbox.min_y, bbox.width(), bbox.height()),
+            (RegionType::Ellipse, _) => format!("<Ellipse {} X=\"{}\" Y=\"{}\" RadiusX=\"{}\" RadiusY=\"{}\"/>", common, (bbox.min_x + bbox.max_x) / 2.0, (bbox.min_y + bbox.max_y) / 2.0, bbox.width() / 2.0, bbox.height() / 2.0),
+            (RegionType::Ruler | RegionType::Arrow, 2) => {
+                let marker = if r.region.region_type == RegionType::Arrow { " MarkerEnd=\"Arrow\"" } else { "" };
+                format!("<Line {} X1=\"{}\" Y1=\"{}\" X2=\"{}\" Y2=\"{}\"{}/>", common, outline[0].0, outline[0].1, outline[1].0, outline[1].1, marker)
+            },
+            (region_type, n) if n >= 3 && region_type.is_closed() => format!("<Polygon {} Points=\"{}\"/>", common, points()),
+            _ => format!("<Polyline {} Points=\"{}\"/>", common, points()),
+        };
+        xml.push_str(&format!("  <ROI ID=\"ROI:{}\" Name=\"{}\">\n", i, label));
+        xml.push_str(&format!("    <Union>\n      {}\n    </Union>\n", shape));
+        if r.region.is_negative() {
+            xml.push_str("    <Description>NegativeROA</Description>\n");
+        }
+        xml.push_str("  </ROI>\n");
+    }
+    xml.push_str("</OME>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::model::RegionType;
+    use super::to_ome_xml;
+
+    #[test]
+    fn regions_become_colored_rois() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new().line_color(255)
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Tumor & edge").vertices([(10.0, 20.0), (40.0, 60.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Ellipse).vertices([(0.0, 0.0), (8.0, 4.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Arrow).vertices([(0.0, 0.0), (5.0, 5.0)]))
+                .region(RegionBuilder::new().negative_roa(true).vertices([(0.0, 0.0), (4.0, 0.0), (4.0, 4.0)])))
+            .build();
+        let xml = to_ome_xml(&export_regions(&annotations, &[]));
+        // Red as RGBA is 0xff0000ff, negative as a signed integer
+        assert!(xml.contains(r#"<Rectangle ID="Shape:0:0" Text="Tumor &amp; edge" StrokeColor="-16776961" X="10" Y="20" Width="30" Height="40"/>"#), "{}", xml);
+        assert!(xml.contains(r#"<ROI ID="ROI:0" Name="Tumor &amp; edge">"#), "{}", xml);
+        assert!(xml.contains(r#"X="4" Y="2" RadiusX="4" RadiusY="2"/>"#), "{}", xml);
+        assert!(xml.contains(r#"<Line ID="Shape:2:0" Text="" StrokeColor="-16776961" X1="0" Y1="0" X2="5" Y2="5" MarkerEnd="Arrow"/>"#), "{}", xml);
+        assert!(xml.contains(r#"Points="0,0 4,0 4,4"/>"#), "{}", xml);
+        assert_eq!(xml.matches("<Description>NegativeROA</Description>").count(), 1);
+        assert_eq!(xml.matches("<ROI ").count(), 4);
+    }
+}
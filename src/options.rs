@@ -0,0 +1,213 @@
+use crate::number::DecimalSeparator;
+use crate::patterns::AttributePatterns;
+use crate::report::OutputFormat;
+use crate::input::DuplicateSlides;
+use crate::aggregate::LabelFilter;
+use crate::algorithm::{AlgorithmKind, GenieClasses};
+use crate::qc::{QcRules, QC_RULE_COLUMNS};
+use crate::scores::{AllredBins, Score};
+use crate::extract::QC_COLUMN;
+use crate::metadata::{LabelMap, Metadata};
+#[cfg(feature = "config")]
+use crate::patterns::{ColumnType, CustomColumn};
+#[cfg(feature = "config")]
+use crate::metadata::FilenamePattern;
+#[cfg(feature = "geometry")]
+use crate::geometry::{Affine, Measurement};
+#[cfg(feature = "export")]
+use crate::render::ColorBy;
+
+/// Settings that change how files are read and reported
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Recover what we can from damaged files instead of discarding them
+    pub lenient: bool,
+    /// Fail files holding anything ImageScope itself does not write
+    pub strict: bool,
+    /// Log which attribute each analysis column was read from
+    pub verbose: bool,
+    /// Seconds to wait for a HTTP(S) download before giving up
+    pub http_timeout: u64,
+    /// Number of times a failed HTTP(S) download is retried
+    pub http_retries: u32,
+    /// Seconds after which a file is abandoned and recorded as failed, None to wait indefinitely
+    pub timeout_per_file: Option<u64>,
+    /// How decimal numbers in attribute values are written
+    pub decimal_separator: DecimalSeparator,
+    /// How the analysis results we report are recognized
+    pub attribute_patterns: AttributePatterns,
+    /// Id or name of the type 3 analysis layer to report, None to use the last one
+    pub analysis_layer: Option<String>,
+    /// What to do when several files annotate the same slide
+    pub duplicate_slides: DuplicateSlides,
+    /// What the report lists
+    pub format: OutputFormat,
+    /// Largest relative difference between declared areas and areas computed from the vertices before a
+    /// region is flagged, None to skip the check. Only checked with the `geometry` feature
+    pub area_tolerance: Option<f64>,
+    /// Algorithms whose analysis layers are each reported in their own columns, after the shape measurements
+    pub algorithm_columns: Vec<AlgorithmKind>,
+    /// Algorithms whose specific results, such as the nuclei of each intensity, are reported after the algorithm columns
+    pub results: Vec<AlgorithmKind>,
+    /// Classes of GENIE classifier layers whose area and percentage are reported after the algorithm results
+    pub genie_classes: GenieClasses,
+    /// Scores computed from the positive pixel counts, reported after the algorithm results
+    pub scores: Vec<Score>,
+    /// Largest difference between the reported positivity and the one recomputed from the counts before a region
+    /// is flagged, only checked with `Score::PositivityDelta`
+    pub positivity_tolerance: f64,
+    /// Add a column flagging implausible values of each region
+    pub qc: bool,
+    /// Thresholds regions and slides are checked against
+    pub qc_rules: QcRules,
+    /// Fields read from the file names, added before the metadata columns
+    #[cfg(feature = "config")]
+    pub filename_pattern: Option<FilenamePattern>,
+    /// Study manifest whose columns are added to the records of matching slides
+    pub metadata: Option<Metadata>,
+    /// Column of the metadata holding the slide names
+    pub join_on: String,
+    /// Normalized forms of region labels, applied before grouping and filtering
+    pub label_map: Option<LabelMap>,
+    /// Labels of the regions counting towards slide-level metrics
+    pub slide_labels: LabelFilter,
+    /// How Allred scores are binned
+    pub allred: AllredBins,
+    /// Extra output columns, in order
+    #[cfg(feature = "config")]
+    pub columns: Vec<CustomColumn>,
+    /// Shape measurements of the drawn regions reported after the extra columns
+    #[cfg(feature = "geometry")]
+    pub measurements: Vec<Measurement>,
+    /// Label regions with the labels of the regions they lie inside, e.g. `Tissue / Tumor`
+    #[cfg(feature = "geometry")]
+    pub nested_labels: bool,
+    /// Tolerance in microns for simplifying exported outlines, None to export every vertex
+    #[cfg(feature = "geometry")]
+    pub simplify: Option<f64>,
+    /// Export coordinates in microns instead of level-0 pixels
+    #[cfg(feature = "geometry")]
+    pub microns: bool,
+    /// Transform applied to exported pixel coordinates, e.g. to register annotations to another scan
+    #[cfg(feature = "geometry")]
+    pub affine: Option<Affine>,
+    /// How many level-0 pixels make one pixel of rendered images
+    #[cfg(feature = "export")]
+    pub downsample: f64,
+    /// What decides the color of rendered regions
+    #[cfg(feature = "export")]
+    pub color_by: ColorBy,
+    /// Side of the square tiles in tile manifests, in level-0 pixels
+    #[cfg(feature = "export")]
+    pub tile_size: f64,
+    /// Labels of the regions tiled in tile manifests, empty for all labels
+    #[cfg(feature = "export")]
+    pub tile_labels: Vec<String>,
+    /// Pixels added on every side of crop boxes
+    #[cfg(feature = "export")]
+    pub crop_margin: f64,
+    /// Labels burned into masks, in order of mask value, empty for all labels
+    #[cfg(feature = "raster")]
+    pub mask_labels: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            lenient: false,
+            strict: false,
+            verbose: false,
+            http_timeout: 30,
+            http_retries: 2,
+            timeout_per_file: None,
+            decimal_separator: DecimalSeparator::Auto,
+            attribute_patterns: AttributePatterns::default(),
+            analysis_layer: None,
+            duplicate_slides: DuplicateSlides::default(),
+            format: OutputFormat::default(),
+            area_tolerance: None,
+            algorithm_columns: Vec::new(),
+            results: Vec::new(),
+            genie_classes: GenieClasses::default(),
+            scores: Vec::new(),
+            positivity_tolerance: 0.01,
+            label_map: None,
+            slide_labels: LabelFilter::default(),
+            allred: AllredBins::default(),
+            qc: false,
+            qc_rules: QcRules::default(),
+            #[cfg(feature = "config")]
+            filename_pattern: None,
+            metadata: None,
+            join_on: String::from("slide_name"),
+            #[cfg(feature = "config")]
+            columns: Vec::new(),
+            #[cfg(feature = "geometry")]
+            measurements: Vec::new(),
+            #[cfg(feature = "geometry")]
+            nested_labels: false,
+            #[cfg(feature = "geometry")]
+            simplify: None,
+            #[cfg(feature = "geometry")]
+            microns: false,
+            #[cfg(feature = "geometry")]
+            affine: None,
+            #[cfg(feature = "export")]
+            downsample: 1.0,
+            #[cfg(feature = "export")]
+            color_by: ColorBy::default(),
+            #[cfg(feature = "export")]
+            tile_size: 256.0,
+            #[cfg(feature = "export")]
+            tile_labels: Vec::new(),
+            #[cfg(feature = "export")]
+            crop_margin: 0.0,
+            #[cfg(feature = "raster")]
+            mask_labels: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Names of the extra output columns
+    pub fn column_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        #[cfg(feature = "config")]
+        names.extend(self.columns.iter().filter(|c| c.kind == ColumnType::Number).map(|c| c.name.as_str()));
+        #[cfg(feature = "geometry")]
+        names.extend(self.measurements.iter().flat_map(|m| m.columns().iter().copied()));
+        names.extend(self.algorithm_columns.iter().flat_map(|a| a.columns().iter().copied()));
+        names.extend(self.results.iter().flat_map(|a| a.result_columns().iter().map(|(name, _)| *name)));
+        names.extend(self.genie_classes.columns());
+        names.extend(self.scores.iter().flat_map(|s| s.columns().iter().copied()));
+        names
+    }
+
+    /// Names of the extra text columns, written after the other extra columns
+    pub fn text_column_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        #[cfg(feature = "config")]
+        names.extend(self.columns.iter().filter(|c| c.kind == ColumnType::Text).map(|c| c.name.as_str()));
+        if self.qc {
+            names.push(QC_COLUMN);
+        }
+        if self.qc_rules.is_active() {
+            names.extend(QC_RULE_COLUMNS);
+        }
+        #[cfg(feature = "config")]
+        if let Some(pattern) = &self.filename_pattern {
+            names.extend(pattern.columns());
+        }
+        if let Some(metadata) = &self.metadata {
+            names.extend(metadata.columns());
+        }
+        names
+    }
+
+    /// Names of all extra columns in output order
+    pub fn output_column_names(&self) -> Vec<&str> {
+        let mut names = self.column_names();
+        names.extend(self.text_column_names());
+        names
+    }
+}
@@ -0,0 +1,241 @@
+use std::{error, path};
+use std::fs;
+use std::borrow::Cow;
+use quick_xml::DeError;
+use crate::model::Annotations;
+use crate::encoding::decode_xml;
+use crate::recover::parse_xml_lenient;
+#[cfg(feature = "export")]
+use crate::sedeen::{is_sedeen_session, parse_sedeen_str};
+
+/// Try to open and real a XML file using pre-defined structure
+pub fn parse_xml(path: &path::Path) -> Annotations {
+    dbg!(path);
+    // Read file into string and convert the XML into Rust data structure
+    match try_parse_xml(path) {
+        Ok(annotations) => return annotations,
+        Err(e) => eprintln!("Error parsing XML from {}: {}", path.display(), e),
+    }
+    // Error parsing so return empty
+    Annotations::default()
+}
+
+/// Open and read a XML file, reporting why it could not be read or parsed
+pub fn try_parse_xml(path: &path::Path) -> Result<Annotations, Box<dyn error::Error>> {
+    with_file_bytes(path, parse_xml_bytes)?
+}
+
+/// Open and read a XML file, recovering what we can if it is damaged
+/// Returns the annotations and a description of anything skipped, which is empty if the file was intact
+pub fn try_parse_xml_lenient(path: &path::Path) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+    with_file_bytes(path, parse_xml_bytes_lenient)?
+}
+
+/// Read a file and hand its raw contents to `f`
+/// With the `mmap` feature the file is memory-mapped instead of copied into memory. A file truncated by another
+/// process while it is mapped makes the process crash with SIGBUS, so only map files that are no longer written.
+pub(crate) fn with_file_bytes<T>(path: &path::Path, f: impl FnOnce(&[u8]) -> T) -> Result<T, Box<dyn error::Error>> {
+    #[cfg(feature = "mmap")]
+    {
+        let file = fs::File::open(path)?;
+        // Empty files cannot be mapped on all platforms
+        if file.metadata()?.len() == 0 {
+            return Ok(f(&[]));
+        }
+        // Safety: the mapping is only read while parsing and dropped before returning.
+        // Another process changing the file meanwhile can yield garbage, which gives a parse error, but truncating
+        // it makes reading the lost pages raise SIGBUS. That kills the whole process, which catching panics per
+        // file cannot prevent.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(f(&mmap))
+    }
+    #[cfg(not(feature = "mmap"))]
+    {
+        Ok(f(&fs::read(path)?))
+    }
+}
+
+/// Memory-map a XML file and parse it without copying it into a String first
+#[cfg(feature = "mmap")]
+pub fn parse_xml_mmap(path: &path::Path) -> Result<Annotations, Box<dyn error::Error>> {
+    try_parse_xml(path)
+}
+
+/// Magic bytes at the start of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// True if `bytes` look like gzip-compressed data
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&GZIP_MAGIC)
+}
+
+/// Decompress gzip-compressed file contents, anything else is passed through untouched
+pub(crate) fn decompress(bytes: &[u8]) -> Result<Cow<'_, [u8]>, Box<dyn error::Error>> {
+    if !is_gzip(bytes) {
+        return Ok(Cow::Borrowed(bytes));
+    }
+    #[cfg(feature = "gzip")]
+    {
+        use std::io::Read;
+        let mut decompressed = Vec::new();
+        flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+        Ok(Cow::Owned(decompressed))
+    }
+    #[cfg(not(feature = "gzip"))]
+    {
+        Err("file is gzip-compressed, rebuild with the `gzip` feature to read it".into())
+    }
+}
+
+/// Convert raw file contents into the pre-defined structure
+/// Gzip-compressed contents are decompressed, and UTF-16 and Windows-1252 files are converted to UTF-8 first.
+/// With the `export` feature, Sedeen sessions are recognized by their root element and imported.
+pub fn parse_xml_bytes(bytes: &[u8]) -> Result<Annotations, Box<dyn error::Error>> {
+    parse_xml_bytes_reporting(bytes).map(|(annotations, _)| annotations)
+}
+
+/// Convert raw file contents into the pre-defined structure like `parse_xml_bytes`
+/// Also returns a description of anything an imported Sedeen session had that ImageScope cannot hold.
+pub(crate) fn parse_xml_bytes_reporting(bytes: &[u8]) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+    let bytes = decompress(bytes)?;
+    let xml = decode_xml(&bytes)?;
+    #[cfg(feature = "export")]
+    if is_sedeen_session(&xml) {
+        return parse_sedeen_str(&xml);
+    }
+    Ok((parse_xml_str(&xml)?, Vec::new()))
+}
+
+/// Convert raw file contents into the pre-defined structure, recovering what we can if it is damaged
+/// Returns the annotations and a description of anything skipped, which is empty if the XML was intact
+pub fn parse_xml_bytes_lenient(bytes: &[u8]) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+    let bytes = decompress(bytes)?;
+    let xml = decode_xml(&bytes)?;
+    #[cfg(feature = "export")]
+    if is_sedeen_session(&xml) {
+        return parse_sedeen_str(&xml);
+    }
+    Ok(match parse_xml_str(&xml) {
+        Ok(annotations) => (annotations, Vec::new()),
+        Err(e) => {
+            let (annotations, mut skipped) = parse_xml_lenient(skip_leading_junk(&xml));
+            skipped.insert(0, format!("Recovering from error: {}", e));
+            (annotations, skipped)
+        },
+    })
+}
+
+/// Convert XML already held in memory into the pre-defined structure
+/// Byte-order marks, whitespace and other junk in front of the XML are ignored, as are
+/// namespace declarations and prefixes on element names
+pub fn parse_xml_str(xml: &str) -> Result<Annotations, DeError> {
+    quick_xml::de::from_str(skip_leading_junk(xml))
+}
+
+/// Skip anything in front of the XML declaration or root element
+/// Some middleware prepends a byte-order mark, blank lines or even a text header
+pub(crate) fn skip_leading_junk(xml: &str) -> &str {
+    let trimmed = xml.trim_start_matches(|c: char| c == '\u{FEFF}' || c.is_whitespace());
+    if trimmed.starts_with('<') {
+        return trimmed;
+    }
+    // Look for the declaration first, then for the root element
+    match trimmed.find("<?xml").or_else(|| trimmed.find("<Annotations")) {
+        Some(start) => &trimmed[start..],
+        None => trimmed,
+    }
+}
+
+/// Name of the root element, after any declaration, comments and doctype
+#[cfg(feature = "export")]
+pub(crate) fn root_element(xml: &str) -> Option<&str> {
+    let mut rest = skip_leading_junk(xml);
+    while let Some(after) = rest.strip_prefix("<?").or_else(|| rest.strip_prefix("<!")) {
+        let end = after.find('>')?;
+        rest = after[end + 1..].trim_start();
+    }
+    let name = rest.strip_prefix('<')?;
+    let end = name.find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(name.len());
+    Some(&name[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::fs;
+    use super::{decompress, parse_xml_str, skip_leading_junk, try_parse_xml, with_file_bytes};
+
+    const EXPORT: &str = include_str!("../tests/data/imagescope_export.xml");
+
+    #[test]
+    fn file_bytes_are_the_file_contents() {
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_file_bytes_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (full, empty) = (dir.join("full.xml"), dir.join("empty.xml"));
+        fs::write(&full, EXPORT).unwrap();
+        fs::write(&empty, "").unwrap();
+        let read = with_file_bytes(&full, |bytes| bytes.to_vec()).unwrap();
+        let read_empty = with_file_bytes(&empty, |bytes| bytes.len()).unwrap();
+        let parsed = try_parse_xml(&full).unwrap();
+        #[cfg(feature = "mmap")]
+        let mapped = super::parse_xml_mmap(&full).unwrap();
+        let missing = with_file_bytes(&dir.join("missing.xml"), |bytes| bytes.len());
+        fs::remove_dir_all(&dir).unwrap();
+        // Mapped or read, the contents are the same
+        assert_eq!(read, EXPORT.as_bytes());
+        assert_eq!(read_empty, 0);
+        assert_eq!(parsed, parse_xml_str(EXPORT).unwrap());
+        #[cfg(feature = "mmap")]
+        assert_eq!(mapped, parsed);
+        assert!(missing.is_err());
+    }
+
+
+    /// `xml` gzip-compressed
+    #[cfg(feature = "gzip")]
+    fn gzip(xml: &str) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(xml.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn only_gzip_contents_are_decompressed() {
+        let plain = decompress(EXPORT.as_bytes()).unwrap();
+        assert!(matches!(plain, Cow::Borrowed(_)));
+        assert_eq!(&*plain, EXPORT.as_bytes());
+        #[cfg(not(feature = "gzip"))]
+        assert!(decompress(&[0x1F, 0x8B, 0x08]).unwrap_err().to_string().contains("gzip"));
+        #[cfg(feature = "gzip")]
+        {
+            use super::{is_gzip, parse_xml_bytes};
+            let compressed = gzip(EXPORT);
+            assert!(is_gzip(&compressed));
+            assert_eq!(&*decompress(&compressed).unwrap(), EXPORT.as_bytes());
+            assert_eq!(parse_xml_bytes(&compressed).unwrap(), parse_xml_str(EXPORT).unwrap());
+            // Concatenated streams, as left by `cat a.gz b.gz`, are read to the end
+            let (first, second) = EXPORT.split_at(EXPORT.len() / 2);
+            let mut members = gzip(first);
+            members.extend(gzip(second));
+            assert_eq!(&*decompress(&members).unwrap(), EXPORT.as_bytes());
+            assert!(decompress(&compressed[..compressed.len() / 2]).is_err());
+        }
+    }
+
+
+    #[test]
+    fn junk_in_front_of_the_xml_is_skipped() {
+        assert_eq!(skip_leading_junk("\u{FEFF}\r\n  <Annotations/>"), "<Annotations/>");
+        assert_eq!(skip_leading_junk("Exported by LIS v2\n<?xml version=\"1.0\"?><Annotations/>"), "<?xml version=\"1.0\"?><Annotations/>");
+        // Without a declaration the root element is looked for
+        assert_eq!(skip_leading_junk("HTTP/1.1 200 OK\n\n<Annotations/>"), "<Annotations/>");
+        assert_eq!(skip_leading_junk("not xml at all"), "not xml at all");
+        let expected = parse_xml_str(EXPORT).unwrap();
+        assert_eq!(parse_xml_str(&format!("\u{FEFF}\n\nExported by LIS v2\n{}", EXPORT)).unwrap(), expected);
+        // A byte-order mark also survives decoding the raw bytes
+        let mut bytes = vec![0xEF, 0xBB, 0xBF];
+        bytes.extend(EXPORT.as_bytes());
+        assert_eq!(super::parse_xml_bytes(&bytes).unwrap(), expected);
+    }
+}
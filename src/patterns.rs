@@ -0,0 +1,152 @@
+/// Start of the AttributeHeader names of the analysis results we report, per output column
+/// Several patterns may be given for a column, they are tried in order.
+/// Matching ignores case and whitespace, so `Np =` also finds `Np  = Number of Positive` and `NP=`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributePatterns {
+    pub positivity: Vec<String>,
+    pub num_wpositive: Vec<String>,
+    pub num_positive: Vec<String>,
+    pub num_spositive: Vec<String>,
+    pub num_total: Vec<String>,
+}
+
+impl Default for AttributePatterns {
+    /// Names written by the ImageScope Positive Pixel Count algorithm
+    fn default() -> Self {
+        Self {
+            positivity: vec![String::from("Positivity =")],
+            num_wpositive: vec![String::from("Nwp =")],
+            num_positive: vec![String::from("Np =")],
+            num_spositive: vec![String::from("Nsp =")],
+            num_total: vec![String::from("NTotal =")],
+        }
+    }
+}
+
+impl AttributePatterns {
+    /// Patterns of a column given by its short name: positivity, nwp, np, nsp or ntotal
+    pub fn column_mut(&mut self, column: &str) -> Option<&mut Vec<String>> {
+        match column.to_ascii_lowercase().as_str() {
+            "positivity" => Some(&mut self.positivity),
+            "nwp" => Some(&mut self.num_wpositive),
+            "np" => Some(&mut self.num_positive),
+            "nsp" => Some(&mut self.num_spositive),
+            "ntotal" => Some(&mut self.num_total),
+            _ => None,
+        }
+    }
+
+    /// Add a pattern, given as `column=pattern`, after the ones already known for that column
+    pub fn add(&mut self, definition: &str) -> Result<(), String> {
+        let (column, pattern) = definition.split_once('=').ok_or(format!("Expected column=pattern, got {}", definition))?;
+        self.column_mut(column.trim()).ok_or(format!("Unknown column {}", column))?.push(pattern.to_string());
+        Ok(())
+    }
+}
+
+/// True if `name` starts with `pattern`, ignoring case and whitespace
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    normalize(name).starts_with(&normalize(pattern))
+}
+
+/// True if `label` matches `glob`, where `*` stands for any text, ignoring case and surrounding whitespace
+/// e.g. `Tumor*` matches `tumor 1` and `Tumor edge` but not `Peritumoral`.
+pub fn matches_glob(label: &str, glob: &str) -> bool {
+    let (label, glob) = (label.trim().to_lowercase(), glob.trim().to_lowercase());
+    let parts: Vec<&str> = glob.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return label == glob;
+    }
+    if !label.starts_with(first) || label.len() < first.len() + last.len() || !label.ends_with(last) {
+        return false;
+    }
+    // Middle parts are found in order between the first and the last
+    let mut rest = &label[first.len()..label.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// First of `items` whose name matches one of `patterns`, trying the patterns in order
+/// Returns the item and the pattern it matched.
+pub fn find_match<'a, 'p, T>(patterns: &'p [String], items: &'a [T], name: impl Fn(&T) -> &str) -> Option<(&'a T, &'p str)> {
+    patterns.iter().find_map(|pattern| {
+        items.iter().find(|item| matches_pattern(name(item), pattern)).map(|item| (item, pattern.as_str()))
+    })
+}
+
+/// Lower case without any whitespace
+fn normalize(text: &str) -> String {
+    text.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect()
+}
+
+/// An extra output column holding the attribute whose name matches `pattern`
+#[cfg(feature = "config")]
+#[derive(Debug, Clone)]
+pub struct CustomColumn {
+    pub name: String,
+    pub pattern: regex::Regex,
+    /// Whether the values are numbers or text
+    pub kind: ColumnType,
+    /// Fail files in which some region lacks a value
+    pub required: bool,
+}
+
+/// Type of the values of a custom column
+/// Numbers are parsed like the standard columns and written after them, text is kept as written and goes with
+/// the other text columns.
+#[cfg(feature = "config")]
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    #[default]
+    Number,
+    Text,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttributePatterns, find_match, matches_glob, matches_pattern};
+
+    #[test]
+    fn patterns_match_the_start_ignoring_case_and_whitespace() {
+        assert!(matches_pattern("Np  = Number of Positive", "Np ="));
+        assert!(matches_pattern("NP=", "Np ="));
+        assert!(!matches_pattern("Nwp = Number of Weak Positive", "Np ="));
+        assert!(!matches_pattern("Np", "Np ="));
+    }
+
+    #[test]
+    fn globs_match_the_whole_label() {
+        assert!(matches_glob("tumor 1", "Tumor*"));
+        assert!(matches_glob(" Tumor edge ", "tumor*"));
+        assert!(!matches_glob("Peritumoral", "Tumor*"));
+        assert!(matches_glob("Tumor", "Tumor"));
+        assert!(!matches_glob("Tumor 1", "Tumor"));
+        assert!(matches_glob("Tumor core 1", "*core*"));
+        assert!(matches_glob("Tumor core 1", "t*c*1"));
+        assert!(!matches_glob("Tumor 1", "t*c*1"));
+        // The first and last parts may not overlap
+        assert!(!matches_glob("ab", "ab*b"));
+        assert!(matches_glob("anything", "*"));
+    }
+
+    #[test]
+    fn patterns_are_tried_in_order() {
+        let names = ["Positive Pixels", "Np = Number of Positive"];
+        let patterns = [String::from("Np ="), String::from("Positive")];
+        assert_eq!(find_match(&patterns, &names, |n| n), Some((&names[1], "Np =")));
+        assert_eq!(find_match(&patterns[1..], &names, |n| n), Some((&names[0], "Positive")));
+        assert_eq!(find_match(&[String::from("Nsp =")], &names, |n| n), None);
+        let mut patterns = AttributePatterns::default();
+        patterns.add("np=Positive Pixels").unwrap();
+        assert_eq!(patterns.num_positive, [String::from("Np ="), String::from("Positive Pixels")]);
+        assert!(patterns.add("area=Area").is_err());
+        assert!(patterns.add("Positive Pixels").is_err());
+    }
+}
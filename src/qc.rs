@@ -0,0 +1,215 @@
+use serde::Deserialize;
+use crate::aggregate::Aggregate;
+use crate::extract::{RegionInfo, RegionRecord};
+
+/// Names of the columns added when QC rules are configured
+pub const QC_RULE_COLUMNS: [&str; 2] = ["qc_result", "qc_failed"];
+
+/// Name of the area rule in the `qc_failed` column
+const AREA_BELOW_MINIMUM: &str = "area below minimum";
+
+/// Thresholds regions and slides must meet, for automated gating of a batch
+/// Rules left out are not checked. The analyzed area is the region area without its excluded parts where known,
+/// otherwise its declared area, and is only checked for regions.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct QcRules {
+    pub min_ntotal: Option<f64>,
+    pub min_area_mm2: Option<f64>,
+    pub min_positivity: Option<f64>,
+    pub max_positivity: Option<f64>,
+    /// Largest number of failing regions and slides in a batch before it fails as a whole
+    pub max_failures: Option<usize>,
+}
+
+impl QcRules {
+    /// True if any rule is configured
+    pub fn is_active(&self) -> bool {
+        self.min_ntotal.is_some() || self.min_area_mm2.is_some() || self.min_positivity.is_some() || self.max_positivity.is_some()
+    }
+
+    /// Rules a region fails, missing values fail the rules that need them
+    pub fn region_failures(&self, info: &RegionInfo, microns_per_pixel: Option<f64>) -> Vec<&'static str> {
+        let square_microns = |pixels: Option<f64>| pixels.zip(microns_per_pixel).map(|(a, m)| a * m * m);
+        #[cfg(feature = "geometry")]
+        let area = square_microns(info.effective_area()).or(info.area_microns());
+        #[cfg(not(feature = "geometry"))]
+        let area = info.area_microns().or(square_microns(info.area()));
+        self.region_rule_failures(info.num_total(), info.positivity(), area)
+    }
+
+    /// Rules a region read back from a report fails
+    /// Its area is only known from an `effective_area_microns` column. Without one, the area verdict of a
+    /// `qc_failed` column written at extraction is kept, as the area cannot be checked again.
+    pub fn record_failures(&self, record: &RegionRecord) -> Vec<&'static str> {
+        // Extra columns of a report are read back as text
+        let area = record.columns.iter().find(|(name, _)| name == "effective_area_microns").map(|(_, value)| *value)
+            .or_else(|| record.text_columns.iter().find(|(name, _)| name == "effective_area_microns").map(|(_, value)| value.parse::<f64>().ok().filter(|a| !a.is_nan())));
+        let failed_before = record.text_columns.iter().find(|(name, _)| name == QC_RULE_COLUMNS[1]).map(|(_, value)| value.split(';').any(|rule| rule == AREA_BELOW_MINIMUM));
+        match (area, failed_before) {
+            (None, Some(failed_before)) => {
+                let mut failed = self.failures(record.num_total, record.positivity);
+                if self.min_area_mm2.is_some() && failed_before {
+                    failed.push(AREA_BELOW_MINIMUM);
+                }
+                failed
+            },
+            (area, _) => self.region_rule_failures(record.num_total, record.positivity, area.flatten()),
+        }
+    }
+
+    /// Rules failed by a region with an area in square microns
+    fn region_rule_failures(&self, num_total: Option<f64>, positivity: Option<f64>, area_microns: Option<f64>) -> Vec<&'static str> {
+        let mut failed = self.failures(num_total, positivity);
+        if self.min_area_mm2.is_some_and(|min| !area_microns.is_some_and(|a| a / 1e6 >= min)) {
+            failed.push(AREA_BELOW_MINIMUM);
+        }
+        failed
+    }
+
+    /// Rules a slide fails on its counts added up
+    pub fn slide_failures(&self, slide: &Aggregate) -> Vec<&'static str> {
+        self.failures(Some(slide.num_total), slide.positivity())
+    }
+
+    fn failures(&self, num_total: Option<f64>, positivity: Option<f64>) -> Vec<&'static str> {
+        let mut failed = Vec::new();
+        if self.min_ntotal.is_some_and(|min| !num_total.is_some_and(|n| n >= min)) {
+            failed.push("ntotal below minimum");
+        }
+        if self.min_positivity.is_some_and(|min| !positivity.is_some_and(|p| p >= min)) {
+            failed.push("positivity below minimum");
+        }
+        if self.max_positivity.is_some_and(|max| !positivity.is_some_and(|p| p <= max)) {
+            failed.push("positivity above maximum");
+        }
+        failed
+    }
+
+    /// Error if more regions and slides of the batch failed than the budget allows
+    pub fn check_budget(&self, region_failures: usize, slide_failures: usize) -> Result<(), String> {
+        match self.max_failures {
+            Some(budget) if region_failures + slide_failures > budget => Err(format!("{} region(s) and {} slide(s) failed the QC rules, more than the budget of {}", region_failures, slide_failures, budget)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Values of `QC_RULE_COLUMNS` for the rules `failed`
+pub(crate) fn qc_rule_values(failed: &[&str]) -> [String; 2] {
+    [String::from(if failed.is_empty() { "pass" } else { "fail" }), failed.join(";")]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::aggregate::{Aggregate, GroupBy, run_summarize_to_writer};
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::extract::RegionRecord;
+    use crate::options::Options;
+    use crate::report::read_records;
+    use super::{QC_RULE_COLUMNS, QcRules, qc_rule_values};
+
+    /// Extract a slide with a 0.0025 mm² and a 0.25 mm² region into a CSV report, then summarize the report
+    /// Returns the QC result of each region in the report and whether the summary failed the budget.
+    fn extract_and_summarize(name: &str, options: &Options) -> (Vec<(bool, bool)>, bool) {
+        let annotations = AnnotationsBuilder::new().microns_per_pixel(0.5)
+            .layer(AnnotationBuilder::new()
+                .region(RegionBuilder::new().text("small").vertices([(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)]))
+                .region(RegionBuilder::new().text("large").vertices([(0.0, 0.0), (1000.0, 0.0), (1000.0, 1000.0), (0.0, 1000.0)])))
+            .build();
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_qc_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (xml, csv) = (dir.join("case1.xml"), dir.join("case1.csv"));
+        fs::write(&xml, annotations.to_xml_string().unwrap()).unwrap();
+        let mut report = Vec::new();
+        let summaries = crate::run_to_writer(&xml, options, &mut report).unwrap();
+        assert_eq!(summaries[0].qc_failures, 1);
+        fs::write(&csv, report).unwrap();
+        let records = read_records(&csv).unwrap();
+        let summarized = run_summarize_to_writer(&csv, GroupBy::Slide, options, &mut Vec::new());
+        fs::remove_dir_all(&dir).unwrap();
+        let results = records.iter().map(|r| {
+            let extracted = r.text_columns.iter().any(|(name, value)| name == QC_RULE_COLUMNS[0] && value == "pass");
+            (extracted, options.qc_rules.record_failures(r).is_empty())
+        }).collect();
+        (results, summarized.is_err())
+    }
+
+    fn area_rules() -> QcRules {
+        QcRules { min_area_mm2: Some(0.01), max_failures: Some(1), ..QcRules::default() }
+    }
+
+    #[test]
+    fn summarized_report_keeps_the_qc_result_of_extraction() {
+        let options = Options { qc_rules: area_rules(), ..Options::default() };
+        let (results, over_budget) = extract_and_summarize("declared", &options);
+        assert_eq!(results.len(), 2);
+        for (extracted, summarized) in results {
+            assert_eq!(extracted, summarized);
+        }
+        assert!(!over_budget);
+    }
+
+    #[cfg(feature = "geometry")]
+    #[test]
+    fn summarized_report_checks_the_effective_area_column() {
+        let options = Options { qc_rules: area_rules(), measurements: vec![crate::geometry::Measurement::EffectiveArea], ..Options::default() };
+        let (results, over_budget) = extract_and_summarize("effective", &options);
+        assert_eq!(results.iter().filter(|(extracted, summarized)| extracted == summarized).count(), 2);
+        assert!(!over_budget);
+    }
+
+    /// Record of a region read back from a report with the given extra text columns
+    fn record(positivity: Option<f64>, num_total: Option<f64>, text_columns: &[(&str, &str)]) -> RegionRecord {
+        RegionRecord {
+            filename: String::from("case1.xml"),
+            slide_name: String::from("case1.svs"),
+            region_id: String::from("1"),
+            text_label: String::new(),
+            positivity,
+            num_wpositive: None,
+            num_positive: None,
+            num_spositive: None,
+            num_all_positive: 0.0,
+            num_total,
+            algorithm: String::new(),
+            columns: Vec::new(),
+            text_columns: text_columns.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn rules_fail_values_out_of_range_or_missing() {
+        let rules = QcRules { min_ntotal: Some(1000.0), min_positivity: Some(0.05), max_positivity: Some(0.95), min_area_mm2: Some(0.5), max_failures: None };
+        assert!(rules.is_active());
+        assert!(!QcRules { max_failures: Some(3), ..QcRules::default() }.is_active());
+        let passing = record(Some(0.5), Some(1000.0), &[("effective_area_microns", "500000")]);
+        assert_eq!(rules.record_failures(&passing), Vec::<&str>::new());
+        let failing = record(Some(0.99), Some(999.0), &[("effective_area_microns", "499999")]);
+        assert_eq!(rules.record_failures(&failing), ["ntotal below minimum", "positivity above maximum", "area below minimum"]);
+        assert_eq!(rules.record_failures(&record(None, None, &[])), ["ntotal below minimum", "positivity below minimum", "positivity above maximum", "area below minimum"]);
+        // Without an area column, the verdict written at extraction is kept
+        assert_eq!(rules.record_failures(&record(Some(0.5), Some(1000.0), &[("qc_failed", "")])), Vec::<&str>::new());
+        assert_eq!(rules.record_failures(&record(Some(0.5), Some(1000.0), &[("qc_failed", "ntotal below minimum;area below minimum")])), ["area below minimum"]);
+        assert_eq!(qc_rule_values(&["ntotal below minimum", "area below minimum"]), [String::from("fail"), String::from("ntotal below minimum;area below minimum")]);
+        assert_eq!(qc_rule_values(&[]), [String::from("pass"), String::new()]);
+    }
+
+    #[test]
+    fn slides_are_checked_on_their_counts_added_up() {
+        let rules = QcRules { min_ntotal: Some(100.0), max_positivity: Some(0.5), ..QcRules::default() };
+        let slide = Aggregate { slide_name: String::from("case1.svs"), regions: 2, num_all_positive: 60.0, num_total: 100.0, ..Aggregate::default() };
+        assert_eq!(rules.slide_failures(&slide), ["positivity above maximum"]);
+        let empty = Aggregate { slide_name: String::from("case2.svs"), ..Aggregate::default() };
+        assert_eq!(rules.slide_failures(&empty), ["ntotal below minimum", "positivity above maximum"]);
+    }
+
+    #[test]
+    fn budget_covers_regions_and_slides() {
+        let rules = QcRules { max_failures: Some(2), ..QcRules::default() };
+        assert!(rules.check_budget(1, 1).is_ok());
+        assert!(rules.check_budget(2, 1).is_err());
+        assert!(QcRules::default().check_budget(100, 100).is_ok());
+    }
+}
@@ -0,0 +1,187 @@
+use std::error;
+use serde_json::Value;
+use crate::builder::{AnnotationBuilder, AnnotationsBuilder, DEFAULT_LINE_COLOR, RegionBuilder};
+use crate::model::{AnnotationAttributesAttribute, Annotations, Extra, RegionType, XmlValue};
+
+/// One layer per QuPath classification, in the order they first appear
+struct Class {
+    name: String,
+    color: u32,
+    regions: Vec<RegionBuilder>,
+}
+
+/// Read a QuPath GeoJSON export (a FeatureCollection, an array of features or a single feature)
+/// Every classification becomes a user-drawn layer named after it, outlined in its color, whose
+/// regions carry the classification name as their text, unclassified objects are outlined in ImageScope's default
+/// green. Holes become negative regions.
+/// Returns the annotations and a description of every object or geometry skipped.
+pub fn import_qupath_geojson(contents: &[u8]) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+    let document: Value = serde_json::from_slice(contents)?;
+    let features = match &document {
+        Value::Array(features) => features.iter().collect(),
+        Value::Object(object) => match object.get("features") {
+            Some(Value::Array(features)) => features.iter().collect(),
+            _ if object.get("type").and_then(Value::as_str) == Some("Feature") => vec![&document],
+            _ => return Err("Not a GeoJSON FeatureCollection or Feature".into()),
+        },
+        _ => return Err("Not a GeoJSON FeatureCollection or Feature".into()),
+    };
+    let mut classes: Vec<Class> = Vec::new();
+    let mut skipped = Vec::new();
+    for feature in features {
+        let properties = feature.get("properties");
+        let classification = properties.and_then(|p| p.get("classification"));
+        let class_name = classification.and_then(|c| c.get("name")).and_then(Value::as_str).unwrap_or_default();
+        let text = if class_name.is_empty() {
+            properties.and_then(|p| p.get("name")).and_then(Value::as_str).unwrap_or_default()
+        } else {
+            class_name
+        };
+        let position = match classes.iter().position(|c| c.name == class_name) {
+            Some(position) => position,
+            None => {
+                let color = classification.and_then(class_color).unwrap_or(DEFAULT_LINE_COLOR);
+                classes.push(Class { name: class_name.to_string(), color, regions: Vec::new() });
+                classes.len() - 1
+            },
+        };
+        match feature.get("geometry") {
+            Some(geometry) if !geometry.is_null() => add_geometry(geometry, text, &mut classes[position].regions, &mut skipped)?,
+            _ => skipped.push(format!("Skipping QuPath object {:?} without a geometry", text)),
+        }
+    }
+
+    // Classes whose objects were all skipped would leave empty layers
+    classes.retain(|c| !c.regions.is_empty());
+    let mut builder = AnnotationsBuilder::new();
+    for class in &mut classes {
+        let mut layer = AnnotationBuilder::new().name(class.name.clone()).line_color(class.color);
+        for region in class.regions.drain(..) {
+            layer = layer.region(region);
+        }
+        builder = builder.layer(layer);
+    }
+    let mut annotations = builder.build();
+    for (layer, class) in annotations.annotation.iter_mut().zip(&classes) {
+        if class.name.is_empty() {
+            continue;
+        }
+        let mut extra = Extra::new();
+        extra.insert(String::from("@DisplayColor"), XmlValue::Text(class.color.to_string()));
+        layer.attributes.attribute = Some(vec![AnnotationAttributesAttribute { name: class.name.clone(), id: String::from("0"), value: String::new(), extra }]);
+    }
+    Ok((annotations, skipped))
+}
+
+/// Classification color as an ImageScope BGR integer, from `color: [r, g, b]` or the packed `colorRGB`
+fn class_color(classification: &Value) -> Option<u32> {
+    let (r, g, b) = match (classification.get("color"), classification.get("colorRGB")) {
+        (Some(Value::Array(rgb)), _) if rgb.len() >= 3 => {
+            let channel = |i: usize| rgb[i].as_u64().map(|c| c.min(255) as u32);
+            (channel(0)?, channel(1)?, channel(2)?)
+        },
+        (_, Some(packed)) => {
+            let packed = packed.as_i64()? as u32;
+            ((packed >> 16) & 0xff, (packed >> 8) & 0xff, packed & 0xff)
+        },
+        _ => return None,
+    };
+    Some(r | (g << 8) | (b << 16))
+}
+
+/// Regions for a GeoJSON geometry: polygons are freehand outlines with their holes as negative
+/// regions, lines are polylines. Other geometries are described in `skipped`.
+fn add_geometry(geometry: &Value, text: &str, regions: &mut Vec<RegionBuilder>, skipped: &mut Vec<String>) -> Result<(), Box<dyn error::Error>> {
+    let kind = geometry.get("type").and_then(Value::as_str).unwrap_or_default();
+    let coordinates = geometry.get("coordinates");
+    match kind {
+        "Polygon" => add_polygon(coordinates.ok_or("Polygon without coordinates")?, text, regions)?,
+        "MultiPolygon" => for polygon in as_array(coordinates)? {
+            add_polygon(polygon, text, regions)?;
+        },
+        "LineString" => regions.push(region(RegionType::Polyline, text, points(coordinates.ok_or("LineString without coordinates")?)?)),
+        "MultiLineString" => for line in as_array(coordinates)? {
+            regions.push(region(RegionType::Polyline, text, points(line)?));
+        },
+        "GeometryCollection" => for part in as_array(geometry.get("geometries"))? {
+            add_geometry(part, text, regions, skipped)?;
+        },
+        other => skipped.push(format!("Skipping QuPath {} geometry {:?}, ImageScope has no matching region type", other, text)),
+    }
+    Ok(())
+}
+
+/// The outer ring of a polygon and its holes
+fn add_polygon(rings: &Value, text: &str, regions: &mut Vec<RegionBuilder>) -> Result<(), Box<dyn error::Error>> {
+    for (i, ring) in as_array(Some(rings))?.iter().enumerate() {
+        let mut ring = points(ring)?;
+        // GeoJSON repeats the first point to close a ring, ImageScope closes it implicitly
+        if ring.len() > 1 && ring.first() == ring.last() {
+            ring.pop();
+        }
+        regions.push(region(RegionType::Freehand, text, ring).negative_roa(i > 0));
+    }
+    Ok(())
+}
+
+fn region(region_type: RegionType, text: &str, points: Vec<(f64, f64)>) -> RegionBuilder {
+    RegionBuilder::new().region_type(region_type).text(text).vertices(points)
+}
+
+fn as_array(value: Option<&Value>) -> Result<&Vec<Value>, Box<dyn error::Error>> {
+    value.and_then(Value::as_array).ok_or_else(|| "Expected an array of GeoJSON coordinates".into())
+}
+
+/// `[[x, y], ...]` positions, ignoring any third coordinate
+fn points(positions: &Value) -> Result<Vec<(f64, f64)>, Box<dyn error::Error>> {
+    as_array(Some(positions))?.iter()
+        .map(|p| match (p.get(0).and_then(Value::as_f64), p.get(1).and_then(Value::as_f64)) {
+            (Some(x), Some(y)) => Ok((x, y)),
+            _ => Err(format!("Invalid GeoJSON position {}", p).into()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::RegionType;
+    use crate::render::layer_color;
+    use super::import_qupath_geojson;
+
+    #[test]
+    fn classifications_become_colored_layers() {
+        let geojson = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "geometry": {"type": "Polygon", "coordinates": [[[0, 0], [10, 0], [10, 10], [0, 0]], [[2, 2], [4, 2], [4, 4], [2, 2]]]},
+             "properties": {"objectType": "annotation", "classification": {"name": "Tumor", "color": [200, 0, 0]}}},
+            {"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[0, 0], [5, 5, 1]]},
+             "properties": {"name": "Margin"}},
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [1, 1]},
+             "properties": {"classification": {"name": "Stroma", "colorRGB": -16744448}}}
+        ]}"#;
+        let (annotations, skipped) = import_qupath_geojson(geojson.as_bytes()).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert!(skipped[0].contains("Point"), "{}", skipped[0]);
+        // Stroma only had a point, so it has no layer
+        let names: Vec<&str> = annotations.annotation.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["Tumor", ""]);
+        assert_eq!(layer_color(&annotations.annotation[0]).as_deref(), Some("#c80000"));
+        // Unclassified objects are outlined in ImageScope's default green
+        assert_eq!(layer_color(&annotations.annotation[1]).as_deref(), Some("#00ff00"));
+        let tumor = &annotations.annotation[0].regions.region;
+        assert_eq!((&tumor[0].region_type, tumor[0].label(), tumor[0].is_negative()), (&RegionType::Freehand, String::from("Tumor"), false));
+        // Rings drop the repeated first point
+        assert_eq!(tumor[0].outline(), [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]);
+        assert!(tumor[1].is_negative());
+        let margin = &annotations.annotation[1].regions.region[0];
+        assert_eq!((&margin.region_type, margin.label()), (&RegionType::Polyline, String::from("Margin")));
+        assert_eq!(margin.outline(), [(0.0, 0.0), (5.0, 5.0)]);
+    }
+
+    #[test]
+    fn packed_colors_are_read() {
+        let geojson = r#"{"type": "Feature", "geometry": {"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1]]]},
+            "properties": {"classification": {"name": "Stroma", "colorRGB": -16744448}}}"#;
+        let (annotations, _) = import_qupath_geojson(geojson.as_bytes()).unwrap();
+        assert_eq!(layer_color(&annotations.annotation[0]).as_deref(), Some("#008000"));
+    }
+}
@@ -0,0 +1,235 @@
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use crate::model::{Annotation, Annotations, Region};
+
+/// Position of a complete or truncated element in the source text
+#[derive(Debug, Clone)]
+struct Span {
+    /// Byte offset of the `<` of the start tag
+    start: usize,
+    /// Byte offset just past the start tag
+    start_tag_end: usize,
+    /// Byte offset just past the end tag, None if the element is truncated
+    end: Option<usize>,
+}
+
+impl Span {
+    /// Text of the start tag, turned into an empty element
+    fn empty_element(&self, xml: &str) -> String {
+        let tag = &xml[self.start..self.start_tag_end];
+        if tag.ends_with("/>") {
+            tag.to_string()
+        } else {
+            format!("{}/>", tag.trim_end_matches('>'))
+        }
+    }
+
+    /// Element name as written in the start tag, including any namespace prefix
+    fn name<'a>(&self, xml: &'a str) -> &'a str {
+        let tag = &xml[self.start + 1..self.start_tag_end];
+        let end = tag.find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(tag.len());
+        &tag[..end]
+    }
+}
+
+/// Elements of interest found while scanning a damaged document
+#[derive(Debug, Default)]
+struct Layout {
+    root: Option<Span>,
+    layers: Vec<LayerLayout>,
+}
+
+/// An annotation layer and the children needed to rebuild it
+#[derive(Debug)]
+struct LayerLayout {
+    layer: Span,
+    attributes: Option<Span>,
+    headers: Option<Span>,
+    regions: Vec<Span>,
+}
+
+/// Best-effort parse of a document that failed to parse as a whole
+/// Each layer is parsed on its own, and a layer that still fails is rebuilt from whichever of its regions parse.
+/// Returns what could be recovered and a description of everything that was skipped.
+pub fn parse_xml_lenient(xml: &str) -> (Annotations, Vec<String>) {
+    let mut skipped = Vec::new();
+    let (layout, scan_error) = scan(xml);
+    if let Some(e) = scan_error {
+        skipped.push(format!("XML is damaged at byte {}, content from there on is ignored", e));
+    }
+    // Root attributes such as MicronsPerPixel
+    let mut annotations = layout.root.as_ref()
+        .and_then(|root| quick_xml::de::from_str::<Annotations>(&root.empty_element(xml)).ok())
+        .unwrap_or_default();
+    for (number, LayerLayout { layer, attributes, headers, regions }) in layout.layers.iter().enumerate() {
+        // Try the layer as a whole first
+        if let Some(end) = layer.end {
+            if let Ok(annotation) = quick_xml::de::from_str::<Annotation>(&xml[layer.start..end]) {
+                annotations.annotation.push(annotation);
+                continue;
+            }
+        }
+        // Rebuild the layer from its start tag and complete children
+        let mut shell = xml[layer.start..layer.start_tag_end].trim_end_matches("/>").trim_end_matches('>').to_string();
+        shell.push('>');
+        if let Some(attributes) = attributes.as_ref().and_then(|a| a.end.map(|end| &xml[a.start..end])) {
+            shell.push_str(attributes);
+        }
+        shell.push_str("<Regions>");
+        if let Some(headers) = headers.as_ref().and_then(|h| h.end.map(|end| &xml[h.start..end])) {
+            shell.push_str(headers);
+        }
+        shell.push_str("</Regions></");
+        shell.push_str(layer.name(xml));
+        shell.push('>');
+        let mut annotation = match quick_xml::de::from_str::<Annotation>(&shell) {
+            Ok(annotation) => annotation,
+            Err(e) => {
+                skipped.push(format!("Skipped annotation layer {}: {}", number + 1, e));
+                continue;
+            },
+        };
+        for (index, region) in regions.iter().enumerate() {
+            let parsed = match region.end {
+                Some(end) => quick_xml::de::from_str::<Region>(&xml[region.start..end]).map_err(|e| e.to_string()),
+                None => Err(String::from("region is truncated")),
+            };
+            match parsed {
+                Ok(region) => annotation.regions.region.push(region),
+                Err(e) => skipped.push(format!("Skipped region {} of annotation layer {}: {}", index + 1, annotation.id, e)),
+            }
+        }
+        skipped.push(format!("Annotation layer {} was damaged, kept {} of {} regions", annotation.id, annotation.regions.region.len(), regions.len()));
+        annotations.annotation.push(annotation);
+    }
+    (annotations, skipped)
+}
+
+/// Locate layers and regions, stopping at the first syntax error
+/// Elements are matched on their local name so namespace prefixes are ignored.
+/// Returns the layout found so far and the byte offset of the error, if any
+fn scan(xml: &str) -> (Layout, Option<u64>) {
+    let mut layout = Layout::default();
+    let mut reader = Reader::from_str(xml);
+    // Names and spans of the currently open elements
+    let mut open: Vec<(Vec<u8>, Span)> = Vec::new();
+    loop {
+        let before = reader.buffer_position() as usize;
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(_) => return (layout, Some(reader.error_position())),
+        };
+        let after = reader.buffer_position() as usize;
+        match event {
+            Event::Start(tag) => {
+                let span = Span { start: before, start_tag_end: after, end: None };
+                open.push((tag.local_name().as_ref().to_vec(), span));
+                record(&mut layout, &open);
+            },
+            Event::Empty(tag) => {
+                let span = Span { start: before, start_tag_end: after, end: Some(after) };
+                open.push((tag.local_name().as_ref().to_vec(), span));
+                record(&mut layout, &open);
+                open.pop();
+            },
+            Event::End(_) => {
+                if let Some((name, span)) = open.pop() {
+                    close(&mut layout, &name, open.len(), span.start, after);
+                }
+            },
+            Event::Eof => return (layout, None),
+            _ => {},
+        }
+    }
+}
+
+/// Remember a newly opened element if it is one we care about
+fn record(layout: &mut Layout, open: &[(Vec<u8>, Span)]) {
+    let names: Vec<&[u8]> = open.iter().map(|(name, _)| name.as_slice()).collect();
+    let span = open.last().expect("An element was just opened").1.clone();
+    match names.as_slice() {
+        [b"Annotations"] => layout.root = Some(span),
+        [b"Annotations", b"Annotation"] => layout.layers.push(LayerLayout { layer: span, attributes: None, headers: None, regions: Vec::new() }),
+        [b"Annotations", b"Annotation", child @ ..] => {
+            let Some(layer) = layout.layers.last_mut() else { return };
+            match child {
+                [b"Attributes"] => layer.attributes = Some(span),
+                [b"Regions", b"RegionAttributeHeaders"] => layer.headers = Some(span),
+                [b"Regions", b"Region"] => layer.regions.push(span),
+                _ => {},
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Record where an element we care about ends, `depth` is its nesting depth starting from 0 for the root
+fn close(layout: &mut Layout, name: &[u8], depth: usize, start: usize, end: usize) {
+    let target = match (depth, name) {
+        (0, b"Annotations") => layout.root.as_mut(),
+        (1, b"Annotation") => layout.layers.last_mut().map(|l| &mut l.layer),
+        (2, b"Attributes") => layout.layers.last_mut().and_then(|l| l.attributes.as_mut()),
+        (3, b"RegionAttributeHeaders") => layout.layers.last_mut().and_then(|l| l.headers.as_mut()),
+        (3, b"Region") => layout.layers.last_mut().and_then(|l| l.regions.last_mut()),
+        _ => None,
+    };
+    // Only close the span that was opened by this element
+    if let Some(span) = target.filter(|s| s.start == start) {
+        span.end = Some(end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYER: &str = r#"<Annotation Id="1" Name="Tumor" Type="4"><Attributes><Attribute Name="Description" Id="0" Value="first"/></Attributes><Regions><RegionAttributeHeaders><AttributeHeader Id="9999" Name="Region"/></RegionAttributeHeaders><Region Id="1" Type="0"><Vertices><Vertex X="0" Y="0"/><Vertex X="10" Y="0"/><Vertex X="10" Y="10"/></Vertices></Region><Region Id="2" Type="1"><Vertices><Vertex X="0" Y="0"/><Vertex X="5" Y="5"/></Vertices></Region></Regions></Annotation>"#;
+
+    /// Region ids of each recovered layer
+    fn region_ids(annotations: &Annotations) -> Vec<Vec<&str>> {
+        annotations.annotation.iter().map(|layer| layer.regions.region.iter().map(|r| r.id.as_str()).collect()).collect()
+    }
+
+    #[test]
+    fn complete_layers_are_kept_whole() {
+        let xml = format!(r#"<Annotations MicronsPerPixel="0.25">{}<Annotation Id="2" Type="4"><Regions><Region Id="3""#, LAYER);
+        let (annotations, skipped) = parse_xml_lenient(&xml);
+        assert_eq!(annotations.microns_per_pixel.as_deref(), Some("0.25"));
+        assert_eq!(annotations.annotation[0].name, "Tumor");
+        assert_eq!(annotations.annotation[0].attributes.attribute.as_ref().unwrap()[0].value, "first");
+        assert!(annotations.annotation[0].regions.region_attribute_headers.attribute_header.is_some());
+        // The second layer is cut off inside its only region's start tag
+        assert_eq!(region_ids(&annotations), [vec!["1", "2"], vec![]]);
+        assert!(skipped[0].starts_with("XML is damaged at byte"), "{:?}", skipped);
+    }
+
+    #[test]
+    fn truncated_layer_keeps_its_complete_regions() {
+        let cut = LAYER.find(r#"<Vertex X="5""#).unwrap();
+        let xml = format!(r#"<Annotations MicronsPerPixel="0.25">{}"#, &LAYER[..cut]);
+        let (annotations, skipped) = parse_xml_lenient(&xml);
+        assert_eq!(region_ids(&annotations), [vec!["1"]]);
+        assert_eq!(annotations.annotation[0].attributes.attribute.as_ref().unwrap()[0].value, "first");
+        assert_eq!(annotations.annotation[0].regions.region[0].vertices.as_ref().unwrap().vertex.len(), 3);
+        assert!(skipped.contains(&"Skipped region 2 of annotation layer 1: region is truncated".to_string()), "{:?}", skipped);
+        assert!(skipped.contains(&"Annotation layer 1 was damaged, kept 1 of 2 regions".to_string()), "{:?}", skipped);
+    }
+
+    #[test]
+    fn namespace_prefixes_are_ignored() {
+        let xml = r#"<a:Annotations xmlns:a="urn:x" MicronsPerPixel="0.5"><a:Annotation Id="4" Type="4"><a:Regions><a:Region Id="7" Type="0"><a:Vertices><a:Vertex X="1" Y="2"/></a:Vertices></a:Region><a:Region Id="8" "#;
+        let (annotations, skipped) = parse_xml_lenient(xml);
+        assert_eq!(annotations.annotation.len(), 1);
+        assert_eq!(annotations.annotation[0].id, "4");
+        assert_eq!(region_ids(&annotations), [vec!["7"]]);
+        assert!(!skipped.is_empty());
+    }
+
+    #[test]
+    fn nothing_recognisable_recovers_nothing() {
+        let (annotations, skipped) = parse_xml_lenient("<Other><Annotation Id=\"1\" Type=\"4\"/></Other>");
+        assert!(annotations.annotation.is_empty());
+        assert!(annotations.microns_per_pixel.is_none());
+        assert!(skipped.is_empty());
+    }
+}
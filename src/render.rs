@@ -0,0 +1,134 @@
+use std::{error, str::FromStr};
+use quick_xml::escape::escape;
+use crate::convert::ExportRegion;
+use crate::geometry::BoundingBox;
+use crate::model::{Annotation, XmlValue};
+
+/// What decides the color a region is drawn in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorBy {
+    /// The line color of the region's layer, as shown in ImageScope
+    #[default]
+    Layer,
+    /// One color per text label
+    Label,
+}
+
+impl FromStr for ColorBy {
+    type Err = Box<dyn error::Error>;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "layer" => Ok(ColorBy::Layer),
+            "label" => Ok(ColorBy::Label),
+            other => Err(format!("Unknown color scheme {}, expected layer or label", other).into()),
+        }
+    }
+}
+
+/// Colors handed out to labels (and layers without a line color), in order
+const PALETTE: [&str; 10] = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f", "#bcbd22", "#17becf"];
+
+/// Line color of a layer as `#rrggbb`, ImageScope stores it as a decimal number in BGR order
+pub(crate) fn layer_color(layer: &Annotation) -> Option<String> {
+    let Some(XmlValue::Text(color)) = layer.extra.get("@LineColor") else {
+        return None;
+    };
+    let bgr: u32 = color.trim().parse().ok()?;
+    Some(format!("#{:02x}{:02x}{:02x}", bgr & 0xff, (bgr >> 8) & 0xff, (bgr >> 16) & 0xff))
+}
+
+/// `#rrggbb` or `#aarrggbb` as an ImageScope BGR integer
+pub(crate) fn hex_to_bgr(color: &str) -> Option<u32> {
+    let hex = color.trim().strip_prefix('#')?;
+    let hex = if hex.len() == 8 { &hex[2..] } else { hex };
+    let rgb = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6)?;
+    Some(((rgb >> 16) & 0xff) | (rgb & 0xff00) | ((rgb & 0xff) << 16))
+}
+
+/// SVG drawing of the region outlines, scaled down by `downsample`
+pub fn to_svg(regions: &[ExportRegion], downsample: f64, color_by: ColorBy) -> String {
+    let outlines: Vec<Vec<(f64, f64)>> = regions.iter().map(|r| r.region.outline()).collect();
+    let all_points: Vec<(f64, f64)> = outlines.iter().flatten().copied().collect();
+    // The drawing starts at the slide origin so positions match the slide
+    let (width, height) = BoundingBox::of(&all_points).map(|b| (b.max_x.max(0.0), b.max_y.max(0.0))).unwrap_or((0.0, 0.0));
+    let (width, height) = ((width / downsample).ceil().max(1.0), (height / downsample).ceil().max(1.0));
+    let mut labels: Vec<String> = Vec::new();
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n", w = width, h = height);
+    for (r, outline) in regions.iter().zip(&outlines) {
+        let label = r.region.label();
+        let color = match color_by {
+            ColorBy::Layer => layer_color(r.layer),
+            ColorBy::Label => None,
+        }.unwrap_or_else(|| {
+            let key = match color_by { ColorBy::Layer => r.layer.id.clone(), ColorBy::Label => label.clone() };
+            let position = labels.iter().position(|l| *l == key).unwrap_or_else(|| {
+                labels.push(key);
+                labels.len() - 1
+            });
+            PALETTE[position % PALETTE.len()].to_string()
+        });
+        let points = outline.iter().map(|(x, y)| format!("{},{}", x / downsample, y / downsample)).collect::<Vec<_>>().join(" ");
+        let title = format!("<title>{}</title>", escape(format!("Layer {} region {}: {}", r.layer.id, r.region.id, label).as_str()));
+        match outline.len() {
+            0 => continue,
+            1 => svg.push_str(&format!("  <circle cx=\"{}\" cy=\"{}\" r=\"3\" fill=\"{}\">{}</circle>\n", outline[0].0 / downsample, outline[0].1 / downsample, color, title)),
+            n if n >= 3 && r.region.region_type.is_closed() => svg.push_str(&format!("  <polygon points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\">{}</polygon>\n", points, color, title)),
+            _ => svg.push_str(&format!("  <polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\">{}</polyline>\n", points, color, title)),
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::model::RegionType;
+    use super::{ColorBy, PALETTE, hex_to_bgr, layer_color, to_svg};
+
+    #[test]
+    fn line_colors_convert_between_bgr_and_hex() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new().line_color(255))
+            .layer(AnnotationBuilder::new().line_color(0x123456))
+            .build();
+        let colors: Vec<Option<String>> = annotations.annotation.iter().map(layer_color).collect();
+        assert_eq!(colors, [Some(String::from("#ff0000")), Some(String::from("#563412"))]);
+        assert_eq!(hex_to_bgr("#ff0000"), Some(255));
+        assert_eq!(hex_to_bgr(" #563412 "), Some(0x123456));
+        // The alpha channel is ignored
+        assert_eq!(hex_to_bgr("#80ff0000"), Some(255));
+        assert_eq!(hex_to_bgr("ff0000"), None);
+        assert_eq!(hex_to_bgr("#f00"), None);
+        assert_eq!(hex_to_bgr("#gg0000"), None);
+    }
+
+    #[test]
+    fn regions_are_drawn_from_the_slide_origin() {
+        let mut annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new().line_color(255)
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("A & B").vertices([(20.0, 10.0), (100.0, 50.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Ruler).text("A & B").vertices([(10.0, 10.0), (50.0, 10.0)])))
+            .layer(AnnotationBuilder::new().region(RegionBuilder::new().text("Point").vertex(80.0, 40.0)))
+            .build();
+        // A layer without a line color gets one from the palette
+        annotations.annotation[1].extra.shift_remove("@LineColor");
+        let regions = export_regions(&annotations, &[]);
+        let svg = to_svg(&regions, 2.0, ColorBy::Layer);
+        let lines: Vec<&str> = svg.lines().collect();
+        assert_eq!(lines, [
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="50" height="25" viewBox="0 0 50 25">"#,
+            r##"  <polygon points="10,5 50,5 50,25 10,25" fill="none" stroke="#ff0000" stroke-width="2"><title>Layer 1 region 1: A &amp; B</title></polygon>"##,
+            r##"  <polyline points="5,5 25,5" fill="none" stroke="#ff0000" stroke-width="2"><title>Layer 1 region 2: A &amp; B</title></polyline>"##,
+            r##"  <circle cx="40" cy="20" r="3" fill="#1f77b4"><title>Layer 2 region 3: Point</title></circle>"##,
+            "</svg>",
+        ]);
+        // By label, each label gets the next palette color
+        let svg = to_svg(&regions, 2.0, ColorBy::Label);
+        assert_eq!((svg.matches(PALETTE[0]).count(), svg.matches(PALETTE[1]).count()), (2, 1));
+        assert!(!svg.contains("#ff0000"));
+        assert!("shape".parse::<ColorBy>().is_err());
+    }
+}
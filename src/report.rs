@@ -0,0 +1,277 @@
+use std::{error, fs, io::{self, Write}, path, str::FromStr};
+use crate::extract::RegionRecord;
+use crate::metadata::parse_csv_line;
+use crate::model::{Annotations, Vertex};
+
+/// What the report lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// One line per region with its analysis results
+    #[default]
+    Csv,
+    /// One line per vertex of every region
+    VerticesCsv,
+    /// One line per region and metric (long format), for dplyr and ggplot
+    Tidy,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Box<dyn error::Error>;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "vertices-csv" => Ok(OutputFormat::VerticesCsv),
+            "tidy" => Ok(OutputFormat::Tidy),
+            other => Err(format!("Unknown output format {}, expected csv, vertices-csv or tidy", other).into()),
+        }
+    }
+}
+
+/// Column names of the CSV output
+pub const CSV_HEADER: &str = "Filename,Slide Name,Region ID,text label,positivity,num weak positive,num positive,num strong positive,num all positive,num total,algorithm";
+
+/// Column names of the vertex CSV output
+pub const VERTICES_HEADER: &str = "Filename,Layer ID,Region ID,vertex,x,y";
+
+/// Column names of the tidy CSV output
+pub const TIDY_HEADER: &str = "Filename,Slide Name,Region ID,text label,algorithm,metric,value";
+
+/// Write one CSV line per vertex of every region of every layer, numbering the vertices of a region from 0
+/// Vertices that are not numbers are skipped.
+pub fn write_vertex_records<W: Write>(filename: &str, annotations: &Annotations, writer: &mut W) -> io::Result<()> {
+    for layer in &annotations.annotation {
+        for region in &layer.regions.region {
+            let points = region.vertices.iter().flat_map(|v| &v.vertex).filter_map(Vertex::point);
+            for (i, (x, y)) in points.enumerate() {
+                writeln!(writer, "{},{},{},{},{},{}", csv_field(filename), csv_field(&layer.id), csv_field(&region.id), i, x, y)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the CSV header line
+pub fn write_csv_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    write_csv_header_with_columns(writer, &[])
+}
+
+/// Write the CSV header line followed by the names of extra `columns`
+pub fn write_csv_header_with_columns<W: Write>(writer: &mut W, columns: &[&str]) -> io::Result<()> {
+    write!(writer, "{}", CSV_HEADER)?;
+    for column in columns {
+        write!(writer, ",{}", csv_field(column))?;
+    }
+    writeln!(writer)
+}
+
+/// Write one CSV line per record, without a header
+/// Missing positivity and extra columns are written as NaN and missing counts as 0, text columns as they are
+/// in the order they were added
+pub fn write_csv_records<W: Write>(records: &[RegionRecord], writer: &mut W) -> io::Result<()> {
+    write_records(records, None, writer)
+}
+
+/// Write one CSV line per record, without a header, with the text columns named in `text_columns` in that order
+/// Text columns a record lacks are left empty.
+pub fn write_csv_records_with_columns<W: Write>(records: &[RegionRecord], text_columns: &[&str], writer: &mut W) -> io::Result<()> {
+    write_records(records, Some(text_columns), writer)
+}
+
+/// Body of `write_csv_records` and `write_csv_records_with_columns`
+fn write_records<W: Write>(records: &[RegionRecord], text_columns: Option<&[&str]>, writer: &mut W) -> io::Result<()> {
+    for r in records {
+        write!(writer, "{},{},{},{},{},{},{},{},{},{},{}",
+            csv_field(&r.filename),
+            csv_field(&r.slide_name),
+            csv_field(&r.region_id),
+            csv_field(&r.text_label),
+            r.positivity.unwrap_or(f64::NAN),
+            r.num_wpositive.unwrap_or(0.0),
+            r.num_positive.unwrap_or(0.0),
+            r.num_spositive.unwrap_or(0.0),
+            r.num_all_positive,
+            r.num_total.unwrap_or(0.0),
+            csv_field(&r.algorithm))?;
+        for (_, value) in &r.columns {
+            write!(writer, ",{}", value.unwrap_or(f64::NAN))?;
+        }
+        match text_columns {
+            Some(names) => for (i, name) in names.iter().enumerate() {
+                // A name given twice refers to the second column of that name
+                let nth = names[..i].iter().filter(|n| *n == name).count();
+                let value = r.text_columns.iter().filter(|(n, _)| n == name).nth(nth).map_or("", |(_, v)| v.as_str());
+                write!(writer, ",{}", csv_field(value))?;
+            },
+            None => for (_, value) in &r.text_columns {
+                write!(writer, ",{}", csv_field(value))?;
+            },
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Read the records of a CSV report written before, so it can be summarized without parsing the XML files again
+/// Columns after the standard ones are kept as text columns. Missing counts were written as 0 and stay 0.
+pub fn read_records(path: &path::Path) -> Result<Vec<RegionRecord>, Box<dyn error::Error>> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Error reading records from {}: {}", path.display(), e))?;
+    let mut lines = contents.lines().filter(|l| !l.trim().is_empty());
+    let header = lines.next().ok_or(format!("Records file {} is empty", path.display()))?;
+    let standard = CSV_HEADER.split(',').count();
+    if !header.starts_with(CSV_HEADER) {
+        return Err(format!("{} is not a CSV report, expected the header {}", path.display(), CSV_HEADER).into());
+    }
+    let extra: Vec<String> = parse_csv_line(header).into_iter().skip(standard).collect();
+    let number = |value: &str| value.parse::<f64>().ok().filter(|n| !n.is_nan());
+    lines.enumerate().map(|(i, line)| {
+        let fields = parse_csv_line(line);
+        if fields.len() != standard + extra.len() {
+            return Err(format!("Line {} of {} has {} columns instead of {}", i + 2, path.display(), fields.len(), standard + extra.len()).into());
+        }
+        Ok(RegionRecord {
+            filename: fields[0].clone(),
+            slide_name: fields[1].clone(),
+            region_id: fields[2].clone(),
+            text_label: fields[3].clone(),
+            positivity: number(&fields[4]),
+            num_wpositive: number(&fields[5]),
+            num_positive: number(&fields[6]),
+            num_spositive: number(&fields[7]),
+            num_all_positive: number(&fields[8]).unwrap_or(0.0),
+            num_total: number(&fields[9]),
+            algorithm: fields[10].clone(),
+            columns: Vec::new(),
+            text_columns: extra.iter().cloned().zip(fields[standard..].iter().cloned()).collect(),
+        })
+    }).collect()
+}
+
+/// True if `path` is a CSV file, e.g. a report to summarize
+pub fn is_csv_file(path: &path::Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"))
+}
+
+/// Write one CSV line per metric of every record, without a header
+/// Metrics are named after the columns of the wide output. Missing values are written as NA, which R reads
+/// as missing.
+pub fn write_tidy_records<W: Write>(records: &[RegionRecord], writer: &mut W) -> io::Result<()> {
+    for r in records {
+        let metrics = [
+            ("positivity", r.positivity),
+            ("num weak positive", r.num_wpositive),
+            ("num positive", r.num_positive),
+            ("num strong positive", r.num_spositive),
+            ("num all positive", Some(r.num_all_positive)),
+            ("num total", r.num_total),
+        ];
+        let columns = r.columns.iter().map(|(name, value)| (name.as_str(), *value));
+        let region = [&r.filename, &r.slide_name, &r.region_id, &r.text_label, &r.algorithm].map(|field| csv_field(field)).join(",");
+        for (metric, value) in metrics.into_iter().chain(columns) {
+            let value = value.map(|v| v.to_string()).unwrap_or_else(|| String::from("NA"));
+            writeln!(writer, "{},{},{}", region, csv_field(metric), value)?;
+        }
+        for (metric, value) in &r.text_columns {
+            let value = if value.is_empty() { String::from("NA") } else { csv_field(value) };
+            writeln!(writer, "{},{},{}", region, csv_field(metric), value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if it holds a comma, quote or line break
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write a complete CSV report (header and records)
+pub fn report_csv<W: Write>(records: &[RegionRecord], writer: &mut W) -> io::Result<()> {
+    write_csv_header(writer)?;
+    write_csv_records(records, writer)
+}
+
+/// Render a complete CSV report into a String
+pub fn report_csv_string(records: &[RegionRecord]) -> String {
+    let mut buffer = Vec::new();
+    report_csv(records, &mut buffer).expect("Writing into memory should not fail");
+    String::from_utf8(buffer).expect("CSV output is built from UTF-8 strings")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::extract::RegionRecord;
+    use crate::metadata::parse_csv_line;
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use super::{read_records, report_csv, write_tidy_records, write_vertex_records};
+
+    #[test]
+    fn records_with_commas_and_quotes_read_back() {
+        let record = RegionRecord {
+            filename: String::from("case1.xml"),
+            slide_name: String::from("case1.svs"),
+            region_id: String::from("1"),
+            text_label: String::from("Tumor, \"edge\""),
+            positivity: Some(0.25),
+            num_wpositive: Some(1.0),
+            num_positive: Some(2.0),
+            num_spositive: Some(3.0),
+            num_all_positive: 6.0,
+            num_total: Some(24.0),
+            algorithm: String::from("Positive Pixel Count 9.1"),
+            columns: Vec::new(),
+            text_columns: Vec::new(),
+        };
+        let path = std::env::temp_dir().join(format!("read_imagescope_xml_records_{}.csv", std::process::id()));
+        let mut csv = Vec::new();
+        report_csv(std::slice::from_ref(&record), &mut csv).unwrap();
+        fs::write(&path, csv).unwrap();
+        let read = read_records(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), vec![record]);
+    }
+
+    #[test]
+    fn tidy_records_quote_text_fields() {
+        let record = RegionRecord {
+            filename: String::from("case1.xml"),
+            slide_name: String::from("case1.svs"),
+            region_id: String::from("1"),
+            text_label: String::from("Tumor, edge"),
+            positivity: None,
+            num_wpositive: None,
+            num_positive: None,
+            num_spositive: None,
+            num_all_positive: 0.0,
+            num_total: None,
+            algorithm: String::new(),
+            columns: Vec::new(),
+            text_columns: vec![(String::from("qc"), String::from("a,b"))],
+        };
+        let mut tidy = Vec::new();
+        write_tidy_records(&[record], &mut tidy).unwrap();
+        for line in String::from_utf8(tidy).unwrap().lines() {
+            assert_eq!(parse_csv_line(line).len(), 7, "{}", line);
+            assert!(line.starts_with("case1.xml,case1.svs,1,\"Tumor, edge\","));
+        }
+    }
+
+    #[test]
+    fn vertex_records_quote_text_fields() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new().region(RegionBuilder::new().id("1,a").vertices([(0.0, 0.0), (10.0, 5.0)])))
+            .build();
+        let mut vertices = Vec::new();
+        write_vertex_records("case 1, block \"A\".xml", &annotations, &mut vertices).unwrap();
+        let vertices = String::from_utf8(vertices).unwrap();
+        let lines: Vec<Vec<String>> = vertices.lines().map(parse_csv_line).collect();
+        assert_eq!(lines, [
+            ["case 1, block \"A\".xml", "1", "1,a", "0", "0", "0"],
+            ["case 1, block \"A\".xml", "1", "1,a", "1", "10", "5"],
+        ]);
+    }
+}
@@ -0,0 +1,198 @@
+use std::error;
+use serde::Deserialize;
+use crate::extract::RegionInfo;
+use crate::options::Options;
+
+/// Scores derived from the positive pixel counts that can be reported as extra output columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    /// 1 × %weak + 2 × %positive + 3 × %strong, from 0 to 300
+    HScore,
+    /// Allred proportion and intensity scores and their sum, binned by `AllredBins`
+    Allred,
+    /// Weak, positive and strong counts as percentages of the total count
+    Percentages,
+    /// Positivity recomputed from the counts minus the reported positivity, flagged when off by more than
+    /// `Options::positivity_tolerance`
+    PositivityDelta,
+    /// Analyzed, region and positive areas in mm² and positive pixels per mm² of the region, see `density`
+    Density,
+}
+
+/// Cutoffs turning pixel counts into Allred scores
+/// Each score is the number of its cutoffs the value is above. The proportion is the percentage of positive
+/// pixels, so by default none scores 0, up to 1% 1, up to 10% 2, up to 33% 3, up to 66% 4 and more 5. The
+/// intensity is the mean of the positive pixels counting weak as 1, positive as 2 and strong as 3, so by
+/// default below 1.5 scores 1 (weak), below 2.5 2 (intermediate) and higher 3 (strong).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AllredBins {
+    pub proportion_cutoffs: Vec<f64>,
+    pub intensity_cutoffs: Vec<f64>,
+}
+
+impl Default for AllredBins {
+    fn default() -> Self {
+        Self { proportion_cutoffs: vec![0.0, 1.0, 10.0, 100.0 / 3.0, 200.0 / 3.0], intensity_cutoffs: vec![0.0, 1.5, 2.5] }
+    }
+}
+
+impl AllredBins {
+    /// Proportion and intensity score, None without a positive total count
+    pub fn scores(&self, info: &RegionInfo) -> Option<(f64, f64)> {
+        let total = info.num_total().filter(|&total| total > 0.0)?;
+        let (weak, positive, strong) = (info.num_wpositive().unwrap_or(0.0), info.num_positive().unwrap_or(0.0), info.num_spositive().unwrap_or(0.0));
+        let all = weak + positive + strong;
+        let intensity = if all > 0.0 { (weak + 2.0 * positive + 3.0 * strong) / all } else { 0.0 };
+        let bin = |cutoffs: &[f64], value: f64| cutoffs.iter().filter(|&&c| value > c).count() as f64;
+        Some((bin(&self.proportion_cutoffs, 100.0 * all / total), bin(&self.intensity_cutoffs, intensity)))
+    }
+}
+
+impl Score {
+    /// Parse a comma separated list of score names, e.g. `h-score`
+    pub fn parse_list(names: &str) -> Result<Vec<Score>, Box<dyn error::Error>> {
+        names.split(',').map(|name| match name.trim().to_ascii_lowercase().as_str() {
+            "h-score" | "hscore" => Ok(Score::HScore),
+            "allred" => Ok(Score::Allred),
+            "percentages" | "percent" => Ok(Score::Percentages),
+            "positivity-check" | "positivity-delta" => Ok(Score::PositivityDelta),
+            "density" => Ok(Score::Density),
+            other => Err(format!("Unknown score {}", other).into()),
+        }).collect()
+    }
+
+    /// Names of the output columns holding this score
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            Score::HScore => &["h_score"],
+            Score::Allred => &["allred_proportion", "allred_intensity", "allred_score"],
+            Score::Percentages => &["percent_weak", "percent_positive", "percent_strong"],
+            Score::PositivityDelta => &["positivity_delta"],
+            Score::Density => &["analyzed_mm2", "region_mm2", "positive_mm2", "positive_pixels_per_mm2"],
+        }
+    }
+
+    /// Values for each of `columns()`, None without a positive total count or, for the positivity delta, a
+    /// reported positivity
+    /// Percentages are of the total count, and missing weak or strong counts count as 0. Areas need the scan
+    /// resolution `microns_per_pixel`.
+    pub fn values(&self, info: &RegionInfo, options: &Options, microns_per_pixel: Option<f64>) -> Vec<Option<f64>> {
+        let percent = |count: Option<f64>| info.num_total().filter(|&total| total > 0.0).map(|total| 100.0 * count.unwrap_or(0.0) / total);
+        match self {
+            Score::HScore => vec![percent(info.num_wpositive()).zip(percent(info.num_positive())).zip(percent(info.num_spositive()))
+                .map(|((weak, positive), strong)| weak + 2.0 * positive + 3.0 * strong)],
+            Score::Allred => {
+                let scores = options.allred.scores(info);
+                vec![scores.map(|s| s.0), scores.map(|s| s.1), scores.map(|s| s.0 + s.1)]
+            },
+            Score::Percentages => vec![percent(info.num_wpositive()), percent(info.num_positive()), percent(info.num_spositive())],
+            Score::PositivityDelta => vec![info.num_total().filter(|&total| total > 0.0).zip(info.positivity())
+                .map(|(total, positivity)| info.get_total_positive() / total - positivity)],
+            Score::Density => density(info, microns_per_pixel),
+        }
+    }
+}
+
+/// Areas of a region in mm², comparable between scanners at different resolutions
+/// The analyzed and positive areas are the total and positive pixel counts times the pixel area. The region area
+/// is, with the `geometry` feature, the area left after its NegativeROA regions are removed, otherwise its declared
+/// AreaMicrons, or its pixel area without. Positive pixels per mm² are of the region area.
+fn density(info: &RegionInfo, microns_per_pixel: Option<f64>) -> Vec<Option<f64>> {
+    let pixel_mm2 = microns_per_pixel.map(|m| m * m / 1e6);
+    let analyzed = info.num_total().zip(pixel_mm2).map(|(n, a)| n * a);
+    #[cfg(feature = "geometry")]
+    let effective = info.effective_area().zip(pixel_mm2).map(|(n, a)| n * a);
+    #[cfg(not(feature = "geometry"))]
+    let effective = None;
+    let region = effective
+        .or_else(|| info.area_microns().map(|a| a / 1e6))
+        .or_else(|| info.area().zip(pixel_mm2).map(|(n, a)| n * a))
+        .filter(|&a| a > 0.0);
+    let positive = info.num_total().and(pixel_mm2).map(|a| info.get_total_positive() * a);
+    let per_mm2 = info.num_total().and(region).map(|r| info.get_total_positive() / r);
+    vec![analyzed, region, positive, per_mm2]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+    use crate::extract::{FileSummary, RegionInfo, extract_regions_with_summary};
+    use crate::options::Options;
+    use crate::parse::parse_xml_str;
+    use super::{AllredBins, Score};
+
+    /// A 200 × 200 pixel region at 0.5 microns per pixel with Positive Pixel Count results
+    fn region(positivity: f64, nwp: f64, np: f64, nsp: f64, ntotal: f64) -> RegionInfo {
+        let xml = format!(r#"<Annotations MicronsPerPixel="0.5">
+            <Annotation Id="1" Type="4"><Regions>
+                <Region Id="1" Type="0" Area="40000" AreaMicrons="10000" NegativeROA="0"><Vertices>
+                    <Vertex X="0" Y="0"/><Vertex X="200" Y="0"/><Vertex X="200" Y="200"/><Vertex X="0" Y="200"/>
+                </Vertices></Region>
+            </Regions></Annotation>
+            <Annotation Id="2" Name="Positive Pixel Count v9" Type="3"><Regions>
+                <RegionAttributeHeaders>
+                    <AttributeHeader Id="1" Name="Positivity ="/>
+                    <AttributeHeader Id="2" Name="Nwp = Number of Weak Positive"/>
+                    <AttributeHeader Id="3" Name="Np = Number of Positive"/>
+                    <AttributeHeader Id="4" Name="Nsp = Number of Strong Positive"/>
+                    <AttributeHeader Id="5" Name="NTotal = Nwp + Np + Nsp + Nn"/>
+                </RegionAttributeHeaders>
+                <Region Id="2" InputRegionId="1"><Attributes>
+                    <Attribute Name="1" Value="{}"/><Attribute Name="2" Value="{}"/><Attribute Name="3" Value="{}"/>
+                    <Attribute Name="4" Value="{}"/><Attribute Name="5" Value="{}"/>
+                </Attributes></Region>
+            </Regions></Annotation>
+        </Annotations>"#, positivity, nwp, np, nsp, ntotal);
+        let annotations = parse_xml_str(&xml).unwrap();
+        let mut summary = FileSummary::new(path::Path::new("case1.xml"));
+        extract_regions_with_summary(&annotations, &Options::default(), &mut summary).remove("1").unwrap()
+    }
+
+    fn values(score: Score, info: &RegionInfo) -> Vec<Option<f64>> {
+        score.values(info, &Options::default(), Some(0.5))
+    }
+
+    #[test]
+    fn h_score_and_percentages_are_of_the_total() {
+        let info = region(0.6, 10.0, 20.0, 30.0, 100.0);
+        assert_eq!(values(Score::HScore, &info), [Some(140.0)]);
+        assert_eq!(values(Score::Percentages, &info), [Some(10.0), Some(20.0), Some(30.0)]);
+        let empty = region(0.0, 0.0, 0.0, 0.0, 0.0);
+        assert_eq!(values(Score::HScore, &empty), [None]);
+    }
+
+    #[test]
+    fn allred_scores_count_the_cutoffs_exceeded() {
+        // 60% positive scores 4, a mean intensity of 140 / 60 scores 2
+        let info = region(0.6, 10.0, 20.0, 30.0, 100.0);
+        assert_eq!(values(Score::Allred, &info), [Some(4.0), Some(2.0), Some(6.0)]);
+        // Just above a cutoff is the next score, on it is not
+        assert_eq!(values(Score::Allred, &region(0.01, 0.0, 0.0, 1.0, 100.0)), [Some(1.0), Some(3.0), Some(4.0)]);
+        assert_eq!(values(Score::Allred, &region(0.02, 0.0, 0.0, 2.0, 100.0)), [Some(2.0), Some(3.0), Some(5.0)]);
+        assert_eq!(values(Score::Allred, &region(0.0, 0.0, 0.0, 0.0, 100.0)), [Some(0.0), Some(0.0), Some(0.0)]);
+        let bins = AllredBins { proportion_cutoffs: vec![50.0], intensity_cutoffs: vec![2.0] };
+        assert_eq!(bins.scores(&info), Some((1.0, 1.0)));
+        assert_eq!(bins.scores(&region(0.0, 0.0, 0.0, 0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn positivity_delta_compares_the_counts_with_the_reported_positivity() {
+        assert_eq!(values(Score::PositivityDelta, &region(0.6, 10.0, 20.0, 30.0, 100.0)), [Some(0.0)]);
+        let delta = values(Score::PositivityDelta, &region(0.5, 10.0, 20.0, 30.0, 100.0))[0].unwrap();
+        assert!((delta - 0.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn density_is_in_square_millimeters() {
+        let info = region(0.6, 10.0, 20.0, 30.0, 100.0);
+        let density = values(Score::Density, &info);
+        // A pixel is 0.25 µm², the region 10000 µm²
+        let expected = [2.5e-5, 0.01, 1.5e-5, 6000.0];
+        for (value, expected) in density.iter().zip(expected) {
+            assert!((value.unwrap() - expected).abs() < expected * 1e-9, "{:?}", density);
+        }
+        // Areas need the scan resolution
+        assert_eq!(Score::Density.values(&info, &Options::default(), None)[0], None);
+    }
+}
@@ -0,0 +1,162 @@
+use std::error;
+use serde::Deserialize;
+use crate::builder::{AnnotationBuilder, AnnotationsBuilder, DEFAULT_LINE_COLOR, RegionBuilder};
+use crate::encoding::decode_xml;
+use crate::model::{Annotations, RegionType};
+use crate::parse::{decompress, root_element, skip_leading_junk};
+use crate::render::hex_to_bgr;
+
+/// Root `session` element of a Sedeen `.session.xml` file
+#[derive(Deserialize, Debug, Default)]
+struct SedeenSession {
+    #[serde(rename = "image", default)]
+    image: Vec<SedeenImage>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct SedeenImage {
+    #[serde(rename = "overlays", default)]
+    overlays: SedeenOverlays,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct SedeenOverlays {
+    #[serde(rename = "graphic", default)]
+    graphic: Vec<SedeenGraphic>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SedeenGraphic {
+    #[serde(rename = "@type", default)]
+    graphic_type: String,
+    #[serde(rename = "@name", default)]
+    name: String,
+    #[serde(rename = "pen", default)]
+    pen: SedeenPen,
+    #[serde(rename = "point-list", default)]
+    point_list: SedeenPointList,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct SedeenPen {
+    #[serde(rename = "@color", default)]
+    color: String,
+}
+
+/// Points are written as `x,y` text
+#[derive(Deserialize, Debug, Default)]
+struct SedeenPointList {
+    #[serde(rename = "point", default)]
+    point: Vec<String>,
+}
+
+/// True if the XML is a Sedeen session rather than ImageScope annotations
+pub(crate) fn is_sedeen_session(xml: &str) -> bool {
+    root_element(xml) == Some("session")
+}
+
+/// Read a Sedeen `.session.xml` file
+/// Sedeen has no layers, so graphics are grouped into one user-drawn layer per pen color, named after the
+/// color. Regions carry the graphic name as their text. Rectangles and ellipses keep their two corners as in
+/// ImageScope, and points and text are skipped.
+/// Returns the annotations and a description of every graphic skipped.
+pub fn import_sedeen_session(contents: &[u8]) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+    let bytes = decompress(contents)?;
+    let xml = decode_xml(&bytes)?;
+    parse_sedeen_str(&xml)
+}
+
+/// Convert a Sedeen session already held in memory into the pre-defined structure
+/// Returns the annotations and a description of every graphic skipped.
+pub(crate) fn parse_sedeen_str(xml: &str) -> Result<(Annotations, Vec<String>), Box<dyn error::Error>> {
+    let session: SedeenSession = quick_xml::de::from_str(skip_leading_junk(xml))?;
+    let mut layers: Vec<(String, Vec<RegionBuilder>)> = Vec::new();
+    let mut skipped = Vec::new();
+    for graphic in session.image.into_iter().flat_map(|image| image.overlays.graphic) {
+        let region_type = match graphic.graphic_type.to_ascii_lowercase().as_str() {
+            "polygon" => RegionType::Freehand,
+            "rectangle" => RegionType::Rectangle,
+            "ellipse" => RegionType::Ellipse,
+            "polyline" => RegionType::Polyline,
+            "line" | "ruler" => RegionType::Ruler,
+            other => {
+                skipped.push(format!("Skipping Sedeen {} graphic {:?}, ImageScope has no matching region type", other, graphic.name));
+                continue;
+            },
+        };
+        let mut points = Vec::with_capacity(graphic.point_list.point.len());
+        for point in &graphic.point_list.point {
+            let coordinates = point.split_once(',').and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)));
+            match coordinates {
+                Some(point) => points.push(point),
+                None => return Err(format!("Invalid point {:?} in Sedeen graphic {:?}", point, graphic.name).into()),
+            }
+        }
+        let color = graphic.pen.color.trim().to_ascii_lowercase();
+        let position = match layers.iter().position(|(c, _)| *c == color) {
+            Some(position) => position,
+            None => {
+                layers.push((color, Vec::new()));
+                layers.len() - 1
+            },
+        };
+        layers[position].1.push(RegionBuilder::new().region_type(region_type).text(graphic.name).vertices(points));
+    }
+
+    let mut builder = AnnotationsBuilder::new();
+    for (color, regions) in layers {
+        let mut layer = AnnotationBuilder::new().name(color.clone()).line_color(hex_to_bgr(&color).unwrap_or(DEFAULT_LINE_COLOR));
+        for region in regions {
+            layer = layer.region(region);
+        }
+        builder = builder.layer(layer);
+    }
+    Ok((builder.build(), skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::RegionType;
+    use crate::render::layer_color;
+    use super::import_sedeen_session;
+
+    #[test]
+    fn graphics_are_grouped_by_pen_color() {
+        let xml = r##"<?xml version="1.0"?>
+            <session software="Sedeen Viewer" version="5.4">
+                <image identifier="case1.svs">
+                    <overlays>
+                        <graphic type="polygon" name="Tumor">
+                            <pen color="#FF0000" width="3"/>
+                            <point-list><point>0,0</point><point>10.5,0</point><point>10.5,10</point></point-list>
+                        </graphic>
+                        <graphic type="rectangle" name="Box">
+                            <pen color="#0000ff" width="1"/>
+                            <point-list><point>2,2</point><point>4,4</point></point-list>
+                        </graphic>
+                        <graphic type="line" name="Ruler">
+                            <pen color="#ff0000"/>
+                            <point-list><point>0, 0</point><point>5, 5</point></point-list>
+                        </graphic>
+                        <graphic type="text" name="Note">
+                            <pen color="#ff0000"/>
+                            <point-list><point>1,1</point></point-list>
+                        </graphic>
+                    </overlays>
+                </image>
+            </session>"##;
+        let (annotations, skipped) = import_sedeen_session(xml.as_bytes()).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert!(skipped[0].contains("text"), "{}", skipped[0]);
+        let names: Vec<&str> = annotations.annotation.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["#ff0000", "#0000ff"]);
+        assert_eq!(layer_color(&annotations.annotation[0]).as_deref(), Some("#ff0000"));
+        assert_eq!(layer_color(&annotations.annotation[1]).as_deref(), Some("#0000ff"));
+        let red = &annotations.annotation[0].regions.region;
+        assert_eq!((&red[0].region_type, red[0].label()), (&RegionType::Freehand, String::from("Tumor")));
+        assert_eq!(red[0].outline(), [(0.0, 0.0), (10.5, 0.0), (10.5, 10.0)]);
+        assert_eq!((&red[1].region_type, red[1].label()), (&RegionType::Ruler, String::from("Ruler")));
+        let blue = &annotations.annotation[1].regions.region[0];
+        assert_eq!((&blue.region_type, blue.outline()), (&RegionType::Rectangle, vec![(2.0, 2.0), (4.0, 2.0), (4.0, 4.0), (2.0, 4.0)]));
+    }
+}
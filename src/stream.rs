@@ -0,0 +1,215 @@
+use std::{error, io, path};
+use futures::stream::{self, Stream, StreamExt};
+use crate::extract::{FileSummary, RegionRecord};
+use crate::options::Options;
+use crate::report::{OutputFormat, TIDY_HEADER, VERTICES_HEADER, write_csv_header_with_columns, write_csv_records_with_columns, write_tidy_records, write_vertex_records};
+use crate::input::{XmlSource, xml_sources};
+use crate::{process_source, read_source};
+
+/// Process documents on the blocking thread pool, at most `concurrency` at a time
+/// Results are yielded in the same order as `sources`
+pub fn file_stream(sources: Vec<XmlSource>, options: Options, concurrency: usize) -> impl Stream<Item = (Vec<RegionRecord>, FileSummary)> {
+    stream::iter(sources)
+        .map(move |source| {
+            let options = options.clone();
+            async move {
+                let fallback = source.path.clone();
+                match tokio::task::spawn_blocking(move || process_source(&source, &options)).await {
+                    Ok(result) => result,
+                    // The worker panicked, report the file as having no regions
+                    Err(e) => {
+                        let mut summary = FileSummary::new(&fallback);
+                        summary.fail(format!("Error processing {}: {}", fallback.display(), e));
+                        (Vec::new(), summary)
+                    },
+                }
+            }
+        })
+        .buffered(concurrency.max(1))
+}
+
+/// Stream the records of all `sources`, processing at most `concurrency` documents at a time
+pub fn extract_stream(sources: Vec<XmlSource>, options: Options, concurrency: usize) -> impl Stream<Item = RegionRecord> {
+    file_stream(sources, options, concurrency).flat_map(|(records, _summary)| stream::iter(records))
+}
+
+/// Async version of `run_to_writer`, processing at most `concurrency` files at a time
+/// Must be called from within a tokio runtime. Finding, downloading and parsing the files happens on the
+/// blocking thread pool, but `writer` is written to directly and should not block for long, e.g. a buffer or file.
+pub async fn run_async<W: io::Write>(search_path: &path::Path, options: &Options, writer: &mut W, concurrency: usize) -> Result<Vec<FileSummary>, Box<dyn error::Error>> {
+    if options.format == OutputFormat::VerticesCsv {
+        return run_vertices_async(search_path, options, writer, concurrency).await;
+    }
+    let mut summaries = Vec::new();
+    let tidy = options.format == OutputFormat::Tidy;
+    if tidy {
+        writeln!(writer, "{}", TIDY_HEADER)?;
+    } else {
+        write_csv_header_with_columns(writer, &options.output_column_names())?;
+    }
+    let sources = find_sources(search_path, options).await?;
+    let mut files = Box::pin(file_stream(sources, options.clone(), concurrency));
+    while let Some((records, summary)) = files.next().await {
+        if tidy {
+            write_tidy_records(&records, writer)?;
+        } else {
+            write_csv_records_with_columns(&records, &options.text_column_names(), writer)?;
+        }
+        summaries.push(summary);
+    }
+    Ok(summaries)
+}
+
+/// Async version of writing the vertices of every XML file in `search_path`, reading at most `concurrency` at a time
+async fn run_vertices_async<W: io::Write>(search_path: &path::Path, options: &Options, writer: &mut W, concurrency: usize) -> Result<Vec<FileSummary>, Box<dyn error::Error>> {
+    let mut summaries = Vec::new();
+    writeln!(writer, "{}", VERTICES_HEADER)?;
+    let sources = find_sources(search_path, options).await?;
+    let mut files = stream::iter(sources)
+        .map(|source| {
+            let options = options.clone();
+            async move {
+                let fallback = source.path.clone();
+                let read = tokio::task::spawn_blocking(move || {
+                    let mut summary = source.summary();
+                    let annotations = read_source(&source, &options, &mut summary);
+                    (source.filename, annotations, summary)
+                }).await;
+                read.unwrap_or_else(|e| {
+                    // The worker panicked, report the file as having no vertices
+                    let mut summary = FileSummary::new(&fallback);
+                    summary.fail(format!("Error processing {}: {}", fallback.display(), e));
+                    (String::new(), None, summary)
+                })
+            }
+        })
+        .buffered(concurrency.max(1));
+    while let Some((filename, annotations, summary)) = files.next().await {
+        if let Some(annotations) = annotations {
+            write_vertex_records(&filename, &annotations, writer)?;
+        }
+        summaries.push(summary);
+    }
+    Ok(summaries)
+}
+
+/// Find the documents in `search_path` on the blocking thread pool
+/// Listing folders, archives and object storage blocks, and so does downloading URLs
+async fn find_sources(search_path: &path::Path, options: &Options) -> Result<Vec<XmlSource>, Box<dyn error::Error>> {
+    let (path, discovery_options) = (search_path.to_path_buf(), options.clone());
+    Ok(tokio::task::spawn_blocking(move || xml_sources(&path, &discovery_options).map_err(|e| e.to_string())).await??)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use futures::StreamExt;
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::options::Options;
+    use crate::report::OutputFormat;
+    use crate::input::xml_sources;
+    use crate::run_to_writer;
+    use super::{file_stream, run_async};
+
+    /// Run a future to completion on a fresh runtime
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn async_reports_match_the_blocking_ones() {
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_async_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["case1", "case2", "case3"] {
+            let annotations = AnnotationsBuilder::new().layer(AnnotationBuilder::new().region(RegionBuilder::new().text(name).vertices([(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]))).build();
+            fs::write(dir.join(format!("{}.xml", name)), annotations.to_xml_string().unwrap()).unwrap();
+        }
+        let mut outputs = Vec::new();
+        for format in [OutputFormat::Csv, OutputFormat::Tidy, OutputFormat::VerticesCsv] {
+            let options = Options { format, ..Options::default() };
+            let (mut blocking, mut streamed) = (Vec::new(), Vec::new());
+            run_to_writer(&dir, &options, &mut blocking).unwrap();
+            let summaries = block_on(run_async(&dir, &options, &mut streamed, 2)).unwrap();
+            outputs.push((String::from_utf8(blocking).unwrap(), String::from_utf8(streamed).unwrap(), summaries.len()));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        for (blocking, streamed, files) in outputs {
+            assert_eq!(streamed, blocking);
+            assert_eq!(files, 3);
+        }
+    }
+
+
+    /// Write a document with `regions` regions labelled after its name for each of `names` into `dir`
+    fn write_fixtures(dir: &std::path::Path, names: &[&str], regions: impl Fn(usize) -> usize) {
+        for (i, name) in names.iter().enumerate() {
+            let mut layer = AnnotationBuilder::new();
+            for _ in 0..regions(i) {
+                layer = layer.region(RegionBuilder::new().text(*name).vertices([(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]));
+            }
+            fs::write(dir.join(format!("{}.xml", name)), AnnotationsBuilder::new().layer(layer).build().to_xml_string().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn files_are_yielded_in_source_order() {
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_file_stream_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let names: Vec<String> = (0..12).map(|i| format!("case{:02}", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        // The first files take longest, so later ones tend to finish first
+        write_fixtures(&dir, &names, |i| 400 / (i + 1));
+        fs::write(dir.join("case05.xml"), "<Annotations><Annotation").unwrap();
+        let mut results = Vec::new();
+        for concurrency in [0, 1, 4, 64] {
+            let sources = xml_sources(&dir, &Options::default()).unwrap();
+            let files: Vec<_> = block_on(file_stream(sources, Options::default(), concurrency).collect());
+            results.push(files);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        for files in results {
+            let order: Vec<String> = files.iter().map(|(_, summary)| summary.path.file_stem().unwrap().to_string_lossy().to_string()).collect();
+            assert_eq!(order, names);
+            let labels: Vec<(usize, Option<&str>)> = files.iter().map(|(records, _)| (records.len(), records.first().map(|r| r.text_label.as_str()))).collect();
+            for (i, (count, label)) in labels.into_iter().enumerate() {
+                // The broken file fails on its own
+                let expected = if i == 5 { (0, None) } else { (400 / (i + 1), Some(names[i])) };
+                assert_eq!((count, label), expected);
+            }
+            assert_eq!(files.iter().filter(|(_, summary)| summary.failed()).count(), 1);
+        }
+    }
+
+    /// FIFOs cannot be memory-mapped
+    #[cfg(all(unix, not(feature = "mmap")))]
+    #[test]
+    fn files_are_read_concurrently() {
+        use std::{sync::mpsc, thread, time};
+        let dir = std::env::temp_dir().join(format!("read_imagescope_xml_file_stream_fifo_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let fifos: Vec<std::path::PathBuf> = ["case1", "case2", "case3"].iter().map(|name| dir.join(format!("{}.xml", name))).collect();
+        for fifo in &fifos {
+            assert!(std::process::Command::new("mkfifo").arg(fifo).status().unwrap().success());
+        }
+        let sources = xml_sources(&dir, &Options::default()).unwrap();
+        // Opening a FIFO for writing waits for its reader, so writing the last one first only finishes if all are
+        // being read at once
+        let writer_fifos = fifos.clone();
+        thread::spawn(move || {
+            for fifo in writer_fifos.iter().rev() {
+                let label = fifo.file_stem().unwrap().to_string_lossy().to_string();
+                let annotations = AnnotationsBuilder::new().layer(AnnotationBuilder::new().region(RegionBuilder::new().text(label).vertices([(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]))).build();
+                fs::write(fifo, annotations.to_xml_string().unwrap()).unwrap();
+            }
+        });
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let files: Vec<_> = block_on(file_stream(sources, Options::default(), 3).collect());
+            sender.send(files).unwrap();
+        });
+        let files = receiver.recv_timeout(time::Duration::from_secs(20));
+        fs::remove_dir_all(&dir).unwrap();
+        let labels: Vec<String> = files.expect("All three files should be read at once").into_iter().flat_map(|(records, _)| records).map(|r| r.text_label).collect();
+        assert_eq!(labels, ["case1", "case2", "case3"]);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::model::{Annotations, Extra};
+
+/// Attributes and child elements ImageScope writes that we do not otherwise use, per element
+const ANNOTATION_EXTRA: &[&str] = &["@ReadOnly", "@NameReadOnly", "@LineColorReadOnly", "@Incremental", "@LineColor", "@Visible", "@Selected", "@MarkupImagePath", "@MacroName", "Plots"];
+const ATTRIBUTE_HEADER_EXTRA: &[&str] = &["@ColumnWidth"];
+const REGION_EXTRA: &[&str] = &["@Zoom", "@Selected", "@ImageFocus", "@DisplayId"];
+
+/// Check that a document only holds what ImageScope itself writes
+/// Our structures keep unknown attributes and elements for round trips, which rules out serde's
+/// `deny_unknown_fields`, so everything left over is checked here instead.
+/// Returns a description of each violation, empty if the document is canonical.
+pub fn check_strict(annotations: &Annotations) -> Vec<String> {
+    let mut violations = Vec::new();
+    unexpected(&mut violations, "Annotations", &annotations.extra, &[]);
+    for layer in &annotations.annotation {
+        let at = format!("Annotation {}", layer.id);
+        unexpected(&mut violations, &at, &layer.extra, ANNOTATION_EXTRA);
+        unexpected(&mut violations, &format!("{} Attributes", at), &layer.attributes.extra, &[]);
+        for attribute in layer.attributes.attribute.iter().flatten() {
+            unexpected(&mut violations, &format!("{} Attribute {}", at, attribute.name), &attribute.extra, &[]);
+        }
+        unexpected(&mut violations, &format!("{} Regions", at), &layer.regions.extra, &[]);
+        let headers = &layer.regions.region_attribute_headers;
+        unexpected(&mut violations, &format!("{} RegionAttributeHeaders", at), &headers.extra, &[]);
+        for header in headers.attribute_header.iter().flatten() {
+            unexpected(&mut violations, &format!("{} AttributeHeader {}", at, header.id), &header.extra, ATTRIBUTE_HEADER_EXTRA);
+        }
+        if layer.annotation_type == "3" && headers.attribute_header.is_none() {
+            violations.push(format!("{}: analysis layer has no RegionAttributeHeaders", at));
+        }
+        for region in &layer.regions.region {
+            let at = format!("{} Region {}", at, region.id);
+            unexpected(&mut violations, &at, &region.extra, REGION_EXTRA);
+            unexpected(&mut violations, &format!("{} Attributes", at), &region.attributes.extra, &[]);
+            for attribute in region.attributes.attribute.iter().flatten() {
+                unexpected(&mut violations, &format!("{} Attribute {}", at, attribute.name), &attribute.extra, &[]);
+            }
+            if let Some(vertices) = &region.vertices {
+                unexpected(&mut violations, &format!("{} Vertices", at), &vertices.extra, &[]);
+                for vertex in &vertices.vertex {
+                    unexpected(&mut violations, &format!("{} Vertex", at), &vertex.extra, &[]);
+                }
+            }
+        }
+    }
+    violations
+}
+
+/// Record every entry of `extra` that is not in `allowed`
+fn unexpected(violations: &mut Vec<String>, at: &str, extra: &Extra, allowed: &[&str]) {
+    for name in extra.keys().filter(|name| !allowed.contains(&name.as_str())) {
+        violations.push(format!("{}: unexpected {}", at, describe(name)));
+    }
+}
+
+/// Describe an attribute (`@Name`) or element (`Name`) for a violation message
+fn describe(name: &str) -> String {
+    match name.strip_prefix('@') {
+        Some(attribute) => format!("attribute {}", attribute),
+        None => format!("element <{}>", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_xml_str;
+
+    const EXPORT: &str = include_str!("../tests/data/imagescope_export.xml");
+
+    #[test]
+    fn imagescope_export_is_canonical() {
+        assert_eq!(check_strict(&parse_xml_str(EXPORT).unwrap()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unknown_attributes_and_elements_are_reported_where_they_are() {
+        let xml = EXPORT
+            .replacen("<Annotations ", "<Annotations Software=\"other\" ", 1)
+            .replacen("<Plots/>", "<Plots/><Notes/>", 1)
+            .replacen("Text=\"necrosis\"", "Text=\"necrosis\" Confidence=\"0.9\"", 1)
+            .replacen("<Vertex X=\"20000\" Y=\"4000\"/>", "<Vertex X=\"20000\" Y=\"4000\" W=\"1\"/>", 1);
+        assert_eq!(check_strict(&parse_xml_str(&xml).unwrap()), [
+            "Annotations: unexpected attribute Software",
+            "Annotation 1: unexpected element <Notes>",
+            "Annotation 1 Region 2: unexpected attribute Confidence",
+            "Annotation 2 Region 3 Vertex: unexpected attribute W",
+        ]);
+    }
+
+    #[test]
+    fn analysis_layers_need_headers() {
+        let xml = r#"<Annotations><Annotation Id="5" Type="3"><Regions/></Annotation><Annotation Id="6" Type="4"><Regions/></Annotation></Annotations>"#;
+        assert_eq!(check_strict(&parse_xml_str(xml).unwrap()), ["Annotation 5: analysis layer has no RegionAttributeHeaders"]);
+    }
+}
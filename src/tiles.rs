@@ -0,0 +1,107 @@
+use serde::Serialize;
+use crate::convert::ExportRegion;
+use crate::geometry::{BoundingBox, Point, intersection_area, polygon_contains_box};
+use crate::report::csv_field;
+
+/// Column names of the tile manifest
+pub const TILES_HEADER: &str = "Region ID,text label,x,y,size";
+
+/// A square tile lying entirely inside a region, in level-0 pixels
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Tile {
+    pub region_id: String,
+    /// Text label of the region the tile lies in
+    pub label: String,
+    /// Top left corner
+    pub x: f64,
+    pub y: f64,
+    pub size: f64,
+}
+
+/// Tiles of a grid of `size` pixels starting at the slide origin that lie entirely inside a closed region
+/// Only regions with one of `labels` are tiled, all if empty. Tiles touching a NegativeROA region are left out.
+pub fn tile_regions(regions: &[ExportRegion], size: f64, labels: &[String]) -> Vec<Tile> {
+    let closed = |r: &&ExportRegion| r.region.region_type.is_closed() && r.region.outline().len() >= 3;
+    let excluded: Vec<Vec<Point>> = regions.iter().filter(closed).filter(|r| r.region.is_negative()).map(|r| r.region.outline()).collect();
+    let mut tiles = Vec::new();
+    for r in regions.iter().filter(closed).filter(|r| !r.region.is_negative()) {
+        let label = r.region.label();
+        if !labels.is_empty() && !labels.contains(&label) {
+            continue;
+        }
+        let outline = r.region.outline();
+        let Some(bbox) = BoundingBox::of(&outline) else { continue };
+        let (first_column, last_column) = ((bbox.min_x / size).floor() as i64, (bbox.max_x / size).ceil() as i64);
+        let (first_row, last_row) = ((bbox.min_y / size).floor() as i64, (bbox.max_y / size).ceil() as i64);
+        for row in first_row..last_row {
+            for column in first_column..last_column {
+                let (x, y) = (column as f64 * size, row as f64 * size);
+                let tile = BoundingBox { min_x: x, min_y: y, max_x: x + size, max_y: y + size };
+                if !polygon_contains_box(&outline, &tile) {
+                    continue;
+                }
+                let corners = [(x, y), (x + size, y), (x + size, y + size), (x, y + size)];
+                if excluded.iter().any(|hole| intersection_area(hole, &corners) > 0.0) {
+                    continue;
+                }
+                tiles.push(Tile { region_id: r.region.id.clone(), label: label.clone(), x, y, size });
+            }
+        }
+    }
+    tiles
+}
+
+/// CSV with one line per tile
+pub fn tiles_csv(tiles: &[Tile]) -> String {
+    let mut csv = format!("{}\n", TILES_HEADER);
+    for t in tiles {
+        csv.push_str(&format!("{},{},{},{},{}\n", csv_field(&t.region_id), csv_field(&t.label), t.x, t.y, t.size));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::model::RegionType;
+    use super::{Tile, tile_regions, tiles_csv};
+
+    fn rectangle(text: &str, from: (f64, f64), to: (f64, f64)) -> RegionBuilder {
+        RegionBuilder::new().region_type(RegionType::Rectangle).text(text).vertices([from, to])
+    }
+
+    #[test]
+    fn only_tiles_fully_inside_are_kept() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(rectangle("Tumor", (0.0, 0.0), (35.0, 25.0)))
+                .region(rectangle("Stroma", (50.0, 0.0), (70.0, 20.0)))
+                .region(RegionBuilder::new().region_type(RegionType::Ruler).text("Tumor").vertices([(0.0, 0.0), (100.0, 100.0)])))
+            .build();
+        let regions = export_regions(&annotations, &[]);
+        let tiles = tile_regions(&regions, 10.0, &[String::from("Tumor")]);
+        let corners: Vec<(f64, f64)> = tiles.iter().map(|t| (t.x, t.y)).collect();
+        assert_eq!(corners, [(0.0, 0.0), (10.0, 0.0), (20.0, 0.0), (0.0, 10.0), (10.0, 10.0), (20.0, 10.0)]);
+        assert!(tiles.iter().all(|t| t.region_id == "1" && t.label == "Tumor" && t.size == 10.0));
+        // Without labels every closed region is tiled
+        assert_eq!(tile_regions(&regions, 10.0, &[]).len(), 10);
+    }
+
+    #[test]
+    fn tiles_touching_a_hole_are_left_out() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(rectangle("Tumor", (0.0, 0.0), (30.0, 20.0)))
+                .region(rectangle("", (12.0, 2.0), (14.0, 4.0)).negative_roa(true))
+                // Only shares an edge with its neighbours
+                .region(rectangle("", (20.0, 10.0), (30.0, 20.0)).negative_roa(true)))
+            .build();
+        let regions = export_regions(&annotations, &[]);
+        let tiles = tile_regions(&regions, 10.0, &[]);
+        let corners: Vec<(f64, f64)> = tiles.iter().map(|t| (t.x, t.y)).collect();
+        assert_eq!(corners, [(0.0, 0.0), (20.0, 0.0), (0.0, 10.0), (10.0, 10.0)]);
+        let tile = Tile { region_id: String::from("1"), label: String::from("Tumor, A"), x: 0.0, y: 10.0, size: 10.0 };
+        assert_eq!(tiles_csv(&[tile]), "Region ID,text label,x,y,size\n1,\"Tumor, A\",0,10,10\n");
+    }
+}
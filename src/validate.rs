@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+use std::{error, fmt, path};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use crate::encoding::decode_xml;
+use crate::input::xml_sources;
+use crate::options::Options;
+use crate::parse::{decompress, skip_leading_junk};
+
+/// Schema for ImageScope annotation files, used when no other schema is given
+const BUNDLED_SCHEMA: &str = include_str!("../schema/imagescope-annotations.xsd");
+
+/// A XML schema (XSD) to validate annotation files against
+/// Only the parts of XSD needed to describe ImageScope files are supported: elements with occurrence
+/// limits, named and anonymous complex types, attributes of the built-in string, integer, decimal and
+/// boolean types, `xs:any` and `xs:anyAttribute`. The order of child elements is not checked.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    elements: Vec<ElementDecl>,
+    types: HashMap<String, ComplexType>,
+}
+
+/// An element allowed at some position
+#[derive(Debug, Clone)]
+struct ElementDecl {
+    name: String,
+    min: u32,
+    /// None if unbounded
+    max: Option<u32>,
+    content: Content,
+}
+
+/// What an element may contain
+#[derive(Debug, Clone)]
+enum Content {
+    /// Text only
+    Simple(SimpleType),
+    /// Attributes and child elements
+    Complex(ComplexType),
+    /// A top-level complex type
+    Named(String),
+}
+
+/// Allowed attributes and child elements
+#[derive(Debug, Clone, Default)]
+struct ComplexType {
+    attributes: Vec<AttributeDecl>,
+    any_attribute: bool,
+    children: Vec<ElementDecl>,
+    any_children: bool,
+}
+
+/// An allowed attribute
+#[derive(Debug, Clone)]
+struct AttributeDecl {
+    name: String,
+    required: bool,
+    simple_type: SimpleType,
+}
+
+/// Built-in types of attribute values and text
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SimpleType {
+    String,
+    Integer,
+    Decimal,
+    Boolean,
+}
+
+impl SimpleType {
+    /// Type named in a `type` attribute, anything unknown is treated as a string
+    fn from_name(name: &str) -> Self {
+        match name.rsplit(':').next().unwrap_or(name) {
+            "integer" | "int" | "long" | "short" | "nonNegativeInteger" | "positiveInteger" | "unsignedInt" => SimpleType::Integer,
+            "decimal" | "double" | "float" => SimpleType::Decimal,
+            "boolean" => SimpleType::Boolean,
+            _ => SimpleType::String,
+        }
+    }
+
+    /// True if `value` is valid for this type
+    fn accepts(&self, value: &str) -> bool {
+        let value = value.trim();
+        match self {
+            SimpleType::String => true,
+            SimpleType::Integer => value.parse::<i64>().is_ok(),
+            SimpleType::Decimal => value.parse::<f64>().is_ok_and(f64::is_finite),
+            SimpleType::Boolean => matches!(value, "0" | "1" | "true" | "false"),
+        }
+    }
+}
+
+/// A minimal element tree of the schema document
+#[derive(Debug, Default)]
+struct Node {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+}
+
+impl Schema {
+    /// The bundled ImageScope annotations schema
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_SCHEMA).expect("Bundled schema should be valid")
+    }
+
+    /// Read a schema from XSD text
+    pub fn parse(xsd: &str) -> Result<Self, Box<dyn error::Error>> {
+        let root = schema_tree(xsd)?;
+        if root.name != "schema" {
+            return Err(format!("Expected a <xs:schema> root element, found <{}>", root.name).into());
+        }
+        let mut schema = Schema { elements: Vec::new(), types: HashMap::new() };
+        for node in &root.children {
+            match node.name.as_str() {
+                "element" => schema.elements.push(element_decl(node)?),
+                "complexType" => {
+                    let name = node.attribute("name").ok_or("Top-level complexType without a name")?;
+                    schema.types.insert(name.to_string(), complex_type(node)?);
+                },
+                _ => {},
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Resolve the content of an element to a complex type, None for simple content
+    fn complex<'a>(&'a self, content: &'a Content) -> Result<Option<&'a ComplexType>, String> {
+        match content {
+            Content::Simple(_) => Ok(None),
+            Content::Complex(complex) => Ok(Some(complex)),
+            Content::Named(name) => self.types.get(name).map(Some).ok_or(format!("schema has no complexType named {}", name)),
+        }
+    }
+}
+
+/// Read the schema document into a tree, keeping only local element names
+fn schema_tree(xsd: &str) -> Result<Node, Box<dyn error::Error>> {
+    let mut reader = Reader::from_str(xsd);
+    let mut stack: Vec<Node> = vec![Node::default()];
+    loop {
+        match reader.read_event()? {
+            Event::Start(tag) => stack.push(schema_node(&tag)?),
+            Event::Empty(tag) => {
+                let node = schema_node(&tag)?;
+                stack.last_mut().expect("Stack always holds the document").children.push(node);
+            },
+            Event::End(_) => {
+                let node = stack.pop().expect("Reader checks that end tags match");
+                stack.last_mut().ok_or("Unbalanced schema document")?.children.push(node);
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+    let mut document = stack.pop().ok_or("Unbalanced schema document")?;
+    document.children.pop().ok_or_else(|| "Empty schema document".into())
+}
+
+/// Turn a schema start tag into a node without children
+fn schema_node(tag: &BytesStart) -> Result<Node, Box<dyn error::Error>> {
+    let mut attributes = HashMap::new();
+    for attribute in tag.attributes() {
+        let attribute = attribute?;
+        attributes.insert(String::from_utf8_lossy(attribute.key.local_name().as_ref()).to_string(), attribute.unescape_value()?.to_string());
+    }
+    Ok(Node { name: String::from_utf8_lossy(tag.local_name().as_ref()).to_string(), attributes, children: Vec::new() })
+}
+
+/// Read a `xs:element` declaration
+fn element_decl(node: &Node) -> Result<ElementDecl, Box<dyn error::Error>> {
+    let name = node.attribute("name").ok_or("Element declaration without a name")?.to_string();
+    let min = node.attribute("minOccurs").map(str::parse).transpose()?.unwrap_or(1);
+    let max = match node.attribute("maxOccurs") {
+        Some("unbounded") => None,
+        Some(max) => Some(max.parse()?),
+        None => Some(1),
+    };
+    let content = match (node.attribute("type"), node.children.iter().find(|c| c.name == "complexType")) {
+        (_, Some(complex)) => Content::Complex(complex_type(complex)?),
+        (Some(type_name), None) if type_name.contains(':') => Content::Simple(SimpleType::from_name(type_name)),
+        (Some(type_name), None) => Content::Named(type_name.to_string()),
+        // No type at all allows anything, treat it as text
+        (None, None) => Content::Simple(SimpleType::String),
+    };
+    Ok(ElementDecl { name, min, max, content })
+}
+
+/// Read a `xs:complexType` definition
+fn complex_type(node: &Node) -> Result<ComplexType, Box<dyn error::Error>> {
+    let mut complex = ComplexType::default();
+    for child in &node.children {
+        match child.name.as_str() {
+            "attribute" => complex.attributes.push(AttributeDecl {
+                name: child.attribute("name").ok_or("Attribute declaration without a name")?.to_string(),
+                required: child.attribute("use") == Some("required"),
+                simple_type: SimpleType::from_name(child.attribute("type").unwrap_or("xs:string")),
+            }),
+            "anyAttribute" => complex.any_attribute = true,
+            // Order is not checked, so all groups are read the same way
+            "sequence" | "all" | "choice" => {
+                for particle in &child.children {
+                    match particle.name.as_str() {
+                        "element" => complex.children.push(element_decl(particle)?),
+                        "any" => complex.any_children = true,
+                        _ => {},
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(complex)
+}
+
+/// A place where a document does not match the schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// What is known about an element while its content is read
+enum Frame<'a> {
+    /// Complex content with the number of each child element seen so far
+    Complex { name: String, line: usize, complex: &'a ComplexType, counts: HashMap<String, u32> },
+    /// Text content of the given type
+    Simple { name: String, simple_type: SimpleType },
+    /// Unknown or `xs:any` content, not checked
+    Skip,
+}
+
+/// Check a XML document against `schema`
+/// Returns every violation found, empty if the document is valid
+pub fn validate_xml(xml: &str, schema: &Schema) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<Frame> = Vec::new();
+    // Line number of the current position, counted incrementally
+    let (mut line, mut counted) = (1, 0);
+    let mut line_at = |position: usize| {
+        line += xml.as_bytes()[counted..position].iter().filter(|&&b| b == b'\n').count();
+        counted = position;
+        line
+    };
+    let mut seen_root = false;
+    loop {
+        let before = reader.buffer_position() as usize;
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(e) => {
+                let line = line_at(reader.error_position() as usize);
+                violations.push(Violation { line, message: format!("XML is not well-formed: {}", e) });
+                break;
+            },
+        };
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(tag) | Event::Empty(tag) => {
+                // The reader position is past the tag, which may span lines, so report its start
+                let start = before + xml[before..].find('<').unwrap_or(0);
+                let line = line_at(start);
+                let name = String::from_utf8_lossy(tag.local_name().as_ref()).to_string();
+                let decl = match stack.last_mut() {
+                    None if seen_root => None,
+                    None => {
+                        seen_root = true;
+                        let decl = schema.elements.iter().find(|e| e.name == name);
+                        if decl.is_none() {
+                            violations.push(Violation { line, message: format!("unexpected root element <{}>", name) });
+                        }
+                        decl
+                    },
+                    Some(Frame::Complex { name: parent, complex, counts, .. }) => match complex.children.iter().find(|e| e.name == name) {
+                        Some(decl) => {
+                            let count = counts.entry(name.clone()).or_insert(0);
+                            *count += 1;
+                            if decl.max.is_some_and(|max| *count > max) {
+                                violations.push(Violation { line, message: format!("too many <{}> elements in <{}>", name, parent) });
+                            }
+                            Some(decl)
+                        },
+                        None => {
+                            if !complex.any_children {
+                                violations.push(Violation { line, message: format!("unexpected element <{}> in <{}>", name, parent) });
+                            }
+                            None
+                        },
+                    },
+                    Some(Frame::Simple { name: parent, .. }) => {
+                        violations.push(Violation { line, message: format!("unexpected element <{}> in text-only <{}>", name, parent) });
+                        None
+                    },
+                    Some(Frame::Skip) => None,
+                };
+                let frame = match decl.map(|d| (d, schema.complex(&d.content))) {
+                    None => Frame::Skip,
+                    Some((_, Err(message))) => {
+                        violations.push(Violation { line, message });
+                        Frame::Skip
+                    },
+                    Some((decl, Ok(None))) => {
+                        let simple_type = match decl.content { Content::Simple(simple_type) => simple_type, _ => SimpleType::String };
+                        Frame::Simple { name, simple_type }
+                    },
+                    Some((_, Ok(Some(complex)))) => {
+                        check_attributes(&tag, &name, complex, line, &mut violations);
+                        Frame::Complex { name, line, complex, counts: HashMap::new() }
+                    },
+                };
+                if empty {
+                    close(frame, &mut violations);
+                } else {
+                    stack.push(frame);
+                }
+            },
+            Event::End(_) => {
+                if let Some(frame) = stack.pop() {
+                    close(frame, &mut violations);
+                }
+            },
+            Event::Text(text) => {
+                let Ok(text) = text.unescape() else { continue };
+                if text.trim().is_empty() {
+                    continue;
+                }
+                let line = line_at(before);
+                match stack.last() {
+                    Some(Frame::Simple { name, simple_type }) if !simple_type.accepts(&text) => {
+                        violations.push(Violation { line, message: format!("invalid {:?} text \"{}\" in <{}>", simple_type, text.trim(), name) });
+                    },
+                    Some(Frame::Complex { name, .. }) => {
+                        violations.push(Violation { line, message: format!("unexpected text in <{}>", name) });
+                    },
+                    _ => {},
+                }
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+    violations
+}
+
+/// Check the attributes of an element with complex content
+fn check_attributes(tag: &BytesStart, name: &str, complex: &ComplexType, line: usize, violations: &mut Vec<Violation>) {
+    let mut present = Vec::new();
+    for attribute in tag.attributes() {
+        let Ok(attribute) = attribute else {
+            violations.push(Violation { line, message: format!("malformed attribute in <{}>", name) });
+            continue;
+        };
+        let key = attribute.key;
+        // Namespace declarations are not attributes as far as the schema is concerned
+        if key.as_namespace_binding().is_some() {
+            continue;
+        }
+        let attribute_name = String::from_utf8_lossy(key.local_name().as_ref()).to_string();
+        let value = attribute.unescape_value().map(|v| v.to_string()).unwrap_or_default();
+        match complex.attributes.iter().find(|a| a.name == attribute_name) {
+            Some(decl) if !decl.simple_type.accepts(&value) => {
+                violations.push(Violation { line, message: format!("invalid {:?} value \"{}\" for attribute {} of <{}>", decl.simple_type, value, attribute_name, name) });
+            },
+            Some(_) => {},
+            None if complex.any_attribute => {},
+            None => violations.push(Violation { line, message: format!("unexpected attribute {} in <{}>", attribute_name, name) }),
+        }
+        present.push(attribute_name);
+    }
+    for decl in complex.attributes.iter().filter(|a| a.required && !present.contains(&a.name)) {
+        violations.push(Violation { line, message: format!("missing required attribute {} in <{}>", decl.name, name) });
+    }
+}
+
+/// Check that an element that just ended has enough of each child element
+fn close(frame: Frame, violations: &mut Vec<Violation>) {
+    if let Frame::Complex { name, line, complex, counts } = frame {
+        for decl in &complex.children {
+            if counts.get(&decl.name).copied().unwrap_or(0) < decl.min {
+                violations.push(Violation { line, message: format!("missing <{}> in <{}>", decl.name, name) });
+            }
+        }
+    }
+}
+
+/// Check raw file contents against `schema`, decompressing and decoding them first
+pub fn validate_bytes(bytes: &[u8], schema: &Schema) -> Result<Vec<Violation>, Box<dyn error::Error>> {
+    let bytes = decompress(bytes)?;
+    let xml = decode_xml(&bytes)?;
+    Ok(validate_xml(skip_leading_junk(&xml), schema))
+}
+
+/// Validate every XML document in `search_path`, printing each violation as `file:line: message`
+/// Uses the bundled ImageScope schema unless `schema_path` is given.
+/// Returns the number of documents that are not valid.
+pub fn run_validate(search_path: &path::Path, schema_path: Option<&path::Path>, options: &Options) -> Result<usize, Box<dyn error::Error>> {
+    let schema = match schema_path {
+        Some(schema_path) => Schema::parse(&std::fs::read_to_string(schema_path)?)
+            .map_err(|e| format!("Error reading schema from {}: {}", schema_path.display(), e))?,
+        None => Schema::bundled(),
+    };
+    let mut invalid = 0;
+    for source in xml_sources(search_path, options)? {
+        let violations = match source.contents().and_then(|bytes| validate_bytes(&bytes, &schema)) {
+            Ok(violations) => violations,
+            Err(e) => vec![Violation { line: 0, message: e.to_string() }],
+        };
+        for violation in &violations {
+            println!("{}:{}: {}", source.filename, violation.line, violation.message);
+        }
+        if !violations.is_empty() {
+            invalid += 1;
+        }
+    }
+    Ok(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Schema, Violation, validate_bytes, validate_xml};
+
+    const EXPORT: &str = include_str!("../tests/data/imagescope_export.xml");
+
+    /// Messages of the violations of a document against the bundled schema
+    fn messages(xml: &str) -> Vec<String> {
+        validate_xml(xml, &Schema::bundled()).into_iter().map(|v| v.message).collect()
+    }
+
+    #[test]
+    fn imagescope_export_is_valid() {
+        assert_eq!(validate_xml(EXPORT, &Schema::bundled()), []);
+        assert_eq!(validate_bytes(EXPORT.as_bytes(), &Schema::bundled()).unwrap(), []);
+    }
+
+    #[test]
+    fn required_and_typed_attributes_are_checked() {
+        assert_eq!(messages(r#"<Annotations><Annotation Type="4"/></Annotations>"#), ["missing required attribute Id in <Annotation>"]);
+        assert_eq!(messages(r#"<Annotations MicronsPerPixel="fast"/>"#), ["invalid Decimal value \"fast\" for attribute MicronsPerPixel of <Annotations>"]);
+        assert_eq!(messages(r#"<Annotations><Annotation Id="1" Type="4" Visible="yes"/></Annotations>"#), ["invalid Boolean value \"yes\" for attribute Visible of <Annotation>"]);
+        assert_eq!(messages(r#"<Annotations><Annotation Id="1.5" Type="4"/></Annotations>"#), ["invalid Integer value \"1.5\" for attribute Id of <Annotation>"]);
+        assert_eq!(messages(r#"<Annotations Owner="me"/>"#), ["unexpected attribute Owner in <Annotations>"]);
+    }
+
+    #[test]
+    fn unexpected_elements_and_occurrence_limits_are_checked() {
+        assert_eq!(messages("<Annotation Id=\"1\" Type=\"4\"/>"), ["unexpected root element <Annotation>"]);
+        assert_eq!(messages("<Annotations><Layer/></Annotations>"), ["unexpected element <Layer> in <Annotations>"]);
+        assert_eq!(messages("<Annotations><Annotation Id=\"1\" Type=\"4\"><Regions/><Regions/></Annotation></Annotations>"), ["too many <Regions> elements in <Annotation>"]);
+        // The bundled schema has no required elements, so a schema of its own checks the lower limit
+        let schema = Schema::parse(r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+            <xs:element name="Slide"><xs:complexType><xs:sequence>
+                <xs:element name="Name" type="xs:string" minOccurs="1"/>
+                <xs:element name="Level" type="xs:integer" minOccurs="0" maxOccurs="unbounded"/>
+            </xs:sequence></xs:complexType></xs:element>
+        </xs:schema>"#).unwrap();
+        assert_eq!(validate_xml("<Slide><Name>case1</Name><Level>0</Level><Level>1</Level></Slide>", &schema), []);
+        let messages: Vec<String> = validate_xml("<Slide><Level>top</Level></Slide>", &schema).into_iter().map(|v| v.message).collect();
+        assert_eq!(messages, ["invalid Integer text \"top\" in <Level>", "missing <Name> in <Slide>"]);
+    }
+
+    #[test]
+    fn any_content_is_not_checked() {
+        let xml = r#"<Annotations><Annotation Id="1" Type="4"><Plots><Plot Kind="line"><Point/></Plot></Plots></Annotation></Annotations>"#;
+        assert_eq!(messages(xml), Vec::<String>::new());
+    }
+
+    #[test]
+    fn violations_report_the_line_of_their_tag() {
+        let xml = "<Annotations>\n<Annotation Id=\"1\" Type=\"4\">\n<Regions>\n<Region\n  Id=\"x\"/>\n</Regions>\n</Annotation>\n</Annotations>";
+        assert_eq!(validate_xml(xml, &Schema::bundled()), [Violation { line: 4, message: String::from("invalid Integer value \"x\" for attribute Id of <Region>") }]);
+    }
+
+    #[test]
+    fn malformed_documents_are_a_violation() {
+        let violations = validate_xml("<Annotations>\n<Annotation Id=\"1\" Type=\"4\">\n</Annotations>", &Schema::bundled());
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].line, 3);
+        assert!(violations[0].message.starts_with("XML is not well-formed"), "{}", violations[0].message);
+    }
+}
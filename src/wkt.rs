@@ -0,0 +1,59 @@
+use crate::convert::ExportRegion;
+use crate::model::Region;
+use crate::report::csv_field;
+
+/// Column names of the WKT export
+pub const WKT_HEADER: &str = "Layer ID,Region ID,text label,geometry";
+
+/// Well-known text of a region: a polygon for closed shapes, a line string for lines and a point for
+/// single vertices
+pub fn wkt_geometry(region: &Region) -> String {
+    let outline = region.outline();
+    let coordinates = |points: &[(f64, f64)]| points.iter().map(|(x, y)| format!("{} {}", x, y)).collect::<Vec<_>>().join(", ");
+    match outline.len() {
+        0 => String::from("GEOMETRYCOLLECTION EMPTY"),
+        1 => format!("POINT ({})", coordinates(&outline)),
+        n if n >= 3 && region.region_type.is_closed() => {
+            // Rings end where they start
+            let mut ring = outline.clone();
+            ring.push(outline[0]);
+            format!("POLYGON (({}))", coordinates(&ring))
+        },
+        _ => format!("LINESTRING ({})", coordinates(&outline)),
+    }
+}
+
+/// CSV with the well-known text of each region, ready for PostGIS or Shapely
+pub fn to_wkt_csv(regions: &[ExportRegion]) -> String {
+    let mut csv = format!("{}\n", WKT_HEADER);
+    for r in regions {
+        csv.push_str(&format!("{},{},{},{}\n", csv_field(&r.layer.id), csv_field(&r.region.id), csv_field(&r.region.label()), csv_field(&wkt_geometry(r.region))));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{AnnotationBuilder, AnnotationsBuilder, RegionBuilder};
+    use crate::convert::export_regions;
+    use crate::model::RegionType;
+    use super::{WKT_HEADER, to_wkt_csv};
+
+    #[test]
+    fn regions_are_written_as_well_known_text() {
+        let annotations = AnnotationsBuilder::new()
+            .layer(AnnotationBuilder::new()
+                .region(RegionBuilder::new().region_type(RegionType::Rectangle).text("Tumor, core").vertices([(0.0, 0.0), (10.0, 20.0)]))
+                .region(RegionBuilder::new().region_type(RegionType::Ruler).vertices([(0.0, 0.0), (3.5, 4.0)]))
+                .region(RegionBuilder::new().vertex(7.0, 8.0)))
+            .build();
+        let csv = to_wkt_csv(&export_regions(&annotations, &[]));
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines, [
+            WKT_HEADER,
+            "1,1,\"Tumor, core\",\"POLYGON ((0 0, 10 0, 10 20, 0 20, 0 0))\"",
+            "1,2,,\"LINESTRING (0 0, 3.5 4)\"",
+            "1,3,,POINT (7 8)",
+        ]);
+    }
+}
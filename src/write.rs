@@ -0,0 +1,66 @@
+use std::io;
+use serde::Serialize;
+use quick_xml::DeError;
+use quick_xml::se::Serializer;
+use crate::model::Annotations;
+
+/// Declaration written at the top of every XML file
+const XML_DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+
+impl Annotations {
+    /// Convert the annotations back into ImageScope XML
+    /// Output starts with an XML declaration and uses `Annotations` as the root element, one element per line
+    pub fn to_xml_string(&self) -> Result<String, DeError> {
+        let mut xml = String::from(XML_DECLARATION);
+        xml.push('\n');
+        let mut serializer = Serializer::with_root(&mut xml, Some("Annotations"))?;
+        serializer.indent('\t', 1);
+        self.serialize(serializer)?;
+        xml.push('\n');
+        Ok(xml)
+    }
+
+    /// Write the annotations as ImageScope XML into `writer`
+    pub fn write_xml<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        let xml = self.to_xml_string().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        writer.write_all(xml.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse_xml_str;
+
+    const EXPORT: &str = include_str!("../tests/data/imagescope_export.xml");
+
+    #[test]
+    fn round_trip_keeps_a_real_export() {
+        let parsed = parse_xml_str(EXPORT).unwrap();
+        let written = parsed.to_xml_string().unwrap();
+        assert_eq!(parse_xml_str(&written).unwrap(), parsed);
+        assert_eq!(parse_xml_str(&written).unwrap().to_xml_string().unwrap(), written);
+    }
+
+    #[test]
+    fn round_trip_does_not_add_missing_attributes() {
+        let written = parse_xml_str(EXPORT).unwrap().to_xml_string().unwrap();
+        let region = written.lines().find(|line| line.contains("<Region Id=\"3\"")).unwrap();
+        assert!(!region.contains("NegativeROA"));
+        assert!(!region.contains("Analyze"));
+        assert!(!written.contains(" Z=\"\""));
+        let reparsed = parse_xml_str(&written).unwrap();
+        let region = &reparsed.annotation[1].regions.region[0];
+        assert_eq!(region.negative_roa, None);
+        assert_eq!(region.analyze, None);
+        assert!(reparsed.annotation[0].regions.region[1].is_negative());
+    }
+
+    #[test]
+    fn round_trip_keeps_extra_in_document_order() {
+        let parsed = parse_xml_str(EXPORT).unwrap();
+        let written = parsed.to_xml_string().unwrap();
+        let keys = |annotations: &crate::model::Annotations| annotations.annotation[0].extra.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(keys(&parsed), ["@ReadOnly", "@NameReadOnly", "@LineColorReadOnly", "@Incremental", "@LineColor", "@Visible", "@Selected", "@MarkupImagePath", "@MacroName", "Plots"]);
+        assert_eq!(keys(&parse_xml_str(&written).unwrap()), keys(&parsed));
+    }
+}